@@ -0,0 +1,173 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use colored::Colorize;
+
+use crate::{db::Conn, Error};
+
+// What to do when completing a migration while other sessions still use the schema
+// which is about to be removed. Postgres doesn't expose the search_path of other
+// sessions, so a session is considered to use a schema if it holds locks on any of
+// its views, if its application_name mentions the schema or if the last query it ran
+// mentioned the schema, for example `SET search_path TO migration_...`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ActiveClients {
+    // Complete without checking
+    Ignore,
+
+    // Print the sessions using the schema and complete anyway
+    #[default]
+    Warn,
+
+    // Fail with `Error::ConflictingState` without changing anything
+    Refuse,
+
+    // Wait for the sessions to stop using the schema, failing like `Refuse` if they
+    // haven't by the time the timeout runs out
+    Wait(Duration),
+}
+
+struct ActiveClient {
+    pid: i32,
+    application_name: String,
+    client: String,
+    reason: String,
+}
+
+impl ActiveClient {
+    fn describe(&self) -> String {
+        let application = if self.application_name.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", self.application_name)
+        };
+
+        format!(
+            "session {}{} from {} {}",
+            self.pid, application, self.client, self.reason
+        )
+    }
+}
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn check(
+    db: &mut dyn Conn,
+    schemas: &[String],
+    behaviour: ActiveClients,
+) -> anyhow::Result<()> {
+    if behaviour == ActiveClients::Ignore || schemas.is_empty() {
+        return Ok(());
+    }
+
+    let mut clients = find(db, schemas)?;
+    if clients.is_empty() {
+        return Ok(());
+    }
+
+    match behaviour {
+        ActiveClients::Ignore => Ok(()),
+        ActiveClients::Warn => {
+            println!(
+                "{} {} sessions still use {}, which will be removed:",
+                "Warning:".yellow(),
+                clients.len(),
+                schemas.join(", "),
+            );
+            for client in &clients {
+                println!("  - {}", client.describe());
+            }
+            println!();
+            Ok(())
+        }
+        ActiveClients::Refuse => Err(in_use_error(schemas, &clients, None)),
+        ActiveClients::Wait(timeout) => {
+            println!(
+                "Waiting for {} sessions to stop using {}",
+                clients.len(),
+                schemas.join(", "),
+            );
+
+            let deadline = Instant::now() + timeout;
+            while !clients.is_empty() {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(in_use_error(schemas, &clients, Some(timeout)));
+                }
+
+                std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+                clients = find(db, schemas)?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn find(db: &mut dyn Conn, schemas: &[String]) -> anyhow::Result<Vec<ActiveClient>> {
+    let clients = db
+        .query_with_params(
+            "
+            SELECT
+                a.pid,
+                COALESCE(a.application_name, '') AS application_name,
+                COALESCE(host(a.client_addr), 'local socket') AS client,
+                CASE
+                    WHEN EXISTS (
+                        SELECT 1
+                        FROM pg_locks l
+                        JOIN pg_class c ON c.oid = l.relation
+                        JOIN pg_namespace n ON n.oid = c.relnamespace
+                        WHERE l.pid = a.pid AND n.nspname::TEXT = ANY($1)
+                    ) THEN 'is using views in the schema'
+                    WHEN EXISTS (
+                        SELECT 1 FROM unnest($1::TEXT[]) schema
+                        WHERE position(schema IN a.application_name) > 0
+                    ) THEN 'has an application_name referring to the schema'
+                    WHEN EXISTS (
+                        SELECT 1 FROM unnest($1::TEXT[]) schema
+                        WHERE position(schema IN a.query) > 0
+                    ) THEN 'last ran a query referring to the schema'
+                END AS reason
+            FROM pg_stat_activity a
+            WHERE a.pid <> pg_backend_pid()
+                AND a.datname = current_database()
+                AND a.backend_type = 'client backend'
+            ORDER BY a.pid
+            ",
+            &[&schemas],
+        )
+        .context("failed to check for sessions using the previous schema")?
+        .iter()
+        .filter_map(|row| {
+            Some(ActiveClient {
+                pid: row.get("pid"),
+                application_name: row.get("application_name"),
+                client: row.get("client"),
+                reason: row.get::<_, Option<String>>("reason")?,
+            })
+        })
+        .collect();
+
+    Ok(clients)
+}
+
+fn in_use_error(
+    schemas: &[String],
+    clients: &[ActiveClient],
+    waited: Option<Duration>,
+) -> anyhow::Error {
+    let descriptions: Vec<String> = clients.iter().map(ActiveClient::describe).collect();
+    let waited = match waited {
+        Some(timeout) => format!(" after waiting for {}s", timeout.as_secs()),
+        None => String::new(),
+    };
+
+    Error::ConflictingState(format!(
+        "{} is still in use{} and completing would break these sessions:\n  - {}\nSwitch them over to the new schema or use --active-clients warn to complete anyway.",
+        schemas.join(", "),
+        waited,
+        descriptions.join("\n  - "),
+    ))
+    .into()
+}
@@ -0,0 +1,128 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+// Backfills normally run to the end while migrating. For disaster scenarios, like a
+// table which turns out to be far larger than expected, they can be skipped or limited
+// instead. Anything which isn't backfilled is left pending in the state and is picked
+// up by `Reshape::backfill`, which can be run in controlled windows with its own
+// limits. Writes are kept in sync by the triggers in the meantime, but rows which
+// haven't been backfilled yet are missing their new values, so a migration can't be
+// completed while any backfills are pending.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct BackfillLimit {
+    pub skip: bool,
+    pub max_rows: Option<u64>,
+    pub max_duration: Option<Duration>,
+}
+
+impl BackfillLimit {
+    // Whether backfills must run separately from their actions, so they can be
+    // skipped or stopped early
+    pub fn is_limited(&self) -> bool {
+        self.skip || self.max_rows.is_some() || self.max_duration.is_some()
+    }
+
+    // The window for the next backfill of a command which started at `started_at`
+    // and has backfilled `rows_backfilled` rows so far
+    pub fn window(
+        &self,
+        started_at: Instant,
+        rows_backfilled: u64,
+        cursor: Option<BackfillCursor>,
+    ) -> BackfillWindow {
+        let max_rows = if self.skip { Some(0) } else { self.max_rows };
+        BackfillWindow {
+            cursor,
+            max_rows: max_rows.map(|max_rows| max_rows.saturating_sub(rows_backfilled)),
+            deadline: self.max_duration.map(|duration| started_at + duration),
+        }
+    }
+}
+
+// How much a single backfill may do before it's stopped, and where it continues from
+// if it was stopped before
+#[derive(Clone, Debug)]
+pub(crate) struct BackfillWindow {
+    cursor: Option<BackfillCursor>,
+    max_rows: Option<u64>,
+    deadline: Option<Instant>,
+}
+
+impl BackfillWindow {
+    pub fn cursor(&self) -> Option<&BackfillCursor> {
+        self.cursor.as_ref()
+    }
+
+    // Checked before every batch with the number of rows touched so far
+    pub fn is_exhausted(&self, rows: u64) -> bool {
+        self.max_rows.is_some_and(|max_rows| rows >= max_rows)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+// Where a backfill which was stopped early continues from
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BackfillCursor {
+    // The raw values of the key columns of the last row touched
+    Key(Vec<Vec<u8>>),
+    // The first page which hasn't been touched
+    Page(i64),
+}
+
+// Returned by a backfill once its window is exhausted. It's not a failure, the
+// backfill is left pending instead.
+#[derive(Debug)]
+pub(crate) struct BackfillStopped {
+    pub cursor: Option<BackfillCursor>,
+}
+
+impl fmt::Display for BackfillStopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "backfill was stopped after reaching its limit")
+    }
+}
+
+impl std::error::Error for BackfillStopped {}
+
+// A backfill which was skipped or stopped early while migrating. The action is
+// identified by its position in the migrations being applied.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PendingBackfill {
+    pub migration: String,
+    pub action: String,
+    pub migration_index: usize,
+    pub action_index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cursor: Option<BackfillCursor>,
+}
+
+impl PendingBackfill {
+    pub(crate) fn new(
+        migration: impl Into<String>,
+        action: impl Into<String>,
+        migration_index: usize,
+        action_index: usize,
+        cursor: Option<BackfillCursor>,
+    ) -> Self {
+        PendingBackfill {
+            migration: migration.into(),
+            action: action.into(),
+            migration_index,
+            action_index,
+            cursor,
+        }
+    }
+
+    // Whether some of the rows have already been backfilled
+    pub fn is_started(&self) -> bool {
+        self.cursor.is_some()
+    }
+}
@@ -0,0 +1,60 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use crate::Error;
+
+// CancellationToken lets a service embedding Reshape stop a running migration,
+// for example when shutting down. Clones share the same underlying flag, so one
+// clone can be passed to `Reshape::migrate_with` while another is cancelled from
+// a different thread.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+// Cancellation is checked by the connection before every statement, which means
+// long-running operations like backfills stop between batches
+#[derive(Clone)]
+pub(crate) struct Cancellation {
+    token: CancellationToken,
+    deadline: Option<Instant>,
+}
+
+impl Cancellation {
+    pub(crate) fn new(token: CancellationToken, deadline: Option<Instant>) -> Self {
+        Cancellation { token, deadline }
+    }
+
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        if self.token.is_cancelled() {
+            return Err(Error::Cancelled("operation was cancelled".to_string()).into());
+        }
+
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(Error::Cancelled("deadline was exceeded".to_string()).into());
+            }
+        }
+
+        Ok(())
+    }
+}
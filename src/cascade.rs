@@ -0,0 +1,139 @@
+use anyhow::Context;
+use colored::Colorize;
+
+use crate::{db::Conn, Error};
+
+// What to do when dropping Reshape's schemas or tables would also drop objects which
+// depend on them. Schemas and tables are dropped using `DROP ... CASCADE`, which
+// silently removes anything built on top of them, like a view in `public` selecting
+// from one of the migration's views or a function taking one of them as a parameter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CascadeDrops {
+    // Print the objects which would be dropped along and continue
+    #[default]
+    Warn,
+
+    // Fail with `Error::ConflictingState` without dropping anything
+    Refuse,
+}
+
+// Check for objects which would be dropped along with the schemas and the tables in
+// `public`. This must be done before anything is changed, so refusing leaves the
+// database as it was.
+pub fn check(
+    db: &mut dyn Conn,
+    schemas: &[String],
+    tables: &[String],
+    behaviour: CascadeDrops,
+) -> anyhow::Result<()> {
+    let dependents = find(db, schemas, tables)?;
+    if dependents.is_empty() {
+        return Ok(());
+    }
+
+    let dropping = schemas
+        .iter()
+        .map(|schema| format!("schema {}", schema))
+        .chain(tables.iter().map(|table| format!("table {}", table)))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    match behaviour {
+        CascadeDrops::Warn => {
+            println!(
+                "{} dropping {} will also drop these objects which depend on them:",
+                "Warning:".yellow(),
+                dropping,
+            );
+            for dependent in &dependents {
+                println!("  - {}", dependent);
+            }
+            println!();
+            Ok(())
+        }
+        CascadeDrops::Refuse => Err(Error::ConflictingState(format!(
+            "dropping {} would also drop these objects which depend on them:\n  - {}\nRemove them first or use --cascade-drops warn to drop them anyway.",
+            dropping,
+            dependents.join("\n  - "),
+        ))
+        .into()),
+    }
+}
+
+// Objects which Postgres would drop along with the schemas and tables, apart from
+// those which are part of them. Objects within the schemas are part of them, as are
+// indices, constraints, triggers and owned sequences of the tables.
+//
+// Dependencies are followed through `pg_depend`. Views depend on other objects through
+// their rewrite rule, so those are replaced with the view itself. Only objects reached
+// through a normal dependency are reported, the internal parts of those, like the row
+// type of a view, go with them.
+fn find(db: &mut dyn Conn, schemas: &[String], tables: &[String]) -> anyhow::Result<Vec<String>> {
+    if schemas.is_empty() && tables.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let dependents = db
+        .query_with_params(
+            "
+            WITH RECURSIVE
+                edges AS (
+                    SELECT
+                        CASE
+                            WHEN d.classid = 'pg_rewrite'::regclass THEN 'pg_class'::regclass
+                            ELSE d.classid
+                        END AS classid,
+                        COALESCE(r.ev_class, d.objid) AS objid,
+                        d.refclassid,
+                        d.refobjid,
+                        d.deptype
+                    FROM pg_depend d
+                    LEFT JOIN pg_rewrite r ON d.classid = 'pg_rewrite'::regclass AND r.oid = d.objid
+                    WHERE d.deptype IN ('n', 'a', 'i')
+                ),
+                roots (classid, objid) AS (
+                    SELECT 'pg_namespace'::regclass, oid
+                    FROM pg_namespace
+                    WHERE nspname::TEXT = ANY($1)
+                    UNION
+                    SELECT d.classid, d.objid
+                    FROM pg_depend d
+                    JOIN pg_namespace n ON d.refclassid = 'pg_namespace'::regclass AND d.refobjid = n.oid
+                    WHERE n.nspname::TEXT = ANY($1)
+                    UNION
+                    SELECT 'pg_class'::regclass, c.oid
+                    FROM unnest($2::TEXT[]) AS name
+                    JOIN pg_class c ON c.oid = to_regclass(format('public.%I', name))
+                    WHERE c.relkind IN ('r', 'p')
+                ),
+                owned (classid, objid) AS (
+                    SELECT classid, objid FROM roots
+                    UNION
+                    SELECT e.classid, e.objid
+                    FROM edges e
+                    JOIN owned o ON e.refclassid = o.classid AND e.refobjid = o.objid
+                    WHERE e.deptype IN ('a', 'i')
+                ),
+                dropped (classid, objid, cascaded) AS (
+                    SELECT classid, objid, FALSE FROM roots
+                    UNION
+                    SELECT e.classid, e.objid, e.deptype = 'n'
+                    FROM edges e
+                    JOIN dropped o ON e.refclassid = o.classid AND e.refobjid = o.objid
+                )
+            SELECT DISTINCT format('%s %s', object.type, object.identity) AS description
+            FROM dropped
+            CROSS JOIN LATERAL pg_identify_object(dropped.classid, dropped.objid, 0) AS object
+            WHERE dropped.cascaded
+                AND (dropped.classid, dropped.objid) NOT IN (SELECT classid, objid FROM owned)
+            ORDER BY description
+            ",
+            &[&schemas, &tables],
+        )
+        .context("failed to find objects depending on the objects being dropped")?
+        .iter()
+        .map(|row| row.get("description"))
+        .collect();
+
+    Ok(dependents)
+}
@@ -0,0 +1,502 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context};
+use postgres::{config::Host, Config};
+
+use crate::{redact, Error};
+
+const DEFAULT_HOST: &str = "localhost";
+const DEFAULT_PORT: u16 = 5432;
+const DEFAULT_DATABASE: &str = "postgres";
+const DEFAULT_USERNAME: &str = "postgres";
+const DEFAULT_PASSWORD: &str = "postgres";
+
+// Names of the environment variables for the host, port, database, username and
+// password, in that order
+const RESHAPE_VARIABLES: [&str; 5] = [
+    "DB_HOST",
+    "DB_PORT",
+    "DB_NAME",
+    "DB_USERNAME",
+    "DB_PASSWORD",
+];
+const LIBPQ_VARIABLES: [&str; 5] = ["PGHOST", "PGPORT", "PGDATABASE", "PGUSER", "PGPASSWORD"];
+
+// Where to connect to and as whom, gathered from several sources. Each setting is
+// taken from the first of these which sets it:
+//
+// 1. The connection URL
+// 2. The individual settings, like the host and database
+// 3. A connection URL in the `POSTGRES_URL` or `DB_URL` environment variable
+// 4. The service from the connection service file, see `with_service`
+// 5. The `DB_*` environment variables, followed by the `PG*` ones used by libpq
+// 6. For the password, a matching line in the password file, see `read_pgpass`
+// 7. Defaults, which connect to the "postgres" database on localhost as "postgres"
+//
+// Settings are resolved one by one, so setting only the database still uses the
+// host from the environment. Any other parameters of the URL which is used, like
+// `sslmode`, are kept.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct ConnectionSettings {
+    url: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    database: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    service: Option<String>,
+}
+
+// Passwords are left out, so the settings can be logged
+impl fmt::Debug for ConnectionSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionSettings")
+            .field("url", &self.url.as_deref().map(redact))
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("database", &self.database)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "********"))
+            .field("password_file", &self.password_file)
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+// The part of a source which decides where to connect to
+#[derive(Default)]
+struct Target {
+    hosts: Vec<Host>,
+    ports: Vec<u16>,
+    database: Option<String>,
+    username: Option<String>,
+    password: Option<Vec<u8>>,
+}
+
+impl ConnectionSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    pub fn with_database(mut self, database: impl Into<String>) -> Self {
+        self.database = Some(database.into());
+        self
+    }
+
+    pub fn with_username(mut self, username: impl Into<String>) -> Self {
+        self.username = Some(username.into());
+        self
+    }
+
+    pub fn with_password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    // Read the password from the first line of a file, for example a mounted secret
+    pub fn with_password_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.password_file = Some(path.into());
+        self
+    }
+
+    // Use a connection defined in the connection service file, like libpq does for
+    // `PGSERVICE`. The file is `PGSERVICEFILE` or `~/.pg_service.conf`, followed by
+    // `pg_service.conf` in `PGSYSCONFDIR`.
+    pub fn with_service(mut self, service: impl Into<String>) -> Self {
+        self.service = Some(service.into());
+        self
+    }
+
+    // Resolve the settings using the environment of the current process
+    pub fn resolve(&self) -> anyhow::Result<Config> {
+        self.resolve_with_env(|name| std::env::var(name).ok())
+    }
+
+    // Resolve the settings, looking up environment variables using `env`
+    pub fn resolve_with_env(&self, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<Config> {
+        let url = self.url.as_deref().map(parse_url).transpose()?;
+        let env_url = ["POSTGRES_URL", "DB_URL"]
+            .iter()
+            .find_map(|name| env(name))
+            .map(|url| parse_url(&url))
+            .transpose()?;
+
+        let service = match self.service.clone().or_else(|| env("PGSERVICE")) {
+            Some(service) => Some(read_service(&env, &service)?),
+            None => None,
+        };
+
+        let sources = [
+            url.as_ref().map(Target::from_config).unwrap_or_default(),
+            self.target()?,
+            env_url
+                .as_ref()
+                .map(Target::from_config)
+                .unwrap_or_default(),
+            service
+                .as_ref()
+                .map(Target::from_config)
+                .unwrap_or_default(),
+            Target::from_env(&env, RESHAPE_VARIABLES)?,
+            Target::from_env(&env, LIBPQ_VARIABLES)?,
+        ];
+
+        let mut config = match url.as_ref().or(env_url.as_ref()).or(service.as_ref()) {
+            Some(base) => without_target(base),
+            None => Config::new(),
+        };
+
+        let hosts = sources
+            .iter()
+            .map(|source| &source.hosts)
+            .find(|hosts| !hosts.is_empty())
+            .cloned()
+            .unwrap_or_else(|| vec![Host::Tcp(DEFAULT_HOST.to_string())]);
+        let ports = sources
+            .iter()
+            .map(|source| &source.ports)
+            .find(|ports| !ports.is_empty())
+            .cloned()
+            .unwrap_or_else(|| vec![DEFAULT_PORT]);
+        let database = sources
+            .iter()
+            .find_map(|source| source.database.clone())
+            .unwrap_or_else(|| DEFAULT_DATABASE.to_string());
+        let username = sources
+            .iter()
+            .find_map(|source| source.username.clone())
+            .unwrap_or_else(|| DEFAULT_USERNAME.to_string());
+
+        let password = match sources.iter().find_map(|source| source.password.clone()) {
+            Some(password) => password,
+            None => read_pgpass(&env, &hosts[0], ports[0], &database, &username)?
+                .unwrap_or_else(|| DEFAULT_PASSWORD.to_string())
+                .into_bytes(),
+        };
+
+        for host in hosts {
+            match host {
+                Host::Tcp(host) => config.host(&host),
+                #[cfg(unix)]
+                Host::Unix(path) => config.host_path(path),
+            };
+        }
+        for port in ports {
+            config.port(port);
+        }
+        config.dbname(&database).user(&username).password(password);
+
+        Ok(config)
+    }
+
+    fn target(&self) -> anyhow::Result<Target> {
+        let password = match (&self.password, &self.password_file) {
+            (Some(_), Some(_)) => bail!("only one of a password and a password file can be set"),
+            (Some(password), None) => Some(password.clone()),
+            (None, Some(path)) => Some(read_password_file(path)?),
+            (None, None) => None,
+        };
+
+        Ok(Target {
+            hosts: self
+                .host
+                .iter()
+                .flat_map(|host| parse_hosts(host))
+                .collect(),
+            ports: self.port.into_iter().collect(),
+            database: self.database.clone(),
+            username: self.username.clone(),
+            password: password.map(String::into_bytes),
+        })
+    }
+}
+
+impl Target {
+    fn from_config(config: &Config) -> Self {
+        Target {
+            hosts: config.get_hosts().to_vec(),
+            ports: config.get_ports().to_vec(),
+            database: config.get_dbname().map(str::to_string),
+            username: config.get_user().map(str::to_string),
+            password: config.get_password().map(<[u8]>::to_vec),
+        }
+    }
+
+    fn from_env(
+        env: &impl Fn(&str) -> Option<String>,
+        [host, port, database, username, password]: [&str; 5],
+    ) -> anyhow::Result<Self> {
+        let ports = match env(port) {
+            Some(value) => value
+                .split(',')
+                .map(|part| {
+                    part.trim()
+                        .parse::<u16>()
+                        .map_err(|_| anyhow!("invalid port \"{}\" in {}", value, port))
+                })
+                .collect::<anyhow::Result<Vec<u16>>>()?,
+            None => Vec::new(),
+        };
+
+        Ok(Target {
+            hosts: env(host)
+                .map(|hosts| parse_hosts(&hosts))
+                .unwrap_or_default(),
+            ports,
+            database: env(database),
+            username: env(username),
+            password: env(password).map(String::into_bytes),
+        })
+    }
+}
+
+// Look up the password for a connection in the password file, `PGPASSFILE` or
+// `~/.pgpass`. Each line has the format `hostname:port:database:username:password`,
+// where `*` matches anything and `\` escapes colons and backslashes. Like libpq,
+// the file is ignored if others can read it.
+fn read_pgpass(
+    env: &impl Fn(&str) -> Option<String>,
+    host: &Host,
+    port: u16,
+    database: &str,
+    username: &str,
+) -> anyhow::Result<Option<String>> {
+    let path = match env("PGPASSFILE")
+        .map(PathBuf::from)
+        .or_else(|| env("HOME").map(|home| Path::new(&home).join(".pgpass")))
+    {
+        Some(path) if path.is_file() => path,
+        _ => return Ok(None),
+    };
+
+    #[cfg(unix)]
+    {
+        use colored::Colorize;
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(&path)
+            .with_context(|| format!("failed to read password file {}", path.display()))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            eprintln!(
+                "{} password file {} is ignored as it can be read by other users, it should be limited to u=rw (0600) or less",
+                "Warning:".yellow(),
+                path.display()
+            );
+            return Ok(None);
+        }
+    }
+
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read password file {}", path.display()))?;
+
+    // Connections over Unix sockets match "localhost"
+    let host = match host {
+        Host::Tcp(host) => host.as_str(),
+        #[cfg(unix)]
+        Host::Unix(_) => "localhost",
+    };
+    let port = port.to_string();
+    let wanted = [host, port.as_str(), database, username];
+
+    for line in contents.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = split_pgpass_line(line);
+        if fields.len() < 5 {
+            continue;
+        }
+
+        let matches = fields
+            .iter()
+            .zip(wanted)
+            .all(|(field, value)| field == "*" || field == value);
+        if matches {
+            return Ok(Some(fields[4].clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+fn split_pgpass_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    fields.last_mut().unwrap().push(escaped);
+                }
+            }
+            // The password is the rest of the line
+            ':' if fields.len() < 5 => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+// Read a service from the connection service file, which is made up of sections
+// named after the services with `key=value` lines using libpq's parameter names
+fn read_service(env: &impl Fn(&str) -> Option<String>, service: &str) -> anyhow::Result<Config> {
+    let user_file = env("PGSERVICEFILE")
+        .map(PathBuf::from)
+        .or_else(|| env("HOME").map(|home| Path::new(&home).join(".pg_service.conf")));
+    let system_file = env("PGSYSCONFDIR").map(|dir| Path::new(&dir).join("pg_service.conf"));
+
+    let files: Vec<PathBuf> = user_file
+        .into_iter()
+        .chain(system_file)
+        .filter(|path| path.is_file())
+        .collect();
+
+    for path in &files {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read service file {}", path.display()))?;
+
+        let parameters = match service_parameters(&contents, service) {
+            Some(parameters) => parameters,
+            None => continue,
+        };
+
+        let connection_string: Vec<String> = parameters
+            .iter()
+            .map(|(key, value)| {
+                format!(
+                    "{}='{}'",
+                    key,
+                    value.replace('\\', "\\\\").replace('\'', "\\'")
+                )
+            })
+            .collect();
+        return connection_string
+            .join(" ")
+            .parse()
+            .map_err(|err: postgres::Error| anyhow!(err))
+            .with_context(|| format!("invalid service \"{}\" in {}", service, path.display()));
+    }
+
+    if files.is_empty() {
+        bail!(
+            "service \"{}\" can't be used as no connection service file exists",
+            service
+        );
+    }
+    let files: Vec<String> = files
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    bail!(
+        "service \"{}\" isn't defined in {}",
+        service,
+        files.join(", ")
+    )
+}
+
+// The parameters of a section in a service file, if it exists
+fn service_parameters(contents: &str, service: &str) -> Option<Vec<(String, String)>> {
+    let mut parameters = None;
+
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+        {
+            if parameters.is_some() {
+                break;
+            }
+            if name.trim() == service {
+                parameters = Some(Vec::new());
+            }
+            continue;
+        }
+
+        if let (Some(parameters), Some((key, value))) = (&mut parameters, line.split_once('=')) {
+            parameters.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    parameters
+}
+
+fn parse_url(url: &str) -> anyhow::Result<Config> {
+    url.parse()
+        .map_err(|err: postgres::Error| Error::Connection(err.into()).into())
+}
+
+// Hosts are separated by commas, like in libpq. Those starting with a slash are
+// directories containing Unix sockets.
+fn parse_hosts(hosts: &str) -> Vec<Host> {
+    let mut config = Config::new();
+    for host in hosts.split(',').filter(|host| !host.is_empty()) {
+        config.host(host);
+    }
+    config.get_hosts().to_vec()
+}
+
+fn read_password_file(path: &Path) -> anyhow::Result<String> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("failed to read password file {}", path.display()))?;
+    Ok(contents.lines().next().unwrap_or_default().to_string())
+}
+
+// A copy of the config with every parameter except where to connect to and as whom,
+// as hosts and ports can only be added to a config and never replaced
+fn without_target(config: &Config) -> Config {
+    let mut copy = Config::new();
+    copy.ssl_mode(config.get_ssl_mode())
+        .ssl_negotiation(config.get_ssl_negotiation())
+        .keepalives(config.get_keepalives())
+        .keepalives_idle(config.get_keepalives_idle())
+        .target_session_attrs(config.get_target_session_attrs())
+        .channel_binding(config.get_channel_binding())
+        .load_balance_hosts(config.get_load_balance_hosts());
+
+    if let Some(options) = config.get_options() {
+        copy.options(options);
+    }
+    if let Some(application_name) = config.get_application_name() {
+        copy.application_name(application_name);
+    }
+    if let Some(connect_timeout) = config.get_connect_timeout() {
+        copy.connect_timeout(*connect_timeout);
+    }
+    if let Some(tcp_user_timeout) = config.get_tcp_user_timeout() {
+        copy.tcp_user_timeout(*tcp_user_timeout);
+    }
+    if let Some(keepalives_interval) = config.get_keepalives_interval() {
+        copy.keepalives_interval(keepalives_interval);
+    }
+    if let Some(keepalives_retries) = config.get_keepalives_retries() {
+        copy.keepalives_retries(keepalives_retries);
+    }
+
+    copy
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+
+use crate::{db::Conn, schema::Table};
+
+// Generate a Diesel schema, like the `schema.rs` written by `diesel print-schema`, for
+// the views of a migration. Diesel's own schema inference only looks at tables, so it
+// can't be pointed at a migration's schema. The tables are described using the names
+// they have in the migration, which Diesel will find as long as the connection uses the
+// migration's schema, see `reshape schema-query`.
+//
+// Types and nullability come from the backing columns. Types which Diesel doesn't
+// know, like enums and domains, get a type in `sql_types` which the application has
+// to implement `ToSql` and `FromSql` for. Tables without a primary key are left out,
+// as Diesel requires one.
+pub fn schema(db: &mut dyn Conn, migration_name: &str, tables: &[Table]) -> anyhow::Result<String> {
+    let mut tables: Vec<&Table> = tables.iter().collect();
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut custom_types: BTreeMap<String, String> = BTreeMap::new();
+    let mut definitions: Vec<String> = Vec::new();
+    let mut skipped: Vec<&str> = Vec::new();
+    let mut joinables: Vec<(String, String, String)> = Vec::new();
+    let mut included: Vec<&str> = Vec::new();
+
+    for table in &tables {
+        let primary_key = primary_key(db, table)?;
+        if primary_key.is_empty() {
+            skipped.push(&table.name);
+            continue;
+        }
+        included.push(&table.name);
+
+        let mut used_types: Vec<String> = Vec::new();
+        let columns: Vec<String> = table
+            .columns
+            .iter()
+            .map(|column| {
+                let sql_type = sql_type(&column.data_type).unwrap_or_else(|| {
+                    let (name, attribute) = custom_type(&column.data_type);
+                    custom_types.insert(name.clone(), attribute);
+                    if !used_types.contains(&name) {
+                        used_types.push(name.clone());
+                    }
+
+                    if column.data_type.ends_with("[]") {
+                        format!("Array<{}>", name)
+                    } else {
+                        name
+                    }
+                });
+
+                let sql_type = if column.nullable {
+                    format!("Nullable<{}>", sql_type)
+                } else {
+                    sql_type
+                };
+                format!(
+                    "{}        {} -> {},",
+                    sql_name(&column.name, "        "),
+                    rust_name(&column.name),
+                    sql_type
+                )
+            })
+            .collect();
+
+        let imports: String = used_types
+            .iter()
+            .map(|name| format!("    use super::sql_types::{};\n", name))
+            .collect();
+        let primary_key: Vec<String> = primary_key.iter().map(|name| rust_name(name)).collect();
+        definitions.push(format!(
+            "diesel::table! {{\n    use diesel::sql_types::*;\n{}\n{}    {} ({}) {{\n{}\n    }}\n}}\n",
+            imports,
+            sql_name(&table.name, "    "),
+            rust_name(&table.name),
+            primary_key.join(", "),
+            columns.join("\n"),
+        ));
+
+        for (column, referenced) in foreign_keys(db, table, &tables)? {
+            joinables.push((table.name.to_string(), referenced, column));
+        }
+    }
+
+    // Foreign keys to tables which were left out can't be joined on
+    joinables.retain(|(_, referenced, _)| included.contains(&referenced.as_str()));
+
+    let mut output = format!(
+        "// @generated by Reshape for migration '{}'. Regenerate after adding migrations.\n",
+        migration_name
+    );
+    for table in &skipped {
+        output.push_str(&format!(
+            "// Skipped table {}, which has no primary key\n",
+            table
+        ));
+    }
+
+    if !custom_types.is_empty() {
+        output.push_str("\npub mod sql_types {\n");
+        let types: Vec<String> = custom_types
+            .iter()
+            .map(|(name, type_name)| {
+                format!(
+                    "    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]\n    #[diesel({})]\n    pub struct {};\n",
+                    type_name, name
+                )
+            })
+            .collect();
+        output.push_str(&types.join("\n"));
+        output.push_str("}\n");
+    }
+
+    for definition in &definitions {
+        output.push('\n');
+        output.push_str(definition);
+    }
+
+    if !joinables.is_empty() {
+        output.push('\n');
+        for (table, referenced, column) in &joinables {
+            output.push_str(&format!(
+                "diesel::joinable!({} -> {} ({}));\n",
+                rust_name(table),
+                rust_name(referenced),
+                rust_name(column)
+            ));
+        }
+    }
+
+    if included.len() > 1 {
+        output.push_str("\ndiesel::allow_tables_to_appear_in_same_query!(\n");
+        for table in &included {
+            output.push_str(&format!("    {},\n", rust_name(table)));
+        }
+        output.push_str(");\n");
+    }
+
+    Ok(output)
+}
+
+// Columns of the primary key using their names in the migration
+fn primary_key(db: &mut dyn Conn, table: &Table) -> anyhow::Result<Vec<String>> {
+    let columns: Vec<String> = db
+        .query_with_params(
+            "
+            SELECT pg_attribute.attname::TEXT AS column_name
+            FROM pg_index
+            JOIN pg_attribute
+                ON pg_attribute.attrelid = pg_index.indrelid
+                AND pg_attribute.attnum = ANY(pg_index.indkey)
+            WHERE pg_index.indrelid = to_regclass(format('public.%I', $1::TEXT))
+                AND pg_index.indisprimary
+            ORDER BY array_position(pg_index.indkey::INT2[], pg_attribute.attnum)
+            ",
+            &[&table.real_name],
+        )
+        .with_context(|| format!("failed to get primary key of {}", table.name))?
+        .iter()
+        .map(|row| row.get("column_name"))
+        .collect();
+
+    Ok(columns
+        .iter()
+        .filter_map(|real_name| {
+            table
+                .columns
+                .iter()
+                .find(|column| &column.real_name == real_name)
+                .map(|column| column.name.to_string())
+        })
+        .collect())
+}
+
+// Single column foreign keys, as the column and the referenced table, using their names
+// in the migration. Diesel can only join on those.
+fn foreign_keys(
+    db: &mut dyn Conn,
+    table: &Table,
+    tables: &[&Table],
+) -> anyhow::Result<Vec<(String, String)>> {
+    let foreign_keys: Vec<(String, String)> = db
+        .query_with_params(
+            "
+            SELECT
+                pg_attribute.attname::TEXT AS column_name,
+                referenced.relname::TEXT AS referenced_table
+            FROM pg_constraint
+            JOIN pg_attribute
+                ON pg_attribute.attrelid = pg_constraint.conrelid
+                AND pg_attribute.attnum = pg_constraint.conkey[1]
+            JOIN pg_class AS referenced ON referenced.oid = pg_constraint.confrelid
+            WHERE pg_constraint.conrelid = to_regclass(format('public.%I', $1::TEXT))
+                AND pg_constraint.contype = 'f'
+                AND array_length(pg_constraint.conkey, 1) = 1
+            ORDER BY pg_constraint.conname
+            ",
+            &[&table.real_name],
+        )
+        .with_context(|| format!("failed to get foreign keys of {}", table.name))?
+        .iter()
+        .map(|row| (row.get("column_name"), row.get("referenced_table")))
+        .collect();
+
+    Ok(foreign_keys
+        .into_iter()
+        .filter_map(|(real_column, real_referenced)| {
+            let column = table
+                .columns
+                .iter()
+                .find(|column| column.real_name == real_column)?;
+            let referenced = tables
+                .iter()
+                .find(|table| table.real_name == real_referenced)?;
+            Some((column.name.to_string(), referenced.name.to_string()))
+        })
+        .collect())
+}
+
+// The Diesel type for a type as formatted by Postgres, if Diesel has one built in
+fn sql_type(data_type: &str) -> Option<String> {
+    if let Some(element) = data_type.strip_suffix("[]") {
+        return sql_type(element).map(|element| format!("Array<{}>", element));
+    }
+
+    // Modifiers like the length of `character varying(10)` don't change the type
+    let base = match data_type.find('(') {
+        Some(index) => {
+            let (name, rest) = data_type.split_at(index);
+            let suffix = rest.find(')').map(|end| &rest[end + 1..]).unwrap_or("");
+            format!("{}{}", name, suffix)
+        }
+        None => data_type.to_string(),
+    };
+
+    let sql_type = match base.as_str() {
+        "smallint" => "Int2",
+        "integer" => "Int4",
+        "bigint" => "Int8",
+        "real" => "Float4",
+        "double precision" => "Float8",
+        "numeric" => "Numeric",
+        "text" | "character varying" | "character" | "name" => "Text",
+        "boolean" => "Bool",
+        "bytea" => "Bytea",
+        "date" => "Date",
+        "time without time zone" => "Time",
+        "timestamp without time zone" => "Timestamp",
+        "timestamp with time zone" => "Timestamptz",
+        "interval" => "Interval",
+        "uuid" => "Uuid",
+        "json" => "Json",
+        "jsonb" => "Jsonb",
+        "inet" => "Inet",
+        "cidr" => "Cidr",
+        "macaddr" => "MacAddr",
+        "money" => "Money",
+        "oid" => "Oid",
+        "\"char\"" => "CChar",
+        _ => return None,
+    };
+    Some(sql_type.to_string())
+}
+
+// A type for `sql_types`, named like Diesel names them, along with the attribute
+// pointing it at the Postgres type. Types outside of the search path, like those from
+// extensions, are formatted with their schema.
+fn custom_type(data_type: &str) -> (String, String) {
+    let data_type = data_type.trim_end_matches("[]");
+    let data_type = data_type.split('(').next().unwrap_or(data_type);
+    let unquote = |name: &str| name.trim_matches('"').to_string();
+
+    let (schema, name) = match data_type.split_once('.') {
+        Some((schema, name)) => (Some(unquote(schema)), unquote(name)),
+        None => (None, unquote(data_type)),
+    };
+
+    let rust_name: String = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<String>>()
+        .concat();
+
+    let attribute = match schema {
+        Some(schema) => format!(
+            "postgres_type(name = \"{}\", schema = \"{}\")",
+            name, schema
+        ),
+        None => format!("postgres_type(name = \"{}\")", name),
+    };
+    (rust_name, attribute)
+}
+
+const KEYWORDS: &[&str] = &[
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+    "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where",
+    "while", "async", "await", "dyn",
+];
+
+// Names which aren't valid Rust identifiers get a trailing underscore and keep their
+// name in the database using `sql_name`, the same way Diesel handles them
+fn rust_name(name: &str) -> String {
+    let valid = name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !name.starts_with(|c: char| c.is_ascii_digit());
+    if valid && !KEYWORDS.contains(&name) {
+        return name.to_string();
+    }
+
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name.to_lowercase())
+    } else {
+        format!("{}_", name.to_lowercase())
+    }
+}
+
+fn sql_name(name: &str, indent: &str) -> String {
+    if rust_name(name) == name {
+        String::new()
+    } else {
+        format!("{}#[sql_name = \"{}\"]\n", indent, name)
+    }
+}
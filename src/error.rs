@@ -0,0 +1,218 @@
+use std::fmt;
+
+use crate::redact;
+
+// Error is the taxonomy of failures that Reshape surfaces to callers. All public
+// methods still return `anyhow::Result` but errors which fall into one of these
+// categories are wrapped in an `Error` and can be retrieved with
+// `anyhow::Error::downcast_ref::<reshape::Error>()`. The CLI uses the category
+// to pick an exit code so automation can react without parsing messages.
+//
+// Errors which don't fit any category (for example unexpected database errors
+// while applying a migration which couldn't be cleaned up) are passed through
+// untouched.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    // Connecting to Postgres failed or the connection options were invalid
+    Connection(anyhow::Error),
+
+    // The provided migrations are invalid, for example a migration file couldn't be
+    // parsed or the migrations don't match the ones that have already been applied
+    Validation(anyhow::Error),
+
+    // The current state doesn't allow the requested operation, for example when
+    // trying to complete a migration while it's being aborted or when another
+    // instance of Reshape is already running
+    ConflictingState(String),
+
+    // A migration failed to apply and all changes have been aborted
+    MigrationFailed(anyhow::Error),
+
+    // A previous migration was left dangling and must be aborted before continuing
+    AbortRequired(String),
+
+    // The operation was cancelled or its deadline passed. Progress has been saved
+    // and running the same operation again will continue where it stopped.
+    Cancelled(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Connection(_) => 3,
+            Error::Validation(_) => 4,
+            Error::ConflictingState(_) => 5,
+            Error::MigrationFailed(_) => 6,
+            Error::AbortRequired(_) => 7,
+            Error::Cancelled(_) => 8,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Connection(_) => "connection",
+            Error::Validation(_) => "validation",
+            Error::ConflictingState(_) => "conflicting_state",
+            Error::MigrationFailed(_) => "migration_failed",
+            Error::AbortRequired(_) => "abort_required",
+            Error::Cancelled(_) => "cancelled",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connection(_) => write!(f, "failed to connect to database"),
+            Error::Validation(_) => write!(f, "invalid migrations"),
+            Error::ConflictingState(message) => write!(f, "{}", message),
+            Error::MigrationFailed(_) => write!(f, "migration failed and has been aborted"),
+            Error::AbortRequired(message) => write!(f, "{}", message),
+            Error::Cancelled(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Connection(err) | Error::Validation(err) | Error::MigrationFailed(err) => {
+                Some(err.as_ref())
+            }
+            Error::ConflictingState(_) | Error::AbortRequired(_) | Error::Cancelled(_) => None,
+        }
+    }
+}
+
+// QueryError is attached to the error chain whenever a statement fails, so the
+// statement and everything Postgres reported about the failure are available both when
+// printing the error and to callers finding it with `anyhow::Error::chain`.
+#[derive(Debug)]
+pub struct QueryError {
+    statement: String,
+    code: Option<String>,
+    message: String,
+    detail: Option<String>,
+    hint: Option<String>,
+    position: Option<ErrorPosition>,
+}
+
+#[derive(Debug)]
+enum ErrorPosition {
+    // 1-based character index into the statement
+    Original(usize),
+    // Position in a query run by a function, for example an expression in a trigger
+    Internal { position: usize, query: String },
+}
+
+// Statements are shortened in messages, the full statement is available through
+// `QueryError::statement`
+const MAX_STATEMENT_LENGTH: usize = 300;
+
+impl QueryError {
+    pub(crate) fn new(statement: &str, err: &postgres::Error) -> Self {
+        let db_error = match err.as_db_error() {
+            Some(db_error) => db_error,
+            None => {
+                return QueryError {
+                    statement: statement.to_string(),
+                    code: None,
+                    message: err.to_string(),
+                    detail: None,
+                    hint: None,
+                    position: None,
+                }
+            }
+        };
+
+        let position = db_error.position().map(|position| match position {
+            postgres::error::ErrorPosition::Original(position) => {
+                ErrorPosition::Original(*position as usize)
+            }
+            postgres::error::ErrorPosition::Internal { position, query } => {
+                ErrorPosition::Internal {
+                    position: *position as usize,
+                    query: query.to_string(),
+                }
+            }
+        });
+
+        QueryError {
+            statement: statement.to_string(),
+            code: Some(db_error.code().code().to_string()),
+            message: db_error.message().to_string(),
+            detail: db_error.detail().map(str::to_string),
+            hint: db_error.hint().map(str::to_string),
+            position,
+        }
+    }
+
+    pub fn statement(&self) -> &str {
+        &self.statement
+    }
+
+    // SQLSTATE code, not set if the statement never reached the server
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    // The line where Postgres reported the error together with the 0-based character
+    // offset of the error in that line. Errors inside functions, like the expressions
+    // in the triggers Reshape creates, point into the query run by the function.
+    pub fn error_line(&self) -> Option<(&str, usize)> {
+        let (text, position) = match &self.position {
+            Some(ErrorPosition::Original(position)) => (self.statement.as_str(), *position),
+            Some(ErrorPosition::Internal { position, query }) => (query.as_str(), *position),
+            None => return None,
+        };
+
+        let mut offset = position.checked_sub(1)?;
+        for line in text.split('\n') {
+            let length = line.chars().count();
+            if offset < length {
+                return Some((line, offset));
+            }
+            offset -= length + 1;
+        }
+
+        None
+    }
+}
+
+// Passwords in the statement or in what Postgres reported are redacted, as the
+// message is printed and recorded
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut message = self.message.clone();
+        if let Some(code) = &self.code {
+            message.push_str(&format!(" (SQLSTATE {})", code));
+        }
+        if let Some(detail) = &self.detail {
+            message.push_str(&format!(", detail: {}", detail));
+        }
+        if let Some(hint) = &self.hint {
+            message.push_str(&format!(", hint: {}", hint));
+        }
+
+        let statement = self
+            .statement
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if statement.chars().count() > MAX_STATEMENT_LENGTH {
+            let truncated: String = statement.chars().take(MAX_STATEMENT_LENGTH).collect();
+            message.push_str(&format!(", in statement: {}...", truncated));
+        } else {
+            message.push_str(&format!(", in statement: {}", statement));
+        }
+
+        write!(f, "{}", redact(&message))
+    }
+}
+
+impl std::error::Error for QueryError {}
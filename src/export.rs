@@ -0,0 +1,285 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use version::version;
+
+use crate::{db::Conn, recording::Event};
+
+// SqlScripts holds the SQL run by Reshape for each phase of the pending migrations, for
+// organizations where every change to a production database has to be reviewed and run
+// by a DBA. `start.sql` is run first, followed by either `complete.sql` or `abort.sql`.
+// Each script ends by bringing Reshape's state up to date, so the regular commands like
+// `reshape status` keep working and can take over from there.
+//
+// The statements are captured while Reshape applies, aborts and completes the migrations
+// against a copy of the database. Anything read from the database along the way, like
+// constraint names or the position of identity sequences, comes from that copy, so it
+// must have the same schema as the database the scripts are run against.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SqlScripts {
+    pub start: String,
+    pub complete: String,
+    pub abort: String,
+}
+
+impl SqlScripts {
+    // Write the scripts to `start.sql`, `complete.sql` and `abort.sql` in `dir`, which
+    // is created if needed
+    pub fn write_to(&self, dir: impl AsRef<Path>) -> anyhow::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create directory {}", dir.display()))?;
+
+        for (name, script) in [
+            ("start.sql", &self.start),
+            ("complete.sql", &self.complete),
+            ("abort.sql", &self.abort),
+        ] {
+            let path = dir.join(name);
+            fs::write(&path, script)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+// One phase of the migrations, built from the statements recorded while it ran
+pub(crate) struct Script {
+    description: String,
+    state_schema: String,
+    statements: Vec<String>,
+}
+
+impl Script {
+    pub fn new(description: impl Into<String>, state_schema: impl Into<String>) -> Self {
+        Script {
+            description: description.into(),
+            state_schema: state_schema.into(),
+            statements: Vec::new(),
+        }
+    }
+
+    // Add the statements which changed the database. Changes to the state are left out,
+    // as they can't be reproduced without their parameters. The state after the phase
+    // is added using `state` instead.
+    pub fn statements(&mut self, events: &[Event]) -> anyhow::Result<()> {
+        for event in events {
+            let (sql, params, rows) = match event {
+                Event::Statement {
+                    sql,
+                    params,
+                    rows,
+                    error: None,
+                    ..
+                } => (sql, *params, *rows),
+                _ => continue,
+            };
+
+            if !changes_database(sql, rows) || changes_state(sql, &self.state_schema) {
+                continue;
+            }
+            if params > 0 {
+                return Err(anyhow!(
+                    "statement can't be exported as it takes parameters: {}",
+                    sql
+                ));
+            }
+            if sql
+                .get(..4)
+                .is_some_and(|head| head.eq_ignore_ascii_case("COPY"))
+            {
+                return Err(anyhow!(
+                    "statement can't be exported as the data it copies isn't recorded: {}",
+                    sql
+                ));
+            }
+
+            // The state schema is set up by every command, which only has to happen once
+            let statement = dedent(sql);
+            if self.sets_up_state(&statement) && self.statements.contains(&statement) {
+                continue;
+            }
+
+            // Transactions which only changed the state are left out entirely
+            let ends_transaction = sql == "COMMIT" || sql.starts_with("RELEASE SAVEPOINT");
+            let empty_transaction = self
+                .statements
+                .last()
+                .is_some_and(|last| last == "BEGIN" || last.starts_with("SAVEPOINT"));
+            if ends_transaction && empty_transaction {
+                self.statements.pop();
+                continue;
+            }
+
+            self.statements.push(statement);
+        }
+
+        Ok(())
+    }
+
+    // Bring the state up to date with the database the phase ran against. The data
+    // table is replaced and migrations completed after `completed_before` are added.
+    pub fn state(&mut self, db: &mut dyn Conn, completed_before: i32) -> anyhow::Result<()> {
+        let schema = &self.state_schema;
+
+        let data: Vec<(String, String)> = db
+            .query(&format!(
+                "
+                SELECT quote_literal(key) AS key, quote_literal(value::TEXT) AS value
+                FROM {}.data
+                ORDER BY key
+                ",
+                schema
+            ))
+            .context("failed to get state for export")?
+            .iter()
+            .map(|row| (row.get("key"), row.get("value")))
+            .collect();
+
+        let migrations: Vec<String> = db
+            .query_with_params(
+                &format!(
+                    "
+                    SELECT format(
+                        'INSERT INTO {schema}.migrations (name, description, actions, stats, hash) VALUES (%L, %L, %L, %L, %L)',
+                        name, description, actions, stats, hash
+                    ) AS statement
+                    FROM {schema}.migrations
+                    WHERE index > $1
+                    ORDER BY index
+                    ",
+                    schema = schema,
+                ),
+                &[&completed_before],
+            )
+            .context("failed to get completed migrations for export")?
+            .iter()
+            .map(|row| row.get("statement"))
+            .collect();
+
+        let keys: Vec<&str> = data.iter().map(|(key, _)| key.as_str()).collect();
+        self.statements.push("BEGIN;".to_string());
+        self.statements.push(format!(
+            "DELETE FROM {}.data WHERE key NOT IN ({});",
+            schema,
+            keys.join(", ")
+        ));
+        for (key, value) in &data {
+            self.statements.push(format!(
+                "INSERT INTO {}.data (key, value) VALUES ({}, {}) ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value;",
+                schema, key, value
+            ));
+        }
+        for migration in migrations {
+            self.statements.push(format!("{};", migration));
+        }
+        self.statements.push("COMMIT;".to_string());
+
+        Ok(())
+    }
+
+    fn sets_up_state(&self, statement: &str) -> bool {
+        let schema = &self.state_schema;
+        statement == format!("CREATE SCHEMA IF NOT EXISTS {}", schema)
+            || ["data", "migrations", "change_log"].iter().any(|table| {
+                let table = format!("{}.{}", schema, table);
+                statement.starts_with(&format!("CREATE TABLE IF NOT EXISTS {}", table))
+                    || statement.starts_with(&format!("ALTER TABLE {}", table))
+            })
+    }
+
+    pub fn render(&self) -> String {
+        let mut script = format!(
+            "-- {}\n-- Exported by Reshape {}. Run using psql, which stops at the first error.\n\\set ON_ERROR_STOP on\n",
+            self.description,
+            version!(),
+        );
+
+        if self.statements.is_empty() {
+            script.push_str("\n-- Nothing to run\n");
+        }
+        for statement in &self.statements {
+            script.push('\n');
+            script.push_str(statement);
+            if !statement.ends_with(';') {
+                script.push(';');
+            }
+            script.push('\n');
+        }
+
+        script
+    }
+}
+
+// Statements run using `Conn::run` don't return any rows, which is how they can be told
+// apart in the recording. Statements run using `query` are mostly reads, apart from
+// the ones touching rows while backfilling.
+fn changes_database(sql: &str, rows: Option<usize>) -> bool {
+    if rows.is_none() {
+        return true;
+    }
+
+    let words: Vec<String> = sql
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_uppercase())
+        .collect();
+    match words.first().map(String::as_str) {
+        Some("SELECT") | Some("SHOW") => false,
+        Some("WITH") => words
+            .iter()
+            .any(|word| ["INSERT", "UPDATE", "DELETE"].contains(&word.as_str())),
+        _ => true,
+    }
+}
+
+fn changes_state(sql: &str, state_schema: &str) -> bool {
+    let modifies = ["INSERT", "UPDATE", "DELETE"].iter().any(|keyword| {
+        sql.get(..keyword.len())
+            .is_some_and(|head| head.eq_ignore_ascii_case(keyword))
+    });
+    let tables = [
+        format!("{}.data", state_schema),
+        format!("{}.migrations", state_schema),
+    ];
+    modifies && tables.iter().any(|table| sql.contains(table.as_str()))
+}
+
+// Statements are recorded trimmed, so the first line has lost the indentation which
+// the others still have from the source code
+fn dedent(sql: &str) -> String {
+    let mut lines = sql.lines();
+    let first = lines.next().unwrap_or_default();
+    let rest: Vec<&str> = lines.collect();
+
+    let indent = rest
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    std::iter::once(first)
+        .chain(rest.iter().map(|line| line.get(indent..).unwrap_or("")))
+        .map(str::trim_end)
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+// The index of the last completed migration, which migrations completed by an exported
+// phase come after
+pub(crate) fn last_completed_migration(db: &mut dyn Conn) -> anyhow::Result<i32> {
+    let index = db
+        .query(&format!(
+            "SELECT COALESCE(MAX(index), 0) AS index FROM {}.migrations",
+            db.namespace().state_schema()
+        ))
+        .context("failed to get last completed migration")?
+        .first()
+        .map(|row| row.get("index"))
+        .unwrap_or_default();
+
+    Ok(index)
+}
@@ -2,28 +2,116 @@ use anyhow::Context;
 
 use crate::db::Conn;
 
+// Functions used by the triggers of in-progress migrations to tell writes made through
+// the new schema apart from writes made through the old one. They are created in the
+// state schema when migrations are applied and dropped once they are completed or
+// aborted, and both are always created and dropped together.
+//
+// A write is from the new schema if the first schema in the search_path is the schema
+// of the last migration being applied. Anything else, like the schema of an earlier
+// migration or `public`, is the old schema. Applications which can't change the
+// search_path, like background jobs using qualified names, can instead set
+// `reshape.is_new_schema` for their transaction, either to the name of the new schema
+// or to `YES`. The name only matches the migrations it belongs to, so with several
+// namespaces migrating at the same time it doesn't affect the others.
+//
+// `reshape.schema`, set using `SchemaOverride`, takes precedence over both. Every
+// trigger which depends on the schema goes through these functions, so the override
+// applies to all of them.
+fn helpers(state_schema: &str, target_schema: &str) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "is_new_schema",
+            format!(
+                "
+                CREATE OR REPLACE FUNCTION {state_schema}.is_new_schema()
+                RETURNS BOOLEAN AS $$
+                DECLARE
+                    pinned TEXT := COALESCE(current_setting('reshape.schema', TRUE), '');
+                    setting TEXT := COALESCE(current_setting('reshape.is_new_schema', TRUE), '');
+                    -- Only the first schema counts, so fallbacks like public can follow it.
+                    -- current_schemas takes care of quoting, whitespace and $user.
+                    first_schema TEXT := (current_schemas(FALSE))[1];
+                BEGIN
+                    -- Settings which have been reset are empty rather than missing
+                    IF pinned = 'new' THEN
+                        RETURN TRUE;
+                    ELSIF pinned = 'old' THEN
+                        RETURN FALSE;
+                    ELSIF pinned <> '' THEN
+                        RAISE EXCEPTION 'reshape.schema must be either new or old, not %', pinned;
+                    END IF;
+
+                    RETURN COALESCE(first_schema = '{target_schema}', FALSE)
+                        OR setting = 'YES'
+                        OR setting = '{target_schema}';
+                END
+                $$ LANGUAGE plpgsql STABLE;
+                "
+            ),
+        ),
+        (
+            "is_old_schema",
+            format!(
+                "
+                CREATE OR REPLACE FUNCTION {state_schema}.is_old_schema()
+                RETURNS BOOLEAN AS $$
+                BEGIN
+                    RETURN NOT {state_schema}.is_new_schema();
+                END
+                $$ LANGUAGE plpgsql STABLE;
+                "
+            ),
+        ),
+    ]
+}
+
+// Pins which schema the writes in the current transaction count as, for applications
+// which can't set the search_path to the migration's schema
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemaOverride {
+    Old,
+    New,
+}
+
+impl SchemaOverride {
+    // Value for `reshape.schema`
+    pub fn setting(&self) -> &'static str {
+        match self {
+            SchemaOverride::Old => "old",
+            SchemaOverride::New => "new",
+        }
+    }
+
+    // Query to run at the start of every transaction which should be pinned. It only
+    // lasts for the transaction, so it's safe to use behind a pooler in transaction
+    // pooling mode.
+    pub fn query(&self) -> String {
+        format!("SET LOCAL reshape.schema = '{}'", self.setting())
+    }
+}
+
 pub fn set_up_helpers(db: &mut dyn Conn, target_migration: &str) -> anyhow::Result<()> {
-    let query = format!(
-        "
-			CREATE OR REPLACE FUNCTION reshape.is_new_schema()
-			RETURNS BOOLEAN AS $$
-            DECLARE
-                setting TEXT := current_setting('reshape.is_new_schema', TRUE);
-                setting_bool BOOLEAN := setting IS NOT NULL AND setting = 'YES';
-			BEGIN
-				RETURN current_setting('search_path') = 'migration_{}' OR setting_bool;
-			END
-			$$ language 'plpgsql';
-        ",
-        target_migration,
-    );
-    db.query(&query)
-        .context("failed creating helper function reshape.is_new_schema()")?;
+    let state_schema = db.namespace().state_schema();
+    let target_schema = db.namespace().schema_name_for_migration(target_migration);
+
+    for (name, query) in helpers(&state_schema, &target_schema) {
+        db.query(&query)
+            .with_context(|| format!("failed creating helper function {}()", name))?;
+    }
 
     Ok(())
 }
 
 pub fn tear_down_helpers(db: &mut dyn Conn) -> anyhow::Result<()> {
-    db.query("DROP FUNCTION IF EXISTS reshape.is_new_schema;")?;
+    let state_schema = db.namespace().state_schema();
+
+    for (name, _) in helpers(&state_schema, "").iter().rev() {
+        db.query(&format!(
+            "DROP FUNCTION IF EXISTS {}.{};",
+            state_schema, name
+        ))?;
+    }
+
     Ok(())
 }
@@ -0,0 +1,192 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, Context};
+use colored::*;
+
+use crate::{
+    db::{Conn, DbConn, DdlBatch, Workers},
+    migrations::IndexBuild,
+    state::{ActionStats, Phase},
+};
+
+// Building an index concurrently scans the table twice and waits for every transaction
+// using it, so migrations adding several indices spend most of their time on this.
+// Indices on different tables don't depend on each other, so consecutive `add_index`
+// actions are queued and built at the same time on separate connections. Indices on
+// the same table are built one after another, as concurrent builds on one table would
+// only wait for each other.
+pub(crate) struct IndexBuilds {
+    max_parallel: usize,
+    workers: Workers,
+    queued: Vec<QueuedBuild>,
+}
+
+struct QueuedBuild {
+    migration: String,
+    description: String,
+    build: IndexBuild,
+}
+
+impl IndexBuilds {
+    pub fn new(db: &DbConn, max_parallel: usize) -> Self {
+        IndexBuilds {
+            max_parallel,
+            workers: Workers::new(db),
+            queued: Vec::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_parallel > 1
+    }
+
+    pub fn queue(&mut self, migration: &str, description: String, build: IndexBuild) {
+        self.queued.push(QueuedBuild {
+            migration: migration.to_string(),
+            description,
+            build,
+        });
+    }
+
+    // Build all queued indices. Indices on a single table are built on the batch's
+    // connection as usual, after any batched statements have been run so the workers
+    // see the tables as they were left by the actions before.
+    pub fn build(
+        &mut self,
+        batch: &mut DdlBatch,
+        stats: &mut Vec<ActionStats>,
+    ) -> anyhow::Result<()> {
+        if self.queued.is_empty() {
+            return Ok(());
+        }
+        batch.flush()?;
+
+        // Builds are grouped by table, keeping the order they were queued in
+        let mut tables: Vec<Vec<QueuedBuild>> = Vec::new();
+        for queued in self.queued.drain(..) {
+            match tables
+                .iter_mut()
+                .find(|builds| builds[0].build.table == queued.build.table)
+            {
+                Some(builds) => builds.push(queued),
+                None => tables.push(vec![queued]),
+            }
+        }
+
+        let builds: usize = tables.iter().map(Vec::len).sum();
+        print!(
+            "  + Building {} indices on {} tables ",
+            builds,
+            tables.len()
+        );
+
+        let result = if tables.len() == 1 {
+            build_table(batch, tables.remove(0), &AtomicBool::new(false))
+        } else {
+            let workers = self.workers.get(self.max_parallel.min(tables.len()))?;
+            build_in_parallel(workers, tables)
+        };
+
+        match result {
+            Ok(table_stats) => {
+                println!("{}", "done".green());
+                stats.extend(table_stats);
+                Ok(())
+            }
+            Err(err) => {
+                println!("{}", "failed".red());
+                Err(err)
+            }
+        }
+    }
+
+    pub fn finish(self, db: &mut DbConn) {
+        self.workers.finish(db);
+    }
+}
+
+// Every worker takes the next table once it's done with the previous one. Once a build
+// has failed, no more tables are started, but those in progress are finished.
+fn build_in_parallel(
+    workers: &mut [DbConn],
+    tables: Vec<Vec<QueuedBuild>>,
+) -> anyhow::Result<Vec<ActionStats>> {
+    let tables = Mutex::new(VecDeque::from(tables));
+    let failed = AtomicBool::new(false);
+
+    let results: Vec<anyhow::Result<Vec<ActionStats>>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = workers
+            .iter_mut()
+            .map(|worker| {
+                let tables = &tables;
+                let failed = &failed;
+                scope.spawn(move || {
+                    let mut stats = Vec::new();
+                    loop {
+                        let builds = match tables.lock().unwrap().pop_front() {
+                            Some(builds) => builds,
+                            None => return Ok(stats),
+                        };
+                        stats.extend(build_table(worker, builds, failed)?);
+                    }
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow!("index build panicked")))
+            })
+            .collect()
+    });
+
+    let mut stats = Vec::new();
+    for result in results {
+        stats.extend(result?);
+    }
+    Ok(stats)
+}
+
+fn build_table(
+    db: &mut dyn Conn,
+    builds: Vec<QueuedBuild>,
+    failed: &AtomicBool,
+) -> anyhow::Result<Vec<ActionStats>> {
+    let mut stats = Vec::new();
+    for queued in builds {
+        if failed.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let started_at = SystemTime::now();
+        let counters = db.counters();
+        let result = db
+            .run(&queued.build.query)
+            .context("failed to create index")
+            .with_context(|| format!("failed to {}", queued.description));
+        if let Err(err) = result {
+            failed.store(true, Ordering::SeqCst);
+            return Err(err);
+        }
+
+        stats.push(ActionStats::new(
+            &queued.migration,
+            queued.description,
+            Phase::Start,
+            started_at,
+            db.counters() - counters,
+        ));
+    }
+
+    Ok(stats)
+}
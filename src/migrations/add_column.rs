@@ -1,19 +1,76 @@
-use super::{common, Action, Column, MigrationContext};
+use super::{
+    common, Action, AddForeignKey, Backfill, BackfillOrder, Column, ForeignKey,
+    ForeignKeyValidation, LockLevel, LogicalSchema, MigrationContext, VersionRequirement,
+};
 use crate::{
-    db::{Conn, Transaction},
+    db::{self, Conn, Transaction},
     schema::Schema,
 };
 use anyhow::{bail, Context};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct AddColumn {
     pub table: String,
     pub column: Column,
     pub up: Option<Transformation>,
+
+    // Maintain the column with a trigger which sets it to the current time whenever
+    // a row is updated
+    #[serde(default)]
+    pub auto_updated_at: bool,
+
+    // Record rows written while the migration is in progress in `reshape.change_log`
+    #[serde(default)]
+    pub change_log: bool,
+
+    // How existing rows are filled in using `up`
+    #[serde(default, deserialize_with = "common::deserialize_backfill")]
+    #[schemars(with = "common::BackfillSetting")]
+    pub backfill: Backfill,
+
+    // The order existing rows are touched in when backfilling in batches. With an `up`
+    // from another table, the columns are those of the other table.
+    #[serde(default)]
+    #[schemars(with = "common::BackfillOrderSetting")]
+    pub backfill_order: BackfillOrder,
+
+    // Defer the backfill until all actions in the migration have run. Deferred
+    // backfills run in order of priority, highest first.
+    pub backfill_priority: Option<i32>,
+
+    // SQL expression to fill in existing rows and rows written using the old schema
+    // when there is no `up`, for example to add a NOT NULL column without a default
+    pub backfill_value: Option<String>,
+
+    // Reference another table from the new column. The foreign key is enforced for
+    // new writes straight away and existing rows are checked on completion.
+    pub foreign_key: Option<ColumnForeignKey>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct ColumnForeignKey {
+    pub referenced_table: String,
+    pub referenced_column: String,
+}
+
+impl ColumnForeignKey {
+    pub fn new(referenced_table: impl Into<String>, referenced_column: impl Into<String>) -> Self {
+        ColumnForeignKey {
+            referenced_table: referenced_table.into(),
+            referenced_column: referenced_column.into(),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[schemars(rename = "ColumnUpTransformation")]
 #[serde(untagged)]
 pub enum Transformation {
     Simple(String),
@@ -25,6 +82,110 @@ pub enum Transformation {
 }
 
 impl AddColumn {
+    pub fn new(table: impl Into<String>, column: Column) -> Self {
+        AddColumn {
+            table: table.into(),
+            column,
+            up: None,
+            auto_updated_at: false,
+            change_log: false,
+            backfill: Backfill::default(),
+            backfill_order: BackfillOrder::default(),
+            backfill_priority: None,
+            backfill_value: None,
+            foreign_key: None,
+        }
+    }
+
+    pub fn with_up(mut self, up: impl Into<String>) -> Self {
+        self.up = Some(Transformation::Simple(up.into()));
+        self
+    }
+
+    // Fill in the column using a value from another table. `where` should match
+    // rows in `table` with the row being updated.
+    pub fn with_up_update(
+        mut self,
+        table: impl Into<String>,
+        value: impl Into<String>,
+        r#where: impl Into<String>,
+    ) -> Self {
+        self.up = Some(Transformation::Update {
+            table: table.into(),
+            value: value.into(),
+            r#where: r#where.into(),
+        });
+        self
+    }
+
+    pub fn with_auto_updated_at(mut self, auto_updated_at: bool) -> Self {
+        self.auto_updated_at = auto_updated_at;
+        self
+    }
+
+    pub fn with_change_log(mut self, change_log: bool) -> Self {
+        self.change_log = change_log;
+        self
+    }
+
+    pub fn with_backfill(mut self, backfill: Backfill) -> Self {
+        self.backfill = backfill;
+        self
+    }
+
+    pub fn with_backfill_order(mut self, backfill_order: BackfillOrder) -> Self {
+        self.backfill_order = backfill_order;
+        self
+    }
+
+    pub fn with_backfill_priority(mut self, backfill_priority: i32) -> Self {
+        self.backfill_priority = Some(backfill_priority);
+        self
+    }
+
+    pub fn with_backfill_value(mut self, backfill_value: impl Into<String>) -> Self {
+        self.backfill_value = Some(backfill_value.into());
+        self
+    }
+
+    pub fn with_foreign_key(mut self, foreign_key: ColumnForeignKey) -> Self {
+        self.foreign_key = Some(foreign_key);
+        self
+    }
+
+    // A backfill value is applied the same way as a simple `up` transformation
+    fn transformation(&self) -> Option<Transformation> {
+        match (&self.up, &self.backfill_value) {
+            (Some(up), _) => Some(up.clone()),
+            (None, Some(value)) => Some(Transformation::Simple(value.to_string())),
+            (None, None) => None,
+        }
+    }
+
+    // Whether `complete` has already been committed, which renames the temporary column
+    // to its real name. Composite actions use this to skip steps which were completed
+    // before an interruption, as renaming the column can't be repeated.
+    pub(super) fn is_completed(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+    ) -> anyhow::Result<bool> {
+        let temp_column_exists = !db
+            .query_with_params(
+                "
+                SELECT 1
+                FROM pg_attribute
+                WHERE attrelid = to_regclass(format('public.%I', $1::TEXT))
+                    AND attname = $2
+                    AND NOT attisdropped
+                ",
+                &[&self.table, &self.temp_column_name(ctx)],
+            )
+            .context("failed to check for temporary column")?
+            .is_empty();
+        Ok(!temp_column_exists)
+    }
+
     fn temp_column_name(&self, ctx: &MigrationContext) -> String {
         format!(
             "{}_temp_column_{}_{}",
@@ -52,6 +213,34 @@ impl AddColumn {
         )
     }
 
+    fn change_log_trigger_name(&self, ctx: &MigrationContext) -> String {
+        format!(
+            "{}_add_column_{}_{}_change_log",
+            ctx.prefix(),
+            self.table,
+            self.column.name
+        )
+    }
+
+    // The foreign key is created on the temporary column and validated on completion
+    fn add_foreign_key(&self, ctx: &MigrationContext) -> Option<AddForeignKey> {
+        self.foreign_key.as_ref().map(|foreign_key| {
+            AddForeignKey::new(
+                &self.table,
+                ForeignKey::new(
+                    [self.temp_column_name(ctx)],
+                    &foreign_key.referenced_table,
+                    [&foreign_key.referenced_column],
+                ),
+            )
+            .with_validate(ForeignKeyValidation::Complete)
+        })
+    }
+
+    fn foreign_key_name(&self) -> String {
+        format!("{}_{}_fkey", self.table, self.column.name)
+    }
+
     fn not_null_constraint_name(&self, ctx: &MigrationContext) -> String {
         format!(
             "{}_add_column_not_null_{}_{}",
@@ -77,7 +266,78 @@ impl Action for AddColumn {
         db: &mut dyn Conn,
         schema: &Schema,
     ) -> anyhow::Result<()> {
-        let table = schema.get_table(db, &self.table)?;
+        if self.column.unique || self.column.check.is_some() {
+            bail!(
+                "unique and check are only supported when creating a table, use an add_index or custom action to add constraints to column \"{}\"",
+                self.column.name,
+            );
+        }
+
+        if self.up.is_some() && self.backfill_value.is_some() {
+            bail!(
+                "up and backfill_value can't both be set for column \"{}\"",
+                self.column.name,
+            );
+        }
+
+        // Existing rows would be left as NULL and fail the NOT NULL constraint on completion
+        let transformation = self.transformation();
+        let requires_value = !self.column.nullable
+            && self.column.default.is_none()
+            && self.column.generated.is_none();
+        if transformation.is_some() && self.backfill == Backfill::None && requires_value {
+            bail!(
+                "backfill can't be disabled for column \"{}\" as it's NOT NULL without a default",
+                self.column.name,
+            );
+        }
+
+        // Without any value for the existing rows, the NOT NULL constraint can only be
+        // satisfied if the table is empty. Checking now avoids failing on completion.
+        if transformation.is_none() && requires_value {
+            let table = schema.get_table(db, &self.table)?;
+            let has_rows = !db
+                .query(&format!(
+                    r#"SELECT 1 FROM public."{table}" LIMIT 1"#,
+                    table = table.real_name,
+                ))
+                .context("failed to check for existing rows")?
+                .is_empty();
+
+            if has_rows {
+                let rows: i64 = db
+                    .query(&format!(
+                        r#"SELECT COUNT(*) AS rows FROM public."{table}""#,
+                        table = table.real_name,
+                    ))
+                    .context("failed to count existing rows")?
+                    .first()
+                    .map(|row| row.get("rows"))
+                    .unwrap_or_default();
+                bail!(
+                    "column \"{}\" is NOT NULL without a default but table \"{}\" has {} existing rows, set up, backfill_value or a default to fill them in",
+                    self.column.name,
+                    self.table,
+                    rows,
+                );
+            }
+        }
+
+        // The triggers would record the deferred backfill as writes
+        if self.backfill_priority.is_some() && (self.auto_updated_at || self.change_log) {
+            bail!(
+                "backfill_priority can't be combined with auto_updated_at or change_log for column \"{}\"",
+                self.column.name,
+            );
+        }
+
+        // The table is only needed to set up triggers. Skipping the lookup otherwise
+        // lets the column be added in the same statement as other changes to the table.
+        let table = if transformation.is_some() || self.change_log {
+            Some(schema.get_table(db, &self.table)?)
+        } else {
+            None
+        };
         let temp_column_name = self.temp_column_name(ctx);
 
         let mut definition_parts = vec![
@@ -95,200 +355,221 @@ impl Action for AddColumn {
             definition_parts.push(generated.to_string());
         }
 
-        // Add column as NOT NULL
-        let query = format!(
-            r#"
-			ALTER TABLE "{table}"
-            ADD COLUMN IF NOT EXISTS {definition};
-			"#,
-            table = self.table,
-            definition = definition_parts.join(" "),
-        );
-        db.run(&query).context("failed to add column")?;
-
-        let declarations: Vec<String> = table
-            .columns
-            .iter()
-            .map(|column| {
-                format!(
-                    "\"{alias}\" public.{table}.{real_name}%TYPE := NEW.{real_name};",
-                    table = table.real_name,
-                    alias = column.name,
-                    real_name = column.real_name,
-                )
-            })
-            .collect();
-
-        if let Some(up) = &self.up {
-            if let Transformation::Simple(up) = up {
-                // Add triggers to fill in values as they are inserted/updated
-                let query = format!(
-                    r#"
-                    CREATE OR REPLACE FUNCTION {trigger_name}()
-                    RETURNS TRIGGER AS $$
-                    BEGIN
-                        IF NOT reshape.is_new_schema() THEN
-                            DECLARE
-                                {declarations}
-                            BEGIN
-                                NEW."{temp_column_name}" = {up};
-                            END;
-                        END IF;
-                        RETURN NEW;
-                    END
-                    $$ language 'plpgsql';
-
-                    DROP TRIGGER IF EXISTS "{trigger_name}" ON "{table}";
-                    CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{table}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
-                    "#,
-                    temp_column_name = temp_column_name,
-                    trigger_name = self.trigger_name(ctx),
-                    up = up,
-                    table = self.table,
-                    declarations = declarations.join("\n"),
-                );
-                db.run(&query).context("failed to create up trigger")?;
-
-                // Backfill values in batches
-                common::batch_touch_rows(db, &table.real_name, Some(&temp_column_name))
-                    .context("failed to batch update existing rows")?;
-            }
-
-            if let Transformation::Update {
-                table: from_table,
-                value,
-                r#where,
-            } = up
-            {
-                let existing_schema_name = match &ctx.existing_schema_name {
-                    Some(name) => name,
-                    None => bail!("can't use update without previous migration"),
-                };
+        // With a transformation, the column is only added together with the triggers
+        // filling it in, so a failure can't leave a column behind which writes are
+        // missing from. Otherwise, the column is added on its own so it can be batched
+        // with other changes to the table.
+        let add_column = |db: &mut dyn Conn| -> anyhow::Result<()> {
+            // Add column as NOT NULL
+            let query = format!(
+                r#"
+    			ALTER TABLE "{table}"
+                ADD COLUMN IF NOT EXISTS {definition};
+    			"#,
+                table = self.table,
+                definition = definition_parts.join(" "),
+            );
+            db.run(&query).context("failed to add column")?;
 
-                let from_table = schema.get_table(db, &from_table)?;
+            if let Some(up) = &transformation {
+                let table = table
+                    .as_ref()
+                    .expect("table should be loaded when up is set");
 
-                let from_table_assignments: Vec<String> = from_table
+                let declarations: Vec<String> = table
                     .columns
                     .iter()
                     .map(|column| {
                         format!(
-                            "{table}.{alias} = NEW.{real_name};",
-                            table = from_table.name,
+                            "\"{alias}\" public.{table}.{real_name}%TYPE := NEW.{real_name};",
+                            table = table.real_name,
                             alias = column.name,
                             real_name = column.real_name,
                         )
                     })
                     .collect();
 
-                // Add triggers to fill in values as they are inserted/updated
-                let query = format!(
-                    r#"
-                    CREATE OR REPLACE FUNCTION {trigger_name}()
-                    RETURNS TRIGGER AS $$
-                    #variable_conflict use_variable
-                    BEGIN
-                        IF NOT reshape.is_new_schema() THEN
-                            DECLARE
-                                {from_table} migration_{existing_schema_name}.{from_table}%ROWTYPE;
-                            BEGIN
-                                {assignments}
-
-                                -- Don't trigger reverse trigger when making this update
-                                perform set_config('reshape.disable_triggers', 'TRUE', TRUE);
-
-                                UPDATE public."{changed_table_real}"
-                                SET "{temp_column_name}" = {value}
-                                WHERE {where};
-
-                                perform set_config('reshape.disable_triggers', '', TRUE);
-                            END;
-                        END IF;
-                        RETURN NEW;
-                    END
-                    $$ language 'plpgsql';
-
-                    DROP TRIGGER IF EXISTS "{trigger_name}" ON "{from_table_real}";
-                    CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{from_table_real}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
-                    "#,
-                    assignments = from_table_assignments.join("\n"),
-                    changed_table_real = table.real_name,
-                    from_table = from_table.name,
-                    from_table_real = from_table.real_name,
-                    trigger_name = self.trigger_name(ctx),
-                    // declarations = from_table_declarations.join("\n"),
-                    temp_column_name = temp_column_name,
-                );
-                db.run(&query).context("failed to create up trigger")?;
-
-                let from_table_columns = from_table
-                    .columns
-                    .iter()
-                    .map(|column| format!("{} as {}", column.real_name, column.name))
-                    .collect::<Vec<String>>()
-                    .join(", ");
+                if let Transformation::Simple(up) = up {
+                    // Add triggers to fill in values as they are inserted/updated
+                    let state_schema = ctx.namespace.state_schema();
+                    let query = format!(
+                        r#"
+                        CREATE OR REPLACE FUNCTION {trigger_name}()
+                        RETURNS TRIGGER AS $$
+                        BEGIN
+                            IF {state_schema}.is_old_schema() THEN
+                                DECLARE
+                                    {declarations}
+                                BEGIN
+                                    {assignment}
+                                END;
+                            END IF;
+                            RETURN NEW;
+                        END
+                        $$ language 'plpgsql';
+
+                        DROP TRIGGER IF EXISTS "{trigger_name}" ON "{table}";
+                        CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{table}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
+                        "#,
+                        assignment = common::with_public_in_search_path(&format!(
+                            r#"NEW."{}" = {};"#,
+                            temp_column_name, up
+                        )),
+                        trigger_name = self.trigger_name(ctx),
+                        table = self.table,
+                        declarations = declarations.join("\n"),
+                    );
+                    db.run(&query).context("failed to create up trigger")?;
+                }
+
+                if let Transformation::Update {
+                    table: from_table,
+                    value,
+                    r#where,
+                } = up
+                {
+                    let existing_schema = match &ctx.existing_schema_name {
+                        Some(name) => ctx.namespace.schema_name_for_migration(name),
+                        None => bail!("can't use update without previous migration"),
+                    };
+
+                    let from_table = schema.get_table(db, from_table)?;
+
+                    let from_table_assignments: Vec<String> = from_table
+                        .columns
+                        .iter()
+                        .map(|column| {
+                            format!(
+                                "{table}.{alias} = NEW.{real_name};",
+                                table = from_table.name,
+                                alias = column.name,
+                                real_name = column.real_name,
+                            )
+                        })
+                        .collect();
+
+                    // Add triggers to fill in values as they are inserted/updated. The other
+                    // table is only updated once the row has been written, as a BEFORE INSERT
+                    // trigger also fires for upserts which end up updating the conflicting
+                    // row or doing nothing.
+                    let state_schema = ctx.namespace.state_schema();
+                    let query = format!(
+                        r#"
+                        CREATE OR REPLACE FUNCTION {trigger_name}()
+                        RETURNS TRIGGER AS $$
+                        #variable_conflict use_variable
+                        BEGIN
+                            IF {state_schema}.is_old_schema() THEN
+                                DECLARE
+                                    {from_table} {existing_schema}.{from_table}%ROWTYPE;
+                                BEGIN
+                                    {assignments}
 
-                let changed_table_assignments: Vec<String> = table
-                    .columns
-                    .iter()
-                    .map(|column| {
-                        format!(
-                            "{table}.{alias} := NEW.{real_name};",
-                            table = table.name,
-                            alias = column.name,
-                            real_name = column.real_name,
-                        )
-                    })
-                    .collect();
+                                    -- Don't trigger reverse trigger when making this update
+                                    perform set_config('reshape.disable_triggers', 'TRUE', TRUE);
 
-                // Add triggers to fill in values as they are inserted/updated
-                let query = format!(
-                    r#"
-                    CREATE OR REPLACE FUNCTION {trigger_name}()
-                    RETURNS TRIGGER AS $$
-                    #variable_conflict use_variable
-                    BEGIN
-                        IF NOT reshape.is_new_schema() AND NOT current_setting('reshape.disable_triggers', TRUE) = 'TRUE' THEN
-                            DECLARE
-                                {changed_table} migration_{existing_schema_name}.{changed_table}%ROWTYPE;
-                                __temp_row migration_{existing_schema_name}.{from_table}%ROWTYPE;
-                            BEGIN
-                                {changed_table_assignments}
-
-                                SELECT {from_table_columns}
-                                INTO __temp_row
-                                FROM migration_{existing_schema_name}.{from_table} {from_table}
-                                WHERE {where};
+                                    {update}
 
+                                    perform set_config('reshape.disable_triggers', '', TRUE);
+                                END;
+                            END IF;
+                            RETURN NULL;
+                        END
+                        $$ language 'plpgsql';
+
+                        DROP TRIGGER IF EXISTS "{trigger_name}" ON "{from_table_real}";
+                        CREATE TRIGGER "{trigger_name}" AFTER UPDATE OR INSERT ON "{from_table_real}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
+                        "#,
+                        assignments = from_table_assignments.join("\n"),
+                        update = common::with_public_in_search_path(&format!(
+                            r#"UPDATE public."{}" SET "{}" = {} WHERE {};"#,
+                            table.real_name, temp_column_name, value, r#where
+                        )),
+                        from_table = from_table.name,
+                        from_table_real = from_table.real_name,
+                        trigger_name = self.trigger_name(ctx),
+                        // declarations = from_table_declarations.join("\n"),
+                    );
+                    db.run(&query).context("failed to create up trigger")?;
+
+                    let from_table_columns = from_table
+                        .columns
+                        .iter()
+                        .map(|column| format!("{} as {}", column.real_name, column.name))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+
+                    let changed_table_assignments: Vec<String> = table
+                        .columns
+                        .iter()
+                        .map(|column| {
+                            format!(
+                                "{table}.{alias} := NEW.{real_name};",
+                                table = table.name,
+                                alias = column.name,
+                                real_name = column.real_name,
+                            )
+                        })
+                        .collect();
+
+                    // Add triggers to fill in values as they are inserted/updated
+                    let state_schema = ctx.namespace.state_schema();
+                    let query = format!(
+                        r#"
+                        CREATE OR REPLACE FUNCTION {trigger_name}()
+                        RETURNS TRIGGER AS $$
+                        #variable_conflict use_variable
+                        BEGIN
+                            IF {state_schema}.is_old_schema() AND NOT current_setting('reshape.disable_triggers', TRUE) = 'TRUE' THEN
                                 DECLARE
-                                    {from_table} migration_{existing_schema_name}.{from_table}%ROWTYPE;
+                                    {changed_table} {existing_schema}.{changed_table}%ROWTYPE;
+                                    __temp_row {existing_schema}.{from_table}%ROWTYPE;
                                 BEGIN
-                                    {from_table} = __temp_row;
-                                    NEW.{temp_column_name} = {value};
+                                    {changed_table_assignments}
+
+                                    SELECT {from_table_columns}
+                                    INTO __temp_row
+                                    FROM {existing_schema}.{from_table} {from_table}
+                                    WHERE {where};
+
+                                    DECLARE
+                                        {from_table} {existing_schema}.{from_table}%ROWTYPE;
+                                    BEGIN
+                                        {from_table} = __temp_row;
+                                        NEW.{temp_column_name} = {value};
+                                    END;
                                 END;
-                            END;
-                        END IF;
-                        RETURN NEW;
-                    END
-                    $$ language 'plpgsql';
-
-                    DROP TRIGGER IF EXISTS "{trigger_name}" ON "{changed_table_real}";
-                    CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{changed_table_real}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
-                    "#,
-                    changed_table_assignments = changed_table_assignments.join("\n"),
-                    changed_table_real = table.real_name,
-                    changed_table = table.name,
-                    from_table = from_table.name,
-                    trigger_name = self.reverse_trigger_name(ctx),
-                    temp_column_name = temp_column_name,
-                    // declarations = declarations.join("\n"),
-                );
-                db.run(&query)
-                    .context("failed to create reverse up trigger")?;
-
-                // Backfill values in batches by touching the from table
-                common::batch_touch_rows(db, &from_table.real_name, None)
-                    .context("failed to batch update existing rows")?;
+                            END IF;
+                            RETURN NEW;
+                        END
+                        $$ language 'plpgsql';
+
+                        DROP TRIGGER IF EXISTS "{trigger_name}" ON "{changed_table_real}";
+                        CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{changed_table_real}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
+                        "#,
+                        changed_table_assignments = changed_table_assignments.join("\n"),
+                        changed_table_real = table.real_name,
+                        changed_table = table.name,
+                        from_table = from_table.name,
+                        trigger_name = self.reverse_trigger_name(ctx),
+                        temp_column_name = temp_column_name,
+                        // declarations = declarations.join("\n"),
+                    );
+                    db.run(&query)
+                        .context("failed to create reverse up trigger")?;
+                }
             }
+
+            Ok(())
+        };
+        if transformation.is_some() {
+            db::atomically(db, add_column)?;
+        } else {
+            add_column(db)?;
+        }
+
+        if !ctx.backfill_deferred() {
+            self.backfill(ctx, db, schema)?;
         }
 
         // Add a temporary NOT NULL constraint if the column shouldn't be nullable.
@@ -310,6 +591,29 @@ impl Action for AddColumn {
                 .context("failed to add NOT NULL constraint")?;
         }
 
+        if let Some(add_foreign_key) = self.add_foreign_key(ctx) {
+            add_foreign_key.run(ctx, db, schema)?;
+        }
+
+        // The trigger writes to the temporary column until the migration is completed
+        if self.auto_updated_at {
+            common::create_auto_updated_at_trigger(
+                db,
+                &self.table,
+                &self.column.name,
+                &temp_column_name,
+            )?;
+        }
+
+        // Start recording changed rows once the backfill is done so only writes
+        // made during the migration are logged
+        if self.change_log {
+            let table = table
+                .as_ref()
+                .expect("table should be loaded when change_log is set");
+            common::create_change_log_trigger(db, ctx, &self.change_log_trigger_name(ctx), table)?;
+        }
+
         Ok(())
     }
 
@@ -318,8 +622,35 @@ impl Action for AddColumn {
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // Check the existing rows before taking any locks, as this scans the whole table
+        let add_foreign_key = self.add_foreign_key(ctx);
+        if let Some(add_foreign_key) = &add_foreign_key {
+            add_foreign_key.validate_on_complete(ctx, db)?;
+        }
+
+        // Validate the temporary NOT NULL constraint (should always be valid).
+        // New writes are already checked by the constraint, so this can happen
+        // before the triggers are removed.
+        if !self.column.nullable {
+            common::validate_not_null_constraint(
+                db,
+                &self.table,
+                &self.not_null_constraint_name(ctx),
+                &self.temp_column_name(ctx),
+            )
+            .context("failed to validate NOT NULL constraint")?;
+        }
+
         let mut transaction = db.transaction().context("failed to create transaction")?;
 
+        common::drop_change_log_trigger(
+            &mut transaction,
+            ctx,
+            &self.change_log_trigger_name(ctx),
+            &self.table,
+            false,
+        )?;
+
         // Remove triggers and procedures
         let query = format!(
             r#"
@@ -335,20 +666,6 @@ impl Action for AddColumn {
 
         // Update column to be NOT NULL if necessary
         if !self.column.nullable {
-            // Validate the temporary constraint (should always be valid).
-            // This performs a sequential scan but does not take an exclusive lock.
-            let query = format!(
-                r#"
-                ALTER TABLE "{table}"
-                VALIDATE CONSTRAINT "{constraint_name}"
-                "#,
-                table = self.table,
-                constraint_name = self.not_null_constraint_name(ctx),
-            );
-            transaction
-                .run(&query)
-                .context("failed to validate NOT NULL constraint")?;
-
             // Update the column to be NOT NULL.
             // This requires an exclusive lock but since PG 12 it can check
             // the existing constraint for correctness which makes the lock short-lived.
@@ -392,6 +709,30 @@ impl Action for AddColumn {
             ))
             .context("failed to rename column to final name")?;
 
+        if let Some(add_foreign_key) = &add_foreign_key {
+            transaction
+                .run(&format!(
+                    r#"
+                    ALTER TABLE "{table}"
+                    RENAME CONSTRAINT "{temp_constraint_name}" TO "{constraint_name}"
+                    "#,
+                    table = self.table,
+                    temp_constraint_name = add_foreign_key.temp_constraint_name(ctx),
+                    constraint_name = self.foreign_key_name(),
+                ))
+                .context("failed to rename temporary foreign key")?;
+        }
+
+        // Point the auto_updated_at trigger at the final column name
+        if self.auto_updated_at {
+            common::create_auto_updated_at_trigger(
+                &mut transaction,
+                &self.table,
+                &self.column.name,
+                &self.column.name,
+            )?;
+        }
+
         Ok(Some(transaction))
     }
 
@@ -404,6 +745,24 @@ impl Action for AddColumn {
     }
 
     fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        if self.auto_updated_at {
+            common::drop_auto_updated_at_triggers(db, &self.table, Some(&self.column.name))?;
+        }
+
+        if self.change_log {
+            common::drop_change_log_trigger(
+                db,
+                ctx,
+                &self.change_log_trigger_name(ctx),
+                &self.table,
+                true,
+            )?;
+        }
+
+        if let Some(add_foreign_key) = self.add_foreign_key(ctx) {
+            add_foreign_key.abort(ctx, db)?;
+        }
+
         // Remove column
         let query = format!(
             r#"
@@ -428,4 +787,109 @@ impl Action for AddColumn {
 
         Ok(())
     }
+
+    fn version_requirements(&self) -> Vec<VersionRequirement> {
+        let mut requirements = Vec::new();
+
+        if self.column.default.is_some() {
+            requirements.push(VersionRequirement::new(
+                110000,
+                "adding a column with a default without rewriting the table",
+            ));
+        }
+
+        if !self.column.nullable {
+            requirements.push(VersionRequirement::new(
+                120000,
+                "setting NOT NULL using an existing constraint without a full table scan",
+            ));
+        }
+
+        requirements
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        vec![&self.column.name]
+    }
+
+    fn backfill(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        match &self.transformation() {
+            // Backfill values by touching the rows, which fires the up trigger
+            Some(Transformation::Simple(_)) => {
+                let table = schema.get_table(db, &self.table)?;
+                common::backfill_rows(
+                    db,
+                    &table.real_name,
+                    Some(&self.temp_column_name(ctx)),
+                    self.backfill,
+                    &self.backfill_order.with_real_names(&table),
+                    ctx.backfill_window(),
+                )
+            }
+            // Backfill values by touching the from table
+            Some(Transformation::Update { table, .. }) => {
+                let from_table = schema.get_table(db, table)?;
+                common::backfill_rows(
+                    db,
+                    &from_table.real_name,
+                    None,
+                    self.backfill,
+                    &self.backfill_order.with_real_names(&from_table),
+                    ctx.backfill_window(),
+                )
+            }
+            None => Ok(()),
+        }
+        .context("failed to backfill existing rows")
+    }
+
+    fn backfill_priority(&self) -> Option<i32> {
+        self.backfill_priority
+    }
+
+    // The triggers would record a deferred backfill as writes
+    fn can_defer_backfill(&self) -> bool {
+        self.transformation().is_some()
+            && self.backfill != Backfill::None
+            && !self.auto_updated_at
+            && !self.change_log
+    }
+
+    fn rewritten_tables(&self) -> Vec<&str> {
+        if self.transformation().is_some() && self.backfill != Backfill::None {
+            vec![self.table.as_str()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.add_column(&self.table, &self.column.name);
+        if let Some(Transformation::Update { table, .. }) = &self.up {
+            schema.require_table(table);
+        }
+        if let BackfillOrder::Columns(columns) = &self.backfill_order {
+            let table = match &self.up {
+                Some(Transformation::Update { table, .. }) => table,
+                _ => &self.table,
+            };
+            schema.require_columns(table, columns);
+        }
+        if let Some(foreign_key) = &self.foreign_key {
+            schema.require_columns(
+                &foreign_key.referenced_table,
+                [&foreign_key.referenced_column],
+            );
+        }
+    }
+
+    // Adding the column and renaming it on completion take brief ACCESS EXCLUSIVE locks
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
 }
@@ -1,15 +1,35 @@
-use super::{common::ForeignKey, Action, MigrationContext};
+use super::{
+    common::{self, ForeignKey},
+    Action, LockLevel, LogicalSchema, MigrationContext,
+};
 use crate::{
     db::{Conn, Transaction},
     schema::Schema,
 };
 use anyhow::Context;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct AddForeignKey {
     pub table: String,
     foreign_key: ForeignKey,
+
+    #[serde(default)]
+    pub validate: ForeignKeyValidation,
+}
+
+// When the existing rows are checked against the new foreign key. Validation scans
+// the entire table, so it can be deferred to `complete` to keep starting the
+// migration fast. The foreign key is enforced for new writes either way.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ForeignKeyValidation {
+    #[default]
+    Start,
+    Complete,
 }
 
 #[typetag::serde(name = "add_foreign_key")]
@@ -59,15 +79,16 @@ impl Action for AddForeignKey {
         ))
         .context("failed to create foreign key")?;
 
-        db.run(&format!(
-            r#"
-            ALTER TABLE "{table}"
-            VALIDATE CONSTRAINT "{constraint_name}"
-            "#,
-            table = table.real_name,
-            constraint_name = self.temp_constraint_name(ctx),
-        ))
-        .context("failed to validate foreign key")?;
+        if self.validate == ForeignKeyValidation::Start {
+            self.validate_constraint(
+                db,
+                ctx,
+                &table.real_name,
+                &columns,
+                &referenced_table.real_name,
+                &referenced_columns,
+            )?;
+        }
 
         Ok(())
     }
@@ -77,17 +98,27 @@ impl Action for AddForeignKey {
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
-        db.run(&format!(
-            r#"
-            ALTER TABLE {table}
-            RENAME CONSTRAINT {temp_constraint_name} TO {constraint_name}
-            "#,
-            table = self.table,
-            temp_constraint_name = self.temp_constraint_name(ctx),
-            constraint_name = self.final_constraint_name(),
-        ))
-        .context("failed to rename temporary constraint")?;
-        Ok(None)
+        // The migration can't be aborted at this point, so if validation fails the
+        // offending rows have to be fixed before trying to complete again
+        if self.validate == ForeignKeyValidation::Complete {
+            self.validate_on_complete(ctx, db)?;
+        }
+
+        // The rename is committed together with the state, otherwise completing again
+        // after a crash would try to rename a constraint which no longer exists
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+        transaction
+            .run(&format!(
+                r#"
+                ALTER TABLE {table}
+                RENAME CONSTRAINT {temp_constraint_name} TO {constraint_name}
+                "#,
+                table = self.table,
+                temp_constraint_name = self.temp_constraint_name(ctx),
+                constraint_name = self.final_constraint_name(),
+            ))
+            .context("failed to rename temporary constraint")?;
+        Ok(Some(transaction))
     }
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
@@ -105,10 +136,126 @@ impl Action for AddForeignKey {
 
         Ok(())
     }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_columns(&self.table, &self.foreign_key.columns);
+        schema.require_columns(
+            &self.foreign_key.referenced_table,
+            &self.foreign_key.referenced_columns,
+        );
+    }
+
+    // The constraint is added with a SHARE ROW EXCLUSIVE lock on both tables, but
+    // renaming it on completion takes ACCESS EXCLUSIVE
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
 }
 
 impl AddForeignKey {
-    fn temp_constraint_name(&self, ctx: &MigrationContext) -> String {
+    pub fn new(table: impl Into<String>, foreign_key: ForeignKey) -> Self {
+        AddForeignKey {
+            table: table.into(),
+            foreign_key,
+            validate: ForeignKeyValidation::default(),
+        }
+    }
+
+    pub fn with_validate(mut self, validate: ForeignKeyValidation) -> Self {
+        self.validate = validate;
+        self
+    }
+
+    // Validate a foreign key which was created as NOT VALID when the migration was
+    // started. The tables have their final names at this point.
+    pub(super) fn validate_on_complete(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+    ) -> anyhow::Result<()> {
+        let quote = |col: &String| format!("\"{}\"", col);
+        self.validate_constraint(
+            db,
+            ctx,
+            &self.table,
+            &self
+                .foreign_key
+                .columns
+                .iter()
+                .map(quote)
+                .collect::<Vec<_>>(),
+            &self.foreign_key.referenced_table,
+            &self
+                .foreign_key
+                .referenced_columns
+                .iter()
+                .map(quote)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    // Validate the foreign key against the existing rows. If any rows have no match,
+    // a sample of them is included in the error to help fix the data.
+    fn validate_constraint(
+        &self,
+        db: &mut dyn Conn,
+        ctx: &MigrationContext,
+        table: &str,
+        columns: &[String],
+        referenced_table: &str,
+        referenced_columns: &[String],
+    ) -> anyhow::Result<()> {
+        let not_null: Vec<String> = columns
+            .iter()
+            .map(|col| format!("t.{} IS NOT NULL", col))
+            .collect();
+        let matches: Vec<String> = columns
+            .iter()
+            .zip(referenced_columns)
+            .map(|(col, referenced_col)| format!("r.{} = t.{}", referenced_col, col))
+            .collect();
+        let violations = common::validate_constraint(
+            db,
+            table,
+            &self.temp_constraint_name(ctx),
+            &format!(
+                r#"
+                {not_null}
+                AND NOT EXISTS (
+                    SELECT 1 FROM public."{referenced_table}" r WHERE {matches}
+                )
+                "#,
+                not_null = not_null.join(" AND "),
+                referenced_table = referenced_table,
+                matches = matches.join(" AND "),
+            ),
+            &format!(
+                "ROW({})::TEXT",
+                columns
+                    .iter()
+                    .map(|col| format!("t.{}", col))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )
+        .context("failed to validate foreign key")?;
+
+        match violations {
+            None => Ok(()),
+            Some(violations) => Err(violations.error(|sample| {
+                format!(
+                    "failed to validate foreign key, rows in \"{}\" with ({}) = {} have no matching row in \"{}\" ({} rows in total)",
+                    self.table,
+                    self.foreign_key.columns.join(", "),
+                    sample,
+                    self.foreign_key.referenced_table,
+                    violations.count,
+                )
+            })),
+        }
+    }
+
+    pub(super) fn temp_constraint_name(&self, ctx: &MigrationContext) -> String {
         format!("{}_temp_fkey", ctx.prefix())
     }
 
@@ -1,18 +1,32 @@
-use super::{Action, MigrationContext};
+use super::{common, Action, IndexBuild, LockLevel, LogicalSchema, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
-    schema::Schema,
+    schema::{Schema, Table},
 };
-use anyhow::Context;
+use anyhow::{anyhow, bail, Context};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct AddIndex {
     pub table: String,
     pub index: Index,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+impl AddIndex {
+    pub fn new(table: impl Into<String>, index: Index) -> Self {
+        AddIndex {
+            table: table.into(),
+            index,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct Index {
     pub name: String,
     pub columns: Vec<String>,
@@ -22,29 +36,48 @@ pub struct Index {
     pub index_type: Option<String>,
 }
 
-#[typetag::serde(name = "add_index")]
-impl Action for AddIndex {
-    fn describe(&self) -> String {
-        format!(
-            "Adding index \"{}\" to table \"{}\"",
-            self.index.name, self.table
-        )
+impl Index {
+    pub fn new(
+        name: impl Into<String>,
+        columns: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Index {
+            name: name.into(),
+            columns: common::into_strings(columns),
+            unique: false,
+            index_type: None,
+        }
     }
 
-    fn run(
-        &self,
-        _ctx: &MigrationContext,
-        db: &mut dyn Conn,
-        schema: &Schema,
-    ) -> anyhow::Result<()> {
-        let table = schema.get_table(db, &self.table)?;
+    pub fn with_unique(mut self, unique: bool) -> Self {
+        self.unique = unique;
+        self
+    }
 
-        let column_real_names: Vec<String> = table
+    pub fn with_index_type(mut self, index_type: impl Into<String>) -> Self {
+        self.index_type = Some(index_type.into());
+        self
+    }
+}
+
+impl AddIndex {
+    // Columns are looked up by their current names, which includes columns renamed
+    // or replaced by earlier actions in the same migration. The index is built on
+    // the columns backing them, in the order they were listed.
+    fn create_index_query(&self, ctx: &MigrationContext, table: &Table) -> anyhow::Result<String> {
+        let column_real_names: Vec<String> = self
+            .index
             .columns
             .iter()
-            .filter(|column| self.index.columns.contains(&column.name))
-            .map(|column| format!("\"{}\"", column.real_name))
-            .collect();
+            .map(|name| {
+                table
+                    .get_column(name)
+                    .map(|column| format!("\"{}\"", column.real_name))
+                    .ok_or_else(|| {
+                        anyhow!("no column \"{}\" exists on table \"{}\"", name, self.table)
+                    })
+            })
+            .collect::<anyhow::Result<_>>()?;
 
         let unique = if self.index.unique { "UNIQUE" } else { "" };
         let index_type_def = if let Some(index_type) = &self.index.index_type {
@@ -53,15 +86,59 @@ impl Action for AddIndex {
             "".to_string()
         };
 
-        db.run(&format!(
+        Ok(format!(
             r#"
-			CREATE {unique} INDEX CONCURRENTLY "{name}" ON "{table}" {index_type_def} ({columns}) 
+			CREATE {unique} INDEX {concurrently} IF NOT EXISTS "{name}" ON "{table}" {index_type_def} ({columns}) 
 			"#,
+            concurrently = if ctx.atomic { "" } else { "CONCURRENTLY" },
             name = self.index.name,
-            table = self.table,
+            table = table.real_name,
             columns = column_real_names.join(", "),
         ))
-        .context("failed to create index")?;
+    }
+}
+
+#[typetag::serde(name = "add_index")]
+impl Action for AddIndex {
+    fn describe(&self) -> String {
+        format!(
+            "Adding index \"{}\" to table \"{}\"",
+            self.index.name, self.table
+        )
+    }
+
+    fn run(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        let table = schema.get_table(db, &self.table)?;
+        let query = self.create_index_query(ctx, &table)?;
+
+        // Indices can't be created concurrently inside a transaction and building one
+        // would block writes, so atomic migrations may only index empty tables
+        if ctx.atomic {
+            let is_empty = db
+                .query(&format!(
+                    r#"SELECT 1 FROM "{table}" LIMIT 1"#,
+                    table = table.real_name,
+                ))
+                .context("failed to check if table is empty")?
+                .is_empty();
+            if !is_empty {
+                bail!(
+                    "index \"{}\" can't be added in an atomic migration as table \"{}\" isn't empty",
+                    self.index.name,
+                    self.table,
+                );
+            }
+        }
+
+        if !ctx.atomic {
+            common::drop_invalid_index(db, &self.index.name)?;
+        }
+        db.run(&query).context("failed to create index")?;
         Ok(())
     }
 
@@ -85,4 +162,39 @@ impl Action for AddIndex {
         .context("failed to drop index")?;
         Ok(())
     }
+
+    fn can_run_atomically(&self) -> bool {
+        // Only allowed on empty tables, which is checked when the index is created
+        true
+    }
+
+    fn index_build(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<Option<IndexBuild>> {
+        if ctx.atomic {
+            return Ok(None);
+        }
+
+        let table = schema.get_table(db, &self.table)?;
+        let query = self.create_index_query(ctx, &table)?;
+        common::drop_invalid_index(db, &self.index.name)?;
+        Ok(Some(IndexBuild::new(&self.table, &self.index.name, query)))
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        vec![&self.index.name]
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_columns(&self.table, &self.index.columns);
+        schema.add_index(&self.table, &self.index.name);
+    }
+
+    // Indices are built and dropped concurrently
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::ShareUpdateExclusive
+    }
 }
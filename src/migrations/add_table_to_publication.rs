@@ -0,0 +1,136 @@
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::{anyhow, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct AddTableToPublication {
+    pub publication: String,
+    pub table: String,
+
+    // Only publish these columns, requires Postgres 15
+    pub columns: Option<Vec<String>>,
+
+    // Only publish rows matching this condition, requires Postgres 15
+    pub r#where: Option<String>,
+}
+
+impl AddTableToPublication {
+    pub fn new(publication: impl Into<String>, table: impl Into<String>) -> Self {
+        AddTableToPublication {
+            publication: publication.into(),
+            table: table.into(),
+            columns: None,
+            r#where: None,
+        }
+    }
+
+    pub fn with_columns(mut self, columns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.columns = Some(common::into_strings(columns));
+        self
+    }
+
+    pub fn with_where(mut self, r#where: impl Into<String>) -> Self {
+        self.r#where = Some(r#where.into());
+        self
+    }
+}
+
+pub(crate) fn ensure_publication_exists(
+    db: &mut dyn Conn,
+    publication: &str,
+) -> anyhow::Result<()> {
+    let exists = !db
+        .query_with_params(
+            "SELECT 1 FROM pg_publication WHERE pubname = $1",
+            &[&publication],
+        )
+        .context("failed to check for publication")?
+        .is_empty();
+
+    if !exists {
+        return Err(anyhow!("no publication \"{}\" exists", publication));
+    }
+
+    Ok(())
+}
+
+#[typetag::serde(name = "add_table_to_publication")]
+impl Action for AddTableToPublication {
+    fn describe(&self) -> String {
+        format!(
+            "Adding table \"{}\" to publication \"{}\"",
+            self.table, self.publication
+        )
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        // The table is only published once the migration is completed, so subscribers
+        // never receive changes for a table which might still be aborted. Everything
+        // is checked up front so completion doesn't fail halfway.
+        ensure_publication_exists(db, &self.publication)?;
+
+        let table = schema.get_table(db, &self.table)?;
+        for column in self.columns.iter().flatten() {
+            if table.get_column(column).is_none() {
+                return Err(anyhow!(
+                    "no column \"{}\" exists on table \"{}\"",
+                    column,
+                    self.table
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        // Columns are referenced by their final names as the migration's changes to
+        // the table have been completed by now
+        common::set_publication_table(
+            &mut transaction,
+            &self.publication,
+            &self.table,
+            self.columns.as_deref(),
+            self.r#where.as_deref(),
+        )?;
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        match &self.columns {
+            Some(columns) => schema.require_columns(&self.table, columns),
+            None => {
+                schema.require_table(&self.table);
+            }
+        }
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::ShareUpdateExclusive
+    }
+}
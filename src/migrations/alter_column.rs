@@ -1,13 +1,18 @@
-use super::{Action, MigrationContext};
+use super::{
+    Action, Backfill, BackfillOrder, LockLevel, LogicalSchema, MigrationContext, VersionRequirement,
+};
 use crate::{
-    db::{Conn, Transaction},
+    db::{self, Conn, Transaction},
     migrations::common,
     schema::Schema,
 };
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct AlterColumn {
     pub table: String,
     pub column: String,
@@ -15,9 +20,30 @@ pub struct AlterColumn {
     pub down: Option<String>,
     #[serde(default)]
     pub changes: ColumnChanges,
+
+    // Record rows written while the migration is in progress in `reshape.change_log`
+    #[serde(default)]
+    pub change_log: bool,
+
+    // How existing rows are filled in using `up`. Without a backfill, existing rows
+    // will be NULL in the altered column once the migration is completed.
+    #[serde(default, deserialize_with = "common::deserialize_backfill")]
+    #[schemars(with = "common::BackfillSetting")]
+    pub backfill: Backfill,
+
+    // The order existing rows are touched in when backfilling in batches
+    #[serde(default)]
+    #[schemars(with = "common::BackfillOrderSetting")]
+    pub backfill_order: BackfillOrder,
+
+    // Defer the backfill until all actions in the migration have run. Deferred
+    // backfills run in order of priority, highest first.
+    pub backfill_priority: Option<i32>,
 }
 
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Default, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct ColumnChanges {
     pub name: Option<String>,
     #[serde(rename = "type")]
@@ -26,6 +52,32 @@ pub struct ColumnChanges {
     pub default: Option<String>,
 }
 
+impl ColumnChanges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_data_type(mut self, data_type: impl Into<String>) -> Self {
+        self.data_type = Some(data_type.into());
+        self
+    }
+
+    pub fn with_nullable(mut self, nullable: bool) -> Self {
+        self.nullable = Some(nullable);
+        self
+    }
+
+    pub fn with_default(mut self, default: impl Into<String>) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+}
+
 #[typetag::serde(name = "alter_column")]
 impl Action for AlterColumn {
     fn describe(&self) -> String {
@@ -38,10 +90,28 @@ impl Action for AlterColumn {
         db: &mut dyn Conn,
         schema: &Schema,
     ) -> anyhow::Result<()> {
+        // The change log trigger would record the deferred backfill as writes
+        if self.backfill_priority.is_some() && self.change_log {
+            bail!(
+                "backfill_priority can't be combined with change_log for column \"{}\"",
+                self.column,
+            );
+        }
+
         // If we are only changing the name of a column, we don't have to do anything at this stage
         // We'll set the new schema to point to the old column. When the migration is completed,
         // we rename the actual column.
         if self.can_short_circuit() {
+            if self.change_log {
+                let table = schema.get_table(db, &self.table)?;
+                common::create_change_log_trigger(
+                    db,
+                    ctx,
+                    &self.change_log_trigger_name(ctx),
+                    &table,
+                )?;
+            }
+
             return Ok(());
         }
 
@@ -51,25 +121,74 @@ impl Action for AlterColumn {
             .get_column(&self.column)
             .ok_or_else(|| anyhow!("no such column {} exists", self.column))?;
 
+        // Existing rows would be left as NULL and fail the NOT NULL constraint on completion
+        if self.backfill == Backfill::None && !self.changes.nullable.unwrap_or(column.nullable) {
+            bail!(
+                "backfill can't be disabled for column \"{}\" as it's NOT NULL",
+                self.column,
+            );
+        }
+
+        // The replica identity is moved to the copy of its index on completion, which
+        // Postgres only allows if the new column is NOT NULL
+        if self.changes.nullable == Some(true) {
+            let indices = common::get_indices_for_column(db, &table.real_name, &column.real_name)?;
+            if indices.iter().any(|index| index.replica_identity) {
+                bail!(
+                    "column \"{}\" can't be made nullable as it's part of the replica identity of table \"{}\"",
+                    self.column,
+                    self.table,
+                );
+            }
+        }
+
+        // Generated columns compute their own values, which can't be combined with the
+        // triggers keeping the temporary column in sync
+        if is_generated(db, &table.real_name, &column.real_name)? {
+            bail!(
+                "generated column \"{}\" can't be altered, remove and add it again instead",
+                self.column,
+            );
+        }
+
+        // Objects depending on the column are dropped together with it when the migration
+        // is completed, so make sure they can all be recreated before starting
+        get_dependencies(db, &table.real_name, &column.real_name)?
+            .ensure_supported(&self.table, &self.column)?;
+
         let temporary_column_name = self.temporary_column_name(ctx);
-        let temporary_column_type = self.changes.data_type.as_ref().unwrap_or(&column.data_type);
+        // The collation is kept unless the type is changed, in which case it can be set
+        // as part of the new type
+        let temporary_column_type = match (&self.changes.data_type, &column.collation) {
+            (Some(data_type), _) => data_type.to_string(),
+            (None, Some(collation)) => format!("{} COLLATE {}", column.data_type, collation),
+            (None, None) => column.data_type.to_string(),
+        };
 
         // Add temporary, nullable column
         let mut temp_column_definition_parts: Vec<&str> =
-            vec![&temporary_column_name, temporary_column_type];
+            vec![&temporary_column_name, &temporary_column_type];
 
-        // Use either new default value or existing one if one exists
+        // Use either new default value or existing one if one exists. Identity columns
+        // don't have a default, so the temporary column takes values from the identity
+        // sequence instead. Otherwise, rows inserted through the new schema would be left
+        // without a value.
+        let identity = get_identity(db, &table.real_name, &column.real_name)?;
+        let identity_default = identity
+            .as_ref()
+            .map(|identity| format!("nextval('{}'::regclass)", identity.sequence));
         let default_value = self
             .changes
             .default
             .as_ref()
-            .or_else(|| column.default.as_ref());
+            .or(column.default.as_ref())
+            .or(identity_default.as_ref());
         if let Some(default) = default_value {
             temp_column_definition_parts.push("DEFAULT");
             temp_column_definition_parts.push(default);
         }
 
-        let query = format!(
+        let add_column_query = format!(
             r#"
 			ALTER TABLE "{table}"
             ADD COLUMN IF NOT EXISTS {temp_column_definition}
@@ -77,7 +196,6 @@ impl Action for AlterColumn {
             table = self.table,
             temp_column_definition = temp_column_definition_parts.join(" "),
         );
-        db.run(&query).context("failed to add temporary column")?;
 
         // If up or down wasn't provided, we default to simply moving the value over.
         // This is the correct behaviour for example when only changing the default value.
@@ -98,17 +216,18 @@ impl Action for AlterColumn {
             })
             .collect();
 
-        let query = format!(
+        let state_schema = ctx.namespace.state_schema();
+        let triggers_query = format!(
             r#"
                 CREATE OR REPLACE FUNCTION {up_trigger}()
                 RETURNS TRIGGER AS $$
                 BEGIN
-                    IF NOT reshape.is_new_schema() THEN
+                    IF {state_schema}.is_old_schema() THEN
                         DECLARE
                             {declarations}
                             {existing_column} public.{table}.{existing_column_real}%TYPE := NEW.{existing_column_real};
                         BEGIN
-                            NEW.{temp_column} = {up};
+                            {up_assignment}
                         END;
                     END IF;
                     RETURN NEW;
@@ -121,12 +240,12 @@ impl Action for AlterColumn {
                 CREATE OR REPLACE FUNCTION {down_trigger}()
                 RETURNS TRIGGER AS $$
                 BEGIN
-                    IF reshape.is_new_schema() THEN
+                    IF {state_schema}.is_new_schema() THEN
                         DECLARE
                             {declarations}
                             {existing_column} public.{table}.{temp_column}%TYPE := NEW.{temp_column};
                         BEGIN
-                            NEW.{existing_column_real} = {down};
+                            {down_assignment}
                         END;
                     END IF;
                     RETURN NEW;
@@ -139,70 +258,109 @@ impl Action for AlterColumn {
             existing_column = &self.column,
             existing_column_real = column.real_name,
             temp_column = self.temporary_column_name(ctx),
-            up = up,
-            down = down,
+            up_assignment = common::with_public_in_search_path(&format!(
+                "NEW.{} = {};",
+                self.temporary_column_name(ctx),
+                up
+            )),
+            down_assignment = common::with_public_in_search_path(&format!(
+                "NEW.{} = {};",
+                column.real_name, down
+            )),
             table = self.table,
             up_trigger = self.up_trigger_name(ctx),
             down_trigger = self.down_trigger_name(ctx),
             declarations = declarations.join("\n"),
         );
-        db.run(&query)
-            .context("failed to create up and down triggers")?;
 
-        // Backfill values in batches by touching the previous column
-        common::batch_touch_rows(db, &table.real_name, Some(&column.real_name))
-            .context("failed to batch update existing rows")?;
+        // The temporary column is only added together with the triggers keeping it in
+        // sync, so a failure can't leave a column behind which writes are missing from
+        db::atomically(db, |db| {
+            db.run(&add_column_query)
+                .context("failed to add temporary column")?;
+            db.run(&triggers_query)
+                .context("failed to create up and down triggers")
+        })?;
+
+        if !ctx.backfill_deferred() {
+            self.backfill(ctx, db, schema)?;
+        }
 
         // Duplicate any indices to the temporary column
         let indices = common::get_indices_for_column(db, &table.real_name, &column.real_name)?;
         for index in indices {
-            let index_columns: Vec<String> = common::get_index_columns(db, &index.name)?
-                .into_iter()
-                .map(|idx_column| {
-                    // Replace column with temporary column for new index
-                    if idx_column == column.real_name {
-                        temporary_column_name.to_string()
-                    } else {
-                        idx_column
-                    }
-                })
-                .collect();
+            let index_columns = common::get_index_columns(db, index.oid)?;
+            let definitions = |included: bool| -> Vec<String> {
+                index_columns
+                    .iter()
+                    .filter(|idx_column| idx_column.included == included)
+                    .map(|idx_column| {
+                        // Replace column with temporary column for new index
+                        if idx_column.name == column.real_name {
+                            idx_column.definition(&temporary_column_name)
+                        } else {
+                            idx_column.definition(&idx_column.name)
+                        }
+                    })
+                    .collect()
+            };
+            let key_columns = definitions(false);
+            let included_columns = definitions(true);
+            let include_def = if included_columns.is_empty() {
+                String::new()
+            } else {
+                format!("INCLUDE ({})", included_columns.join(", "))
+            };
             let temp_index_name = self.temp_index_name(ctx, index.oid);
 
             let unique_def = if index.unique { "UNIQUE" } else { "" };
 
+            common::drop_invalid_index(db, &temp_index_name)?;
             db.query(&format!(
                 r#"
-                CREATE {unique_def} INDEX CONCURRENTLY IF NOT EXISTS "{new_index_name}" ON "{table}" USING {index_type} ({columns})
+                CREATE {unique_def} INDEX CONCURRENTLY IF NOT EXISTS "{new_index_name}" ON "{table}" USING {index_type} ({columns}) {include_def}
                 "#,
                 new_index_name = temp_index_name,
                 table = table.real_name,
-                columns = index_columns.join(", "),
+                columns = key_columns.join(", "),
                 index_type = index.index_type,
             ))
             .context("failed to create temporary index")?;
         }
 
-        // Add a temporary NOT NULL constraint if the column shouldn't be nullable.
-        // This constraint is set as NOT VALID so it doesn't apply to existing rows and
-        // the existing rows don't need to be scanned under an exclusive lock.
-        // Thanks to this, we can set the full column as NOT NULL later with minimal locking.
-        if !self.changes.nullable.unwrap_or(column.nullable) {
-            let query = format!(
-                r#"
-                ALTER TABLE "{table}"
-                ADD CONSTRAINT "{constraint_name}"
-                CHECK ("{column}" IS NOT NULL) NOT VALID
-                "#,
-                table = self.table,
-                constraint_name = self.not_null_constraint_name(ctx),
-                column = self.temporary_column_name(ctx),
-            );
-            db.run(&query)
-                .context("failed to add NOT NULL constraint")?;
-        }
+        db::atomically(db, |db| {
+            // Add a temporary NOT NULL constraint if the column shouldn't be nullable.
+            // This constraint is set as NOT VALID so it doesn't apply to existing rows and
+            // the existing rows don't need to be scanned under an exclusive lock.
+            // Thanks to this, we can set the full column as NOT NULL later with minimal locking.
+            if !self.changes.nullable.unwrap_or(column.nullable) {
+                let query = format!(
+                    r#"
+                    ALTER TABLE "{table}"
+                    ADD CONSTRAINT "{constraint_name}"
+                    CHECK ("{column}" IS NOT NULL) NOT VALID
+                    "#,
+                    table = self.table,
+                    constraint_name = self.not_null_constraint_name(ctx),
+                    column = self.temporary_column_name(ctx),
+                );
+                db.run(&query)
+                    .context("failed to add NOT NULL constraint")?;
+            }
 
-        Ok(())
+            // Start recording changed rows once the backfill is done so only writes
+            // made during the migration are logged
+            if self.change_log {
+                common::create_change_log_trigger(
+                    db,
+                    ctx,
+                    &self.change_log_trigger_name(ctx),
+                    &table,
+                )?;
+            }
+
+            Ok(())
+        })
     }
 
     fn complete<'a>(
@@ -210,7 +368,18 @@ impl Action for AlterColumn {
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // Renaming the column can't be repeated, so it's done in the same transaction
+        // as the state is updated
         if self.can_short_circuit() {
+            let mut transaction = db.transaction().context("failed to create transaction")?;
+            common::drop_change_log_trigger(
+                &mut transaction,
+                ctx,
+                &self.change_log_trigger_name(ctx),
+                &self.table,
+                false,
+            )?;
+
             if let Some(new_name) = &self.changes.name {
                 let query = format!(
                     r#"
@@ -221,11 +390,19 @@ impl Action for AlterColumn {
                     existing_name = self.column,
                     new_name = new_name,
                 );
-                db.run(&query).context("failed to rename column")?;
+                transaction.run(&query).context("failed to rename column")?;
             }
-            return Ok(None);
+            return Ok(Some(transaction));
         }
 
+        common::drop_change_log_trigger(
+            db,
+            ctx,
+            &self.change_log_trigger_name(ctx),
+            &self.table,
+            false,
+        )?;
+
         // Update column to be NOT NULL if necessary
         let has_not_null_constraint = !db
             .query_with_params(
@@ -241,16 +418,13 @@ impl Action for AlterColumn {
         if has_not_null_constraint {
             // Validate the temporary constraint (should always be valid).
             // This performs a sequential scan but does not take an exclusive lock.
-            let query = format!(
-                r#"
-                ALTER TABLE "{table}"
-                VALIDATE CONSTRAINT "{constraint_name}"
-                "#,
-                table = self.table,
-                constraint_name = self.not_null_constraint_name(ctx),
-            );
-            db.run(&query)
-                .context("failed to validate NOT NULL constraint")?;
+            common::validate_not_null_constraint(
+                db,
+                &self.table,
+                &self.not_null_constraint_name(ctx),
+                &self.temporary_column_name(ctx),
+            )
+            .context("failed to validate NOT NULL constraint")?;
 
             // Update the column to be NOT NULL.
             // This requires an exclusive lock but since PG 12 it can check
@@ -279,26 +453,39 @@ impl Action for AlterColumn {
                 .context("failed to drop NOT NULL constraint")?;
         }
 
-        // Replace old indices with the new temporary ones created for the temporary column
-        let indices = common::get_indices_for_column(db, &self.table, &self.column)?;
+        // Replace old indices with the new temporary ones created for the temporary column.
+        // Once the temporary column has replaced the old one, which is the case when
+        // completing again after a crash, the indices on the column are the new ones.
+        let indices = if self.temporary_column_exists(ctx, db)? {
+            common::get_indices_for_column(db, &self.table, &self.column)?
+        } else {
+            Vec::new()
+        };
         for current_index in indices {
+            // Indices backing a constraint are replaced along with the constraint below
+            if current_index.backs_constraint {
+                continue;
+            }
+
             // To keep the index handling idempotent, we need to do the following:
             // 1. Add a prefix to the existing index
             // 2. Rename temporary index to its final name
             // 3. Drop existing index concurrently
 
             // Add prefix (if not already added) to existing index
-            let prefix = "__reshape_old";
-            let target_index_name = current_index.name.trim_start_matches(prefix);
-            let old_index_name = format!("{}_{}", prefix, target_index_name);
-            db.query(&format!(
-                r#"
-                ALTER INDEX IF EXISTS "{current_name}" RENAME TO "{new_name}"
-                "#,
-                current_name = target_index_name,
-                new_name = old_index_name,
-            ))
-            .context("failed to rename old index")?;
+            let prefix = format!("{}_old_", ctx.namespace.object_prefix());
+            let target_index_name = current_index.name.trim_start_matches(&prefix);
+            let old_index_name = format!("{}{}", prefix, target_index_name);
+            if current_index.name != old_index_name {
+                db.query(&format!(
+                    r#"
+                    ALTER INDEX IF EXISTS "{current_name}" RENAME TO "{new_name}"
+                    "#,
+                    current_name = target_index_name,
+                    new_name = old_index_name,
+                ))
+                .context("failed to rename old index")?;
+            }
 
             // Rename temporary index to real name
             let temp_index_name = self.temp_index_name(ctx, current_index.oid);
@@ -311,6 +498,20 @@ impl Action for AlterColumn {
             ))
             .context("failed to rename temporary index")?;
 
+            // Logical decoding consumers identify changed rows using the replica identity,
+            // which behaves like NOTHING once its index is dropped. It's moved over to the
+            // new index first, which is repeated after a crash until the old one is gone.
+            if current_index.replica_identity {
+                db.run(&format!(
+                    r#"
+                    ALTER TABLE "{table}" REPLICA IDENTITY USING INDEX "{index}"
+                    "#,
+                    table = self.table,
+                    index = target_index_name,
+                ))
+                .context("failed to move replica identity to new index")?;
+            }
+
             // Drop old index concurrently
             db.query(&format!(
                 r#"
@@ -321,28 +522,136 @@ impl Action for AlterColumn {
             .context("failed to drop old index")?;
         }
 
-        // Remove old column
-        let query = format!(
-            r#"
-            ALTER TABLE "{table}" DROP COLUMN IF EXISTS "{column}" CASCADE
-			"#,
-            table = self.table,
-            column = self.column,
-        );
-        db.run(&query).context("failed to drop old column")?;
+        // The new column takes the place of the old one in publications
+        common::replace_publication_column(
+            db,
+            &self.table,
+            &self.column,
+            Some(&self.temporary_column_name(ctx)),
+        )?;
+
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+        transaction
+            .run(&format!(
+                r#"
+                SET LOCAL search_path TO public;
+                LOCK TABLE public."{table}" IN ACCESS EXCLUSIVE MODE;
+                "#,
+                table = self.table,
+            ))
+            .context("failed to lock table")?;
 
-        // Rename temporary column
+        // The old column is replaced in a single transaction, so there is nothing left
+        // to do if the temporary column has already been renamed
+        let temporary_column_name = self.temporary_column_name(ctx);
+        if !self.temporary_column_exists(ctx, &mut transaction)? {
+            transaction.rollback()?;
+            common::validate_deferred(db, ctx)?;
+            return Ok(None);
+        }
+
+        // The old column is given the final name first so the definitions of any
+        // dependent objects refer to the column by the name it will end up with
         let column_name = self.changes.name.as_deref().unwrap_or(&self.column);
+        if column_name != self.column {
+            let query = format!(
+                r#"
+                ALTER TABLE "{table}" RENAME COLUMN "{existing_name}" TO "{new_name}"
+                "#,
+                table = self.table,
+                existing_name = self.column,
+                new_name = column_name,
+            );
+            transaction
+                .run(&query)
+                .context("failed to rename old column")?;
+        }
+
+        let dependencies = get_dependencies(&mut transaction, &self.table, column_name)?;
+        dependencies.ensure_supported(&self.table, &self.column)?;
+
+        // The identity sequence belongs to the old column and is dropped with it, so the
+        // new column is made an identity column which continues where the old one was
+        let identity = get_identity(&mut transaction, &self.table, column_name)?;
+        let identity_position: Option<(i64, bool)> = match &identity {
+            Some(identity) => transaction
+                .query(&format!(
+                    "SELECT last_value, is_called FROM {}",
+                    identity.sequence
+                ))
+                .context("failed to get identity sequence position")?
+                .first()
+                .map(|row| (row.get("last_value"), row.get("is_called"))),
+            None => None,
+        };
+
+        // Sequences owned by the old column would be dropped along with it
+        for dependency in &dependencies.recreatable {
+            if let Dependency::Sequence { name } = dependency {
+                let query = format!(
+                    r#"
+                    ALTER SEQUENCE {sequence} OWNED BY "{table}"."{temp_column}"
+                    "#,
+                    sequence = name,
+                    table = self.table,
+                    temp_column = temporary_column_name,
+                );
+                transaction
+                    .run(&query)
+                    .context("failed to change owner of sequence")?;
+            }
+        }
+
+        // Settings like the statistics target and storage belong to the old column and
+        // would be dropped along with it
+        ColumnSettings::get(
+            &mut transaction,
+            &self.table,
+            column_name,
+            &temporary_column_name,
+        )?
+        .apply(&mut transaction, &self.table, &temporary_column_name)?;
+
+        // The comment belongs to the old column and would be dropped along with it
+        common::copy_column_comment(
+            &mut transaction,
+            &self.table,
+            column_name,
+            &temporary_column_name,
+        )?;
+
+        // Remove old column and put the temporary column in its place
         let query = format!(
             r#"
-            ALTER TABLE "{table}" RENAME COLUMN "{temp_column}" TO "{name}"
-			"#,
+            ALTER TABLE "{table}" DROP COLUMN IF EXISTS "{column}" CASCADE;
+            ALTER TABLE "{table}" RENAME COLUMN "{temp_column}" TO "{name}";
+            "#,
             table = self.table,
-            temp_column = self.temporary_column_name(ctx),
+            column = column_name,
+            temp_column = temporary_column_name,
             name = column_name,
         );
-        db.run(&query)
-            .context("failed to rename temporary column")?;
+        transaction
+            .run(&query)
+            .context("failed to replace old column")?;
+
+        if let (Some(identity), Some((last_value, is_called))) = (identity, identity_position) {
+            let query = format!(
+                r#"
+                ALTER TABLE "{table}" ALTER COLUMN "{column}" DROP DEFAULT;
+                ALTER TABLE "{table}" ALTER COLUMN "{column}" ADD GENERATED {generated} AS IDENTITY;
+                SELECT setval(pg_get_serial_sequence('public."{table}"', '{column}'), {last_value}, {is_called});
+                "#,
+                table = self.table,
+                column = column_name,
+                generated = identity.generated,
+                last_value = last_value,
+                is_called = is_called,
+            );
+            transaction
+                .run(&query)
+                .context("failed to recreate identity")?;
+        }
 
         // Remove triggers and procedures
         let query = format!(
@@ -357,9 +666,78 @@ impl Action for AlterColumn {
             up_trigger = self.up_trigger_name(ctx),
             down_trigger = self.down_trigger_name(ctx),
         );
-        db.run(&query)
+        transaction
+            .run(&query)
             .context("failed to drop up and down triggers")?;
 
+        // Recreate the objects which were dropped with the old column. Constraints are
+        // added as NOT VALID and validated once the exclusive lock has been released.
+        let mut constraints_to_validate = Vec::new();
+        for dependency in &dependencies.recreatable {
+            let (query, object) = match dependency {
+                Dependency::Trigger { definition } => (definition.to_string(), "trigger"),
+                Dependency::Constraint {
+                    table,
+                    name,
+                    definition,
+                    validated,
+                } => {
+                    let mut query = format!(
+                        r#"ALTER TABLE {table} ADD CONSTRAINT "{name}" {definition}"#,
+                        table = table,
+                        name = name,
+                        definition = definition,
+                    );
+                    if *validated {
+                        query.push_str(" NOT VALID");
+                        constraints_to_validate.push((table.to_string(), name.to_string()));
+                    }
+                    (query, "constraint")
+                }
+                // The unique index was already built for the temporary column when the
+                // migration was started, so the constraint is added without a scan
+                // The index is renamed after the constraint, which the replica identity
+                // is moved to if it was set to the old index
+                Dependency::IndexConstraint {
+                    name,
+                    constraint_type,
+                    index_oid,
+                    deferrable,
+                    replica_identity,
+                } => {
+                    let mut query = format!(
+                        r#"ALTER TABLE "{table}" ADD CONSTRAINT "{name}" {constraint_type} USING INDEX "{index}" {deferrable}"#,
+                        table = self.table,
+                        name = name,
+                        constraint_type = constraint_type,
+                        index = self.temp_index_name(ctx, *index_oid),
+                        deferrable = deferrable,
+                    );
+                    if *replica_identity {
+                        query.push_str(&format!(
+                            r#"; ALTER TABLE "{table}" REPLICA IDENTITY USING INDEX "{name}""#,
+                            table = self.table,
+                            name = name,
+                        ));
+                    }
+                    (query, "constraint")
+                }
+                Dependency::View { name, definition } => {
+                    (format!("CREATE VIEW {} AS {}", name, definition), "view")
+                }
+                Dependency::Sequence { .. } => continue,
+            };
+
+            transaction
+                .run(&query)
+                .with_context(|| format!("failed to recreate {}: {}", object, query))?;
+        }
+
+        common::defer_validation(&mut transaction, ctx, &constraints_to_validate)?;
+        transaction.commit()?;
+
+        common::validate_deferred(db, ctx)?;
+
         Ok(None)
     }
 
@@ -386,6 +764,16 @@ impl Action for AlterColumn {
     }
 
     fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        if self.change_log {
+            common::drop_change_log_trigger(
+                db,
+                ctx,
+                &self.change_log_trigger_name(ctx),
+                &self.table,
+                true,
+            )?;
+        }
+
         // Safely remove any indices created for the temporary column
         let temp_column_name = self.temporary_column_name(ctx);
         let indices = common::get_indices_for_column(db, &self.table, &temp_column_name)?;
@@ -428,13 +816,187 @@ impl Action for AlterColumn {
 
         Ok(())
     }
+
+    fn version_requirements(&self) -> Vec<VersionRequirement> {
+        // Renaming a column is supported everywhere but otherwise a temporary column
+        // is added, which will be set as NOT NULL if the final column is not nullable.
+        // Columns which keep their current nullability are resolved from the schema.
+        if self.can_short_circuit() || self.changes.nullable != Some(false) {
+            return Vec::new();
+        }
+
+        vec![not_null_version_requirement()]
+    }
+
+    fn schema_version_requirements(
+        &self,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<Vec<VersionRequirement>> {
+        if self.can_short_circuit() || self.changes.nullable.is_some() {
+            return Ok(self.version_requirements());
+        }
+
+        // Columns which don't exist yet, as they are added by an earlier action, are
+        // assumed to be NOT NULL
+        let nullable = schema
+            .get_table(db, &self.table)?
+            .get_column(&self.column)
+            .map(|column| column.nullable)
+            .unwrap_or(false);
+        if nullable {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![not_null_version_requirement()])
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        self.changes.name.as_deref().into_iter().collect()
+    }
+
+    fn backfill(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        if self.can_short_circuit() {
+            return Ok(());
+        }
+
+        let table = schema.get_table(db, &self.table)?;
+        let column = table
+            .get_column(&self.column)
+            .ok_or_else(|| anyhow!("no such column {} exists", self.column))?;
+
+        // Backfill values by touching the previous column, which fires the up trigger.
+        // Identity columns generated ALWAYS can't be updated, so another column is
+        // touched instead.
+        let generated_always = get_identity(db, &table.real_name, &column.real_name)?
+            .is_some_and(|identity| identity.generated == "ALWAYS");
+        let touched_column = if generated_always {
+            None
+        } else {
+            Some(column.real_name.as_str())
+        };
+        common::backfill_rows(
+            db,
+            &table.real_name,
+            touched_column,
+            self.backfill,
+            &self.backfill_order.with_real_names(&table),
+            ctx.backfill_window(),
+        )
+        .context("failed to backfill existing rows")
+    }
+
+    fn backfill_priority(&self) -> Option<i32> {
+        self.backfill_priority
+    }
+
+    // The change log trigger would record a deferred backfill as writes
+    fn can_defer_backfill(&self) -> bool {
+        !self.can_short_circuit() && self.backfill != Backfill::None && !self.change_log
+    }
+
+    fn rewritten_tables(&self) -> Vec<&str> {
+        // Renaming a column is done without touching any rows
+        if self.can_short_circuit() {
+            Vec::new()
+        } else {
+            vec![self.table.as_str()]
+        }
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_columns(&self.table, [&self.column]);
+        if let BackfillOrder::Columns(columns) = &self.backfill_order {
+            schema.require_columns(&self.table, columns);
+        }
+        if let Some(new_name) = &self.changes.name {
+            schema.rename_column(&self.table, &self.column, new_name);
+        }
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
 }
 
 impl AlterColumn {
+    pub fn new(table: impl Into<String>, column: impl Into<String>) -> Self {
+        AlterColumn {
+            table: table.into(),
+            column: column.into(),
+            up: None,
+            down: None,
+            changes: ColumnChanges::default(),
+            change_log: false,
+            backfill: Backfill::default(),
+            backfill_order: BackfillOrder::default(),
+            backfill_priority: None,
+        }
+    }
+
+    pub fn with_up(mut self, up: impl Into<String>) -> Self {
+        self.up = Some(up.into());
+        self
+    }
+
+    pub fn with_down(mut self, down: impl Into<String>) -> Self {
+        self.down = Some(down.into());
+        self
+    }
+
+    pub fn with_changes(mut self, changes: ColumnChanges) -> Self {
+        self.changes = changes;
+        self
+    }
+
+    pub fn with_change_log(mut self, change_log: bool) -> Self {
+        self.change_log = change_log;
+        self
+    }
+
+    pub fn with_backfill(mut self, backfill: Backfill) -> Self {
+        self.backfill = backfill;
+        self
+    }
+
+    pub fn with_backfill_order(mut self, backfill_order: BackfillOrder) -> Self {
+        self.backfill_order = backfill_order;
+        self
+    }
+
+    pub fn with_backfill_priority(mut self, backfill_priority: i32) -> Self {
+        self.backfill_priority = Some(backfill_priority);
+        self
+    }
+
     fn temporary_column_name(&self, ctx: &MigrationContext) -> String {
         format!("{}_new_{}", ctx.prefix(), self.column)
     }
 
+    fn temporary_column_exists(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+    ) -> anyhow::Result<bool> {
+        let exists = !db
+            .query_with_params(
+                "
+                SELECT 1
+                FROM information_schema.columns
+                WHERE table_schema = 'public' AND table_name = $1 AND column_name = $2
+                ",
+                &[&self.table, &self.temporary_column_name(ctx)],
+            )
+            .context("failed to check for temporary column")?
+            .is_empty();
+        Ok(exists)
+    }
+
     fn up_trigger_name(&self, ctx: &MigrationContext) -> String {
         format!("{}_alter_column_up_trigger", ctx.prefix())
     }
@@ -447,6 +1009,10 @@ impl AlterColumn {
         format!("{}_alter_column_temporary", ctx.prefix())
     }
 
+    fn change_log_trigger_name(&self, ctx: &MigrationContext) -> String {
+        format!("{}_alter_column_change_log", ctx.prefix())
+    }
+
     fn temp_index_name(&self, ctx: &MigrationContext, index_oid: u32) -> String {
         format!("{}_alter_column_temp_index_{}", ctx.prefix(), index_oid)
     }
@@ -458,3 +1024,435 @@ impl AlterColumn {
             && self.changes.default.is_none()
     }
 }
+
+// Objects depending on a column which is being altered. These are dropped along with
+// the old column when the migration is completed and then recreated for the new one.
+enum Dependency {
+    Trigger {
+        definition: String,
+    },
+    Constraint {
+        table: String,
+        name: String,
+        definition: String,
+        validated: bool,
+    },
+    IndexConstraint {
+        name: String,
+        constraint_type: &'static str,
+        index_oid: u32,
+        deferrable: &'static str,
+        replica_identity: bool,
+    },
+    View {
+        name: String,
+        definition: String,
+    },
+    Sequence {
+        name: String,
+    },
+}
+
+struct ColumnDependencies {
+    recreatable: Vec<Dependency>,
+    unsupported: Vec<String>,
+}
+
+impl ColumnDependencies {
+    fn ensure_supported(&self, table: &str, column: &str) -> anyhow::Result<()> {
+        if self.unsupported.is_empty() {
+            return Ok(());
+        }
+
+        let objects: Vec<String> = self
+            .unsupported
+            .iter()
+            .map(|object| format!("  - {}", object))
+            .collect();
+        bail!(
+            "column \"{}\" on table \"{}\" can't be altered as the following objects depend on it and can't be recreated automatically:\n{}",
+            column,
+            table,
+            objects.join("\n"),
+        );
+    }
+}
+
+fn get_dependencies(
+    db: &mut dyn Conn,
+    table: &str,
+    column: &str,
+) -> anyhow::Result<ColumnDependencies> {
+    let namespace = db.namespace().clone();
+    let rows = db
+        .query_with_params(
+            r#"
+            SELECT DISTINCT
+                pg_describe_object(dep.classid, dep.objid, dep.objsubid) AS description,
+                dep.classid::regclass::TEXT AS catalog,
+                trigger.tgname::TEXT AS trigger_name,
+                pg_get_triggerdef(trigger.oid) AS trigger_definition,
+                con.conname::TEXT AS constraint_name,
+                con.contype::TEXT AS constraint_type,
+                pg_get_constraintdef(con.oid) AS constraint_definition,
+                con.convalidated AS constraint_validated,
+                con.conrelid = dep.refobjid AS constraint_on_table,
+                quote_ident(con_namespace.nspname) || '.' || quote_ident(con_table.relname)
+                    AS constraint_table,
+                con.conindid AS constraint_index,
+                con.condeferrable AS constraint_deferrable,
+                con.condeferred AS constraint_deferred,
+                COALESCE(con_index.indisreplident, FALSE) AS constraint_replica_identity,
+                view.oid AS view_oid,
+                view_namespace.nspname::TEXT AS view_schema,
+                view.relkind::TEXT AS view_kind,
+                relation.relkind::TEXT AS relation_kind,
+                quote_ident(relation_namespace.nspname) || '.' || quote_ident(relation.relname)
+                    AS relation_name,
+                attrdef.adnum = dep.refobjsubid AS own_default
+            FROM pg_depend dep
+            LEFT JOIN pg_trigger trigger
+                ON dep.classid = 'pg_trigger'::regclass AND trigger.oid = dep.objid
+            LEFT JOIN pg_constraint con
+                ON dep.classid = 'pg_constraint'::regclass AND con.oid = dep.objid
+            LEFT JOIN pg_class con_table ON con_table.oid = con.conrelid
+            LEFT JOIN pg_index con_index ON con_index.indexrelid = con.conindid
+            LEFT JOIN pg_namespace con_namespace ON con_namespace.oid = con_table.relnamespace
+            LEFT JOIN pg_rewrite rewrite
+                ON dep.classid = 'pg_rewrite'::regclass AND rewrite.oid = dep.objid
+            LEFT JOIN pg_class view ON view.oid = rewrite.ev_class
+            LEFT JOIN pg_namespace view_namespace ON view_namespace.oid = view.relnamespace
+            LEFT JOIN pg_class relation
+                ON dep.classid = 'pg_class'::regclass AND relation.oid = dep.objid
+            LEFT JOIN pg_namespace relation_namespace
+                ON relation_namespace.oid = relation.relnamespace
+            LEFT JOIN pg_attrdef attrdef
+                ON dep.classid = 'pg_attrdef'::regclass AND attrdef.oid = dep.objid
+            WHERE dep.refclassid = 'pg_class'::regclass
+                AND dep.refobjid = to_regclass(format('public.%I', $1::TEXT))
+                AND dep.refobjsubid = (
+                    SELECT attnum
+                    FROM pg_attribute
+                    WHERE attrelid = to_regclass(format('public.%I', $1::TEXT))
+                        AND attname = $2
+                        AND NOT attisdropped
+                )
+                AND dep.deptype IN ('n', 'a')
+            "#,
+            &[&table, &column],
+        )
+        .context("failed to get objects depending on column")?;
+
+    let mut dependencies = ColumnDependencies {
+        recreatable: Vec::new(),
+        unsupported: Vec::new(),
+    };
+    let mut views: Vec<u32> = Vec::new();
+
+    for row in rows {
+        let description: String = row.get("description");
+        let catalog: String = row.get("catalog");
+
+        let dependency = match catalog.as_str() {
+            "pg_trigger" => {
+                let name: String = row.get("trigger_name");
+                if name.starts_with(&namespace.object_prefix())
+                    || name.starts_with("reshape_auto_updated_at_")
+                {
+                    continue;
+                }
+
+                Some(Dependency::Trigger {
+                    definition: row.get("trigger_definition"),
+                })
+            }
+            "pg_constraint" => {
+                let constraint_type: String = row.get("constraint_type");
+                let on_table: bool = row.get("constraint_on_table");
+                match constraint_type.as_str() {
+                    // NOT NULL constraints are recreated along with the new column
+                    "n" => continue,
+                    // Check constraints and foreign keys, including those from other tables
+                    // referencing the column, can be added to the new column without
+                    // blocking writes while they are validated
+                    "c" if on_table => Some(Dependency::Constraint {
+                        table: row.get("constraint_table"),
+                        name: row.get("constraint_name"),
+                        definition: row.get("constraint_definition"),
+                        validated: row.get("constraint_validated"),
+                    }),
+                    "f" => Some(Dependency::Constraint {
+                        table: row.get("constraint_table"),
+                        name: row.get("constraint_name"),
+                        definition: row.get("constraint_definition"),
+                        validated: row.get("constraint_validated"),
+                    }),
+                    // Primary keys and unique constraints are moved over to the index
+                    // built for the new column. Foreign keys referencing the column
+                    // need the constraint to exist, so it's recreated first.
+                    "p" | "u" if on_table => {
+                        let deferrable = match (
+                            row.get::<_, bool>("constraint_deferrable"),
+                            row.get::<_, bool>("constraint_deferred"),
+                        ) {
+                            (false, _) => "",
+                            (true, false) => "DEFERRABLE INITIALLY IMMEDIATE",
+                            (true, true) => "DEFERRABLE INITIALLY DEFERRED",
+                        };
+                        dependencies.recreatable.insert(
+                            0,
+                            Dependency::IndexConstraint {
+                                name: row.get("constraint_name"),
+                                constraint_type: if constraint_type == "p" {
+                                    "PRIMARY KEY"
+                                } else {
+                                    "UNIQUE"
+                                },
+                                index_oid: row.get("constraint_index"),
+                                deferrable,
+                                replica_identity: row.get("constraint_replica_identity"),
+                            },
+                        );
+                        continue;
+                    }
+                    _ => None,
+                }
+            }
+            "pg_rewrite" => {
+                let schema: String = row.get("view_schema");
+                let kind: String = row.get("view_kind");
+
+                // Views for the migration schemas are managed by Reshape
+                if schema.starts_with(&namespace.schema_name_for_migration(""))
+                    || schema == namespace.initial_schema()
+                {
+                    continue;
+                }
+
+                if kind == "v" {
+                    views.push(row.get("view_oid"));
+                    continue;
+                }
+                None
+            }
+            "pg_class" => {
+                let kind: String = row.get("relation_kind");
+                match kind.as_str() {
+                    // Indices are duplicated to the new column when the migration is started
+                    "i" => continue,
+                    "S" => Some(Dependency::Sequence {
+                        name: row.get("relation_name"),
+                    }),
+                    _ => None,
+                }
+            }
+            "pg_attrdef" if row.get::<_, Option<bool>>("own_default") == Some(true) => continue,
+            "pg_publication_rel" => continue,
+            _ => None,
+        };
+
+        match dependency {
+            Some(dependency) => dependencies.recreatable.push(dependency),
+            None => dependencies.unsupported.push(description),
+        }
+    }
+
+    // Dropping a view also drops any views built on top of it, including the ones
+    // Reshape creates for the migration schemas, so those are recreated too. They
+    // are ordered so every view is created after the views it selects from.
+    if !views.is_empty() {
+        let rows = db
+            .query_with_params(
+                r#"
+                WITH RECURSIVE views (oid, depth) AS (
+                    SELECT oid, 0 FROM unnest($1::OID[]) AS oid
+                    UNION ALL
+                    SELECT rewrite.ev_class, views.depth + 1
+                    FROM views
+                    JOIN pg_depend dep
+                        ON dep.classid = 'pg_rewrite'::regclass AND dep.refobjid = views.oid
+                    JOIN pg_rewrite rewrite ON rewrite.oid = dep.objid
+                    WHERE rewrite.ev_class <> views.oid
+                )
+                SELECT
+                    quote_ident(namespace.nspname) || '.' || quote_ident(view.relname) AS name,
+                    view.relkind::TEXT AS kind,
+                    CASE WHEN view.relkind = 'v' THEN pg_get_viewdef(view.oid) END AS definition
+                FROM (
+                    SELECT oid, MAX(depth) AS depth FROM views GROUP BY oid
+                ) dependent
+                JOIN pg_class view ON view.oid = dependent.oid
+                JOIN pg_namespace namespace ON namespace.oid = view.relnamespace
+                ORDER BY dependent.depth
+                "#,
+                &[&views],
+            )
+            .context("failed to get dependent views")?;
+
+        for row in rows {
+            let name: String = row.get("name");
+            let kind: String = row.get("kind");
+            if kind == "v" {
+                dependencies.recreatable.push(Dependency::View {
+                    name,
+                    definition: row.get("definition"),
+                });
+            } else {
+                dependencies
+                    .unsupported
+                    .push(format!("materialized view {}", name));
+            }
+        }
+    }
+
+    Ok(dependencies)
+}
+
+fn not_null_version_requirement() -> VersionRequirement {
+    VersionRequirement::new(
+        120000,
+        "setting NOT NULL using an existing constraint without a full table scan",
+    )
+}
+
+fn is_generated(db: &mut dyn Conn, table: &str, column: &str) -> anyhow::Result<bool> {
+    let generated = !db
+        .query_with_params(
+            "
+            SELECT 1
+            FROM pg_attribute
+            WHERE attrelid = to_regclass(format('public.%I', $1::TEXT))
+                AND attname = $2
+                AND attgenerated <> ''
+            ",
+            &[&table, &column],
+        )
+        .context("failed to check if column is generated")?
+        .is_empty();
+
+    Ok(generated)
+}
+
+struct Identity {
+    // Either ALWAYS or BY DEFAULT
+    generated: &'static str,
+    sequence: String,
+}
+
+fn get_identity(db: &mut dyn Conn, table: &str, column: &str) -> anyhow::Result<Option<Identity>> {
+    let row = db
+        .query_with_params(
+            "
+            SELECT
+                attidentity::TEXT AS identity,
+                pg_get_serial_sequence(format('public.%I', $1::TEXT), $2) AS sequence
+            FROM pg_attribute
+            WHERE attrelid = to_regclass(format('public.%I', $1::TEXT))
+                AND attname = $2
+                AND attidentity <> ''
+            ",
+            &[&table, &column],
+        )
+        .context("failed to get identity for column")?
+        .pop();
+
+    Ok(row.map(|row| Identity {
+        generated: if row.get::<_, String>("identity") == "a" {
+            "ALWAYS"
+        } else {
+            "BY DEFAULT"
+        },
+        sequence: row.get("sequence"),
+    }))
+}
+
+// Settings which are stored on the column itself rather than being part of its
+// definition, and would otherwise be lost when the old column is dropped
+struct ColumnSettings {
+    statistics_target: Option<i32>,
+    storage: Option<String>,
+    compression: Option<String>,
+    options: Vec<String>,
+}
+
+impl ColumnSettings {
+    fn get(
+        db: &mut dyn Conn,
+        table: &str,
+        column: &str,
+        new_column: &str,
+    ) -> anyhow::Result<ColumnSettings> {
+        // Storage and compression only apply to types which can be stored out of line,
+        // so they are dropped if the new type can't use them. `attcompression` was
+        // added in Postgres 14 and `attstattarget` is NULL rather than -1 when unset
+        // from Postgres 17, so both are read through JSON to work across versions.
+        let row = db
+            .query_with_params(
+                "
+                SELECT
+                    NULLIF((to_jsonb(old) ->> 'attstattarget')::INTEGER, -1) AS statistics_target,
+                    CASE
+                        WHEN old.attstorage = old_type.typstorage OR new_type.typstorage = 'p' THEN NULL
+                        WHEN old.attstorage = 'p' THEN 'PLAIN'
+                        WHEN old.attstorage = 'e' THEN 'EXTERNAL'
+                        WHEN old.attstorage = 'm' THEN 'MAIN'
+                        ELSE 'EXTENDED'
+                    END AS storage,
+                    CASE
+                        WHEN new_type.typstorage = 'p' THEN NULL
+                        WHEN to_jsonb(old) ->> 'attcompression' = 'p' THEN 'pglz'
+                        WHEN to_jsonb(old) ->> 'attcompression' = 'l' THEN 'lz4'
+                    END AS compression,
+                    COALESCE(old.attoptions, '{}')::TEXT[] AS options
+                FROM pg_attribute old
+                JOIN pg_type old_type ON old_type.oid = old.atttypid
+                JOIN pg_attribute new ON new.attrelid = old.attrelid AND new.attname = $3
+                JOIN pg_type new_type ON new_type.oid = new.atttypid
+                WHERE old.attrelid = to_regclass(format('public.%I', $1::TEXT))
+                    AND old.attname = $2
+                ",
+                &[&table, &column, &new_column],
+            )
+            .context("failed to get column settings")?
+            .pop()
+            .ok_or_else(|| anyhow!("no column \"{}\" exists on \"{}\"", column, table))?;
+
+        Ok(ColumnSettings {
+            statistics_target: row.get("statistics_target"),
+            storage: row.get("storage"),
+            compression: row.get("compression"),
+            options: row.get("options"),
+        })
+    }
+
+    fn apply(&self, db: &mut dyn Conn, table: &str, column: &str) -> anyhow::Result<()> {
+        let mut changes = Vec::new();
+        if let Some(target) = self.statistics_target {
+            changes.push(format!("SET STATISTICS {}", target));
+        }
+        if let Some(storage) = &self.storage {
+            changes.push(format!("SET STORAGE {}", storage));
+        }
+        if let Some(compression) = &self.compression {
+            changes.push(format!("SET COMPRESSION {}", compression));
+        }
+        if !self.options.is_empty() {
+            changes.push(format!("SET ({})", self.options.join(", ")));
+        }
+
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let query = format!(
+            r#"ALTER TABLE "{table}" {changes}"#,
+            table = table,
+            changes = changes
+                .iter()
+                .map(|change| format!(r#"ALTER COLUMN "{}" {}"#, column, change))
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+        db.run(&query).context("failed to copy column settings")
+    }
+}
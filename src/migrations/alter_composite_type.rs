@@ -0,0 +1,180 @@
+use super::{
+    create_composite_type::composite_type_exists, Action, CompositeAttribute, LockLevel,
+    LogicalSchema, MigrationContext,
+};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::{anyhow, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct AlterCompositeType {
+    pub composite_type: String,
+    #[serde(default)]
+    pub add_attributes: Vec<CompositeAttribute>,
+    #[serde(default)]
+    pub remove_attributes: Vec<String>,
+}
+
+impl AlterCompositeType {
+    pub fn new(composite_type: impl Into<String>) -> Self {
+        AlterCompositeType {
+            composite_type: composite_type.into(),
+            add_attributes: vec![],
+            remove_attributes: vec![],
+        }
+    }
+
+    pub fn with_added_attribute(mut self, attribute: CompositeAttribute) -> Self {
+        self.add_attributes.push(attribute);
+        self
+    }
+
+    pub fn with_removed_attribute(mut self, name: impl Into<String>) -> Self {
+        self.remove_attributes.push(name.into());
+        self
+    }
+
+    fn attribute_exists(&self, db: &mut dyn Conn, attribute: &str) -> anyhow::Result<bool> {
+        let exists = !db
+            .query(&format!(
+                "
+                SELECT a.attname
+                FROM pg_catalog.pg_attribute a
+                JOIN pg_catalog.pg_type t ON t.typrelid = a.attrelid
+                WHERE t.typname = '{composite_type}'
+                AND t.typnamespace = 'public'::regnamespace
+                AND a.attname = '{attribute}'
+                AND NOT a.attisdropped
+                ",
+                composite_type = self.composite_type,
+                attribute = attribute,
+            ))
+            .context("failed to check for composite type attribute")?
+            .is_empty();
+        Ok(exists)
+    }
+}
+
+#[typetag::serde(name = "alter_composite_type")]
+impl Action for AlterCompositeType {
+    fn describe(&self) -> String {
+        format!("Altering composite type \"{}\"", self.composite_type)
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
+        if !composite_type_exists(db, &self.composite_type)? {
+            return Err(anyhow!(
+                "no composite type \"{}\" exists",
+                self.composite_type
+            ));
+        }
+
+        // Attributes are only removed once the migration is completed as the old
+        // schema might still read and write them. We only make sure they exist here.
+        for attribute in &self.remove_attributes {
+            if !self.attribute_exists(db, attribute)? {
+                return Err(anyhow!(
+                    "no attribute \"{}\" exists on composite type \"{}\"",
+                    attribute,
+                    self.composite_type
+                ));
+            }
+        }
+
+        // Adding an attribute doesn't rewrite any existing values, they will simply
+        // have NULL for the new attribute. ADD ATTRIBUTE doesn't have an IF NOT EXISTS
+        // option so we have to check manually to stay idempotent.
+        for attribute in &self.add_attributes {
+            if self.attribute_exists(db, &attribute.name)? {
+                continue;
+            }
+
+            db.run(&format!(
+                r#"
+                ALTER TYPE "{composite_type}"
+                ADD ATTRIBUTE "{name}" {data_type}
+                "#,
+                composite_type = self.composite_type,
+                name = attribute.name,
+                data_type = attribute.data_type,
+            ))
+            .context("failed to add composite type attribute")?;
+        }
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        for attribute in &self.remove_attributes {
+            transaction
+                .run(&format!(
+                    r#"
+                    ALTER TYPE "{composite_type}"
+                    DROP ATTRIBUTE IF EXISTS "{name}"
+                    "#,
+                    composite_type = self.composite_type,
+                    name = attribute,
+                ))
+                .context("failed to remove composite type attribute")?;
+        }
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        // The type might never have existed if the migration failed early
+        if !composite_type_exists(db, &self.composite_type)? {
+            return Ok(());
+        }
+
+        for attribute in &self.add_attributes {
+            db.run(&format!(
+                r#"
+                ALTER TYPE "{composite_type}"
+                DROP ATTRIBUTE IF EXISTS "{name}"
+                "#,
+                composite_type = self.composite_type,
+                name = attribute.name,
+            ))
+            .context("failed to drop composite type attribute")?;
+        }
+
+        Ok(())
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        self.add_attributes
+            .iter()
+            .map(|attribute| attribute.name.as_str())
+            .collect()
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_type(&self.composite_type);
+    }
+
+    // Attributes can't be changed while the type is used by a table, so no tables
+    // are locked
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::None
+    }
+}
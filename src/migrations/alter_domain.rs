@@ -0,0 +1,311 @@
+use super::{Action, DomainConstraint, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::{anyhow, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct AlterDomain {
+    pub domain: String,
+    #[serde(default)]
+    pub add_constraints: Vec<DomainConstraint>,
+    #[serde(default)]
+    pub remove_constraints: Vec<String>,
+}
+
+impl AlterDomain {
+    pub fn new(domain: impl Into<String>) -> Self {
+        AlterDomain {
+            domain: domain.into(),
+            add_constraints: vec![],
+            remove_constraints: vec![],
+        }
+    }
+
+    pub fn with_added_constraint(mut self, constraint: DomainConstraint) -> Self {
+        self.add_constraints.push(constraint);
+        self
+    }
+
+    pub fn with_removed_constraint(mut self, name: impl Into<String>) -> Self {
+        self.remove_constraints.push(name.into());
+        self
+    }
+
+    fn domain_exists(&self, db: &mut dyn Conn) -> anyhow::Result<bool> {
+        let exists = !db
+            .query(&format!(
+                "
+                SELECT typname
+                FROM pg_catalog.pg_type
+                WHERE typtype = 'd'
+                AND typnamespace = 'public'::regnamespace
+                AND typname = '{name}'
+                ",
+                name = self.domain,
+            ))
+            .context("failed to check for domain")?
+            .is_empty();
+        Ok(exists)
+    }
+
+    fn constraint_exists(&self, db: &mut dyn Conn, constraint: &str) -> anyhow::Result<bool> {
+        Ok(self.constraint_definition(db, constraint)?.is_some())
+    }
+
+    // The definition of a constraint on the domain as Postgres normalizes it, without
+    // whether it has been validated
+    fn constraint_definition(
+        &self,
+        db: &mut dyn Conn,
+        constraint: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let definition = db
+            .query(&format!(
+                "
+                SELECT pg_get_constraintdef(con.oid) AS definition
+                FROM pg_catalog.pg_constraint con
+                JOIN pg_catalog.pg_type t ON t.oid = con.contypid
+                WHERE t.typname = '{domain}'
+                AND t.typnamespace = 'public'::regnamespace
+                AND con.conname = '{constraint}'
+                ",
+                domain = self.domain,
+                constraint = constraint,
+            ))
+            .context("failed to check for domain constraint")?
+            .first()
+            .map(|row| {
+                row.get::<'_, _, String>("definition")
+                    .trim_end_matches(" NOT VALID")
+                    .to_string()
+            });
+        Ok(definition)
+    }
+
+    // Added constraints are created with a temporary name and renamed when the
+    // migration is completed, so aborting only drops constraints this migration created
+    fn temp_constraint_name(&self, ctx: &MigrationContext, constraint: &str) -> String {
+        format!("{}_{}", ctx.prefix(), constraint)
+    }
+
+    // Find all columns which use the domain. These will all be checked when a new
+    // constraint is validated, so any existing values which violate the constraint
+    // will be in one of these.
+    fn columns_using_domain(&self, db: &mut dyn Conn) -> anyhow::Result<Vec<String>> {
+        let columns = db
+            .query(&format!(
+                "
+                SELECT c.relname AS table_name, a.attname AS column_name
+                FROM pg_catalog.pg_attribute a
+                JOIN pg_catalog.pg_class c ON c.oid = a.attrelid
+                JOIN pg_catalog.pg_type t ON t.oid = a.atttypid
+                WHERE t.typname = '{domain}'
+                AND t.typtype = 'd'
+                AND c.relkind = 'r'
+                AND c.relnamespace = 'public'::regnamespace
+                AND a.attnum > 0
+                AND NOT a.attisdropped
+                ORDER BY c.relname, a.attnum
+                ",
+                domain = self.domain,
+            ))
+            .context("failed to get columns using domain")?
+            .iter()
+            .map(|row| {
+                format!(
+                    "{}.{}",
+                    row.get::<'_, _, String>("table_name"),
+                    row.get::<'_, _, String>("column_name")
+                )
+            })
+            .collect();
+        Ok(columns)
+    }
+}
+
+#[typetag::serde(name = "alter_domain")]
+impl Action for AlterDomain {
+    fn describe(&self) -> String {
+        format!("Altering domain \"{}\"", self.domain)
+    }
+
+    fn run(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
+        if !self.domain_exists(db)? {
+            return Err(anyhow!("no domain \"{}\" exists", self.domain));
+        }
+
+        // Constraints are only removed once the migration is completed as the old
+        // schema still expects them to hold. We only make sure they exist here.
+        for constraint in &self.remove_constraints {
+            if !self.constraint_exists(db, constraint)? {
+                return Err(anyhow!(
+                    "no constraint \"{}\" exists on domain \"{}\"",
+                    constraint,
+                    self.domain
+                ));
+            }
+        }
+
+        for constraint in &self.add_constraints {
+            // Create the constraint but set it as NOT VALID.
+            // This means the constraint will be enforced for inserts and updates
+            // in both schemas but the existing values won't be checked yet.
+            let temp_name = self.temp_constraint_name(ctx, &constraint.name);
+            if !self.constraint_exists(db, &temp_name)? {
+                db.run(&format!(
+                    r#"
+                    ALTER DOMAIN "{domain}"
+                    ADD CONSTRAINT "{name}" CHECK ({check})
+                    NOT VALID
+                    "#,
+                    domain = self.domain,
+                    name = temp_name,
+                    check = constraint.check,
+                ))
+                .context("failed to add domain constraint")?;
+            }
+
+            // A constraint with the same name can only be kept if it's the same one,
+            // in which case the new one isn't needed
+            if let Some(existing) = self.constraint_definition(db, &constraint.name)? {
+                if Some(existing) != self.constraint_definition(db, &temp_name)? {
+                    return Err(anyhow!(
+                        "a different constraint \"{}\" already exists on domain \"{}\"",
+                        constraint.name,
+                        self.domain
+                    ));
+                }
+
+                db.run(&format!(
+                    r#"
+                    ALTER DOMAIN "{domain}"
+                    DROP CONSTRAINT IF EXISTS "{name}"
+                    "#,
+                    domain = self.domain,
+                    name = temp_name,
+                ))
+                .context("failed to drop domain constraint")?;
+                continue;
+            }
+
+            // Check existing values in all columns using the domain. If any value
+            // violates the constraint, the migration will fail and be aborted.
+            let result = db
+                .run(&format!(
+                    r#"
+                    ALTER DOMAIN "{domain}"
+                    VALIDATE CONSTRAINT "{name}"
+                    "#,
+                    domain = self.domain,
+                    name = temp_name,
+                ))
+                .context("failed to validate domain constraint");
+
+            if let Err(err) = result {
+                let columns = self.columns_using_domain(db)?;
+                return Err(err.context(format!(
+                    "existing values violate constraint \"{}\", check columns using domain \"{}\": {}",
+                    constraint.name,
+                    self.domain,
+                    columns.join(", ")
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // The renames are committed together with the state. Constraints which already
+        // existed when the migration was started have no temporary constraint.
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        for constraint in &self.add_constraints {
+            let temp_name = self.temp_constraint_name(ctx, &constraint.name);
+            if !self.constraint_exists(&mut transaction, &temp_name)? {
+                continue;
+            }
+
+            transaction
+                .run(&format!(
+                    r#"
+                    ALTER DOMAIN "{domain}"
+                    RENAME CONSTRAINT "{temp_name}" TO "{name}"
+                    "#,
+                    domain = self.domain,
+                    temp_name = temp_name,
+                    name = constraint.name,
+                ))
+                .context("failed to rename temporary domain constraint")?;
+        }
+
+        for constraint in &self.remove_constraints {
+            transaction
+                .run(&format!(
+                    r#"
+                    ALTER DOMAIN "{domain}"
+                    DROP CONSTRAINT IF EXISTS "{name}"
+                    "#,
+                    domain = self.domain,
+                    name = constraint,
+                ))
+                .context("failed to remove domain constraint")?;
+        }
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        // The domain might never have existed if the migration failed early
+        if !self.domain_exists(db)? {
+            return Ok(());
+        }
+
+        for constraint in &self.add_constraints {
+            db.run(&format!(
+                r#"
+                ALTER DOMAIN "{domain}"
+                DROP CONSTRAINT IF EXISTS "{name}"
+                "#,
+                domain = self.domain,
+                name = self.temp_constraint_name(ctx, &constraint.name),
+            ))
+            .context("failed to drop domain constraint")?;
+        }
+
+        Ok(())
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        self.add_constraints
+            .iter()
+            .map(|constraint| constraint.name.as_str())
+            .collect()
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_type(&self.domain);
+    }
+
+    // Validating a constraint scans the columns which use the domain with a SHARE lock
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::Share
+    }
+}
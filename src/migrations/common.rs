@@ -1,10 +1,19 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, bail, Context};
+use colored::*;
 use postgres::types::{FromSql, ToSql};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::db::Conn;
+use super::MigrationContext;
+use crate::{
+    backfill::{BackfillCursor, BackfillStopped, BackfillWindow},
+    db::Conn,
+    schema::Table,
+};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct Column {
     pub name: String,
     #[serde(rename = "type")]
@@ -13,19 +22,186 @@ pub struct Column {
     pub nullable: bool,
     pub default: Option<String>,
     pub generated: Option<String>,
+
+    // Column constraints, only supported when creating a table
+    #[serde(default)]
+    pub unique: bool,
+    pub check: Option<String>,
 }
 
 fn nullable_default() -> bool {
     true
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+// How existing rows are filled in when a column is added or changed. Rows written
+// while the migration is in progress are always filled in by triggers.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Backfill {
+    // Leave existing rows untouched, for columns which only need values for new rows
+    None,
+    // Touch existing rows in small batches to avoid holding locks for long
+    #[default]
+    Batched,
+    // Touch all existing rows in a single statement, which is faster for small tables
+    Immediate,
+}
+
+// Migration files may also use `backfill = false` as a shorthand for `"none"`
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub(crate) enum BackfillSetting {
+    Enabled(bool),
+    Mode(Backfill),
+}
+
+pub(crate) fn deserialize_backfill<'de, D>(deserializer: D) -> Result<Backfill, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match BackfillSetting::deserialize(deserializer)? {
+        BackfillSetting::Enabled(true) => Backfill::Batched,
+        BackfillSetting::Enabled(false) => Backfill::None,
+        BackfillSetting::Mode(backfill) => backfill,
+    })
+}
+
+// The order a batched backfill touches existing rows in. Batching by the primary key
+// suits most tables, but is slow for random keys such as UUIDv4 as every batch is
+// spread across the whole table.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "BackfillOrderSetting", into = "BackfillOrderSetting")]
+pub enum BackfillOrder {
+    // Batch by the primary key, or by physical location for tables without one
+    #[default]
+    PrimaryKey,
+    // Batch by ranges of pages using the physical location of rows (`ctid`), which
+    // reads each page once no matter how the table is keyed
+    Ctid,
+    // Batch by these columns, for example an indexed `created_at`. The columns must be
+    // NOT NULL and not change when rows are touched. The primary key breaks ties.
+    Columns(Vec<String>),
+}
+
+impl BackfillOrder {
+    // Columns are referred to by their names in the schema, which might not be the
+    // names of the columns in the table during a migration
+    pub(crate) fn with_real_names(&self, table: &Table) -> BackfillOrder {
+        match self {
+            BackfillOrder::Columns(columns) => {
+                BackfillOrder::Columns(table.real_column_names(columns).cloned().collect())
+            }
+            order => order.clone(),
+        }
+    }
+}
+
+// Migration files use `"primary_key"`, `"ctid"` or a list of columns
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub(crate) enum BackfillOrderSetting {
+    Strategy(BackfillStrategy),
+    Columns(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BackfillStrategy {
+    PrimaryKey,
+    Ctid,
+}
+
+impl From<BackfillOrderSetting> for BackfillOrder {
+    fn from(setting: BackfillOrderSetting) -> Self {
+        match setting {
+            BackfillOrderSetting::Strategy(BackfillStrategy::PrimaryKey) => {
+                BackfillOrder::PrimaryKey
+            }
+            BackfillOrderSetting::Strategy(BackfillStrategy::Ctid) => BackfillOrder::Ctid,
+            BackfillOrderSetting::Columns(columns) => BackfillOrder::Columns(columns),
+        }
+    }
+}
+
+impl From<BackfillOrder> for BackfillOrderSetting {
+    fn from(order: BackfillOrder) -> Self {
+        match order {
+            BackfillOrder::PrimaryKey => {
+                BackfillOrderSetting::Strategy(BackfillStrategy::PrimaryKey)
+            }
+            BackfillOrder::Ctid => BackfillOrderSetting::Strategy(BackfillStrategy::Ctid),
+            BackfillOrder::Columns(columns) => BackfillOrderSetting::Columns(columns),
+        }
+    }
+}
+
+// Builders accept any list of names, for example `["id"]` or `vec!["id".to_string()]`
+pub(crate) fn into_strings(values: impl IntoIterator<Item = impl Into<String>>) -> Vec<String> {
+    values.into_iter().map(Into::into).collect()
+}
+
+impl Column {
+    pub fn new(name: impl Into<String>, data_type: impl Into<String>) -> Self {
+        Column {
+            name: name.into(),
+            data_type: data_type.into(),
+            nullable: nullable_default(),
+            default: None,
+            generated: None,
+            unique: false,
+            check: None,
+        }
+    }
+
+    pub fn with_nullable(mut self, nullable: bool) -> Self {
+        self.nullable = nullable;
+        self
+    }
+
+    pub fn with_default(mut self, default: impl Into<String>) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+
+    pub fn with_generated(mut self, generated: impl Into<String>) -> Self {
+        self.generated = Some(generated.into());
+        self
+    }
+
+    pub fn with_unique(mut self, unique: bool) -> Self {
+        self.unique = unique;
+        self
+    }
+
+    pub fn with_check(mut self, check: impl Into<String>) -> Self {
+        self.check = Some(check.into());
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct ForeignKey {
     pub columns: Vec<String>,
     pub referenced_table: String,
     pub referenced_columns: Vec<String>,
 }
 
+impl ForeignKey {
+    pub fn new(
+        columns: impl IntoIterator<Item = impl Into<String>>,
+        referenced_table: impl Into<String>,
+        referenced_columns: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        ForeignKey {
+            columns: into_strings(columns),
+            referenced_table: referenced_table.into(),
+            referenced_columns: into_strings(referenced_columns),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct PostgresRawValue {
     bytes: Vec<u8>,
@@ -69,54 +245,236 @@ impl ToSql for PostgresRawValue {
     postgres::types::to_sql_checked!();
 }
 
+// Columns in `order` must use their real names. Batched backfills stop with a
+// `BackfillStopped` error once the window, if any, is exhausted. Immediate backfills
+// run in a single statement, so they can't be stopped early.
+pub fn backfill_rows(
+    db: &mut dyn Conn,
+    table: &str,
+    column: Option<&str>,
+    backfill: Backfill,
+    order: &BackfillOrder,
+    window: Option<&BackfillWindow>,
+) -> anyhow::Result<()> {
+    match (backfill, order) {
+        (Backfill::None, _) => Ok(()),
+        (Backfill::Batched, BackfillOrder::PrimaryKey) => {
+            batch_touch_rows(db, table, column, window)
+        }
+        (Backfill::Batched, BackfillOrder::Ctid) => {
+            batch_touch_rows_by_ctid(db, table, column, window)
+        }
+        (Backfill::Batched, BackfillOrder::Columns(columns)) => {
+            batch_touch_rows_in_order(db, table, column, columns, window)
+        }
+        (Backfill::Immediate, _) => touch_all_rows(db, table, column),
+    }
+}
+
+// Touch all existing rows in a table in batches, which fires the triggers set up by
+// the migration so they can backfill new columns. Rows are batched by their primary
+// key using a cursor. Tables without a primary key are batched by physical location
+// instead. Connections which don't batch backfills touch all rows in one statement.
 pub fn batch_touch_rows(
     db: &mut dyn Conn,
     table: &str,
     column: Option<&str>,
+    window: Option<&BackfillWindow>,
+) -> anyhow::Result<()> {
+    batch_touch_rows_in_order(db, table, column, &[], window)
+}
+
+// Batch rows by the `order` columns first, followed by the primary key to break ties.
+// Rows with NULL in any of the columns would be skipped by the cursor, so the columns
+// must be NOT NULL.
+fn batch_touch_rows_in_order(
+    db: &mut dyn Conn,
+    table: &str,
+    column: Option<&str>,
+    order: &[String],
+    window: Option<&BackfillWindow>,
+) -> anyhow::Result<()> {
+    if !db.batch_backfills() {
+        return touch_all_rows(db, table, column);
+    }
+
+    let primary_key = get_primary_key_columns_for_table(db, table)?;
+    if primary_key.is_empty() {
+        if !order.is_empty() {
+            bail!(
+                "table \"{}\" has no primary key, which is needed to backfill in order of {}",
+                table,
+                order.join(", "),
+            );
+        }
+        return batch_touch_rows_by_ctid(db, table, column, window);
+    }
+
+    if !order.is_empty() {
+        let nullable: Vec<String> = db
+            .query_with_params(
+                "
+                SELECT order_column
+                FROM unnest($2::TEXT[]) AS order_column
+                LEFT JOIN pg_attribute
+                    ON attrelid = to_regclass(format('public.%I', $1::TEXT))
+                    AND attname = order_column
+                    AND NOT attisdropped
+                WHERE attnotnull IS DISTINCT FROM TRUE
+                ",
+                &[&table, &order],
+            )
+            .context("failed to check backfill order columns")?
+            .iter()
+            .map(|row| row.get("order_column"))
+            .collect();
+        if !nullable.is_empty() {
+            bail!(
+                "can't backfill table \"{}\" in order of {} as they must exist and be NOT NULL",
+                table,
+                nullable.join(", "),
+            );
+        }
+    }
+
+    let key: Vec<String> = order
+        .iter()
+        .chain(primary_key.iter().filter(|column| !order.contains(column)))
+        .cloned()
+        .collect();
+
+    // If no column to touch is passed, we default to the first column (just to make some "update")
+    let touched_column = match column {
+        Some(column) => column.to_string(),
+        None => get_first_column_for_table(db, table)?,
+    };
+
+    let primary_key_where = primary_key
+        .iter()
+        .map(|column| {
+            format!(
+                r#"
+                "{table}"."{column}" = rows."{column}"
+                "#,
+                table = table,
+                column = column,
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" AND ");
+
+    let statement = format!(
+        r#"
+        UPDATE public."{table}" "{table}"
+        SET "{touched_column}" = "{table}"."{touched_column}"
+        FROM rows
+        WHERE {primary_key_where}
+        RETURNING 1
+        "#,
+    );
+
+    batch_rows_by_primary_key(db, table, &key, false, &statement, window)
+}
+
+// Run a statement for batches of rows in a table, using a cursor over the primary key.
+// Other columns can be put before the primary key to batch the rows in another order.
+// The statement can reference the primary key of the rows in the current batch through
+// `rows` and must return one row per row it touched. When `lock` is set, the rows in
+// each batch are locked until the statement finishes so they can't be changed halfway.
+// With a window, the batches continue from its cursor and stop once it's exhausted.
+pub fn batch_rows_by_primary_key(
+    db: &mut dyn Conn,
+    table: &str,
+    primary_key: &[String],
+    lock: bool,
+    statement: &str,
+    window: Option<&BackfillWindow>,
 ) -> anyhow::Result<()> {
     const BATCH_SIZE: u16 = 1000;
 
-    let mut cursor: Option<PostgresRawValue> = None;
+    report_backfill_started(db, table)?;
 
-    loop {
-        let mut params: Vec<&(dyn ToSql + Sync)> = Vec::new();
+    let primary_key_columns = primary_key
+        .iter()
+        .map(|column| format!("\"{}\"", column))
+        .collect::<Vec<String>>()
+        .join(", ");
 
-        let primary_key = get_primary_key_columns_for_table(db, table)?;
+    let last_row_order = primary_key
+        .iter()
+        .map(|column| format!("rows.\"{}\" DESC", column))
+        .collect::<Vec<String>>()
+        .join(", ");
 
-        // If no column to touch is passed, we default to the first primary key column (just to make some "update")
-        let touched_column = match column {
-            Some(column) => column,
-            None => primary_key.first().unwrap(),
-        };
+    // The cursor holds the raw values of each primary key column for the last row
+    // touched. The values are passed back as separate parameters so Postgres can infer
+    // their types from the columns they're compared to, which works for any type.
+    let cursor_columns = primary_key
+        .iter()
+        .enumerate()
+        .map(|(index, column)| format!("rows.\"{}\" AS cursor_{}", column, index))
+        .collect::<Vec<String>>()
+        .join(", ");
 
-        let primary_key_columns = primary_key.join(", ");
+    let cursor_params = (1..=primary_key.len())
+        .map(|index| format!("${}", index))
+        .collect::<Vec<String>>()
+        .join(", ");
 
-        let primary_key_where = primary_key
-            .iter()
-            .map(|column| {
-                format!(
-                    r#"
-                    "{table}"."{column}" = rows."{column}"
-                    "#,
-                    table = table,
-                    column = column,
-                )
-            })
-            .collect::<Vec<String>>()
-            .join(" AND ");
+    let locking = if lock { "FOR SHARE" } else { "" };
 
-        let returning_columns = primary_key
-            .iter()
-            .map(|column| format!("rows.\"{}\"", column))
-            .collect::<Vec<String>>()
-            .join(", ");
+    // Without batches, the statement runs once for all rows in the table
+    if !db.batch_backfills() {
+        let query = format!(
+            r#"
+            WITH rows AS (
+                SELECT {primary_key_columns}
+                FROM public."{table}"
+                {locking}
+            ), batch AS (
+                {statement}
+            )
+            SELECT COUNT(*) AS touched FROM batch
+            "#,
+        );
+        let touched: i64 = db
+            .query(&query)?
+            .first()
+            .map(|row| row.get("touched"))
+            .unwrap_or_default();
+        db.count_rows_backfilled(touched as u64)?;
 
-        let cursor_where = if let Some(cursor) = &cursor {
-            params.push(cursor);
+        return report_backfill_finished(db);
+    }
+
+    let mut cursor: Option<Vec<PostgresRawValue>> = match window.and_then(|w| w.cursor()) {
+        Some(BackfillCursor::Key(values)) if values.len() == primary_key.len() => Some(
+            values
+                .iter()
+                .map(|bytes| PostgresRawValue {
+                    bytes: bytes.clone(),
+                })
+                .collect(),
+        ),
+        _ => None,
+    };
+    let mut touched_total = 0;
 
+    loop {
+        if window.is_some_and(|window| window.is_exhausted(touched_total)) {
+            let cursor = cursor.map(|values| {
+                BackfillCursor::Key(values.into_iter().map(|value| value.bytes).collect())
+            });
+            return stop_backfill(db, cursor);
+        }
+
+        db.throttle()?;
+
+        let cursor_where = if cursor.is_some() {
             format!(
-                "WHERE ({primary_key_columns}) > $1",
-                primary_key_columns = primary_key_columns
+                "WHERE ({primary_key_columns}) > ({cursor_params})",
+                primary_key_columns = primary_key_columns,
+                cursor_params = cursor_params,
             )
         } else {
             "".to_string()
@@ -130,35 +488,424 @@ pub fn batch_touch_rows(
                 {cursor_where}
                 ORDER BY {primary_key_columns}
                 LIMIT {batch_size}
-            ), update AS (
-                UPDATE public."{table}" "{table}"
-                SET "{touched_column}" = "{table}"."{touched_column}"
-                FROM rows
-                WHERE {primary_key_where}
-                RETURNING {returning_columns}
+                {locking}
+            ), batch AS (
+                {statement}
             )
-            SELECT LAST_VALUE(({primary_key_columns})) OVER () AS last_value
-            FROM update
+            SELECT
+                {cursor_columns},
+                (SELECT COUNT(*) FROM batch) AS touched
+            FROM rows
+            ORDER BY {last_row_order}
             LIMIT 1
             "#,
             batch_size = BATCH_SIZE,
         );
-        let last_value = db
-            .query_with_params(&query, &params)?
+
+        let params: Vec<&(dyn ToSql + Sync)> = cursor
+            .iter()
+            .flatten()
+            .map(|value| value as &(dyn ToSql + Sync))
+            .collect();
+        let row = match db.query_with_params(&query, &params)?.pop() {
+            Some(row) => row,
+            None => break,
+        };
+
+        let touched: i64 = row.get("touched");
+        db.count_rows_backfilled(touched as u64)?;
+        touched_total += touched as u64;
+
+        cursor = Some(
+            (0..primary_key.len())
+                .map(|index| row.get(format!("cursor_{}", index).as_str()))
+                .collect(),
+        );
+    }
+
+    report_backfill_finished(db)
+}
+
+// Stop a backfill early, leaving it to be continued from the cursor later
+fn stop_backfill(db: &mut dyn Conn, cursor: Option<BackfillCursor>) -> anyhow::Result<()> {
+    report_backfill_finished(db)?;
+    Err(BackfillStopped { cursor }.into())
+}
+
+// Touch all existing rows in a table with a single statement. This locks every row
+// until the statement finishes, so it's only suitable for small tables.
+fn touch_all_rows(db: &mut dyn Conn, table: &str, column: Option<&str>) -> anyhow::Result<()> {
+    let touched_column = match column {
+        Some(column) => column.to_string(),
+        None => get_first_column_for_table(db, table)?,
+    };
+    report_backfill_started(db, table)?;
+
+    let query = format!(
+        r#"
+        WITH update AS (
+            UPDATE public."{table}"
+            SET "{touched_column}" = "{touched_column}"
+            RETURNING 1
+        )
+        SELECT COUNT(*) AS touched FROM update
+        "#,
+        table = table,
+        touched_column = touched_column,
+    );
+    let touched: i64 = db
+        .query(&query)?
+        .first()
+        .map(|row| row.get("touched"))
+        .unwrap_or_default();
+    db.count_rows_backfilled(touched as u64)?;
+
+    report_backfill_finished(db)
+}
+
+// Let the connection know a backfill of a table is starting, using the row count
+// estimated by Postgres as the total. Tables which have never been vacuumed or analyzed
+// have no estimate, in which case only the number of rows so far is reported.
+fn report_backfill_started(db: &mut dyn Conn, table: &str) -> anyhow::Result<()> {
+    let estimated_rows: i64 = db
+        .query(&format!(
+            "
+            SELECT reltuples::BIGINT AS estimated_rows
+            FROM pg_class
+            WHERE oid = 'public.\"{table}\"'::regclass
+            ",
+            table = table,
+        ))?
+        .first()
+        .map(|row| row.get("estimated_rows"))
+        .unwrap_or_default();
+    db.run(&format!("SET {} = 'TRUE'", BACKFILL_SETTING))
+        .context("failed to mark session as backfilling")?;
+    db.backfill_started(table, (estimated_rows > 0).then_some(estimated_rows as u64))
+}
+
+// Counterpart to `report_backfill_started`, called once a backfill has finished or stopped
+fn report_backfill_finished(db: &mut dyn Conn) -> anyhow::Result<()> {
+    db.run(&format!("RESET {}", BACKFILL_SETTING))
+        .context("failed to reset backfilling setting")?;
+    db.backfill_finished()
+}
+
+// Columns generated ALWAYS can't be updated, so those are skipped
+fn get_first_column_for_table(db: &mut dyn Conn, table: &str) -> anyhow::Result<String> {
+    db.query(&format!(
+        "
+        SELECT attname::TEXT AS column_name
+        FROM pg_attribute
+        WHERE attrelid = 'public.\"{table}\"'::regclass
+            AND attnum > 0
+            AND NOT attisdropped
+            AND attidentity <> 'a'
+            AND attgenerated = ''
+        ORDER BY attnum
+        LIMIT 1
+        ",
+        table = table,
+    ))?
+    .first()
+    .map(|row| row.get("column_name"))
+    .ok_or_else(|| anyhow!("table {} has no columns which can be updated", table))
+}
+
+// Tables without a primary key are touched in ranges of pages using the `ctid` of
+// each row. Only the pages which exist when the backfill starts are touched, so rows
+// which are moved to new pages by the updates themselves aren't touched again.
+fn batch_touch_rows_by_ctid(
+    db: &mut dyn Conn,
+    table: &str,
+    column: Option<&str>,
+    window: Option<&BackfillWindow>,
+) -> anyhow::Result<()> {
+    const PAGES_PER_BATCH: i64 = 100;
+
+    if !db.batch_backfills() {
+        return touch_all_rows(db, table, column);
+    }
+
+    let touched_column = match column {
+        Some(column) => column.to_string(),
+        None => get_first_column_for_table(db, table)?,
+    };
+    report_backfill_started(db, table)?;
+
+    let pages: i64 = db
+        .query(&format!(
+            "
+            SELECT pg_relation_size('public.\"{table}\"') / current_setting('block_size')::BIGINT AS pages
+            ",
+            table = table,
+        ))?
+        .first()
+        .map(|row| row.get("pages"))
+        .unwrap_or_default();
+
+    let mut start_page = match window.and_then(|w| w.cursor()) {
+        Some(BackfillCursor::Page(page)) => *page,
+        _ => 0,
+    };
+    let mut touched_total = 0;
+    while start_page < pages {
+        if window.is_some_and(|window| window.is_exhausted(touched_total)) {
+            return stop_backfill(db, Some(BackfillCursor::Page(start_page)));
+        }
+
+        db.throttle()?;
+
+        let end_page = start_page + PAGES_PER_BATCH;
+        let query = format!(
+            r#"
+            WITH update AS (
+                UPDATE public."{table}"
+                SET "{touched_column}" = "{touched_column}"
+                WHERE ctid >= '({start_page},0)'::tid AND ctid < '({end_page},0)'::tid
+                RETURNING 1
+            )
+            SELECT COUNT(*) AS touched FROM update
+            "#,
+            table = table,
+            touched_column = touched_column,
+            start_page = start_page,
+            end_page = end_page,
+        );
+        let touched: i64 = db
+            .query(&query)?
             .first()
-            .and_then(|row| row.get("last_value"));
+            .map(|row| row.get("touched"))
+            .unwrap_or_default();
+        db.count_rows_backfilled(touched as u64)?;
+        touched_total += touched as u64;
+
+        start_page = end_page;
+    }
+
+    report_backfill_finished(db)
+}
+
+// Rows found to violate a constraint when checking the existing rows of a table
+pub struct Violations {
+    pub count: i64,
+    pub sample: Vec<String>,
+}
+
+impl Violations {
+    // An error for the violating rows, where `describe` puts the sample into the
+    // message. The sample contains data from the table, so it's left out when the
+    // error is recorded.
+    pub fn error(&self, describe: impl Fn(&str) -> String) -> anyhow::Error {
+        anyhow::Error::new(ViolationsError {
+            message: describe(&self.sample.join(", ")),
+            without_sample: describe("..."),
+        })
+    }
+}
 
-        if last_value.is_none() {
-            break;
+#[derive(Debug)]
+pub(crate) struct ViolationsError {
+    message: String,
+    pub(crate) without_sample: String,
+}
+
+impl std::fmt::Display for ViolationsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ViolationsError {}
+
+// Validate a constraint which was created as NOT VALID. `VALIDATE CONSTRAINT` scans
+// the whole table in a single statement which gives no feedback, so the table is
+// first checked in ranges of pages for rows matching `violation`, reporting progress
+// and any violations found along the way. The constraint is only validated if no
+// violating rows were found, otherwise the number of violating rows is returned with
+// a sample of them described by `sample`. In both expressions the table is aliased
+// as `t`.
+pub fn validate_constraint(
+    db: &mut dyn Conn,
+    table: &str,
+    constraint: &str,
+    violation: &str,
+    sample: &str,
+) -> anyhow::Result<Option<Violations>> {
+    const PAGES_PER_BATCH: i64 = 1000;
+    const SAMPLE_SIZE: usize = 5;
+
+    let pages: i64 = db
+        .query(&format!(
+            "
+            SELECT pg_relation_size('public.\"{table}\"') / current_setting('block_size')::BIGINT AS pages
+            ",
+            table = table,
+        ))?
+        .first()
+        .map(|row| row.get("pages"))
+        .unwrap_or_default();
+
+    let progress = db.progress();
+    progress.validation_started(pages as u64);
+
+    let mut violations = Violations {
+        count: 0,
+        sample: Vec::new(),
+    };
+    let mut start_page = 0;
+    while start_page < pages {
+        db.throttle()?;
+
+        let end_page = start_page + PAGES_PER_BATCH;
+        let row = db
+            .query(&format!(
+                r#"
+                SELECT
+                    COUNT(*) AS violations,
+                    (ARRAY_AGG({sample}))[1:{sample_size}] AS sample
+                FROM public."{table}" t
+                WHERE ctid >= '({start_page},0)'::tid AND ctid < '({end_page},0)'::tid
+                AND ({violation})
+                "#,
+                sample = sample,
+                sample_size = SAMPLE_SIZE,
+                table = table,
+                start_page = start_page,
+                end_page = end_page,
+                violation = violation,
+            ))
+            .with_context(|| format!("failed to check rows for constraint \"{}\"", constraint))?
+            .pop();
+
+        if let Some(row) = row {
+            let count: i64 = row.get("violations");
+            let sample: Option<Vec<String>> = row.get("sample");
+
+            violations.count += count;
+            for value in sample.unwrap_or_default() {
+                if violations.sample.len() < SAMPLE_SIZE {
+                    violations.sample.push(value);
+                }
+            }
+            progress.pages_validated((end_page.min(pages) - start_page) as u64, count as u64);
         }
 
-        cursor = last_value
+        start_page = end_page;
+    }
+
+    if violations.count > 0 {
+        return Ok(Some(violations));
     }
 
+    db.run(&format!(
+        r#"
+        ALTER TABLE "{table}"
+        VALIDATE CONSTRAINT "{constraint}"
+        "#,
+        table = table,
+        constraint = constraint,
+    ))
+    .with_context(|| format!("failed to validate constraint \"{}\"", constraint))?;
+
+    Ok(None)
+}
+
+// Validate a temporary `CHECK (column IS NOT NULL)` constraint, failing with a sample
+// of the offending rows if there are any NULL values
+pub fn validate_not_null_constraint(
+    db: &mut dyn Conn,
+    table: &str,
+    constraint: &str,
+    column: &str,
+) -> anyhow::Result<()> {
+    let violations = validate_constraint(
+        db,
+        table,
+        constraint,
+        &format!(r#"t."{}" IS NULL"#, column),
+        "LEFT(t::TEXT, 100)",
+    )?;
+
+    match violations {
+        None => Ok(()),
+        Some(violations) => Err(violations.error(|sample| {
+            format!(
+                "{} rows in \"{}\" have a NULL value in a column which must not be NULL, for example: {}",
+                violations.count, table, sample,
+            )
+        })),
+    }
+}
+
+// Copy data which is about to be dropped into a new table in the archive schema, so
+// it can still be recovered if the migration turns out to be a mistake. The table is
+// named after the archived table or column and the time it was archived. Only
+// `columns` are copied, or every column if none are given. Nothing is archived if the
+// table or columns no longer exist, which happens when a completion is retried after
+// the data was dropped.
+pub fn archive_rows(
+    db: &mut dyn Conn,
+    ctx: &MigrationContext,
+    table: &str,
+    columns: Option<&[String]>,
+    name: &str,
+) -> anyhow::Result<()> {
+    let select = match columns {
+        Some(columns) => columns
+            .iter()
+            .map(|column| format!("\"{}\"", column))
+            .collect::<Vec<_>>()
+            .join(", "),
+        None => "*".to_string(),
+    };
+    let columns = columns.unwrap_or_default();
+
+    let exists: bool = db
+        .query_with_params(
+            "
+            SELECT COUNT(*) = $2 AS exists
+            FROM pg_attribute
+            WHERE attrelid = to_regclass(format('public.%I', $1::TEXT))
+                AND attname = ANY($3)
+                AND NOT attisdropped
+            HAVING to_regclass(format('public.%I', $1::TEXT)) IS NOT NULL
+            ",
+            &[&table, &(columns.len() as i64), &columns],
+        )
+        .context("failed to check data to archive")?
+        .first()
+        .map(|row| row.get("exists"))
+        .unwrap_or(false);
+    if !exists {
+        return Ok(());
+    }
+
+    let archived_at: String = db
+        .query("SELECT to_char(now(), 'YYYYMMDD_HH24MISS') AS archived_at")?
+        .first()
+        .map(|row| row.get("archived_at"))
+        .unwrap_or_default();
+    let archive_schema = ctx.namespace.archive_schema();
+
+    db.run(&format!(
+        r#"
+        CREATE SCHEMA IF NOT EXISTS {archive_schema};
+        CREATE TABLE {archive_schema}."{name}_{archived_at}" AS
+        SELECT {select} FROM public."{table}";
+        "#,
+        archive_schema = archive_schema,
+        name = name,
+        archived_at = archived_at,
+        select = select,
+        table = table,
+    ))
+    .with_context(|| format!("failed to archive \"{}\"", name))?;
+
     Ok(())
 }
 
-fn get_primary_key_columns_for_table(
+pub fn get_primary_key_columns_for_table(
     db: &mut dyn Conn,
     table: &str,
 ) -> anyhow::Result<Vec<String>> {
@@ -166,11 +913,12 @@ fn get_primary_key_columns_for_table(
     let primary_key_columns: Vec<String> = db
         .query(&format!(
             "
-            SELECT a.attname AS column_name
+            SELECT a.attname::TEXT AS column_name
             FROM   pg_index i
             JOIN   pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
-            WHERE  i.indrelid = '{table}'::regclass
-            AND    i.indisprimary;
+            WHERE  i.indrelid = 'public.\"{table}\"'::regclass
+            AND    i.indisprimary
+            ORDER BY array_position(i.indkey::SMALLINT[], a.attnum)
             ",
             table = table
         ))?
@@ -181,11 +929,36 @@ fn get_primary_key_columns_for_table(
     Ok(primary_key_columns)
 }
 
+// Trigger functions run with the search_path of whoever wrote to the table, which for
+// deployments is the schema of a migration. Expressions from migrations should be able
+// to use functions, operators and types from extensions like PostGIS, which are
+// usually installed in `public`, the same as during backfills. `public` is added to
+// the end of the search_path while they run, keeping the first schema as is since
+// `is_new_schema` and nested triggers depend on it.
+pub fn with_public_in_search_path(statements: &str) -> String {
+    format!(
+        "
+        DECLARE
+            __reshape_search_path TEXT := current_setting('search_path');
+        BEGIN
+            PERFORM set_config('search_path', concat_ws(', ', NULLIF(__reshape_search_path, ''), 'public'), TRUE);
+            {statements}
+            PERFORM set_config('search_path', __reshape_search_path, TRUE);
+        END;
+        "
+    )
+}
+
 pub struct Index {
     pub name: String,
     pub oid: u32,
     pub unique: bool,
     pub index_type: String,
+    // Whether the index backs a primary key, unique or exclusion constraint, in which
+    // case it can't be dropped without dropping the constraint
+    pub backs_constraint: bool,
+    // Whether the table's replica identity is set to this index
+    pub replica_identity: bool,
 }
 
 pub fn get_indices_for_column(
@@ -200,7 +973,15 @@ pub fn get_indices_for_column(
                 i.relname AS name,
                 i.oid AS oid,
                 ix.indisunique AS unique,
-                am.amname AS type
+                am.amname AS type,
+                EXISTS (
+                    SELECT 1
+                    FROM pg_constraint con
+                    WHERE con.conindid = i.oid
+                        AND con.conrelid = t.oid
+                        AND con.contype IN ('p', 'u', 'x')
+                ) AS backs_constraint,
+                ix.indisreplident AS replica_identity
             FROM pg_index ix
             JOIN pg_class t ON t.oid = ix.indrelid
             JOIN pg_class i ON i.oid = ix.indexrelid
@@ -221,55 +1002,640 @@ pub fn get_indices_for_column(
             oid: row.get("oid"),
             unique: row.get("unique"),
             index_type: row.get("type"),
+            backs_constraint: row.get("backs_constraint"),
+            replica_identity: row.get("replica_identity"),
         })
         .collect();
 
     Ok(indices)
 }
 
-pub fn get_index_columns(db: &mut dyn Conn, index_name: &str) -> anyhow::Result<Vec<String>> {
-    // Get all columns which are part of the index in order
-    let (table_oid, column_nums) = db
-        .query(&format!(
+// `REINDEX CONCURRENTLY` builds a copy of each index, suffixed with `_ccnew`, and swaps
+// it in at the end. If it's interrupted, the copies are left behind as invalid indices
+// which still slow down writes, so they are dropped before retrying and when aborting.
+pub fn drop_invalid_reindex_copies(db: &mut dyn Conn, table: &str) -> anyhow::Result<()> {
+    let copies: Vec<String> = db
+        .query_with_params(
+            r"
+            SELECT i.relname::TEXT AS name
+            FROM pg_index ix
+            JOIN pg_class i ON i.oid = ix.indexrelid
+            WHERE ix.indrelid = to_regclass(format('public.%I', $1::TEXT))
+                AND NOT ix.indisvalid
+                AND i.relname ~ '_ccnew[0-9]*$'
+            ",
+            &[&table],
+        )
+        .context("failed to get invalid indices")?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+
+    for copy in copies {
+        db.run(&format!(r#"DROP INDEX CONCURRENTLY IF EXISTS "{}""#, copy))
+            .with_context(|| format!("failed to drop invalid index {}", copy))?;
+    }
+
+    Ok(())
+}
+
+// `CREATE INDEX CONCURRENTLY` leaves an invalid index behind if it fails or is
+// interrupted. `IF NOT EXISTS` would keep it, and it would never be used by queries
+// while still slowing down writes, so it's dropped to be built again instead.
+pub fn drop_invalid_index(db: &mut dyn Conn, name: &str) -> anyhow::Result<()> {
+    let is_invalid = !db
+        .query_with_params(
             "
-            SELECT t.oid AS table_oid, ix.indkey::INTEGER[] AS columns
+            SELECT 1
             FROM pg_index ix
-            JOIN pg_class t ON t.oid = ix.indrelid
             JOIN pg_class i ON i.oid = ix.indexrelid
-            WHERE
-	            i.relname = '{index_name}'
+            WHERE i.relname = $1
+                AND i.relnamespace = 'public'::regnamespace
+                AND NOT ix.indisvalid
             ",
-            index_name = index_name,
-        ))?
+            &[&name],
+        )
+        .context("failed to check for invalid index")?
+        .is_empty();
+    if !is_invalid {
+        return Ok(());
+    }
+
+    print!(
+        "{} ",
+        format!("(rebuilding invalid index \"{}\")", name).yellow()
+    );
+    db.run(&format!(r#"DROP INDEX CONCURRENTLY IF EXISTS "{}""#, name))
+        .with_context(|| format!("failed to drop invalid index {}", name))
+}
+
+pub struct IndexColumn {
+    pub name: String,
+    // Columns added with `INCLUDE`, which aren't part of the key
+    pub included: bool,
+    // Qualified name of the operator class if it isn't the default for the type, like
+    // `public.gin_trgm_ops`. Indices using an access method which has no default
+    // operator class for the type can't be recreated without it.
+    pub operator_class: Option<String>,
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+impl IndexColumn {
+    // The column as written in `CREATE INDEX`, under a different name
+    pub fn definition(&self, name: &str) -> String {
+        let mut parts = vec![format!("\"{}\"", name)];
+        if let Some(operator_class) = &self.operator_class {
+            parts.push(operator_class.to_string());
+        }
+
+        // Ascending indices put nulls last by default and descending ones first
+        if self.descending {
+            parts.push("DESC".to_string());
+        }
+        if self.nulls_first != self.descending {
+            parts.push(if self.nulls_first {
+                "NULLS FIRST".to_string()
+            } else {
+                "NULLS LAST".to_string()
+            });
+        }
+
+        parts.join(" ")
+    }
+}
+
+// All columns which are part of the index in order, followed by any included columns
+pub fn get_index_columns(db: &mut dyn Conn, index_oid: u32) -> anyhow::Result<Vec<IndexColumn>> {
+    db.query_with_params(
+        "
+        SELECT
+            a.attname::TEXT AS name,
+            k.position > ix.indnkeyatts AS included,
+            CASE WHEN NOT opc.opcdefault
+                THEN format('%I.%I', opcn.nspname, opc.opcname)
+            END AS operator_class,
+            COALESCE(ix.indoption[k.position - 1] & 1 = 1, FALSE) AS descending,
+            COALESCE(ix.indoption[k.position - 1] & 2 = 2, FALSE) AS nulls_first
+        FROM pg_index ix
+        CROSS JOIN LATERAL unnest(ix.indkey::SMALLINT[]) WITH ORDINALITY AS k(attnum, position)
+        LEFT JOIN pg_attribute a ON a.attrelid = ix.indrelid AND a.attnum = k.attnum
+        LEFT JOIN pg_opclass opc
+            ON opc.oid = ix.indclass[k.position - 1] AND k.position <= ix.indnkeyatts
+        LEFT JOIN pg_namespace opcn ON opcn.oid = opc.opcnamespace
+        WHERE ix.indexrelid = $1
+        ORDER BY k.position
+        ",
+        &[&index_oid],
+    )
+    .context("failed to get columns for index")?
+    .iter()
+    .map(|row| {
+        Ok(IndexColumn {
+            // Expressions in indices have no column
+            name: row
+                .get::<_, Option<String>>("name")
+                .ok_or_else(|| anyhow!("indices on expressions aren't supported"))?,
+            included: row.get("included"),
+            operator_class: row.get("operator_class"),
+            descending: row.get("descending"),
+            nulls_first: row.get("nulls_first"),
+        })
+    })
+    .collect()
+}
+
+// Columns with `auto_updated_at` enabled are maintained by a trigger which sets the
+// column to the current time whenever a row is updated. Unlike all other triggers
+// created by Reshape, these are permanent and must not use the temporary `__reshape`
+// prefix. The trigger is named after the column as trigger names only need to be
+// unique per table, whereas the function name also includes the table.
+const AUTO_UPDATED_AT_PREFIX: &str = "reshape_auto_updated_at";
+
+// Set for the session while Reshape backfills a table. Backfills touch every row, which
+// would otherwise bump the auto_updated_at column of all of them.
+const BACKFILL_SETTING: &str = "reshape.backfill";
+
+pub fn auto_updated_at_trigger_name(column: &str) -> String {
+    format!("{}_{}", AUTO_UPDATED_AT_PREFIX, column)
+}
+
+// Columns of a table which are maintained by an auto_updated_at trigger
+pub fn get_auto_updated_at_columns(db: &mut dyn Conn, table: &str) -> anyhow::Result<Vec<String>> {
+    let prefix = auto_updated_at_trigger_name("");
+    let columns = db
+        .query(&format!(
+            "
+            SELECT tgname::TEXT AS trigger_name
+            FROM pg_trigger
+            WHERE tgrelid = 'public.\"{table}\"'::regclass AND NOT tgisinternal
+            ",
+            table = table,
+        ))
+        .context("failed to get auto_updated_at triggers")?
+        .iter()
+        .filter_map(|row| {
+            row.get::<'_, _, String>("trigger_name")
+                .strip_prefix(&prefix)
+                .map(|column| column.to_string())
+        })
+        .collect();
+
+    Ok(columns)
+}
+
+pub fn create_auto_updated_at_trigger(
+    db: &mut dyn Conn,
+    table: &str,
+    column: &str,
+    real_column: &str,
+) -> anyhow::Result<()> {
+    let query = format!(
+        r#"
+        CREATE OR REPLACE FUNCTION "{function_name}"()
+        RETURNS TRIGGER AS $$
+        BEGIN
+            IF COALESCE(current_setting('{backfill_setting}', TRUE), '') <> 'TRUE' THEN
+                NEW."{real_column}" = NOW();
+            END IF;
+            RETURN NEW;
+        END
+        $$ language 'plpgsql';
+
+        DROP TRIGGER IF EXISTS "{trigger_name}" ON "{table}";
+        CREATE TRIGGER "{trigger_name}" BEFORE UPDATE ON "{table}" FOR EACH ROW EXECUTE PROCEDURE "{function_name}"();
+        "#,
+        function_name = format!("{}_{}_{}", AUTO_UPDATED_AT_PREFIX, table, column),
+        trigger_name = auto_updated_at_trigger_name(column),
+        table = table,
+        real_column = real_column,
+        backfill_setting = BACKFILL_SETTING,
+    );
+    db.run(&query)
+        .context("failed to create auto_updated_at trigger")?;
+
+    Ok(())
+}
+
+// Drop the auto_updated_at triggers and their functions for a table, optionally only
+// for a single column. The functions are looked up through the triggers as the
+// function name contains the table name at the time of creation, which might have
+// changed since.
+pub fn drop_auto_updated_at_triggers(
+    db: &mut dyn Conn,
+    table: &str,
+    column: Option<&str>,
+) -> anyhow::Result<()> {
+    let trigger_filter = match column {
+        Some(column) => format!("= '{}'", auto_updated_at_trigger_name(column)),
+        None => format!("LIKE '{}\\_%'", AUTO_UPDATED_AT_PREFIX),
+    };
+
+    let functions: Vec<String> = db
+        .query(&format!(
+            "
+            SELECT p.proname AS function_name
+            FROM pg_catalog.pg_trigger t
+            JOIN pg_catalog.pg_class c ON c.oid = t.tgrelid
+            JOIN pg_catalog.pg_proc p ON p.oid = t.tgfoid
+            WHERE c.relname = '{table}'
+            AND c.relnamespace = 'public'::regnamespace
+            AND t.tgname {trigger_filter}
+            ",
+            table = table,
+            trigger_filter = trigger_filter,
+        ))
+        .context("failed to get auto_updated_at triggers")?
+        .iter()
+        .map(|row| row.get("function_name"))
+        .collect();
+
+    for function in functions {
+        db.run(&format!(
+            r#"
+            DROP FUNCTION IF EXISTS "{function}" CASCADE
+            "#,
+            function = function,
+        ))
+        .context("failed to drop auto_updated_at trigger")?;
+    }
+
+    Ok(())
+}
+
+// Actions with `change_log` enabled record every row written to their table while the
+// migration is in progress in `reshape.change_log`. This lets external systems which
+// mirror the data, like caches or search indices, reindex affected rows after
+// completion. Rows are identified by their primary key, using the column names of
+// the schema the migration is applied to.
+pub fn create_change_log_trigger(
+    db: &mut dyn Conn,
+    ctx: &MigrationContext,
+    trigger_name: &str,
+    table: &Table,
+) -> anyhow::Result<()> {
+    let primary_key_columns = get_primary_key_columns_for_table(db, &table.real_name)
+        .context("failed to get primary key columns")?;
+    if primary_key_columns.is_empty() {
+        return Err(anyhow!(
+            "change_log requires table \"{}\" to have a primary key",
+            table.name
+        ));
+    }
+
+    let key_parts: Vec<String> = primary_key_columns
+        .iter()
+        .map(|real_name| {
+            let alias = table
+                .columns
+                .iter()
+                .find(|column| &column.real_name == real_name)
+                .map(|column| column.name.as_str())
+                .unwrap_or(real_name);
+            format!("{}, changed_row.\"{real_name}\"", quote_literal(alias))
+        })
+        .collect();
+
+    let query = format!(
+        r#"
+        CREATE OR REPLACE FUNCTION "{trigger_name}"()
+        RETURNS TRIGGER AS $$
+        DECLARE
+            changed_row RECORD;
+        BEGIN
+            IF TG_OP = 'DELETE' THEN
+                changed_row := OLD;
+            ELSE
+                changed_row := NEW;
+            END IF;
+
+            INSERT INTO {state_schema}.change_log (migration, table_name, operation, row_key)
+            VALUES ({migration}, {table}, TG_OP, jsonb_build_object({key}));
+
+            RETURN NULL;
+        END
+        $$ language 'plpgsql';
+
+        DROP TRIGGER IF EXISTS "{trigger_name}" ON "{table_real}";
+        CREATE TRIGGER "{trigger_name}" AFTER INSERT OR UPDATE OR DELETE ON "{table_real}" FOR EACH ROW EXECUTE PROCEDURE "{trigger_name}"();
+        "#,
+        trigger_name = trigger_name,
+        state_schema = ctx.namespace.state_schema(),
+        migration = quote_literal(&ctx.migration_name),
+        table = quote_literal(&table.name),
+        table_real = table.real_name,
+        key = key_parts.join(", "),
+    );
+    db.run(&query)
+        .context("failed to create change log trigger")?;
+
+    Ok(())
+}
+
+// Quote text as a string literal, for values which can't be passed as parameters,
+// like those inside a function body
+pub fn quote_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+// Entries are kept after completion for consumers to process. When a migration is
+// aborted, the entries are discarded along with the rest of its changes.
+pub fn drop_change_log_trigger(
+    db: &mut dyn Conn,
+    ctx: &MigrationContext,
+    trigger_name: &str,
+    table: &str,
+    clear_entries: bool,
+) -> anyhow::Result<()> {
+    db.run(&format!(
+        r#"
+        DROP FUNCTION IF EXISTS "{trigger_name}" CASCADE
+        "#,
+        trigger_name = trigger_name,
+    ))
+    .context("failed to drop change log trigger")?;
+
+    if clear_entries {
+        db.query_with_params(
+            &format!(
+                "
+                DELETE FROM {}.change_log
+                WHERE migration = $1 AND table_name = $2
+                ",
+                ctx.namespace.state_schema(),
+            ),
+            &[&ctx.migration_name, &table],
+        )
+        .context("failed to clear change log")?;
+    }
+
+    Ok(())
+}
+
+// A publication which lists a table explicitly, along with the column list and row
+// filter it was added with. Tables can also be published through `FOR ALL TABLES`
+// publications, which always include every column and don't need to be updated.
+pub struct PublishedTable {
+    pub publication: String,
+    pub columns: Option<Vec<String>>,
+    pub row_filter: Option<String>,
+}
+
+pub fn get_publications_for_table(
+    db: &mut dyn Conn,
+    table: &str,
+) -> anyhow::Result<Vec<PublishedTable>> {
+    // Column lists and row filters were added in Postgres 15
+    let version_num: i32 = db
+        .query("SELECT current_setting('server_version_num')::INTEGER AS version_num")?
         .first()
-        .map(|row| {
-            (
-                row.get::<'_, _, u32>("table_oid"),
-                row.get::<'_, _, Vec<i32>>("columns"),
-            )
+        .map(|row| row.get("version_num"))
+        .unwrap_or_default();
+    let details = if version_num >= 150000 {
+        "
+        (
+            SELECT array_agg(attname::TEXT ORDER BY attnum)
+            FROM pg_attribute
+            WHERE attrelid = rel.prrelid AND attnum = ANY(rel.prattrs)
+        ) AS columns,
+        pg_get_expr(rel.prqual, rel.prrelid) AS row_filter
+        "
+    } else {
+        "NULL::TEXT[] AS columns, NULL::TEXT AS row_filter"
+    };
+
+    let publications = db
+        .query(&format!(
+            "
+            SELECT pub.pubname::TEXT AS publication, {details}
+            FROM pg_publication_rel rel
+            JOIN pg_publication pub ON pub.oid = rel.prpubid
+            WHERE rel.prrelid = 'public.\"{table}\"'::regclass
+            ",
+            details = details,
+            table = table,
+        ))
+        .context("failed to get publications")?
+        .iter()
+        .map(|row| PublishedTable {
+            publication: row.get("publication"),
+            columns: row.get("columns"),
+            row_filter: row.get("row_filter"),
         })
-        .ok_or_else(|| anyhow!("failed to get columns for index"))?;
+        .collect();
+
+    Ok(publications)
+}
 
-    // Get the name of each of the columns, still in order
-    column_nums
+// Add a table to a publication, replacing any existing column list and row filter.
+// The table is removed and added in a single statement so no changes are missed.
+pub fn set_publication_table(
+    db: &mut dyn Conn,
+    publication: &str,
+    table: &str,
+    columns: Option<&[String]>,
+    row_filter: Option<&str>,
+) -> anyhow::Result<()> {
+    let is_member = get_publications_for_table(db, table)?
         .iter()
-        .map(|column_num| -> anyhow::Result<String> {
-            let name: String = db
-                .query(&format!(
-                    "
-                    SELECT attname AS name
-                    FROM pg_attribute
-                    WHERE attrelid = {table_oid}
-                        AND attnum = {column_num};
-                    ",
-                    table_oid = table_oid,
-                    column_num = column_num,
-                ))?
-                .first()
-                .map(|row| row.get("name"))
-                .ok_or_else(|| anyhow!("expected to find column"))?;
-
-            Ok(name)
+        .any(|published| published.publication == publication);
+    let drop_existing = if is_member {
+        format!(
+            r#"ALTER PUBLICATION "{publication}" DROP TABLE public."{table}";"#,
+            publication = publication,
+            table = table,
+        )
+    } else {
+        "".to_string()
+    };
+
+    let columns = columns
+        .map(|columns| {
+            let columns: Vec<String> = columns
+                .iter()
+                .map(|column| format!("\"{}\"", column))
+                .collect();
+            format!("({})", columns.join(", "))
         })
-        .collect::<anyhow::Result<Vec<String>>>()
+        .unwrap_or_default();
+    let row_filter = row_filter
+        .map(|row_filter| format!("WHERE ({})", row_filter))
+        .unwrap_or_default();
+
+    db.run(&format!(
+        r#"
+        {drop_existing}
+        ALTER PUBLICATION "{publication}" ADD TABLE public."{table}" {columns} {row_filter};
+        "#,
+    ))
+    .with_context(|| {
+        format!(
+            "failed to add table {} to publication {}",
+            table, publication
+        )
+    })
+}
+
+// Dropping a column which is part of a publication's column list either fails or,
+// with CASCADE, silently removes the whole table from the publication, which breaks
+// replication. The column is replaced in the column lists before it's dropped,
+// either with the column which takes its place or by leaving it out.
+pub fn replace_publication_column(
+    db: &mut dyn Conn,
+    table: &str,
+    column: &str,
+    replacement: Option<&str>,
+) -> anyhow::Result<()> {
+    for published in get_publications_for_table(db, table)? {
+        let columns = match &published.columns {
+            Some(columns) if columns.iter().any(|existing| existing == column) => columns,
+            _ => continue,
+        };
+
+        let columns: Vec<String> = columns
+            .iter()
+            .filter_map(|existing| match replacement {
+                _ if existing != column => Some(existing.to_string()),
+                Some(replacement) => Some(replacement.to_string()),
+                None => None,
+            })
+            .collect();
+
+        // A column list can't be empty, so the table is no longer published at all
+        if columns.is_empty() {
+            db.run(&format!(
+                r#"ALTER PUBLICATION "{publication}" DROP TABLE public."{table}""#,
+                publication = published.publication,
+                table = table,
+            ))
+            .context("failed to remove table from publication")?;
+            continue;
+        }
+
+        set_publication_table(
+            db,
+            &published.publication,
+            table,
+            Some(&columns),
+            published.row_filter.as_deref(),
+        )?;
+    }
+
+    Ok(())
+}
+
+// Set the comment on an object, like `COLUMN "users"."name"`, or remove it if the
+// comment is None. The statement is built by the server so the comment is quoted
+// correctly whatever it contains.
+pub fn set_comment(db: &mut dyn Conn, object: &str, comment: Option<&str>) -> anyhow::Result<()> {
+    let statement: String = db
+        .query_with_params(
+            "SELECT format('COMMENT ON %s IS %L', $1::TEXT, $2::TEXT) AS statement",
+            &[&object, &comment],
+        )
+        .context("failed to build comment statement")?
+        .first()
+        .map(|row| row.get("statement"))
+        .ok_or_else(|| anyhow!("failed to build comment statement"))?;
+
+    db.run(&statement)
+        .with_context(|| format!("failed to set comment on {}", object))
+}
+
+// Comments belong to a column, so they must be copied over when a column is replaced
+pub fn copy_column_comment(
+    db: &mut dyn Conn,
+    table: &str,
+    from_column: &str,
+    to_column: &str,
+) -> anyhow::Result<()> {
+    let comment: Option<String> = db
+        .query_with_params(
+            "
+            SELECT col_description(attrelid, attnum) AS comment
+            FROM pg_attribute
+            WHERE attrelid = to_regclass(format('public.%I', $1::TEXT)) AND attname = $2
+            ",
+            &[&table, &from_column],
+        )
+        .context("failed to get column comment")?
+        .first()
+        .and_then(|row| row.get("comment"));
+
+    if let Some(comment) = comment {
+        set_comment(
+            db,
+            &format!(r#"COLUMN "{}"."{}""#, table, to_column),
+            Some(&comment),
+        )?;
+    }
+
+    Ok(())
+}
+
+// Constraints which are recreated as NOT VALID while holding a lock and validated once
+// it's been released. They are recorded in the state schema in the same transaction,
+// so if Reshape crashes before validating them, completing again validates them even
+// though everything else has already been done.
+pub fn defer_validation(
+    db: &mut dyn Conn,
+    ctx: &MigrationContext,
+    constraints: &[(String, String)],
+) -> anyhow::Result<()> {
+    if constraints.is_empty() {
+        return Ok(());
+    }
+
+    let constraints = serde_json::to_value(constraints)?;
+    db.query_with_params(
+        &format!(
+            "INSERT INTO {}.data (key, value) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET value = $2",
+            ctx.namespace.state_schema()
+        ),
+        &[&deferred_validation_key(ctx), &constraints],
+    )
+    .context("failed to record constraints to validate")?;
+
+    Ok(())
+}
+
+// Validate the constraints recorded using `defer_validation`. Validating only takes a
+// lock which allows writes to continue during the scan.
+pub fn validate_deferred(db: &mut dyn Conn, ctx: &MigrationContext) -> anyhow::Result<()> {
+    let key = deferred_validation_key(ctx);
+    let query = format!(
+        "SELECT value FROM {}.data WHERE key = $1",
+        ctx.namespace.state_schema()
+    );
+    let constraints: Vec<(String, String)> = match db
+        .query_with_params(&query, &[&key])
+        .context("failed to get constraints to validate")?
+        .first()
+    {
+        Some(row) => serde_json::from_value(row.get("value"))?,
+        None => return Ok(()),
+    };
+
+    for (table, constraint) in constraints {
+        db.run(&format!(
+            r#"ALTER TABLE {} VALIDATE CONSTRAINT "{}""#,
+            table, constraint
+        ))
+        .with_context(|| format!("failed to validate constraint \"{}\"", constraint))?;
+    }
+
+    db.query_with_params(
+        &format!(
+            "DELETE FROM {}.data WHERE key = $1",
+            ctx.namespace.state_schema()
+        ),
+        &[&key],
+    )
+    .context("failed to remove validated constraints")?;
+
+    Ok(())
+}
+
+fn deferred_validation_key(ctx: &MigrationContext) -> String {
+    format!("deferred_validation{}", ctx.prefix())
 }
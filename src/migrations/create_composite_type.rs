@@ -0,0 +1,150 @@
+use super::{Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct CreateCompositeType {
+    pub name: String,
+    pub attributes: Vec<CompositeAttribute>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct CompositeAttribute {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub data_type: String,
+}
+
+impl CreateCompositeType {
+    pub fn new(name: impl Into<String>) -> Self {
+        CreateCompositeType {
+            name: name.into(),
+            attributes: vec![],
+        }
+    }
+
+    pub fn with_attribute(mut self, attribute: CompositeAttribute) -> Self {
+        self.attributes.push(attribute);
+        self
+    }
+}
+
+impl CompositeAttribute {
+    pub fn new(name: impl Into<String>, data_type: impl Into<String>) -> Self {
+        CompositeAttribute {
+            name: name.into(),
+            data_type: data_type.into(),
+        }
+    }
+}
+
+#[typetag::serde(name = "create_composite_type")]
+impl Action for CreateCompositeType {
+    fn describe(&self) -> String {
+        format!("Creating composite type \"{}\"", self.name)
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
+        // Check if type already exists. CREATE TYPE doesn't have
+        // a IF NOT EXISTS option so we have to do it manually.
+        if composite_type_exists(db, &self.name)? {
+            return Ok(());
+        }
+
+        let attributes_def: Vec<String> = self
+            .attributes
+            .iter()
+            .map(|attribute| format!("\"{}\" {}", attribute.name, attribute.data_type))
+            .collect();
+
+        db.run(&format!(
+            r#"
+            CREATE TYPE "{name}" AS ({attributes})
+            "#,
+            name = self.name,
+            attributes = attributes_def.join(", "),
+        ))
+        .context("failed to create composite type")?;
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        Ok(None)
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        db.run(&format!(
+            r#"
+            DROP TYPE IF EXISTS "{name}"
+            "#,
+            name = self.name,
+        ))
+        .context("failed to drop composite type")?;
+
+        Ok(())
+    }
+
+    fn can_run_atomically(&self) -> bool {
+        true
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        let mut names = vec![self.name.as_str()];
+        names.extend(
+            self.attributes
+                .iter()
+                .map(|attribute| attribute.name.as_str()),
+        );
+        names
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.create_type(&self.name);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::None
+    }
+}
+
+// Every table also has a composite type representing its rows so we
+// must make sure to only look for standalone composite types.
+pub fn composite_type_exists(db: &mut dyn Conn, name: &str) -> anyhow::Result<bool> {
+    let exists = !db
+        .query(&format!(
+            "
+            SELECT t.typname
+            FROM pg_catalog.pg_type t
+            JOIN pg_catalog.pg_class c ON c.oid = t.typrelid
+            WHERE t.typtype = 'c'
+            AND c.relkind = 'c'
+            AND t.typnamespace = 'public'::regnamespace
+            AND t.typname = '{name}'
+            ",
+            name = name,
+        ))
+        .context("failed to check for composite type")?
+        .is_empty();
+    Ok(exists)
+}
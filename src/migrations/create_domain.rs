@@ -0,0 +1,160 @@
+use super::{Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct CreateDomain {
+    pub name: String,
+    // The key can't be `type` as that's used to identify the action
+    pub data_type: String,
+    pub default: Option<String>,
+    #[serde(default)]
+    pub constraints: Vec<DomainConstraint>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct DomainConstraint {
+    pub name: String,
+    pub check: String,
+}
+
+impl CreateDomain {
+    pub fn new(name: impl Into<String>, data_type: impl Into<String>) -> Self {
+        CreateDomain {
+            name: name.into(),
+            data_type: data_type.into(),
+            default: None,
+            constraints: vec![],
+        }
+    }
+
+    pub fn with_default(mut self, default: impl Into<String>) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+
+    pub fn with_constraint(mut self, constraint: DomainConstraint) -> Self {
+        self.constraints.push(constraint);
+        self
+    }
+}
+
+impl DomainConstraint {
+    pub fn new(name: impl Into<String>, check: impl Into<String>) -> Self {
+        DomainConstraint {
+            name: name.into(),
+            check: check.into(),
+        }
+    }
+}
+
+#[typetag::serde(name = "create_domain")]
+impl Action for CreateDomain {
+    fn describe(&self) -> String {
+        format!("Creating domain \"{}\"", self.name)
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
+        // Check if domain already exists. CREATE DOMAIN doesn't have
+        // a IF NOT EXISTS option so we have to do it manually.
+        let domain_exists = !db
+            .query(&format!(
+                "
+                SELECT typname
+                FROM pg_catalog.pg_type
+                WHERE typtype = 'd'
+                AND typnamespace = 'public'::regnamespace
+                AND typname = '{name}'
+                ",
+                name = self.name,
+            ))?
+            .is_empty();
+        if domain_exists {
+            return Ok(());
+        }
+
+        let mut definition_parts = vec![self.data_type.to_string()];
+
+        if let Some(default) = &self.default {
+            definition_parts.push("DEFAULT".to_string());
+            definition_parts.push(default.to_string());
+        }
+
+        for constraint in &self.constraints {
+            definition_parts.push(format!(
+                r#"CONSTRAINT "{name}" CHECK ({check})"#,
+                name = constraint.name,
+                check = constraint.check,
+            ));
+        }
+
+        db.run(&format!(
+            r#"
+            CREATE DOMAIN "{name}" AS {definition}
+            "#,
+            name = self.name,
+            definition = definition_parts.join(" "),
+        ))
+        .context("failed to create domain")?;
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        Ok(None)
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        db.run(&format!(
+            r#"
+            DROP DOMAIN IF EXISTS "{name}"
+            "#,
+            name = self.name,
+        ))
+        .context("failed to drop domain")?;
+
+        Ok(())
+    }
+
+    fn can_run_atomically(&self) -> bool {
+        true
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        let mut names = vec![self.name.as_str()];
+        names.extend(
+            self.constraints
+                .iter()
+                .map(|constraint| constraint.name.as_str()),
+        );
+        names
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.create_type(&self.name);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::None
+    }
+}
@@ -1,17 +1,32 @@
-use super::{Action, MigrationContext};
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
     schema::Schema,
 };
 use anyhow::Context;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct CreateEnum {
     pub name: String,
     pub values: Vec<String>,
 }
 
+impl CreateEnum {
+    pub fn new(
+        name: impl Into<String>,
+        values: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        CreateEnum {
+            name: name.into(),
+            values: common::into_strings(values),
+        }
+    }
+}
+
 #[typetag::serde(name = "create_enum")]
 impl Action for CreateEnum {
     fn describe(&self) -> String {
@@ -80,4 +95,20 @@ impl Action for CreateEnum {
 
         Ok(())
     }
+
+    fn can_run_atomically(&self) -> bool {
+        true
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        vec![&self.name]
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.create_type(&self.name);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::None
+    }
 }
@@ -1,15 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use super::{common::ForeignKey, Action, Column, MigrationContext};
+use super::{
+    common::ForeignKey, Action, Column, LockLevel, LogicalSchema, MigrationContext,
+    VersionRequirement,
+};
 use crate::{
-    db::{Conn, Transaction},
+    db::{self, Conn, Transaction},
     migrations::common,
     schema::Schema,
 };
-use anyhow::Context;
+use anyhow::{bail, Context};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+const UPDATED_AT_COLUMN: &str = "updated_at";
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct CreateTable {
     pub name: String,
     pub columns: Vec<Column>,
@@ -18,17 +26,107 @@ pub struct CreateTable {
     #[serde(default)]
     pub foreign_keys: Vec<ForeignKey>,
 
+    // Sets of columns which must be unique together
+    #[serde(default)]
+    pub unique: Vec<Vec<String>>,
+
+    // Table-level check constraints, each being an SQL expression
+    #[serde(default)]
+    pub checks: Vec<String>,
+
     pub up: Option<Transformation>,
+
+    // Maintain the "updated_at" column with a trigger which sets it to the current
+    // time whenever a row is updated
+    #[serde(default)]
+    pub auto_updated_at: bool,
+
+    // Create the table as UNLOGGED to speed up large backfills. Writes to unlogged
+    // tables skip the WAL, so the table is switched to LOGGED when the migration is
+    // completed.
+    #[serde(default)]
+    pub unlogged: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[schemars(rename = "TableUpTransformation")]
 pub struct Transformation {
     table: String,
-    values: HashMap<String, String>,
+    // Kept sorted so the action always serializes the same way, which the hash of
+    // applied migrations relies on
+    values: BTreeMap<String, String>,
     upsert_constraint: Option<String>,
 }
 
 impl CreateTable {
+    pub fn new(
+        name: impl Into<String>,
+        primary_key: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        CreateTable {
+            name: name.into(),
+            columns: vec![],
+            primary_key: common::into_strings(primary_key),
+            foreign_keys: vec![],
+            unique: vec![],
+            checks: vec![],
+            up: None,
+            auto_updated_at: false,
+            unlogged: false,
+        }
+    }
+
+    pub fn with_column(mut self, column: Column) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    pub fn with_foreign_key(mut self, foreign_key: ForeignKey) -> Self {
+        self.foreign_keys.push(foreign_key);
+        self
+    }
+
+    pub fn with_unique(mut self, columns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.unique.push(common::into_strings(columns));
+        self
+    }
+
+    pub fn with_check(mut self, check: impl Into<String>) -> Self {
+        self.checks.push(check.into());
+        self
+    }
+
+    // Backfill the table from an existing one. `values` maps columns in the new
+    // table to SQL expressions which can reference the columns of `table`.
+    pub fn with_up(mut self, table: impl Into<String>, values: HashMap<String, String>) -> Self {
+        self.up = Some(Transformation {
+            table: table.into(),
+            values: values.into_iter().collect(),
+            upsert_constraint: None,
+        });
+        self
+    }
+
+    // Constraint used to detect conflicts when backfilling, defaults to the primary
+    // key. Has no effect unless `with_up` has been called first.
+    pub fn with_upsert_constraint(mut self, constraint: impl Into<String>) -> Self {
+        if let Some(up) = &mut self.up {
+            up.upsert_constraint = Some(constraint.into());
+        }
+        self
+    }
+
+    pub fn with_auto_updated_at(mut self, auto_updated_at: bool) -> Self {
+        self.auto_updated_at = auto_updated_at;
+        self
+    }
+
+    pub fn with_unlogged(mut self, unlogged: bool) -> Self {
+        self.unlogged = unlogged;
+        self
+    }
+
     fn trigger_name(&self, ctx: &MigrationContext) -> String {
         format!("{}_create_table_{}", ctx.prefix(), self.name)
     }
@@ -46,6 +144,19 @@ impl Action for CreateTable {
         db: &mut dyn Conn,
         schema: &Schema,
     ) -> anyhow::Result<()> {
+        if self.auto_updated_at
+            && !self
+                .columns
+                .iter()
+                .any(|column| column.name == UPDATED_AT_COLUMN)
+        {
+            bail!(
+                "auto_updated_at requires table \"{}\" to have an \"{}\" column",
+                self.name,
+                UPDATED_AT_COLUMN,
+            );
+        }
+
         let mut definition_rows: Vec<String> = self
             .columns
             .iter()
@@ -66,6 +177,14 @@ impl Action for CreateTable {
                     parts.push(generated.to_string());
                 }
 
+                if column.unique {
+                    parts.push("UNIQUE".to_string());
+                }
+
+                if let Some(check) = &column.check {
+                    parts.push(format!("CHECK ({})", check));
+                }
+
                 parts.join(" ")
             })
             .collect();
@@ -79,6 +198,16 @@ impl Action for CreateTable {
             .join(", ");
         definition_rows.push(format!("PRIMARY KEY ({})", primary_key_columns));
 
+        for columns in &self.unique {
+            // Add quotes around all column names
+            let columns: Vec<String> = columns.iter().map(|col| format!("\"{}\"", col)).collect();
+            definition_rows.push(format!("UNIQUE ({})", columns.join(", ")));
+        }
+
+        for check in &self.checks {
+            definition_rows.push(format!("CHECK ({})", check));
+        }
+
         for foreign_key in &self.foreign_keys {
             // Add quotes around all column names
             let columns: Vec<String> = foreign_key
@@ -105,90 +234,118 @@ impl Action for CreateTable {
 
         let query = &format!(
             r#"
-            CREATE TABLE "{name}" (
+            {create} "{name}" (
                 {definition}
             )
             "#,
+            create = if self.unlogged {
+                "CREATE UNLOGGED TABLE"
+            } else {
+                "CREATE TABLE"
+            },
             name = self.name,
             definition = definition_rows.join(",\n"),
         );
-        db.run(query).context("failed to create table")?;
-
-        if let Some(Transformation {
-            table: from_table,
-            values,
-            upsert_constraint,
-        }) = &self.up
-        {
-            let from_table = schema.get_table(db, &from_table)?;
-
-            let declarations: Vec<String> = from_table
-                .columns
-                .iter()
-                .map(|column| {
-                    format!(
-                        "{alias} public.{table}.{real_name}%TYPE := NEW.{real_name};",
-                        table = from_table.real_name,
-                        alias = column.name,
-                        real_name = column.real_name,
-                    )
-                })
-                .collect();
-
-            let (insert_columns, insert_values): (Vec<&str>, Vec<&str>) = values
-                .iter()
-                .map(|(k, v)| -> (&str, &str) { (k, v) }) // Force &String to &str
-                .unzip();
-
-            let update_set: Vec<String> = values
-                .iter()
-                .map(|(field, value)| format!("\"{field}\" = {value}"))
-                .collect();
-
-            // Constraint to check for conflicts. Defaults to the primary key constraint.
-            let conflict_constraint_name = match upsert_constraint {
-                Some(custom_constraint) => custom_constraint.clone(),
-                _ => format!("{table}_pkey", table = self.name),
-            };
-
-            // Add triggers to fill in values as they are inserted/updated
-            let query = format!(
-                r#"
-                    CREATE OR REPLACE FUNCTION {trigger_name}()
-                    RETURNS TRIGGER AS $$
-                    #variable_conflict use_variable
-                    BEGIN
-                        IF NOT reshape.is_new_schema() THEN
-                            DECLARE
-                                {declarations}
-                            BEGIN
-                                INSERT INTO public."{changed_table_real}" ({columns})
-                                VALUES ({values})
-                                ON CONFLICT ON CONSTRAINT "{conflict_constraint_name}"
-                                DO UPDATE SET
-                                    {updates};
-                            END;
-                        END IF;
-                        RETURN NEW;
-                    END
-                    $$ language 'plpgsql';
-
-                    DROP TRIGGER IF EXISTS "{trigger_name}" ON "{from_table_real}";
-                    CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{from_table_real}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
-                    "#,
-                changed_table_real = self.name,
-                from_table_real = from_table.real_name,
-                trigger_name = self.trigger_name(ctx),
-                declarations = declarations.join("\n"),
-                columns = insert_columns.join(", "),
-                values = insert_values.join(", "),
-                updates = update_set.join(",\n"),
-            );
-            db.run(&query).context("failed to create up trigger")?;
-
-            // Backfill values in batches by touching the from table
-            common::batch_touch_rows(db, &from_table.real_name, None)
-                .context("failed to batch update existing rows")?;
+        // The table is created together with its triggers, so it's never left without
+        // the trigger filling it in
+        db::atomically(db, |db| {
+            db.run(query).context("failed to create table")?;
+
+            if self.auto_updated_at {
+                common::create_auto_updated_at_trigger(
+                    db,
+                    &self.name,
+                    UPDATED_AT_COLUMN,
+                    UPDATED_AT_COLUMN,
+                )?;
+            }
+
+            if let Some(Transformation {
+                table: from_table,
+                values,
+                upsert_constraint,
+            }) = &self.up
+            {
+                let from_table = schema.get_table(db, from_table)?;
+
+                let declarations: Vec<String> = from_table
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        format!(
+                            "{alias} public.{table}.{real_name}%TYPE := NEW.{real_name};",
+                            table = from_table.real_name,
+                            alias = column.name,
+                            real_name = column.real_name,
+                        )
+                    })
+                    .collect();
+
+                let (insert_columns, insert_values): (Vec<&str>, Vec<&str>) = values
+                    .iter()
+                    .map(|(k, v)| -> (&str, &str) { (k, v) }) // Force &String to &str
+                    .unzip();
+
+                let update_set: Vec<String> = values
+                    .iter()
+                    .map(|(field, value)| format!("\"{field}\" = {value}"))
+                    .collect();
+
+                // Constraint to check for conflicts. Defaults to the primary key constraint.
+                let conflict_constraint_name = match upsert_constraint {
+                    Some(custom_constraint) => custom_constraint.clone(),
+                    _ => format!("{table}_pkey", table = self.name),
+                };
+
+                // Add triggers to fill in values as they are inserted/updated. These run
+                // after the row has been written, as a BEFORE INSERT trigger also fires for
+                // upserts which end up updating the conflicting row or doing nothing.
+                let state_schema = ctx.namespace.state_schema();
+                let query = format!(
+                    r#"
+                        CREATE OR REPLACE FUNCTION {trigger_name}()
+                        RETURNS TRIGGER AS $$
+                        #variable_conflict use_variable
+                        BEGIN
+                            IF {state_schema}.is_old_schema() THEN
+                                DECLARE
+                                    {declarations}
+                                BEGIN
+                                    {upsert}
+                                END;
+                            END IF;
+                            RETURN NULL;
+                        END
+                        $$ language 'plpgsql';
+
+                        DROP TRIGGER IF EXISTS "{trigger_name}" ON "{from_table_real}";
+                        CREATE TRIGGER "{trigger_name}" AFTER UPDATE OR INSERT ON "{from_table_real}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
+                        "#,
+                    from_table_real = from_table.real_name,
+                    trigger_name = self.trigger_name(ctx),
+                    declarations = declarations.join("\n"),
+                    upsert = common::with_public_in_search_path(&format!(
+                        r#"
+                        INSERT INTO public."{changed_table_real}" ({columns})
+                        VALUES ({values})
+                        ON CONFLICT ON CONSTRAINT "{conflict_constraint_name}"
+                        DO UPDATE SET
+                            {updates};
+                        "#,
+                        changed_table_real = self.name,
+                        columns = insert_columns.join(", "),
+                        values = insert_values.join(", "),
+                        updates = update_set.join(",\n"),
+                    )),
+                );
+                db.run(&query).context("failed to create up trigger")?;
+            }
+
+            Ok(())
+        })?;
+
+        if !ctx.backfill_deferred() {
+            self.backfill(ctx, db, schema)?;
         }
 
         Ok(())
@@ -199,6 +356,8 @@ impl Action for CreateTable {
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
         // Remove triggers and procedures
         let query = format!(
             r#"
@@ -206,9 +365,24 @@ impl Action for CreateTable {
             "#,
             trigger_name = self.trigger_name(ctx),
         );
-        db.run(&query).context("failed to drop up trigger")?;
+        transaction
+            .run(&query)
+            .context("failed to drop up trigger")?;
+
+        // Make the table durable now that the backfill is done. This rewrites the
+        // whole table, and is a no-op if it has already been switched.
+        if self.unlogged {
+            transaction
+                .run(&format!(
+                    r#"
+                    ALTER TABLE "{name}" SET LOGGED
+                    "#,
+                    name = self.name,
+                ))
+                .context("failed to set table as logged")?;
+        }
 
-        Ok(None)
+        Ok(Some(transaction))
     }
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
@@ -223,6 +397,8 @@ impl Action for CreateTable {
         );
         db.run(&query).context("failed to drop up trigger")?;
 
+        common::drop_auto_updated_at_triggers(db, &self.name, None)?;
+
         db.run(&format!(
             r#"
             DROP TABLE IF EXISTS "{name}"
@@ -233,4 +409,100 @@ impl Action for CreateTable {
 
         Ok(())
     }
+
+    fn version_requirements(&self) -> Vec<VersionRequirement> {
+        let has_generated_column = self.columns.iter().any(|column| {
+            column
+                .generated
+                .as_ref()
+                .map(|generated| generated.trim_start().starts_with("ALWAYS AS ("))
+                .unwrap_or(false)
+        });
+
+        if has_generated_column {
+            vec![VersionRequirement::new(120000, "generated columns")]
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Backfill values in batches by touching the from table
+    fn backfill(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        if let Some(Transformation {
+            table: from_table, ..
+        }) = &self.up
+        {
+            let from_table = schema.get_table(db, from_table)?;
+            common::batch_touch_rows(db, &from_table.real_name, None, ctx.backfill_window())
+                .context("failed to batch update existing rows")?;
+        }
+
+        Ok(())
+    }
+
+    fn can_defer_backfill(&self) -> bool {
+        self.up.is_some()
+    }
+
+    fn can_run_atomically(&self) -> bool {
+        // Filling in the table from another one requires a backfill
+        self.up.is_none()
+    }
+
+    fn rewritten_tables(&self) -> Vec<&str> {
+        if self.up.is_some() {
+            vec![self.name.as_str()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        let mut names = vec![self.name.as_str()];
+        names.extend(self.columns.iter().map(|column| column.name.as_str()));
+        names
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| column.name.to_string())
+            .collect();
+        schema.create_table(&self.name, columns);
+
+        schema.require_columns(&self.name, &self.primary_key);
+        for columns in &self.unique {
+            schema.require_columns(&self.name, columns);
+        }
+        for foreign_key in &self.foreign_keys {
+            schema.require_columns(&self.name, &foreign_key.columns);
+            schema.require_columns(
+                &foreign_key.referenced_table,
+                &foreign_key.referenced_columns,
+            );
+        }
+
+        if let Some(up) = &self.up {
+            schema.require_table(&up.table);
+            schema.require_columns(&self.name, up.values.keys());
+        }
+    }
+
+    // Only the new table is locked, unless it's switched to LOGGED on completion or
+    // triggers and foreign keys are added to other tables
+    fn max_lock_level(&self) -> LockLevel {
+        if self.unlogged {
+            LockLevel::AccessExclusive
+        } else if self.up.is_some() || !self.foreign_keys.is_empty() {
+            LockLevel::ShareRowExclusive
+        } else {
+            LockLevel::None
+        }
+    }
 }
@@ -3,9 +3,12 @@ use crate::{
     db::{Conn, Transaction},
     schema::Schema,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct Custom {
     #[serde(default)]
     pub start: Option<String>,
@@ -17,6 +20,27 @@ pub struct Custom {
     pub abort: Option<String>,
 }
 
+impl Custom {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_start(mut self, query: impl Into<String>) -> Self {
+        self.start = Some(query.into());
+        self
+    }
+
+    pub fn with_complete(mut self, query: impl Into<String>) -> Self {
+        self.complete = Some(query.into());
+        self
+    }
+
+    pub fn with_abort(mut self, query: impl Into<String>) -> Self {
+        self.abort = Some(query.into());
+        self
+    }
+}
+
 #[typetag::serde(name = "custom")]
 impl Action for Custom {
     fn describe(&self) -> String {
@@ -42,6 +66,8 @@ impl Action for Custom {
         _ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // The query is run as is, as it might contain statements which can't be run in
+        // a transaction
         if let Some(complete_query) = &self.complete {
             db.run(complete_query)?;
         }
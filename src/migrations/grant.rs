@@ -0,0 +1,190 @@
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::{self, Schema},
+};
+use anyhow::{anyhow, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Grant {
+    pub table: String,
+
+    // Role to grant the privileges to, or PUBLIC for all roles
+    pub role: String,
+
+    // Privileges like SELECT and INSERT, or ALL for every privilege
+    pub privileges: Vec<String>,
+}
+
+impl Grant {
+    pub fn new(
+        table: impl Into<String>,
+        role: impl Into<String>,
+        privileges: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Grant {
+            table: table.into(),
+            role: role.into(),
+            privileges: common::into_strings(privileges),
+        }
+    }
+}
+
+const TABLE_PRIVILEGES: [&str; 7] = [
+    "SELECT",
+    "INSERT",
+    "UPDATE",
+    "DELETE",
+    "TRUNCATE",
+    "REFERENCES",
+    "TRIGGER",
+];
+
+// Privileges in the form they are listed in by Postgres, with ALL expanded
+pub(crate) fn normalize_privileges(privileges: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for privilege in privileges {
+        let privilege = privilege.trim().to_uppercase();
+        let expanded = if privilege == "ALL" || privilege == "ALL PRIVILEGES" {
+            TABLE_PRIVILEGES.iter().map(|p| p.to_string()).collect()
+        } else {
+            vec![privilege]
+        };
+
+        for privilege in expanded {
+            if !normalized.contains(&privilege) {
+                normalized.push(privilege);
+            }
+        }
+    }
+
+    normalized
+}
+
+pub(crate) fn normalize_role(role: &str) -> String {
+    if role.eq_ignore_ascii_case("public") {
+        "PUBLIC".to_string()
+    } else {
+        role.to_string()
+    }
+}
+
+// Check everything up front so the privileges can be changed when completing
+// without failing halfway
+pub(crate) fn check_privilege_change(
+    db: &mut dyn Conn,
+    schema: &Schema,
+    table: &str,
+    role: &str,
+    privileges: &[String],
+) -> anyhow::Result<()> {
+    if privileges.is_empty() {
+        return Err(anyhow!("no privileges given"));
+    }
+
+    for privilege in normalize_privileges(privileges) {
+        if !TABLE_PRIVILEGES.contains(&privilege.as_str()) {
+            return Err(anyhow!(
+                "unknown privilege \"{}\", expected one of {} or ALL",
+                privilege,
+                TABLE_PRIVILEGES.join(", ")
+            ));
+        }
+    }
+
+    let role = normalize_role(role);
+    if role != "PUBLIC" {
+        let exists = !db
+            .query_with_params("SELECT 1 FROM pg_roles WHERE rolname = $1", &[&role])
+            .context("failed to check for role")?
+            .is_empty();
+        if !exists {
+            return Err(anyhow!("no role \"{}\" exists", role));
+        }
+    }
+
+    let real_name = schema.get_table(db, table)?.real_name;
+    let exists = !db
+        .query_with_params(
+            "SELECT 1 WHERE to_regclass(format('public.%I', $1::TEXT)) IS NOT NULL",
+            &[&real_name],
+        )
+        .context("failed to check for table")?
+        .is_empty();
+    if !exists {
+        return Err(anyhow!("no table \"{}\" exists", table));
+    }
+
+    Ok(())
+}
+
+#[typetag::serde(name = "grant")]
+impl Action for Grant {
+    fn describe(&self) -> String {
+        format!(
+            "Granting {} on \"{}\" to \"{}\"",
+            self.privileges.join(", "),
+            self.table,
+            self.role
+        )
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        // The privileges are only granted on the table once the migration is
+        // completed. Until then, only the views for the new schema have them.
+        check_privilege_change(db, schema, &self.table, &self.role, &self.privileges)
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        transaction
+            .run(&format!(
+                r#"GRANT {privileges} ON TABLE "{table}" TO {grantee}"#,
+                privileges = normalize_privileges(&self.privileges).join(", "),
+                table = self.table,
+                grantee = schema::quote_grantee(&normalize_role(&self.role)),
+            ))
+            .context("failed to grant privileges")?;
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, schema: &mut Schema) {
+        schema.change_table(&self.table, |table_changes| {
+            table_changes.grant(
+                &normalize_role(&self.role),
+                &normalize_privileges(&self.privileges),
+            );
+        });
+    }
+
+    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn can_run_atomically(&self) -> bool {
+        true
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_table(&self.table);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::None
+    }
+}
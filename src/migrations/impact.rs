@@ -0,0 +1,130 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::{LogicalSchema, Migration, Object};
+
+// How disruptive an action is, ordered from least to most disruptive so the impact
+// of a whole migration is that of its most disruptive action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ImpactKind {
+    // Adds or changes objects without rewriting or dropping any existing data
+    Additive,
+    // Rewrites existing rows, which takes time and I/O proportional to the table size
+    Rewriting,
+    // Drops data once the migration is completed
+    Destructive,
+}
+
+// The strongest table lock an action takes at any point while it's being started or
+// completed, however briefly. These are Postgres' lock modes, ordered from weakest to
+// strongest. Even a brief ACCESS EXCLUSIVE lock blocks all reads of the table while it
+// waits for running queries to finish.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum LockLevel {
+    // Doesn't lock any existing tables, like creating a type
+    None,
+    AccessShare,
+    RowShare,
+    // Taken when writing rows, doesn't block reads or other writes
+    RowExclusive,
+    // Taken when building indices concurrently, blocks other schema changes but not
+    // reads or writes
+    ShareUpdateExclusive,
+    // Blocks writes
+    Share,
+    // Taken when creating triggers and foreign keys, blocks writes
+    ShareRowExclusive,
+    Exclusive,
+    // Taken by most ALTER TABLE commands, blocks reads and writes
+    AccessExclusive,
+}
+
+impl fmt::Display for LockLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LockLevel::None => "no lock",
+            LockLevel::AccessShare => "ACCESS SHARE",
+            LockLevel::RowShare => "ROW SHARE",
+            LockLevel::RowExclusive => "ROW EXCLUSIVE",
+            LockLevel::ShareUpdateExclusive => "SHARE UPDATE EXCLUSIVE",
+            LockLevel::Share => "SHARE",
+            LockLevel::ShareRowExclusive => "SHARE ROW EXCLUSIVE",
+            LockLevel::Exclusive => "EXCLUSIVE",
+            LockLevel::AccessExclusive => "ACCESS EXCLUSIVE",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// The objects an action touches and how, see `Migration::impact`
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ActionImpact {
+    pub action_type: String,
+    pub description: String,
+    pub kind: ImpactKind,
+    pub lock_level: LockLevel,
+    pub objects: Vec<Object>,
+}
+
+impl Migration {
+    // Report which tables, columns, indices and types each action references, creates
+    // or removes, whether it's additive, rewriting or destructive and the strongest
+    // lock it takes. This is worked out from the migration alone without connecting to
+    // a database, so it can be used to decide whether a migration needs extra approval
+    // before it's deployed.
+    //
+    // Custom actions run arbitrary SQL and are reported without any objects.
+    pub fn impact(&self) -> Vec<ActionImpact> {
+        let mut schema = LogicalSchema::default();
+
+        self.actions
+            .iter()
+            .map(|action| {
+                action.simulate(&mut schema);
+
+                let kind = if action.destructive() {
+                    ImpactKind::Destructive
+                } else if !action.rewritten_tables().is_empty() {
+                    ImpactKind::Rewriting
+                } else {
+                    ImpactKind::Additive
+                };
+
+                ActionImpact {
+                    action_type: action.typetag_name().to_string(),
+                    description: action.describe(),
+                    kind,
+                    lock_level: action.max_lock_level(),
+                    objects: schema.take_touched(),
+                }
+            })
+            .collect()
+    }
+
+    // The strongest lock taken by any of the actions
+    pub fn max_lock_level(&self) -> LockLevel {
+        self.actions
+            .iter()
+            .map(|action| action.max_lock_level())
+            .max()
+            .unwrap_or(LockLevel::None)
+    }
+
+    // Names of all tables touched by the migration, in the order they are first touched
+    pub fn affected_tables(&self) -> Vec<String> {
+        let mut tables: Vec<String> = Vec::new();
+        for object in self.impact().into_iter().flat_map(|impact| impact.objects) {
+            let table = match object {
+                Object::Table(table) | Object::Column(table, _) => table,
+                _ => continue,
+            };
+            if !tables.contains(&table) {
+                tables.push(table);
+            }
+        }
+        tables
+    }
+}
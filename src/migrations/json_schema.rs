@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use schemars::{gen::SchemaGenerator, gen::SchemaSettings, JsonSchema};
+use serde_json::json;
+
+use super::{
+    AddColumn, AddForeignKey, AddIndex, AddTableToPublication, AlterColumn, AlterCompositeType,
+    AlterDomain, Column, CreateCompositeType, CreateDomain, CreateEnum, CreateTable, Custom, Grant,
+    LoadData, MergeColumns, ReindexIndex, ReindexTable, RemoveColumn, RemoveCompositeType,
+    RemoveDomain, RemoveEnum, RemoveForeignKey, RemoveIndex, RemoveTable,
+    RemoveTableFromPublication, RenameTable, Revoke, RewriteTable, Seed, SetColumnComment,
+    SetReplicaIdentity, SetTableComment, SetTypeComment, SplitColumn, WidenPrimaryKey,
+    SCHEMA_VERSION,
+};
+
+// JSON Schema for migration files, generated from the same serde definitions which
+// are used to decode them. Editors can use it to validate and autocomplete both TOML
+// and JSON migrations without connecting to a database.
+pub fn migration_file_schema() -> serde_json::Value {
+    let mut gen = SchemaSettings::draft07().into_generator();
+
+    let mut create_table = action_schema::<CreateTable>(&mut gen, "create_table");
+    create_table["properties"]["include"] = json!({
+        "description": "Column groups to include in the table",
+        "type": "array",
+        "items": { "type": "string" },
+    });
+
+    let actions = vec![
+        create_table,
+        action_schema::<AlterColumn>(&mut gen, "alter_column"),
+        action_schema::<AddColumn>(&mut gen, "add_column"),
+        action_schema::<RemoveColumn>(&mut gen, "remove_column"),
+        action_schema::<AddIndex>(&mut gen, "add_index"),
+        action_schema::<RemoveIndex>(&mut gen, "remove_index"),
+        action_schema::<RemoveTable>(&mut gen, "remove_table"),
+        action_schema::<RenameTable>(&mut gen, "rename_table"),
+        action_schema::<RewriteTable>(&mut gen, "rewrite_table"),
+        action_schema::<CreateEnum>(&mut gen, "create_enum"),
+        action_schema::<RemoveEnum>(&mut gen, "remove_enum"),
+        action_schema::<CreateDomain>(&mut gen, "create_domain"),
+        action_schema::<AlterDomain>(&mut gen, "alter_domain"),
+        action_schema::<RemoveDomain>(&mut gen, "remove_domain"),
+        action_schema::<CreateCompositeType>(&mut gen, "create_composite_type"),
+        action_schema::<AlterCompositeType>(&mut gen, "alter_composite_type"),
+        action_schema::<RemoveCompositeType>(&mut gen, "remove_composite_type"),
+        action_schema::<Custom>(&mut gen, "custom"),
+        action_schema::<AddForeignKey>(&mut gen, "add_foreign_key"),
+        action_schema::<RemoveForeignKey>(&mut gen, "remove_foreign_key"),
+        action_schema::<AddTableToPublication>(&mut gen, "add_table_to_publication"),
+        action_schema::<RemoveTableFromPublication>(&mut gen, "remove_table_from_publication"),
+        action_schema::<SetReplicaIdentity>(&mut gen, "set_replica_identity"),
+        action_schema::<Grant>(&mut gen, "grant"),
+        action_schema::<Revoke>(&mut gen, "revoke"),
+        action_schema::<SetTableComment>(&mut gen, "set_table_comment"),
+        action_schema::<SetColumnComment>(&mut gen, "set_column_comment"),
+        action_schema::<SetTypeComment>(&mut gen, "set_type_comment"),
+        action_schema::<WidenPrimaryKey>(&mut gen, "widen_primary_key"),
+        action_schema::<SplitColumn>(&mut gen, "split_column"),
+        action_schema::<MergeColumns>(&mut gen, "merge_columns"),
+        action_schema::<ReindexIndex>(&mut gen, "reindex_index"),
+        action_schema::<ReindexTable>(&mut gen, "reindex_table"),
+        action_schema::<Seed>(&mut gen, "seed"),
+        action_schema::<LoadData>(&mut gen, "load_data"),
+    ];
+
+    let column_groups = gen.subschema_for::<HashMap<String, Vec<Column>>>();
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Reshape migration",
+        "type": "object",
+        "properties": {
+            "schema_version": {
+                "type": "integer",
+                "minimum": 1,
+                "maximum": SCHEMA_VERSION,
+            },
+            "name": { "type": "string" },
+            "description": { "type": "string" },
+            "atomic": { "type": "boolean" },
+            "column_groups": column_groups,
+            "actions": {
+                "type": "array",
+                "items": { "oneOf": actions },
+            },
+        },
+        "required": ["actions"],
+        "additionalProperties": false,
+        "definitions": gen.definitions(),
+    })
+}
+
+// Names of all action types, as used in the `type` field of migration files
+pub fn action_types() -> Vec<String> {
+    migration_file_schema()["properties"]["actions"]["items"]["oneOf"]
+        .as_array()
+        .map(|actions| {
+            actions
+                .iter()
+                .filter_map(|action| action["title"].as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Actions are decoded based on their `type` field, which isn't part of the action
+// structs themselves and has to be added to their schemas
+fn action_schema<T: JsonSchema>(gen: &mut SchemaGenerator, name: &str) -> serde_json::Value {
+    let mut schema = serde_json::to_value(T::json_schema(gen)).unwrap();
+    schema["title"] = json!(name);
+    schema["properties"]["type"] = json!({ "const": name });
+
+    let mut required = schema["required"].as_array().cloned().unwrap_or_default();
+    required.insert(0, json!("type"));
+    schema["required"] = json!(required);
+
+    schema
+}
@@ -0,0 +1,343 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+};
+
+use super::{Action, LockLevel, LogicalSchema, MigrationContext, VersionRequirement};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+    state::{self, SeedProgress},
+};
+use anyhow::{anyhow, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// LoadData streams a CSV or TSV file into a table using `COPY`, for filling tables
+// created by the migration with more data than fits in a `seed`. Unlike seeds, the
+// file isn't stored with the migration but read when the migration is started,
+// relative to the directory Reshape is run from. Use "-" to read from stdin.
+//
+// Rows are copied in batches which are committed along with the number of rows loaded
+// so far, so an interrupted load continues after the last committed batch.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct LoadData {
+    pub table: String,
+    pub file: String,
+
+    // Defaults to TSV for files ending in `.tsv` and CSV otherwise
+    #[serde(default)]
+    pub format: Option<DataFormat>,
+
+    // Whether the first line holds the column names, which are used if `columns`
+    // isn't set
+    #[serde(default = "default_header")]
+    pub header: bool,
+
+    #[serde(default)]
+    pub columns: Option<Vec<String>>,
+
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+
+    #[serde(default)]
+    pub on_error: LoadErrors,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DataFormat {
+    // CSV as read by `COPY ... WITH (FORMAT csv)`, where empty unquoted fields are NULL
+    Csv,
+    // Tab separated values in Postgres' text format, which uses `\N` for NULL
+    Tsv,
+}
+
+// What to do with rows which can't be loaded, like rows with values of the wrong type
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadErrors {
+    // Fail the migration. Batches which were committed before are kept, so the load
+    // continues after them once the file has been fixed.
+    #[default]
+    Stop,
+    // Skip the rows, which requires Postgres 17
+    Ignore,
+}
+
+fn default_header() -> bool {
+    true
+}
+
+fn default_batch_size() -> usize {
+    10000
+}
+
+impl LoadData {
+    pub fn new(table: impl Into<String>, file: impl Into<String>) -> Self {
+        LoadData {
+            table: table.into(),
+            file: file.into(),
+            format: None,
+            header: default_header(),
+            columns: None,
+            batch_size: default_batch_size(),
+            on_error: LoadErrors::default(),
+        }
+    }
+
+    pub fn with_format(mut self, format: DataFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn with_header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn with_columns<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.columns = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    pub fn with_on_error(mut self, on_error: LoadErrors) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    fn format(&self) -> DataFormat {
+        self.format.unwrap_or(if self.file.ends_with(".tsv") {
+            DataFormat::Tsv
+        } else {
+            DataFormat::Csv
+        })
+    }
+
+    fn open(&self) -> anyhow::Result<Box<dyn BufRead>> {
+        if self.file == "-" {
+            return Ok(Box::new(io::stdin().lock()));
+        }
+
+        let file =
+            File::open(&self.file).with_context(|| format!("failed to open {}", self.file))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+
+    // Column names from the header, which is the first record
+    fn parse_header(&self, record: &[u8]) -> anyhow::Result<Vec<String>> {
+        let record = std::str::from_utf8(record).context("header isn't valid UTF-8")?;
+        let record = record.trim_end_matches(['\r', '\n']);
+        match self.format() {
+            DataFormat::Csv => {
+                let header = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .from_reader(record.as_bytes())
+                    .records()
+                    .next()
+                    .transpose()
+                    .context("failed to read CSV header")?
+                    .ok_or_else(|| anyhow!("header is empty"))?;
+                Ok(header.iter().map(str::to_string).collect())
+            }
+            DataFormat::Tsv => Ok(record.split('\t').map(str::to_string).collect()),
+        }
+    }
+
+    fn copy_batch(
+        &self,
+        db: &mut dyn Conn,
+        query: &str,
+        batch: &[u8],
+        progress: &SeedProgress,
+    ) -> anyhow::Result<()> {
+        let mut transaction: Transaction =
+            db.transaction().context("failed to create transaction")?;
+        transaction
+            .copy_in(query, batch)
+            .with_context(|| format!("failed to load data into \"{}\"", self.table))?;
+        state::save_seed_progress(&mut transaction, Some(progress))?;
+        transaction.commit()
+    }
+}
+
+// Read one record, which is a line unless a quoted CSV field spans multiple lines.
+// The record is kept as is so `COPY` parses it exactly like it would the whole file.
+fn read_record(
+    reader: &mut dyn BufRead,
+    format: DataFormat,
+    record: &mut Vec<u8>,
+) -> anyhow::Result<bool> {
+    record.clear();
+    let mut quotes = 0;
+    loop {
+        let start = record.len();
+        if reader.read_until(b'\n', record)? == 0 {
+            return Ok(!record.is_empty());
+        }
+
+        if format == DataFormat::Csv {
+            quotes += record[start..].iter().filter(|byte| **byte == b'"').count();
+        }
+        if quotes % 2 == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+#[typetag::serde(name = "load_data")]
+impl Action for LoadData {
+    fn describe(&self) -> String {
+        format!("Loading data into \"{}\" from {}", self.table, self.file)
+    }
+
+    fn run(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        let format = self.format();
+        let mut reader = self.open()?;
+        let mut record = Vec::new();
+
+        let header = if self.header && read_record(&mut reader, format, &mut record)? {
+            Some(self.parse_header(&record)?)
+        } else {
+            None
+        };
+        let names = self
+            .columns
+            .clone()
+            .or(header)
+            .ok_or_else(|| anyhow!("columns must be set when the file has no header"))?;
+
+        let table = schema.get_table(db, &self.table)?;
+        let columns = names
+            .iter()
+            .map(|name| {
+                table
+                    .get_column(name)
+                    .map(|column| format!(r#""{}""#, column.real_name))
+                    .ok_or_else(|| {
+                        anyhow!("no column \"{}\" exists on table \"{}\"", name, self.table)
+                    })
+            })
+            .collect::<anyhow::Result<Vec<String>>>()?;
+
+        let mut options = vec![match format {
+            DataFormat::Csv => "FORMAT csv",
+            DataFormat::Tsv => "FORMAT text",
+        }];
+        if self.on_error == LoadErrors::Ignore {
+            options.push("ON_ERROR ignore");
+        }
+        let query = format!(
+            r#"COPY "{}" ({}) FROM STDIN WITH ({})"#,
+            table.real_name,
+            columns.join(", "),
+            options.join(", ")
+        );
+
+        // Continue after the rows loaded before an interruption
+        let mut progress = SeedProgress {
+            migration: ctx.migration_name.to_string(),
+            action_index: ctx.action_index,
+            rows: 0,
+        };
+        let loaded = state::load_seed_progress(db)?
+            .filter(|saved| {
+                saved.migration == progress.migration && saved.action_index == progress.action_index
+            })
+            .map(|saved| saved.rows)
+            .unwrap_or(0);
+        while progress.rows < loaded && read_record(&mut reader, format, &mut record)? {
+            progress.rows += 1;
+        }
+
+        let batch_size = self.batch_size.max(1);
+        let mut batch: Vec<u8> = Vec::new();
+        let mut batch_rows = 0;
+        while read_record(&mut reader, format, &mut record)? {
+            if !record.ends_with(b"\n") {
+                record.push(b'\n');
+            }
+            batch.extend_from_slice(&record);
+            batch_rows += 1;
+
+            if batch_rows == batch_size {
+                progress.rows += batch_rows;
+                self.copy_batch(db, &query, &batch, &progress)?;
+                batch.clear();
+                batch_rows = 0;
+            }
+        }
+
+        if batch_rows > 0 {
+            progress.rows += batch_rows;
+            self.copy_batch(db, &query, &batch, &progress)?;
+        }
+        state::save_seed_progress(db, None)
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        Ok(None)
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        // Progress of an interrupted load doesn't apply if the migration is started again
+        if let Some(progress) = state::load_seed_progress(db)? {
+            if progress.migration == ctx.migration_name && progress.action_index == ctx.action_index
+            {
+                state::save_seed_progress(db, None)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn version_requirements(&self) -> Vec<VersionRequirement> {
+        match self.on_error {
+            LoadErrors::Ignore => vec![VersionRequirement::new(
+                170000,
+                "skipping rows which can't be loaded",
+            )],
+            LoadErrors::Stop => Vec::new(),
+        }
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        match &self.columns {
+            Some(columns) => schema.require_columns(&self.table, columns),
+            None => {
+                schema.require_table(&self.table);
+            }
+        }
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::RowExclusive
+    }
+
+    // The rows are sent alongside the COPY statement, so they wouldn't be in the script
+    fn exportable(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,430 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use super::Migration;
+use crate::snapshot::SchemaSnapshot;
+
+// LogicalSchema tracks the tables, columns, indices and types created by migrations
+// without connecting to a database. Actions are applied to it one by one using
+// `Action::simulate`, which reports a problem whenever an action references an object
+// that doesn't exist or creates one that already does.
+//
+// Only objects created by the migrations themselves are known, so tables which existed
+// before Reshape was adopted will be reported as missing.
+//
+// Every object created or removed is recorded together with the action responsible,
+// which makes up a dependency graph between the actions of a migration. When an action
+// references an object which is only created by a later action in the same migration,
+// the problem includes which action has to be moved.
+#[derive(Default)]
+pub struct LogicalSchema {
+    tables: HashMap<String, Vec<String>>,
+    indices: HashMap<String, String>,
+    types: HashSet<String>,
+
+    migration: String,
+    action_index: usize,
+    action: String,
+    problems: Vec<Problem>,
+    changes: Vec<ObjectChange>,
+    // Objects referenced, created or removed since `take_touched` was last called
+    touched: Vec<Object>,
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Problem {
+    pub migration: String,
+    pub action_index: usize,
+    pub action: String,
+    pub message: String,
+
+    missing: Option<Object>,
+    // Index of a later action in the same migration which should come before this one
+    move_before: Option<usize>,
+}
+
+// An object in the schema which an action references, creates or removes
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Object {
+    Table(String),
+    Column(String, String),
+    Index(String),
+    Type(String),
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Table(table) => write!(f, "table \"{}\"", table),
+            Object::Column(table, column) => {
+                write!(f, "column \"{}\" on table \"{}\"", column, table)
+            }
+            Object::Index(index) => write!(f, "index \"{}\"", index),
+            Object::Type(name) => write!(f, "type \"{}\"", name),
+        }
+    }
+}
+
+struct ObjectChange {
+    migration: String,
+    action_index: usize,
+    action: String,
+    object: Object,
+    created: bool,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, action {} ({}): {}",
+            self.migration,
+            self.action_index + 1,
+            self.action,
+            self.message
+        )
+    }
+}
+
+// Check all migrations in order against the schema built up by the earlier ones
+pub fn check_migrations(migrations: &[Migration]) -> Vec<Problem> {
+    simulate_migrations(migrations).problems
+}
+
+// Check that no action in the migration depends on an object created by a later one.
+// Only the migration itself is simulated, so references to objects which already
+// exist aren't reported.
+pub(crate) fn check_action_order(migration: &Migration) -> Vec<Problem> {
+    simulate_migrations(std::slice::from_ref(migration))
+        .problems
+        .into_iter()
+        .filter(|problem| problem.move_before.is_some())
+        .collect()
+}
+
+pub(crate) fn simulate_migrations(migrations: &[Migration]) -> LogicalSchema {
+    let mut schema = LogicalSchema::default();
+
+    for migration in migrations {
+        for (action_index, action) in migration.actions.iter().enumerate() {
+            schema.migration = migration.name.to_string();
+            schema.action_index = action_index;
+            schema.action = action.describe();
+
+            action.simulate(&mut schema);
+        }
+    }
+
+    schema.suggest_order();
+    schema
+}
+
+impl LogicalSchema {
+    pub(crate) fn into_snapshot(self) -> SchemaSnapshot {
+        SchemaSnapshot {
+            tables: self
+                .tables
+                .into_iter()
+                .map(|(table, columns)| (table, columns.into_iter().collect()))
+                .collect(),
+            indices: self.indices.into_iter().collect(),
+            types: self.types.into_iter().collect(),
+        }
+    }
+
+    pub(crate) fn problem(&mut self, message: impl Into<String>) {
+        self.problems.push(Problem {
+            migration: self.migration.to_string(),
+            action_index: self.action_index,
+            action: self.action.to_string(),
+            message: message.into(),
+            missing: None,
+            move_before: None,
+        });
+    }
+
+    fn missing(&mut self, object: Object) {
+        match &object {
+            Object::Column(table, column) => self.problem(format!(
+                "column \"{}\" doesn't exist on table \"{}\"",
+                column, table
+            )),
+            object => self.problem(format!("{} doesn't exist", object)),
+        }
+        if let Some(problem) = self.problems.last_mut() {
+            problem.missing = Some(object);
+        }
+    }
+
+    fn touch(&mut self, object: Object) {
+        if !self.touched.contains(&object) {
+            self.touched.push(object);
+        }
+    }
+
+    pub(crate) fn take_touched(&mut self) -> Vec<Object> {
+        std::mem::take(&mut self.touched)
+    }
+
+    fn record(&mut self, object: Object, created: bool) {
+        self.touch(object.clone());
+        self.changes.push(ObjectChange {
+            migration: self.migration.to_string(),
+            action_index: self.action_index,
+            action: self.action.to_string(),
+            object,
+            created,
+        });
+    }
+
+    // Find the actions which create a missing object later in the same migration. An
+    // object which is removed before it's created again must have existed already, in
+    // which case the order is left alone.
+    fn suggest_order(&mut self) {
+        for problem in &mut self.problems {
+            let missing = match &problem.missing {
+                Some(missing) => missing,
+                None => continue,
+            };
+
+            let changes: Vec<&ObjectChange> = self
+                .changes
+                .iter()
+                .filter(|change| change.migration == problem.migration && &change.object == missing)
+                .collect();
+            let creator = changes.iter().find(|change| {
+                change.created
+                    && change.action_index > problem.action_index
+                    && !changes
+                        .iter()
+                        .any(|other| !other.created && other.action_index < change.action_index)
+            });
+
+            if let Some(creator) = creator {
+                problem.message = format!(
+                    "{}, it's created by action {} ({}) which must be moved before this one",
+                    problem.message,
+                    creator.action_index + 1,
+                    creator.action,
+                );
+                problem.move_before = Some(creator.action_index);
+            }
+        }
+    }
+
+    pub(crate) fn require_table(&mut self, table: &str) -> bool {
+        self.touch(Object::Table(table.to_string()));
+        if self.tables.contains_key(table) {
+            return true;
+        }
+
+        self.missing(Object::Table(table.to_string()));
+        false
+    }
+
+    pub(crate) fn require_columns<'a>(
+        &mut self,
+        table: &str,
+        columns: impl IntoIterator<Item = &'a String>,
+    ) {
+        let columns: Vec<&String> = columns.into_iter().collect();
+        let exists = self.require_table(table);
+        for column in &columns {
+            self.touch(Object::Column(table.to_string(), column.to_string()));
+        }
+        if !exists {
+            return;
+        }
+
+        let missing: Vec<&String> = columns
+            .into_iter()
+            .filter(|column| !self.tables[table].contains(column))
+            .collect();
+        for column in missing {
+            self.missing(Object::Column(table.to_string(), column.to_string()));
+        }
+    }
+
+    pub(crate) fn create_table(&mut self, table: &str, columns: Vec<String>) {
+        if self.tables.contains_key(table) {
+            self.problem(format!("table \"{}\" already exists", table));
+            return;
+        }
+
+        let mut seen = HashSet::new();
+        for column in &columns {
+            if !seen.insert(column) {
+                self.problem(format!(
+                    "column \"{}\" is defined multiple times on table \"{}\"",
+                    column, table
+                ));
+            }
+        }
+
+        self.record(Object::Table(table.to_string()), true);
+        for column in &columns {
+            self.record(Object::Column(table.to_string(), column.to_string()), true);
+        }
+        self.tables.insert(table.to_string(), columns);
+    }
+
+    // Removals and renames are recorded even for unknown objects, as those may have
+    // existed before the migrations
+    pub(crate) fn rename_table(&mut self, table: &str, new_name: &str) {
+        self.record(Object::Table(table.to_string()), false);
+        self.record(Object::Table(new_name.to_string()), true);
+        if !self.require_table(table) {
+            return;
+        }
+        if self.tables.contains_key(new_name) {
+            self.problem(format!("table \"{}\" already exists", new_name));
+            return;
+        }
+
+        let columns = self.tables.remove(table).unwrap();
+        self.tables.insert(new_name.to_string(), columns);
+        for index_table in self.indices.values_mut() {
+            if index_table == table {
+                *index_table = new_name.to_string();
+            }
+        }
+    }
+
+    pub(crate) fn remove_table(&mut self, table: &str) {
+        self.record(Object::Table(table.to_string()), false);
+        if self.require_table(table) {
+            self.tables.remove(table);
+            self.indices.retain(|_, index_table| index_table != table);
+        }
+    }
+
+    pub(crate) fn add_column(&mut self, table: &str, column: &str) {
+        if !self.require_table(table) {
+            return;
+        }
+
+        let columns = self.tables.get_mut(table).unwrap();
+        if columns.iter().any(|existing| existing == column) {
+            self.problem(format!(
+                "column \"{}\" already exists on table \"{}\"",
+                column, table
+            ));
+            return;
+        }
+        columns.push(column.to_string());
+        self.record(Object::Column(table.to_string(), column.to_string()), true);
+    }
+
+    // Problems with the existing column are reported by `require_columns`
+    pub(crate) fn rename_column(&mut self, table: &str, column: &str, new_name: &str) {
+        if column != new_name {
+            self.record(Object::Column(table.to_string(), column.to_string()), false);
+            self.record(
+                Object::Column(table.to_string(), new_name.to_string()),
+                true,
+            );
+        }
+
+        let columns = match self.tables.get_mut(table) {
+            Some(columns) if columns.iter().any(|existing| existing == column) => columns,
+            _ => return,
+        };
+
+        if column != new_name && columns.iter().any(|existing| existing == new_name) {
+            self.problem(format!(
+                "column \"{}\" already exists on table \"{}\"",
+                new_name, table
+            ));
+            return;
+        }
+
+        for existing in columns.iter_mut().filter(|existing| *existing == column) {
+            *existing = new_name.to_string();
+        }
+    }
+
+    pub(crate) fn remove_column(&mut self, table: &str, column: &str) {
+        self.record(Object::Column(table.to_string(), column.to_string()), false);
+        self.require_columns(table, [&column.to_string()]);
+        if let Some(columns) = self.tables.get_mut(table) {
+            columns.retain(|existing| existing != column);
+        }
+    }
+
+    // The new order must include every column of the table exactly once
+    pub(crate) fn reorder_columns(&mut self, table: &str, columns: &[String]) {
+        self.require_columns(table, columns);
+        let existing = match self.tables.get(table) {
+            Some(existing) => existing,
+            None => return,
+        };
+
+        let missing: Vec<String> = existing
+            .iter()
+            .filter(|column| !columns.contains(column))
+            .cloned()
+            .collect();
+        for column in missing {
+            self.problem(format!(
+                "column \"{}\" on table \"{}\" is missing from the new column order",
+                column, table
+            ));
+        }
+
+        if columns.iter().collect::<HashSet<_>>().len() != columns.len() {
+            self.problem(format!(
+                "the new column order for table \"{}\" includes columns multiple times",
+                table
+            ));
+        }
+
+        if let Some(existing) = self.tables.get_mut(table) {
+            existing.sort_by_key(|column| columns.iter().position(|name| name == column));
+        }
+    }
+
+    pub(crate) fn add_index(&mut self, table: &str, index: &str) {
+        self.touch(Object::Table(table.to_string()));
+        if self.indices.contains_key(index) {
+            self.problem(format!("index \"{}\" already exists", index));
+            return;
+        }
+        self.indices.insert(index.to_string(), table.to_string());
+        self.record(Object::Index(index.to_string()), true);
+    }
+
+    pub(crate) fn remove_index(&mut self, index: &str) {
+        self.record(Object::Index(index.to_string()), false);
+        match self.indices.remove(index) {
+            Some(table) => self.touch(Object::Table(table)),
+            None => self.missing(Object::Index(index.to_string())),
+        }
+    }
+
+    // Enums, domains and composite types share a namespace in Postgres
+    pub(crate) fn create_type(&mut self, name: &str) {
+        if !self.types.insert(name.to_string()) {
+            self.problem(format!("type \"{}\" already exists", name));
+            return;
+        }
+        self.record(Object::Type(name.to_string()), true);
+    }
+
+    pub(crate) fn require_type(&mut self, name: &str) {
+        self.touch(Object::Type(name.to_string()));
+        if !self.types.contains(name) {
+            self.missing(Object::Type(name.to_string()));
+        }
+    }
+
+    pub(crate) fn remove_type(&mut self, name: &str) {
+        self.record(Object::Type(name.to_string()), false);
+        if !self.types.remove(name) {
+            self.missing(Object::Type(name.to_string()));
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashSet;
+
+use super::{
+    common, Action, AddColumn, Backfill, Column, LockLevel, LogicalSchema, MigrationContext,
+    RemoveColumn, VersionRequirement,
+};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::{anyhow, bail, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Merges several columns into a new one, for example `first_name` and `last_name` into
+// `name`. It's the reverse of `split_column` and made up of the same steps: an
+// `add_column` for the new column, with `up` computing it from the originals, followed
+// by a `remove_column` for each original, with its `down` computing it from the new
+// column. Both schemas keep working while the migration is in progress.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct MergeColumns {
+    pub table: String,
+    pub columns: Vec<MergeSource>,
+    pub column: Column,
+
+    // SQL expression over the original columns which the new column is set to
+    pub up: String,
+
+    // Keep the original columns around in the new schema by setting this to false, in
+    // which case they're no longer kept in sync with the new column
+    #[serde(default = "default_remove_originals")]
+    pub remove_originals: bool,
+
+    // How existing rows are filled in, the same as for `add_column`
+    #[serde(default, deserialize_with = "common::deserialize_backfill")]
+    #[schemars(with = "common::BackfillSetting")]
+    pub backfill: Backfill,
+}
+
+// A column merged into the new one, where `down` is an SQL expression over the columns
+// of the new schema computing its value for the old schema
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct MergeSource {
+    pub name: String,
+    pub down: Option<String>,
+}
+
+fn default_remove_originals() -> bool {
+    true
+}
+
+impl MergeSource {
+    pub fn new(name: impl Into<String>) -> Self {
+        MergeSource {
+            name: name.into(),
+            down: None,
+        }
+    }
+
+    pub fn with_down(mut self, down: impl Into<String>) -> Self {
+        self.down = Some(down.into());
+        self
+    }
+}
+
+impl MergeColumns {
+    pub fn new(table: impl Into<String>, column: Column, up: impl Into<String>) -> Self {
+        MergeColumns {
+            table: table.into(),
+            columns: Vec::new(),
+            column,
+            up: up.into(),
+            remove_originals: default_remove_originals(),
+            backfill: Backfill::default(),
+        }
+    }
+
+    pub fn with_column(mut self, column: MergeSource) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    pub fn with_remove_originals(mut self, remove_originals: bool) -> Self {
+        self.remove_originals = remove_originals;
+        self
+    }
+
+    pub fn with_backfill(mut self, backfill: Backfill) -> Self {
+        self.backfill = backfill;
+        self
+    }
+
+    fn add_step(&self) -> AddColumn {
+        AddColumn::new(&self.table, self.column.clone())
+            .with_up(&self.up)
+            .with_backfill(self.backfill)
+    }
+
+    // One `remove_column` for each original, in order, after the new column has been
+    // added so their `down` can use it
+    fn remove_steps(&self) -> Vec<RemoveColumn> {
+        if !self.remove_originals {
+            return Vec::new();
+        }
+
+        self.columns
+            .iter()
+            .map(|source| {
+                let mut step = RemoveColumn::new(&self.table, &source.name);
+                if let Some(down) = &source.down {
+                    step = step.with_down(down);
+                }
+                step
+            })
+            .collect()
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.columns.len() < 2 {
+            problems.push("at least two columns to merge must be given".to_string());
+        }
+
+        let mut names = HashSet::new();
+        for source in &self.columns {
+            if source.name == self.column.name {
+                problems.push(format!(
+                    "column \"{}\" can't be merged into a column with the same name",
+                    source.name
+                ));
+            } else if !names.insert(source.name.as_str()) {
+                problems.push(format!(
+                    "column \"{}\" is merged more than once",
+                    source.name
+                ));
+            }
+
+            match (self.remove_originals, &source.down) {
+                (true, None) => problems.push(format!(
+                    "down must be set to keep \"{}\" up to date for the old schema",
+                    source.name
+                )),
+                (false, Some(_)) => problems.push(format!(
+                    "down for \"{}\" is only used when the original columns are removed",
+                    source.name
+                )),
+                _ => {}
+            }
+        }
+
+        problems
+    }
+}
+
+#[typetag::serde(name = "merge_columns")]
+impl Action for MergeColumns {
+    fn describe(&self) -> String {
+        let names: Vec<String> = self
+            .columns
+            .iter()
+            .map(|source| format!("\"{}\"", source.name))
+            .collect();
+        format!(
+            "Merging columns {} on \"{}\" into \"{}\"",
+            names.join(", "),
+            self.table,
+            self.column.name
+        )
+    }
+
+    fn run(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        if let Some(problem) = self.problems().into_iter().next() {
+            bail!(problem);
+        }
+
+        let table = schema.get_table(db, &self.table)?;
+        for source in &self.columns {
+            table.get_column(&source.name).ok_or_else(|| {
+                anyhow!("no column \"{}\" exists on \"{}\"", source.name, self.table)
+            })?;
+        }
+
+        // Later steps see the column added by the first one, the same as if they were
+        // separate actions
+        let mut schema = schema.clone();
+        let add_step = self.add_step();
+        let add_ctx = ctx.for_step(0);
+        add_step.run(&add_ctx, db, &schema)?;
+        add_step.update_schema(&add_ctx, &mut schema);
+
+        for (index, step) in self.remove_steps().iter().enumerate() {
+            let ctx = ctx.for_step(index + 1);
+            step.run(&ctx, db, &schema)?;
+            step.update_schema(&ctx, &mut schema);
+        }
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // The new column is completed first, unless it was completed before an
+        // interruption. Removing a column is safe to repeat, so all but the last of the
+        // originals are removed straight away and the last is removed in the
+        // transaction which the state is saved in.
+        let add_step = self.add_step();
+        let add_ctx = ctx.for_step(0);
+        if !add_step.is_completed(&add_ctx, db)? {
+            if let Some(transaction) = add_step.complete(&add_ctx, db)? {
+                transaction.commit()?;
+            }
+        }
+
+        let mut remove_steps = self.remove_steps();
+        let last_step = match remove_steps.pop() {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+
+        for (index, step) in remove_steps.iter().enumerate() {
+            if let Some(transaction) = step
+                .complete(&ctx.for_step(index + 1), db)
+                .context("failed to remove original column")?
+            {
+                transaction.commit()?;
+            }
+        }
+
+        last_step
+            .complete(&ctx.for_step(remove_steps.len() + 1), db)
+            .context("failed to remove original column")
+    }
+
+    fn update_schema(&self, ctx: &MigrationContext, schema: &mut Schema) {
+        self.add_step().update_schema(&ctx.for_step(0), schema);
+        for (index, step) in self.remove_steps().iter().enumerate() {
+            step.update_schema(&ctx.for_step(index + 1), schema);
+        }
+    }
+
+    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        for (index, step) in self.remove_steps().iter().enumerate().rev() {
+            step.abort(&ctx.for_step(index + 1), db)?;
+        }
+        self.add_step().abort(&ctx.for_step(0), db)?;
+
+        Ok(())
+    }
+
+    fn version_requirements(&self) -> Vec<VersionRequirement> {
+        self.add_step().version_requirements()
+    }
+
+    fn destructive(&self) -> bool {
+        self.remove_originals
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        vec![self.column.name.as_str()]
+    }
+
+    fn rewritten_tables(&self) -> Vec<&str> {
+        if self.backfill != Backfill::None {
+            vec![self.table.as_str()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        for problem in self.problems() {
+            schema.problem(problem);
+        }
+
+        schema.require_columns(&self.table, self.columns.iter().map(|source| &source.name));
+        schema.add_column(&self.table, &self.column.name);
+        if self.remove_originals {
+            for source in &self.columns {
+                schema.remove_column(&self.table, &source.name);
+            }
+        }
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
+}
@@ -1,13 +1,17 @@
 use crate::{
+    backfill::BackfillWindow,
     db::{Conn, Transaction},
     schema::Schema,
+    state, Namespace,
 };
+use anyhow::{bail, Context};
 use core::fmt::Debug;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 // Re-export migration types
-mod common;
-pub use common::Column;
+pub(crate) mod common;
+pub use common::{Backfill, BackfillOrder, Column, ForeignKey};
 
 mod create_table;
 pub use create_table::CreateTable;
@@ -16,7 +20,7 @@ mod alter_column;
 pub use alter_column::{AlterColumn, ColumnChanges};
 
 mod add_column;
-pub use add_column::AddColumn;
+pub use add_column::{AddColumn, ColumnForeignKey};
 
 mod remove_column;
 pub use remove_column::RemoveColumn;
@@ -33,25 +37,109 @@ pub use remove_table::RemoveTable;
 mod rename_table;
 pub use rename_table::RenameTable;
 
+mod rewrite_table;
+pub use rewrite_table::RewriteTable;
+
 mod create_enum;
 pub use create_enum::CreateEnum;
 
 mod remove_enum;
 pub use remove_enum::RemoveEnum;
 
+mod create_domain;
+pub use create_domain::{CreateDomain, DomainConstraint};
+
+mod alter_domain;
+pub use alter_domain::AlterDomain;
+
+mod remove_domain;
+pub use remove_domain::RemoveDomain;
+
+mod create_composite_type;
+pub use create_composite_type::{CompositeAttribute, CreateCompositeType};
+
+mod alter_composite_type;
+pub use alter_composite_type::AlterCompositeType;
+
+mod remove_composite_type;
+pub use remove_composite_type::RemoveCompositeType;
+
 mod custom;
 pub use custom::Custom;
 
 mod add_foreign_key;
-pub use add_foreign_key::AddForeignKey;
+pub use add_foreign_key::{AddForeignKey, ForeignKeyValidation};
 
 mod remove_foreign_key;
 pub use remove_foreign_key::RemoveForeignKey;
 
+mod add_table_to_publication;
+pub use add_table_to_publication::AddTableToPublication;
+
+mod remove_table_from_publication;
+pub use remove_table_from_publication::RemoveTableFromPublication;
+
+mod set_replica_identity;
+pub use set_replica_identity::{ReplicaIdentity, SetReplicaIdentity};
+
+mod grant;
+pub use grant::Grant;
+
+mod revoke;
+pub use revoke::Revoke;
+
+mod set_table_comment;
+pub use set_table_comment::SetTableComment;
+
+mod set_column_comment;
+pub use set_column_comment::SetColumnComment;
+
+mod set_type_comment;
+pub use set_type_comment::SetTypeComment;
+
+mod widen_primary_key;
+pub use widen_primary_key::{IdentityGeneration, ReferencingColumn, WidenPrimaryKey};
+
+mod split_column;
+pub use split_column::{SplitColumn, SplitTarget};
+
+mod merge_columns;
+pub use merge_columns::{MergeColumns, MergeSource};
+
+mod reindex_index;
+pub use reindex_index::ReindexIndex;
+
+mod reindex_table;
+pub use reindex_table::ReindexTable;
+
+mod seed;
+pub use seed::{Seed, SeedPhase};
+
+mod load_data;
+pub use load_data::{DataFormat, LoadData, LoadErrors};
+
+mod json_schema;
+pub use json_schema::{action_types, migration_file_schema};
+
+mod logical_schema;
+pub(crate) use logical_schema::{check_action_order, simulate_migrations};
+pub use logical_schema::{check_migrations, LogicalSchema, Object, Problem};
+
+mod impact;
+pub use impact::{ActionImpact, ImpactKind, LockLevel};
+
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct Migration {
     pub name: String,
     pub description: Option<String>,
+
+    // Apply and complete the migration in a single transaction, skipping the
+    // intermediate schema. Only allowed if all actions can run atomically.
+    #[serde(default)]
+    pub atomic: bool,
+
     pub actions: Vec<Box<dyn Action>>,
 }
 
@@ -60,14 +148,222 @@ impl Migration {
         Migration {
             name: name.into(),
             description,
+            atomic: false,
             actions: vec![],
         }
     }
 
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn with_atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
     pub fn with_action(mut self, action: impl Action + 'static) -> Self {
         self.actions.push(Box::new(action));
         self
     }
+
+    // Add actions which have already been boxed, for example when they are
+    // generated from another source
+    pub fn with_actions(mut self, actions: impl IntoIterator<Item = Box<dyn Action>>) -> Self {
+        self.actions.extend(actions);
+        self
+    }
+}
+
+/// Define migrations in code which are numbered and named by their position, so
+/// applications can embed their migrations in the binary and migrate themselves
+/// without any migration files. Each migration is a list of actions, optionally
+/// preceded by a name. The first migration is named `1`, or `1_{name}` if it's
+/// named, the second `2` and so on.
+///
+/// Just like with numbered migration files, new migrations must only be added to
+/// the end of the list, as inserting one would renumber the migrations after it.
+/// Use [`Migration::new`] for migrations which need a description or must be
+/// atomic.
+///
+/// ```
+/// use reshape::prelude::*;
+///
+/// let migrations = reshape::migrations![
+///     create_users => [
+///         CreateTable::new("users", ["id"]).with_column(Column::new("id", "INTEGER")),
+///     ],
+///     [
+///         AddColumn::new("users", Column::new("name", "TEXT")),
+///         AddIndex::new("users", Index::new("users_name_idx", ["name"])),
+///     ],
+/// ];
+///
+/// assert_eq!("1_create_users", migrations[0].name);
+/// assert_eq!("2", migrations[1].name);
+/// assert_eq!(2, migrations[1].actions.len());
+/// ```
+#[macro_export]
+macro_rules! migrations {
+    ($($($name:ident =>)? [$($action:expr),* $(,)?]),* $(,)?) => {
+        $crate::migrations::numbered_migrations(vec![$(
+            $crate::migrations::Migration::new(concat!("" $(, stringify!($name))?), None)
+                $(.with_action($action))*
+        ),*])
+    };
+}
+
+// Used by the `migrations!` macro to prefix the name of every migration with its
+// number. Anonymous migrations have an empty name and only get the number.
+#[doc(hidden)]
+pub fn numbered_migrations(migrations: Vec<Migration>) -> Vec<Migration> {
+    migrations
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut migration)| {
+            let number = index + 1;
+            migration.name = if migration.name.is_empty() {
+                number.to_string()
+            } else {
+                format!("{}_{}", number, migration.name)
+            };
+            migration
+        })
+        .collect()
+}
+
+// Upgrades for actions written for older versions of the migration file format. The
+// upgrade at index `i` rewrites an action from version `i + 1` to version `i + 2`, so
+// adding an upgrade here bumps the current version. Files without a version are
+// considered to be version 1.
+const SCHEMA_UPGRADES: &[fn(&mut serde_json::Value) -> anyhow::Result<()>] = &[];
+pub const SCHEMA_VERSION: u32 = SCHEMA_UPGRADES.len() as u32 + 1;
+
+// Rewrite an action written for an older version of the migration file format so it
+// can be decoded by the current version
+pub fn upgrade_action(schema_version: u32, action: &mut serde_json::Value) -> anyhow::Result<()> {
+    if schema_version == 0 {
+        bail!("schema version must be at least 1");
+    }
+
+    if schema_version > SCHEMA_VERSION {
+        bail!(
+            "schema version {} is not supported, this version of Reshape supports up to version {}. Please upgrade Reshape.",
+            schema_version,
+            SCHEMA_VERSION
+        );
+    }
+
+    for upgrade in &SCHEMA_UPGRADES[schema_version as usize - 1..] {
+        upgrade(action)?;
+    }
+
+    Ok(())
+}
+
+// Postgres truncates identifiers longer than this many bytes, which could make two
+// different names refer to the same object
+pub(crate) const MAX_IDENTIFIER_LENGTH: usize = 63;
+const RESERVED_PREFIX: &str = "__reshape";
+
+// Validate that all migrations can be applied without name collisions. The migration
+// name is used unquoted for the migration's schema, so it's restricted to characters
+// which are valid in plain identifiers.
+pub fn validate_migrations(migrations: &[Migration]) -> anyhow::Result<()> {
+    let mut seen_names = HashSet::new();
+
+    for migration in migrations {
+        migration
+            .validate()
+            .with_context(|| format!("invalid migration {}", migration.name))?;
+
+        // Unquoted identifiers are case-insensitive so the schema names would collide
+        if !seen_names.insert(migration.name.to_lowercase()) {
+            bail!(
+                "multiple migrations are named {}, migration names must be unique (ignoring case)",
+                migration.name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+impl Migration {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() {
+            bail!("migration name can't be empty");
+        }
+
+        if !self
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            bail!(
+                "migration name may only contain letters, digits and underscores as it's used for the schema name"
+            );
+        }
+
+        let schema_name = Namespace::default().schema_name_for_migration(&self.name);
+        if schema_name.len() > MAX_IDENTIFIER_LENGTH {
+            bail!(
+                "migration name is too long, the schema name {} can't be longer than {} characters",
+                schema_name,
+                MAX_IDENTIFIER_LENGTH
+            );
+        }
+
+        for action in &self.actions {
+            if self.atomic && !action.can_run_atomically() {
+                bail!(
+                    "{}: action can't be part of an atomic migration as it isn't instantaneous",
+                    action.describe(),
+                );
+            }
+
+            for name in action.created_names() {
+                if name.starts_with(RESERVED_PREFIX) {
+                    bail!(
+                        "{}: {} is not a valid name as names starting with {} are reserved for Reshape",
+                        action.describe(),
+                        name,
+                        RESERVED_PREFIX
+                    );
+                }
+
+                if name.len() > MAX_IDENTIFIER_LENGTH {
+                    bail!(
+                        "{}: {} is not a valid name as it's longer than {} characters",
+                        action.describe(),
+                        name,
+                        MAX_IDENTIFIER_LENGTH
+                    );
+                }
+            }
+        }
+
+        // Actions are applied in order, so an action referencing an object which is
+        // created by a later action would fail part way through the migration
+        let problems = check_action_order(self);
+        if !problems.is_empty() {
+            let problems: Vec<String> = problems
+                .iter()
+                .map(|problem| {
+                    format!(
+                        "action {} ({}): {}",
+                        problem.action_index + 1,
+                        problem.action,
+                        problem.message
+                    )
+                })
+                .collect();
+            bail!("actions are in the wrong order\n{}", problems.join("\n"));
+        }
+
+        Ok(())
+    }
 }
 
 impl PartialEq for Migration {
@@ -88,36 +384,156 @@ impl Clone for Migration {
 pub struct MigrationContext {
     migration_index: usize,
     action_index: usize,
+    migration_name: String,
     existing_schema_name: Option<String>,
+    namespace: Namespace,
+
+    // Set when the action is run and completed inside a single transaction
+    atomic: bool,
+
+    // Set for the actions which make up a composite action, so they each get their
+    // own names for temporary objects
+    step: Option<usize>,
+
+    // Set when the backfill is run separately from `run`, because of a backfill
+    // priority or because backfills are skipped or limited
+    backfill_deferred: bool,
+
+    // Limits how much a deferred backfill may do before it's stopped
+    backfill_window: Option<BackfillWindow>,
+
+    object_names: ObjectNames,
+}
+
+// How temporary objects are named. The names start with the position of the migration
+// and action, as triggers run in alphabetical order, followed by a hash of the migration
+// name so objects left behind by a different migration at the same position are never
+// mistaken for our own. Migrations started by earlier versions only used the position
+// and keep doing so until they are completed or aborted.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ObjectNames {
+    Positional,
+    #[default]
+    Hashed,
 }
 
 impl MigrationContext {
     pub fn new(
         migration_index: usize,
         action_index: usize,
+        migration_name: impl Into<String>,
         existing_schema_name: Option<String>,
     ) -> Self {
         MigrationContext {
             migration_index,
             action_index,
+            migration_name: migration_name.into(),
             existing_schema_name,
+            namespace: Namespace::default(),
+            atomic: false,
+            step: None,
+            backfill_deferred: false,
+            backfill_window: None,
+            object_names: ObjectNames::default(),
+        }
+    }
+
+    pub(crate) fn with_namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    pub(crate) fn with_object_names(mut self, object_names: ObjectNames) -> Self {
+        self.object_names = object_names;
+        self
+    }
+
+    pub(crate) fn with_atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    pub(crate) fn with_deferred_backfill(mut self, backfill_deferred: bool) -> Self {
+        self.backfill_deferred = backfill_deferred;
+        self
+    }
+
+    pub(crate) fn with_backfill_window(mut self, backfill_window: BackfillWindow) -> Self {
+        self.backfill_window = Some(backfill_window);
+        self
+    }
+
+    // Actions with a backfill should skip it in `run` when this is set, it will be
+    // run separately using `backfill` instead
+    pub(crate) fn backfill_deferred(&self) -> bool {
+        self.backfill_deferred
+    }
+
+    pub(crate) fn backfill_window(&self) -> Option<&BackfillWindow> {
+        self.backfill_window.as_ref()
+    }
+
+    pub(crate) fn for_step(&self, step: usize) -> Self {
+        MigrationContext {
+            migration_index: self.migration_index,
+            action_index: self.action_index,
+            migration_name: self.migration_name.clone(),
+            existing_schema_name: self.existing_schema_name.clone(),
+            namespace: self.namespace.clone(),
+            atomic: self.atomic,
+            step: Some(step),
+            backfill_deferred: false,
+            backfill_window: None,
+            object_names: self.object_names,
         }
     }
 
     fn prefix(&self) -> String {
-        format!(
-            "__reshape_{:0>4}_{:0>4}",
-            self.migration_index, self.action_index
-        )
+        self.prefix_for(self.migration_index, self.action_index, self.step)
     }
 
     fn prefix_inverse(&self) -> String {
-        format!(
-            "__reshape_{:0>4}_{:0>4}",
+        self.prefix_for(
             1000 - self.migration_index,
-            1000 - self.action_index
+            1000 - self.action_index,
+            self.step.map(|step| 99 - step),
         )
     }
+
+    fn prefix_for(
+        &self,
+        migration_index: usize,
+        action_index: usize,
+        step: Option<usize>,
+    ) -> String {
+        let mut prefix = format!(
+            "{}_{:0>4}_{:0>4}",
+            self.namespace.object_prefix(),
+            migration_index,
+            action_index
+        );
+        if self.object_names == ObjectNames::Hashed {
+            prefix = format!("{}_{}", prefix, migration_name_hash(&self.migration_name));
+        }
+        match step {
+            Some(step) => format!("{}_{:0>2}", prefix, step),
+            None => prefix,
+        }
+    }
+
+    // Whether a temporary object was created by this action, including any of its steps
+    pub(crate) fn owns_object(&self, name: &str) -> bool {
+        let prefix = self.prefix_for(self.migration_index, self.action_index, None);
+        let prefix_inverse =
+            self.prefix_for(1000 - self.migration_index, 1000 - self.action_index, None);
+        name.contains(&prefix) || name.contains(&prefix_inverse)
+    }
+}
+
+// Short enough to keep names of temporary objects well within the identifier limit
+fn migration_name_hash(name: &str) -> String {
+    format!("{:04x}", state::stable_hash(name.as_bytes()) & 0xffff)
 }
 
 #[typetag::serde(tag = "type")]
@@ -125,6 +541,11 @@ pub trait Action: Debug {
     fn describe(&self) -> String;
     fn run(&self, ctx: &MigrationContext, db: &mut dyn Conn, schema: &Schema)
         -> anyhow::Result<()>;
+    // Make the final changes once the migration is being completed. Changes should be
+    // made in a transaction which is returned, so the state is saved as part of it and
+    // an interrupted completion never makes them twice. Changes which can't be made in
+    // a transaction, like dropping indices concurrently, are made directly before that
+    // and must be safe to repeat.
     fn complete<'a>(
         &self,
         ctx: &MigrationContext,
@@ -132,4 +553,140 @@ pub trait Action: Debug {
     ) -> anyhow::Result<Option<Transaction<'a>>>;
     fn update_schema(&self, ctx: &MigrationContext, schema: &mut Schema);
     fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()>;
+
+    // Postgres features the action depends on which aren't available in all
+    // supported server versions. These are checked before any migration is started.
+    fn version_requirements(&self) -> Vec<VersionRequirement> {
+        Vec::new()
+    }
+
+    // Like `version_requirements`, for actions whose requirements depend on the tables
+    // they change, like the nullability of an existing column. `schema` includes the
+    // changes of all earlier actions.
+    fn schema_version_requirements(
+        &self,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<Vec<VersionRequirement>> {
+        Ok(self.version_requirements())
+    }
+
+    // Whether the action drops data when it's completed. Policies can require
+    // destructive actions to be allowed explicitly.
+    fn destructive(&self) -> bool {
+        false
+    }
+
+    // Names of the tables, columns and other objects created by the action. These
+    // are validated before any migration is started.
+    fn created_names(&self) -> Vec<&str> {
+        Vec::new()
+    }
+
+    // Apply the action to a logical schema built up by earlier actions, reporting any
+    // problems like references to tables which don't exist. Used to validate migrations
+    // without connecting to a database.
+    fn simulate(&self, _schema: &mut LogicalSchema) {}
+
+    // Fill in existing rows. This is normally done as part of `run`, but actions with
+    // a backfill priority skip it there and have it called once all actions in the
+    // migration have run, highest priority first. The schema is the same one which
+    // was passed to `run`.
+    fn backfill(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn backfill_priority(&self) -> Option<i32> {
+        None
+    }
+
+    // Whether the backfill can be run separately from `run`, which lets it be skipped
+    // or stopped early and continued later from where it stopped
+    fn can_defer_backfill(&self) -> bool {
+        false
+    }
+
+    // Whether the action only makes instantaneous changes which can be started and
+    // completed inside a single transaction, as is done for atomic migrations
+    fn can_run_atomically(&self) -> bool {
+        false
+    }
+
+    // Whether the statements the action runs can be exported as SQL scripts using
+    // `Reshape::export_sql`, which only captures the statements and not any data sent
+    // along with them
+    fn exportable(&self) -> bool {
+        true
+    }
+
+    // The index the action would build concurrently in `run`, if that's all it does.
+    // When parallel index builds are enabled, `run` is skipped for actions returning
+    // a build and the index is built alongside those of the actions around it instead.
+    fn index_build(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<Option<IndexBuild>> {
+        Ok(None)
+    }
+
+    // The strongest table lock taken while the action is started or completed, which
+    // policies can limit. Actions which don't declare one are assumed to take the
+    // strongest, as custom SQL might.
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
+
+    // Tables whose rows are rewritten by the action. Their planner statistics will be
+    // stale afterwards, so they are analyzed once the migration has been completed.
+    fn rewritten_tables(&self) -> Vec<&str> {
+        Vec::new()
+    }
+}
+
+// An index built concurrently by an action, see `Action::index_build`
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct IndexBuild {
+    pub table: String,
+    pub index: String,
+    pub query: String,
+}
+
+impl IndexBuild {
+    pub fn new(
+        table: impl Into<String>,
+        index: impl Into<String>,
+        query: impl Into<String>,
+    ) -> Self {
+        IndexBuild {
+            table: table.into(),
+            index: index.into(),
+            query: query.into(),
+        }
+    }
+}
+
+// A Postgres feature and the first server version which supports it, in the
+// `server_version_num` format (for example 120000 for 12.0)
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct VersionRequirement {
+    pub min_version_num: i32,
+    pub feature: &'static str,
+}
+
+impl VersionRequirement {
+    pub fn new(min_version_num: i32, feature: &'static str) -> Self {
+        VersionRequirement {
+            min_version_num,
+            feature,
+        }
+    }
 }
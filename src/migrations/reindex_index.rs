@@ -0,0 +1,128 @@
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext, VersionRequirement};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::{anyhow, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct ReindexIndex {
+    pub index: String,
+
+    // Rebuild the index without blocking writes, which requires Postgres 12. Without
+    // it, writes to the table are blocked until the index has been rebuilt.
+    #[serde(default = "concurrently_default")]
+    pub concurrently: bool,
+}
+
+fn concurrently_default() -> bool {
+    true
+}
+
+impl ReindexIndex {
+    pub fn new(index: impl Into<String>) -> Self {
+        ReindexIndex {
+            index: index.into(),
+            concurrently: concurrently_default(),
+        }
+    }
+
+    pub fn with_concurrently(mut self, concurrently: bool) -> Self {
+        self.concurrently = concurrently;
+        self
+    }
+
+    fn table(&self, db: &mut dyn Conn) -> anyhow::Result<Option<String>> {
+        let table = db
+            .query_with_params(
+                "
+                SELECT ix.indrelid::regclass::TEXT AS table_name
+                FROM pg_index ix
+                WHERE ix.indexrelid = to_regclass(format('public.%I', $1::TEXT))
+                ",
+                &[&self.index],
+            )
+            .context("failed to get table of index")?
+            .first()
+            .map(|row| row.get::<_, String>("table_name"));
+
+        // Tables in `public` are written without a schema, but might be quoted
+        Ok(table.map(|table| table.trim_matches('"').replace(r#""""#, r#"""#)))
+    }
+}
+
+#[typetag::serde(name = "reindex_index")]
+impl Action for ReindexIndex {
+    fn describe(&self) -> String {
+        format!("Rebuilding index \"{}\"", self.index)
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
+        // Rebuilding an index doesn't change the schema, so it's done straight away and
+        // there is nothing left to do when completing
+        let table = self
+            .table(db)?
+            .ok_or_else(|| anyhow!("index \"{}\" doesn't exist", self.index))?;
+        common::drop_invalid_reindex_copies(db, &table)?;
+
+        db.run(&format!(
+            r#"REINDEX INDEX {concurrently} public."{index}""#,
+            concurrently = if self.concurrently {
+                "CONCURRENTLY"
+            } else {
+                ""
+            },
+            index = self.index,
+        ))
+        .context("failed to rebuild index")
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        Ok(None)
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        // The index is kept if it was rebuilt, only a copy from an interrupted rebuild
+        // is removed
+        match self.table(db)? {
+            Some(table) => common::drop_invalid_reindex_copies(db, &table),
+            None => Ok(()),
+        }
+    }
+
+    fn version_requirements(&self) -> Vec<VersionRequirement> {
+        if self.concurrently {
+            vec![VersionRequirement::new(120000, "REINDEX CONCURRENTLY")]
+        } else {
+            Vec::new()
+        }
+    }
+
+    // Indices backing primary keys and unique constraints aren't created by actions,
+    // so the index isn't required to exist in the logical schema
+    fn simulate(&self, _schema: &mut LogicalSchema) {}
+
+    // Without CONCURRENTLY, reads which would use the index are blocked
+    fn max_lock_level(&self) -> LockLevel {
+        if self.concurrently {
+            LockLevel::ShareUpdateExclusive
+        } else {
+            LockLevel::AccessExclusive
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext, VersionRequirement};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct ReindexTable {
+    pub table: String,
+
+    // Rebuild the indices without blocking writes, which requires Postgres 12. Without
+    // it, writes to the table are blocked until all indices have been rebuilt.
+    #[serde(default = "concurrently_default")]
+    pub concurrently: bool,
+}
+
+fn concurrently_default() -> bool {
+    true
+}
+
+impl ReindexTable {
+    pub fn new(table: impl Into<String>) -> Self {
+        ReindexTable {
+            table: table.into(),
+            concurrently: concurrently_default(),
+        }
+    }
+
+    pub fn with_concurrently(mut self, concurrently: bool) -> Self {
+        self.concurrently = concurrently;
+        self
+    }
+}
+
+#[typetag::serde(name = "reindex_table")]
+impl Action for ReindexTable {
+    fn describe(&self) -> String {
+        format!("Rebuilding indices on table \"{}\"", self.table)
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        // Rebuilding indices doesn't change the schema, so it's done straight away and
+        // there is nothing left to do when completing
+        let table = schema.get_table(db, &self.table)?;
+        common::drop_invalid_reindex_copies(db, &table.real_name)?;
+
+        db.run(&format!(
+            r#"REINDEX TABLE {concurrently} public."{table}""#,
+            concurrently = if self.concurrently {
+                "CONCURRENTLY"
+            } else {
+                ""
+            },
+            table = table.real_name,
+        ))
+        .context("failed to rebuild indices")
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        Ok(None)
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        // Indices which were rebuilt are kept, only copies from an interrupted rebuild
+        // are removed
+        common::drop_invalid_reindex_copies(db, &self.table)
+    }
+
+    fn version_requirements(&self) -> Vec<VersionRequirement> {
+        if self.concurrently {
+            vec![VersionRequirement::new(120000, "REINDEX CONCURRENTLY")]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_table(&self.table);
+    }
+
+    // Without CONCURRENTLY, reads which would use the indices are blocked
+    fn max_lock_level(&self) -> LockLevel {
+        if self.concurrently {
+            LockLevel::ShareUpdateExclusive
+        } else {
+            LockLevel::AccessExclusive
+        }
+    }
+}
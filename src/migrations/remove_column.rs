@@ -1,19 +1,29 @@
-use super::{common, Action, MigrationContext};
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext};
 use crate::{
-    db::{Conn, Transaction},
+    db::{self, Conn, Transaction},
     schema::Schema,
 };
 use anyhow::{anyhow, bail, Context};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct RemoveColumn {
     pub table: String,
     pub column: String,
     pub down: Option<Transformation>,
+
+    // Copy the column into the archive schema before it's dropped, together with
+    // the primary key so the values can be matched with their rows
+    #[serde(default)]
+    pub archive: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[schemars(rename = "ColumnDownTransformation")]
 #[serde(untagged)]
 pub enum Transformation {
     Simple(String),
@@ -25,6 +35,41 @@ pub enum Transformation {
 }
 
 impl RemoveColumn {
+    pub fn new(table: impl Into<String>, column: impl Into<String>) -> Self {
+        RemoveColumn {
+            table: table.into(),
+            column: column.into(),
+            down: None,
+            archive: false,
+        }
+    }
+
+    pub fn with_archive(mut self, archive: bool) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    pub fn with_down(mut self, down: impl Into<String>) -> Self {
+        self.down = Some(Transformation::Simple(down.into()));
+        self
+    }
+
+    // Fill in the removed column for the old schema using a value from another
+    // table. `where` should match rows in `table` with the row being updated.
+    pub fn with_down_update(
+        mut self,
+        table: impl Into<String>,
+        value: impl Into<String>,
+        r#where: impl Into<String>,
+    ) -> Self {
+        self.down = Some(Transformation::Update {
+            table: table.into(),
+            value: value.into(),
+            r#where: r#where.into(),
+        });
+        self
+    }
+
     fn trigger_name(&self, ctx: &MigrationContext) -> String {
         format!(
             "{}_remove_column_{}_{}",
@@ -82,244 +127,257 @@ impl Action for RemoveColumn {
             .get_column(&self.column)
             .ok_or_else(|| anyhow!("no such column {} exists", self.column))?;
 
-        // Add down trigger
-        if let Some(down) = &self.down {
-            let declarations: Vec<String> = table
-                .columns
-                .iter()
-                .map(|column| {
-                    format!(
-                        "{alias} public.{table}.{real_name}%TYPE := NEW.{real_name};",
-                        table = table.real_name,
-                        alias = column.name,
-                        real_name = column.real_name,
-                    )
-                })
-                .collect();
-
-            if let Transformation::Simple(down) = down {
-                let query = format!(
-                    r#"
-                    CREATE OR REPLACE FUNCTION {trigger_name}()
-                    RETURNS TRIGGER AS $$
-                    BEGIN
-                        IF reshape.is_new_schema() THEN
-                            DECLARE
-                                {declarations}
-                            BEGIN
-                                NEW.{column_name} = {down};
-                            END;
-                        END IF;
-                        RETURN NEW;
-                    END
-                    $$ language 'plpgsql';
-
-                    DROP TRIGGER IF EXISTS "{trigger_name}" ON "{table}";
-                    CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{table}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
-                    "#,
-                    column_name = self.column,
-                    trigger_name = self.trigger_name(ctx),
-                    down = down,
-                    table = self.table,
-                    declarations = declarations.join("\n"),
-                );
-                db.run(&query).context("failed to create down trigger")?;
-            }
+        // Add down triggers. When the column is NOT NULL, the constraint is also replaced
+        // by a trigger, which is done together with the rest so a failure halfway through
+        // can't leave the column without either.
+        db::atomically(db, |db| {
+            if let Some(down) = &self.down {
+                let declarations: Vec<String> = table
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        format!(
+                            "{alias} public.{table}.{real_name}%TYPE := NEW.{real_name};",
+                            table = table.real_name,
+                            alias = column.name,
+                            real_name = column.real_name,
+                        )
+                    })
+                    .collect();
 
-            if let Transformation::Update {
-                table: from_table,
-                value,
-                r#where,
-            } = down
-            {
-                let existing_schema_name = match &ctx.existing_schema_name {
-                    Some(name) => name,
-                    None => bail!("can't use update without previous migration"),
-                };
-
-                let from_table = schema.get_table(db, &from_table)?;
-
-                let maybe_null_check = if !column.nullable {
-                    // Replace NOT NULL constraint with a constraint trigger that only triggers on the old schema.
-                    // We will add a null check to the down function on the new schema below as well to cover both cases.
-                    // As we are using a complex down function, we must remove the NOT NULL check for the new schema.
-                    // NOT NULL is not checked at the end of a transaction, but immediately upon update.
+                if let Transformation::Simple(down) = down {
+                    let state_schema = ctx.namespace.state_schema();
                     let query = format!(
                         r#"
                         CREATE OR REPLACE FUNCTION {trigger_name}()
                         RETURNS TRIGGER AS $$
                         BEGIN
-                            IF NOT reshape.is_new_schema() THEN
-                                IF NEW.{column} IS NULL THEN
-                                    RAISE EXCEPTION '{column} can not be null';
-                                END IF;
+                            IF {state_schema}.is_new_schema() THEN
+                                DECLARE
+                                    {declarations}
+                                BEGIN
+                                    {assignment}
+                                END;
                             END IF;
                             RETURN NEW;
                         END
                         $$ language 'plpgsql';
 
                         DROP TRIGGER IF EXISTS "{trigger_name}" ON "{table}";
-
-                        CREATE CONSTRAINT TRIGGER "{trigger_name}"
-                            AFTER INSERT OR UPDATE
-                            ON "{table}"
-                            FOR EACH ROW
-                            EXECUTE PROCEDURE {trigger_name}();
+                        CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{table}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
                         "#,
+                        assignment = common::with_public_in_search_path(&format!(
+                            "NEW.{} = {};",
+                            self.column, down
+                        )),
+                        trigger_name = self.trigger_name(ctx),
                         table = self.table,
-                        trigger_name = self.not_null_constraint_trigger_name(ctx),
-                        column = self.column,
+                        declarations = declarations.join("\n"),
                     );
-                    db.run(&query)
-                        .context("failed to create null constraint trigger")?;
+                    db.run(&query).context("failed to create down trigger")?;
+                }
+
+                if let Transformation::Update {
+                    table: from_table,
+                    value,
+                    r#where,
+                } = down
+                {
+                    let existing_schema = match &ctx.existing_schema_name {
+                        Some(name) => ctx.namespace.schema_name_for_migration(name),
+                        None => bail!("can't use update without previous migration"),
+                    };
+
+                    let from_table = schema.get_table(db, from_table)?;
+
+                    let maybe_null_check = if !column.nullable {
+                        // Replace NOT NULL constraint with a constraint trigger that only triggers on the old schema.
+                        // We will add a null check to the down function on the new schema below as well to cover both cases.
+                        // As we are using a complex down function, we must remove the NOT NULL check for the new schema.
+                        // NOT NULL is not checked at the end of a transaction, but immediately upon update.
+                        let state_schema = ctx.namespace.state_schema();
+                        let query = format!(
+                            r#"
+                            CREATE OR REPLACE FUNCTION {trigger_name}()
+                            RETURNS TRIGGER AS $$
+                            BEGIN
+                                IF {state_schema}.is_old_schema() THEN
+                                    IF NEW.{column} IS NULL THEN
+                                        RAISE EXCEPTION '{column} can not be null';
+                                    END IF;
+                                END IF;
+                                RETURN NEW;
+                            END
+                            $$ language 'plpgsql';
+
+                            DROP TRIGGER IF EXISTS "{trigger_name}" ON "{table}";
+
+                            CREATE CONSTRAINT TRIGGER "{trigger_name}"
+                                AFTER INSERT OR UPDATE
+                                ON "{table}"
+                                FOR EACH ROW
+                                EXECUTE PROCEDURE {trigger_name}();
+                            "#,
+                            table = self.table,
+                            trigger_name = self.not_null_constraint_trigger_name(ctx),
+                            column = self.column,
+                        );
+                        db.run(&query)
+                            .context("failed to create null constraint trigger")?;
+
+                        db.run(&format!(
+                            r#"
+                            ALTER TABLE {table}
+                            ALTER COLUMN {column}
+                            DROP NOT NULL
+                            "#,
+                            table = self.table,
+                            column = self.column
+                        ))
+                        .context("failed to remove column not null constraint")?;
 
-                    db.run(&format!(
+                        format!(
+                            r#"
+                            IF {value} IS NULL THEN
+                                RAISE EXCEPTION '{column_name} can not be null';
+                            END IF;
+                            "#,
+                            column_name = self.column,
+                        )
+                    } else {
+                        "".to_string()
+                    };
+
+                    let into_variables = from_table
+                        .columns
+                        .iter()
+                        .map(|column| {
+                            format!(
+                                "NEW.{real_name} AS {alias}",
+                                alias = column.name,
+                                real_name = column.real_name,
+                            )
+                        })
+                        .collect::<Vec<String>>()
+                        .join(", ");
+
+                    // The removed column is only updated once the row has been written, as a
+                    // BEFORE INSERT trigger also fires for upserts which end up updating the
+                    // conflicting row or doing nothing
+                    let state_schema = ctx.namespace.state_schema();
+                    let query = format!(
                         r#"
-                        ALTER TABLE {table}
-                        ALTER COLUMN {column}
-                        DROP NOT NULL
-                        "#,
-                        table = self.table,
-                        column = self.column
-                    ))
-                    .context("failed to remove column not null constraint")?;
+                        CREATE OR REPLACE FUNCTION {trigger_name}()
+                        RETURNS TRIGGER AS $$
+                        #variable_conflict use_variable
+                        BEGIN
+                            IF {state_schema}.is_new_schema() THEN
+                                DECLARE
+                                    {from_table} record;
+                                BEGIN
+                                    SELECT {into_variables}
+                                    INTO {from_table};
 
-                    format!(
-                        r#"
-                        IF {value} IS NULL THEN
-                            RAISE EXCEPTION '{column_name} can not be null';
-                        END IF;
-                        "#,
-                        column_name = self.column,
-                    )
-                } else {
-                    "".to_string()
-                };
+                                    {maybe_null_check}
 
-                let into_variables = from_table
-                    .columns
-                    .iter()
-                    .map(|column| {
-                        format!(
-                            "NEW.{real_name} AS {alias}",
-                            alias = column.name,
-                            real_name = column.real_name,
-                        )
-                    })
-                    .collect::<Vec<String>>()
-                    .join(", ");
-
-                let query = format!(
-                    r#"
-                    CREATE OR REPLACE FUNCTION {trigger_name}()
-                    RETURNS TRIGGER AS $$
-                    #variable_conflict use_variable
-                    BEGIN
-                        IF reshape.is_new_schema() THEN
-                            DECLARE
-                                {from_table} record;
-                            BEGIN
-                                SELECT {into_variables}
-                                INTO {from_table};
-
-                                {maybe_null_check}
-
-                                -- Don't trigger reverse trigger when making this update
-                                perform set_config('reshape.disable_triggers', 'TRUE', TRUE);
-
-                                UPDATE "migration_{existing_schema_name}"."{changed_table}" "{changed_table}"
-                                SET "{column_name}" = {value}
-                                WHERE {where};
-
-                                perform set_config('reshape.disable_triggers', '', TRUE);
-                            END;
-                        END IF;
-                        RETURN NEW;
-                    END
-                    $$ language 'plpgsql';
-
-                    DROP TRIGGER IF EXISTS "{trigger_name}" ON "{from_table_real}";
-                    CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{from_table_real}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
-                    "#,
-                    changed_table = self.table,
-                    from_table = from_table.name,
-                    from_table_real = from_table.real_name,
-                    column_name = self.column,
-                    trigger_name = self.trigger_name(ctx),
-                );
-                db.run(&query).context("failed to create down trigger")?;
-
-                let changed_into_variables = table
-                    .columns
-                    .iter()
-                    .map(|column| {
-                        format!(
-                            "NEW.{real_name} AS {alias}",
-                            alias = column.name,
-                            real_name = column.real_name,
-                        )
-                    })
-                    .collect::<Vec<String>>()
-                    .join(", ");
+                                    -- Don't trigger reverse trigger when making this update
+                                    perform set_config('reshape.disable_triggers', 'TRUE', TRUE);
 
-                let from_table_columns = from_table
-                    .columns
-                    .iter()
-                    .map(|column| format!("{} as {}", column.real_name, column.name))
-                    .collect::<Vec<String>>()
-                    .join(", ");
-
-                let query = format!(
-                    r#"
-                    CREATE OR REPLACE FUNCTION {trigger_name}()
-                    RETURNS TRIGGER AS $$
-                    #variable_conflict use_variable
-                    BEGIN
-                        IF reshape.is_new_schema() AND NOT current_setting('reshape.disable_triggers', TRUE) = 'TRUE' THEN
-                            DECLARE
-                                {changed_table} record;
-                                __temp_row record;
-                            BEGIN
-                                SELECT {changed_into_variables}
-                                INTO {changed_table};
+                                    {update}
 
-                                SELECT *
-                                INTO __temp_row
-                                FROM (
-                                    SELECT {from_table_columns}
-                                    FROM public.{from_table_real}
-                                ) {from_table}
-                                WHERE {where};
+                                    perform set_config('reshape.disable_triggers', '', TRUE);
+                                END;
+                            END IF;
+                            RETURN NULL;
+                        END
+                        $$ language 'plpgsql';
 
+                        DROP TRIGGER IF EXISTS "{trigger_name}" ON "{from_table_real}";
+                        CREATE TRIGGER "{trigger_name}" AFTER UPDATE OR INSERT ON "{from_table_real}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
+                        "#,
+                        update = common::with_public_in_search_path(&format!(
+                            r#"UPDATE "{}"."{}" "{}" SET "{}" = {} WHERE {};"#,
+                            existing_schema, self.table, self.table, self.column, value, r#where
+                        )),
+                        from_table = from_table.name,
+                        from_table_real = from_table.real_name,
+                        trigger_name = self.trigger_name(ctx),
+                    );
+                    db.run(&query).context("failed to create down trigger")?;
+
+                    let changed_into_variables = table
+                        .columns
+                        .iter()
+                        .map(|column| {
+                            format!(
+                                "NEW.{real_name} AS {alias}",
+                                alias = column.name,
+                                real_name = column.real_name,
+                            )
+                        })
+                        .collect::<Vec<String>>()
+                        .join(", ");
+
+                    let from_table_columns = from_table
+                        .columns
+                        .iter()
+                        .map(|column| format!("{} as {}", column.real_name, column.name))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+
+                    let state_schema = ctx.namespace.state_schema();
+                    let query = format!(
+                        r#"
+                        CREATE OR REPLACE FUNCTION {trigger_name}()
+                        RETURNS TRIGGER AS $$
+                        #variable_conflict use_variable
+                        BEGIN
+                            IF {state_schema}.is_new_schema() AND NOT current_setting('reshape.disable_triggers', TRUE) = 'TRUE' THEN
                                 DECLARE
-                                    {from_table} record;
+                                    {changed_table} record;
+                                    __temp_row record;
                                 BEGIN
-                                    {from_table} := __temp_row;
-                                    NEW.{column_name_real} = {value};
+                                    SELECT {changed_into_variables}
+                                    INTO {changed_table};
+
+                                    SELECT *
+                                    INTO __temp_row
+                                    FROM (
+                                        SELECT {from_table_columns}
+                                        FROM public.{from_table_real}
+                                    ) {from_table}
+                                    WHERE {where};
+
+                                    DECLARE
+                                        {from_table} record;
+                                    BEGIN
+                                        {from_table} := __temp_row;
+                                        NEW.{column_name_real} = {value};
+                                    END;
                                 END;
-                            END;
-                        END IF;
-                        RETURN NEW;
-                    END
-                    $$ language 'plpgsql';
-
-                    DROP TRIGGER IF EXISTS "{trigger_name}" ON "{changed_table_real}";
-                    CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{changed_table_real}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
-                    "#,
-                    changed_table = table.name,
-                    changed_table_real = table.real_name,
-                    from_table = from_table.name,
-                    from_table_real = from_table.real_name,
-                    column_name_real = column.real_name,
-                    trigger_name = self.reverse_trigger_name(ctx),
-                    // declarations = declarations.join("\n"),
-                );
-                db.run(&query)
-                    .context("failed to create reverse down trigger")?;
+                            END IF;
+                            RETURN NEW;
+                        END
+                        $$ language 'plpgsql';
+
+                        DROP TRIGGER IF EXISTS "{trigger_name}" ON "{changed_table_real}";
+                        CREATE TRIGGER "{trigger_name}" BEFORE UPDATE OR INSERT ON "{changed_table_real}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
+                        "#,
+                        changed_table = table.name,
+                        changed_table_real = table.real_name,
+                        from_table = from_table.name,
+                        from_table_real = from_table.real_name,
+                        column_name_real = column.real_name,
+                        trigger_name = self.reverse_trigger_name(ctx),
+                        // declarations = declarations.join("\n"),
+                    );
+                    db.run(&query)
+                        .context("failed to create reverse down trigger")?;
+                }
             }
-        }
 
-        Ok(())
+            Ok(())
+        })
     }
 
     fn complete<'a>(
@@ -327,6 +385,8 @@ impl Action for RemoveColumn {
         ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // Indices can't be dropped concurrently inside a transaction. Dropping them
+        // first is safe to repeat, as nothing else has changed yet.
         let indices = common::get_indices_for_column(db, &self.table, &self.column)
             .context("failed getting column indices")?;
 
@@ -340,6 +400,30 @@ impl Action for RemoveColumn {
             .context("failed to drop index")?;
         }
 
+        // The rows are archived in the same transaction as the column is dropped, so
+        // they aren't archived twice if completion is interrupted and run again
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        if self.archive {
+            let mut columns =
+                common::get_primary_key_columns_for_table(&mut transaction, &self.table)?;
+            if !columns.contains(&self.column) {
+                columns.push(self.column.to_string());
+            }
+            common::archive_rows(
+                &mut transaction,
+                ctx,
+                &self.table,
+                Some(&columns),
+                &format!("{}_{}", self.table, self.column),
+            )?;
+        }
+
+        // The auto_updated_at trigger would fail on every update once the column is gone
+        common::drop_auto_updated_at_triggers(&mut transaction, &self.table, Some(&self.column))?;
+
+        common::replace_publication_column(&mut transaction, &self.table, &self.column, None)?;
+
         // Remove column, function and trigger
         let query = format!(
             r#"
@@ -356,10 +440,15 @@ impl Action for RemoveColumn {
             reverse_trigger_name = self.reverse_trigger_name(ctx),
             null_trigger_name = self.not_null_constraint_trigger_name(ctx),
         );
-        db.run(&query)
+        transaction
+            .run(&query)
             .context("failed to drop column and down trigger")?;
 
-        Ok(None)
+        Ok(Some(transaction))
+    }
+
+    fn destructive(&self) -> bool {
+        true
     }
 
     fn update_schema(&self, _ctx: &MigrationContext, schema: &mut Schema) {
@@ -371,7 +460,11 @@ impl Action for RemoveColumn {
     }
 
     fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
-        // We might have temporaily removed the NOT NULL check and have to reinstate it
+        // `run` only drops NOT NULL from columns which had it when the down transformation
+        // is an update, and replaces it with a constraint trigger in the same transaction.
+        // The trigger's function is what tells us the constraint was dropped by us, so
+        // columns which were nullable to begin with are left alone. It's also only
+        // reinstated if the column is still nullable, so a retried abort doesn't do it twice.
         let has_not_null_function = !db
             .query_with_params(
                 "
@@ -384,11 +477,29 @@ impl Action for RemoveColumn {
             )
             .context("failed to get any NOT NULL function")?
             .is_empty();
+        let is_nullable = !db
+            .query_with_params(
+                "
+                SELECT attname
+                FROM pg_attribute
+                WHERE attrelid = to_regclass(format('public.%I', $1::TEXT))
+                AND attname = $2
+                AND NOT attnotnull
+                AND NOT attisdropped
+                ",
+                &[&self.table, &self.column],
+            )
+            .context("failed to check if column is nullable")?
+            .is_empty();
 
-        if has_not_null_function {
-            // Make column NOT NULL again without taking any long lived locks with a temporary constraint
+        if has_not_null_function && is_nullable {
+            // Make column NOT NULL again without taking any long lived locks with a temporary
+            // constraint, which might be left behind by an earlier abort that was interrupted
             let query = format!(
                 r#"
+                 ALTER TABLE "{table}"
+                 DROP CONSTRAINT IF EXISTS "{constraint_name}";
+
                  ALTER TABLE "{table}"
                  ADD CONSTRAINT "{constraint_name}"
                  CHECK ("{column}" IS NOT NULL) NOT VALID
@@ -400,16 +511,13 @@ impl Action for RemoveColumn {
             db.run(&query)
                 .context("failed to add NOT NULL constraint")?;
 
-            let query = format!(
-                r#"
-                ALTER TABLE "{table}"
-                VALIDATE CONSTRAINT "{constraint_name}"
-                "#,
-                table = self.table,
-                constraint_name = self.not_null_constraint_name(ctx),
-            );
-            db.run(&query)
-                .context("failed to validate NOT NULL constraint")?;
+            common::validate_not_null_constraint(
+                db,
+                &self.table,
+                &self.not_null_constraint_name(ctx),
+                &self.column,
+            )
+            .context("failed to validate NOT NULL constraint")?;
 
             // This ALTER TABLE call will not require any exclusive locks as it can use the validated constraint from above
             db.run(&format!(
@@ -422,12 +530,14 @@ impl Action for RemoveColumn {
                 column = self.column
             ))
             .context("failed to reinstate column NOT NULL")?;
+        }
 
+        if has_not_null_function {
             // Drop the temporary constraint
             let query = format!(
                 r#"
                 ALTER TABLE "{table}"
-                DROP CONSTRAINT "{constraint_name}"
+                DROP CONSTRAINT IF EXISTS "{constraint_name}"
                 "#,
                 table = self.table,
                 constraint_name = self.not_null_constraint_name(ctx),
@@ -451,4 +561,15 @@ impl Action for RemoveColumn {
 
         Ok(())
     }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.remove_column(&self.table, &self.column);
+        if let Some(Transformation::Update { table, .. }) = &self.down {
+            schema.require_table(table);
+        }
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
 }
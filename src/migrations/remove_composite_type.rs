@@ -0,0 +1,72 @@
+use super::{Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct RemoveCompositeType {
+    pub composite_type: String,
+}
+
+impl RemoveCompositeType {
+    pub fn new(composite_type: impl Into<String>) -> Self {
+        RemoveCompositeType {
+            composite_type: composite_type.into(),
+        }
+    }
+}
+
+#[typetag::serde(name = "remove_composite_type")]
+impl Action for RemoveCompositeType {
+    fn describe(&self) -> String {
+        format!("Removing composite type \"{}\"", self.composite_type)
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        transaction
+            .run(&format!(
+                r#"
+                DROP TYPE IF EXISTS "{name}"
+                "#,
+                name = self.composite_type,
+            ))
+            .context("failed to drop composite type")?;
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.remove_type(&self.composite_type);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::None
+    }
+}
@@ -0,0 +1,72 @@
+use super::{Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct RemoveDomain {
+    pub domain: String,
+}
+
+impl RemoveDomain {
+    pub fn new(domain: impl Into<String>) -> Self {
+        RemoveDomain {
+            domain: domain.into(),
+        }
+    }
+}
+
+#[typetag::serde(name = "remove_domain")]
+impl Action for RemoveDomain {
+    fn describe(&self) -> String {
+        format!("Removing domain \"{}\"", self.domain)
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        transaction
+            .run(&format!(
+                r#"
+                DROP DOMAIN IF EXISTS "{name}"
+                "#,
+                name = self.domain,
+            ))
+            .context("failed to drop domain")?;
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.remove_type(&self.domain);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::None
+    }
+}
@@ -1,17 +1,28 @@
-use super::{Action, MigrationContext};
+use super::{Action, LockLevel, LogicalSchema, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
     schema::Schema,
 };
 use anyhow::Context;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct RemoveEnum {
     #[serde(rename = "enum")]
     pub enum_name: String,
 }
 
+impl RemoveEnum {
+    pub fn new(enum_name: impl Into<String>) -> Self {
+        RemoveEnum {
+            enum_name: enum_name.into(),
+        }
+    }
+}
+
 #[typetag::serde(name = "remove_enum")]
 impl Action for RemoveEnum {
     fn describe(&self) -> String {
@@ -32,15 +43,18 @@ impl Action for RemoveEnum {
         _ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
-        db.run(&format!(
-            r#"
-            DROP TYPE IF EXISTS {name}
-            "#,
-            name = self.enum_name,
-        ))
-        .context("failed to drop enum")?;
-
-        Ok(None)
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        transaction
+            .run(&format!(
+                r#"
+                DROP TYPE IF EXISTS {name}
+                "#,
+                name = self.enum_name,
+            ))
+            .context("failed to drop enum")?;
+
+        Ok(Some(transaction))
     }
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
@@ -48,4 +62,12 @@ impl Action for RemoveEnum {
     fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
         Ok(())
     }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.remove_type(&self.enum_name);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::None
+    }
 }
@@ -1,17 +1,29 @@
-use super::{Action, MigrationContext};
+use super::{Action, LockLevel, LogicalSchema, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
     schema::Schema,
 };
 use anyhow::{anyhow, Context};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct RemoveForeignKey {
     table: String,
     foreign_key: String,
 }
 
+impl RemoveForeignKey {
+    pub fn new(table: impl Into<String>, foreign_key: impl Into<String>) -> Self {
+        RemoveForeignKey {
+            table: table.into(),
+            foreign_key: foreign_key.into(),
+        }
+    }
+}
+
 #[typetag::serde(name = "remove_foreign_key")]
 impl Action for RemoveForeignKey {
     fn describe(&self) -> String {
@@ -71,16 +83,19 @@ impl Action for RemoveForeignKey {
         _ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
-        db.run(&format!(
-            r#"
-            ALTER TABLE {table}
-            DROP CONSTRAINT IF EXISTS {foreign_key}
-            "#,
-            table = self.table,
-            foreign_key = self.foreign_key,
-        ))
-        .context("failed to remove foreign key")?;
-        Ok(None)
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        transaction
+            .run(&format!(
+                r#"
+                ALTER TABLE {table}
+                DROP CONSTRAINT IF EXISTS {foreign_key}
+                "#,
+                table = self.table,
+                foreign_key = self.foreign_key,
+            ))
+            .context("failed to remove foreign key")?;
+        Ok(Some(transaction))
     }
 
     fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
@@ -88,4 +103,12 @@ impl Action for RemoveForeignKey {
     fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
         Ok(())
     }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_table(&self.table);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
 }
@@ -1,16 +1,27 @@
-use super::{Action, MigrationContext};
+use super::{Action, LockLevel, LogicalSchema, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
     schema::Schema,
 };
 use anyhow::Context;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct RemoveIndex {
     pub index: String,
 }
 
+impl RemoveIndex {
+    pub fn new(index: impl Into<String>) -> Self {
+        RemoveIndex {
+            index: index.into(),
+        }
+    }
+}
+
 #[typetag::serde(name = "remove_index")]
 impl Action for RemoveIndex {
     fn describe(&self) -> String {
@@ -48,4 +59,12 @@ impl Action for RemoveIndex {
     fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
         Ok(())
     }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.remove_index(&self.index);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::ShareUpdateExclusive
+    }
 }
@@ -1,14 +1,35 @@
-use super::{Action, MigrationContext};
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
     schema::Schema,
 };
 use anyhow::Context;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct RemoveTable {
     pub table: String,
+
+    // Copy the table into the archive schema before it's dropped
+    #[serde(default)]
+    pub archive: bool,
+}
+
+impl RemoveTable {
+    pub fn new(table: impl Into<String>) -> Self {
+        RemoveTable {
+            table: table.into(),
+            archive: false,
+        }
+    }
+
+    pub fn with_archive(mut self, archive: bool) -> Self {
+        self.archive = archive;
+        self
+    }
 }
 
 #[typetag::serde(name = "remove_table")]
@@ -28,9 +49,20 @@ impl Action for RemoveTable {
 
     fn complete<'a>(
         &self,
-        _ctx: &MigrationContext,
+        ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // The rows are archived in the same transaction as the table is dropped, so
+        // they aren't archived twice if completion is interrupted and run again
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        if self.archive {
+            common::archive_rows(&mut transaction, ctx, &self.table, None, &self.table)?;
+        }
+
+        // Remove any auto_updated_at functions as they aren't dropped with the table
+        common::drop_auto_updated_at_triggers(&mut transaction, &self.table, None)?;
+
         // Remove table
         let query = format!(
             r#"
@@ -38,9 +70,13 @@ impl Action for RemoveTable {
             "#,
             table = self.table,
         );
-        db.run(&query).context("failed to drop table")?;
+        transaction.run(&query).context("failed to drop table")?;
+
+        Ok(Some(transaction))
+    }
 
-        Ok(None)
+    fn destructive(&self) -> bool {
+        true
     }
 
     fn update_schema(&self, _ctx: &MigrationContext, schema: &mut Schema) {
@@ -52,4 +88,12 @@ impl Action for RemoveTable {
     fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
         Ok(())
     }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.remove_table(&self.table);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
 }
@@ -0,0 +1,90 @@
+use super::{
+    add_table_to_publication::ensure_publication_exists, common, Action, LockLevel, LogicalSchema,
+    MigrationContext,
+};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct RemoveTableFromPublication {
+    pub publication: String,
+    pub table: String,
+}
+
+impl RemoveTableFromPublication {
+    pub fn new(publication: impl Into<String>, table: impl Into<String>) -> Self {
+        RemoveTableFromPublication {
+            publication: publication.into(),
+            table: table.into(),
+        }
+    }
+}
+
+#[typetag::serde(name = "remove_table_from_publication")]
+impl Action for RemoveTableFromPublication {
+    fn describe(&self) -> String {
+        format!(
+            "Removing table \"{}\" from publication \"{}\"",
+            self.table, self.publication
+        )
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        // The table keeps being published until the migration is completed as the
+        // old schema is still in use
+        ensure_publication_exists(db, &self.publication)?;
+        schema.get_table(db, &self.table)?;
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        let is_member = common::get_publications_for_table(&mut transaction, &self.table)?
+            .iter()
+            .any(|published| published.publication == self.publication);
+
+        if is_member {
+            transaction
+                .run(&format!(
+                    r#"ALTER PUBLICATION "{publication}" DROP TABLE public."{table}""#,
+                    publication = self.publication,
+                    table = self.table,
+                ))
+                .context("failed to remove table from publication")?;
+        }
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_table(&self.table);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::ShareUpdateExclusive
+    }
+}
@@ -1,17 +1,29 @@
-use super::{Action, MigrationContext};
+use super::{Action, LockLevel, LogicalSchema, MigrationContext};
 use crate::{
     db::{Conn, Transaction},
     schema::Schema,
 };
 use anyhow::Context;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
 pub struct RenameTable {
     pub table: String,
     pub new_name: String,
 }
 
+impl RenameTable {
+    pub fn new(table: impl Into<String>, new_name: impl Into<String>) -> Self {
+        RenameTable {
+            table: table.into(),
+            new_name: new_name.into(),
+        }
+    }
+}
+
 #[typetag::serde(name = "rename_table")]
 impl Action for RenameTable {
     fn describe(&self) -> String {
@@ -32,6 +44,8 @@ impl Action for RenameTable {
         _ctx: &MigrationContext,
         db: &'a mut dyn Conn,
     ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
         // Rename table
         let query = format!(
             r#"
@@ -41,9 +55,9 @@ impl Action for RenameTable {
             table = self.table,
             new_name = self.new_name,
         );
-        db.run(&query).context("failed to rename table")?;
+        transaction.run(&query).context("failed to rename table")?;
 
-        Ok(None)
+        Ok(Some(transaction))
     }
 
     fn update_schema(&self, _ctx: &MigrationContext, schema: &mut Schema) {
@@ -55,4 +69,16 @@ impl Action for RenameTable {
     fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
         Ok(())
     }
+
+    fn created_names(&self) -> Vec<&str> {
+        vec![&self.new_name]
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.rename_table(&self.table, &self.new_name);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
 }
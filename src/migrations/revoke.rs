@@ -0,0 +1,106 @@
+use super::{
+    common,
+    grant::{check_privilege_change, normalize_privileges, normalize_role},
+    Action, LockLevel, LogicalSchema, MigrationContext,
+};
+use crate::{
+    db::{Conn, Transaction},
+    schema::{self, Schema},
+};
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Revoke {
+    pub table: String,
+
+    // Role to revoke the privileges from, or PUBLIC for all roles
+    pub role: String,
+
+    // Privileges like SELECT and INSERT, or ALL for every privilege
+    pub privileges: Vec<String>,
+}
+
+impl Revoke {
+    pub fn new(
+        table: impl Into<String>,
+        role: impl Into<String>,
+        privileges: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Revoke {
+            table: table.into(),
+            role: role.into(),
+            privileges: common::into_strings(privileges),
+        }
+    }
+}
+
+#[typetag::serde(name = "revoke")]
+impl Action for Revoke {
+    fn describe(&self) -> String {
+        format!(
+            "Revoking {} on \"{}\" from \"{}\"",
+            self.privileges.join(", "),
+            self.table,
+            self.role
+        )
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        // The privileges are kept on the table and the views for the old schema until
+        // the migration is completed, only the views for the new schema lack them
+        check_privilege_change(db, schema, &self.table, &self.role, &self.privileges)
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        transaction
+            .run(&format!(
+                r#"REVOKE {privileges} ON TABLE "{table}" FROM {grantee}"#,
+                privileges = normalize_privileges(&self.privileges).join(", "),
+                table = self.table,
+                grantee = schema::quote_grantee(&normalize_role(&self.role)),
+            ))
+            .context("failed to revoke privileges")?;
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, schema: &mut Schema) {
+        schema.change_table(&self.table, |table_changes| {
+            table_changes.revoke(
+                &normalize_role(&self.role),
+                &normalize_privileges(&self.privileges),
+            );
+        });
+    }
+
+    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn can_run_atomically(&self) -> bool {
+        true
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_table(&self.table);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::None
+    }
+}
@@ -0,0 +1,656 @@
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{self, Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::{anyhow, bail, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// RewriteTable copies a table into a new shadow table which replaces it when the
+// migration is completed. This removes bloat and can change the physical order of the
+// columns. Rows are copied in batches and writes made during the migration are
+// mirrored to the shadow table using triggers.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct RewriteTable {
+    pub table: String,
+
+    // New order of the columns, which must include every column of the table. The
+    // existing order is kept if not set.
+    pub columns: Option<Vec<String>>,
+}
+
+impl RewriteTable {
+    pub fn new(table: impl Into<String>) -> Self {
+        RewriteTable {
+            table: table.into(),
+            columns: None,
+        }
+    }
+
+    pub fn with_columns(mut self, columns: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.columns = Some(common::into_strings(columns));
+        self
+    }
+
+    fn shadow_table_name(&self, ctx: &MigrationContext) -> String {
+        format!("{}_rewrite_{}", ctx.prefix(), self.table)
+    }
+
+    fn old_table_name(&self, ctx: &MigrationContext) -> String {
+        format!("{}_rewrite_old_{}", ctx.prefix(), self.table)
+    }
+
+    fn trigger_name(&self, ctx: &MigrationContext) -> String {
+        format!("{}_rewrite_table_{}", ctx.prefix(), self.table)
+    }
+
+    fn truncate_trigger_name(&self, ctx: &MigrationContext) -> String {
+        format!("{}_rewrite_table_{}_truncate", ctx.prefix(), self.table)
+    }
+
+    fn temp_index_name(&self, ctx: &MigrationContext, index_oid: u32) -> String {
+        format!("{}_rewrite_index_{}", ctx.prefix(), index_oid)
+    }
+}
+
+struct TableColumn {
+    name: String,
+    data_type: String,
+    not_null: bool,
+    default: Option<String>,
+    identity: String,
+    generated: bool,
+}
+
+impl TableColumn {
+    fn definition(&self) -> String {
+        let mut parts = vec![format!("\"{}\"", self.name), self.data_type.to_string()];
+
+        match (self.identity.as_str(), &self.default) {
+            ("a", _) => parts.push("GENERATED ALWAYS AS IDENTITY".to_string()),
+            ("d", _) => parts.push("GENERATED BY DEFAULT AS IDENTITY".to_string()),
+            (_, Some(expression)) if self.generated => {
+                parts.push(format!("GENERATED ALWAYS AS ({}) STORED", expression))
+            }
+            (_, Some(default)) => parts.push(format!("DEFAULT {}", default)),
+            _ => {}
+        }
+
+        if self.not_null {
+            parts.push("NOT NULL".to_string());
+        }
+
+        parts.join(" ")
+    }
+}
+
+fn get_columns(db: &mut dyn Conn, table: &str) -> anyhow::Result<Vec<TableColumn>> {
+    let columns = db
+        .query(&format!(
+            "
+            SELECT
+                pg_attribute.attname::TEXT AS name,
+                format_type(pg_attribute.atttypid, pg_attribute.atttypmod) AS data_type,
+                pg_attribute.attnotnull AS not_null,
+                pg_get_expr(pg_attrdef.adbin, pg_attrdef.adrelid) AS default,
+                pg_attribute.attidentity::TEXT AS identity,
+                pg_attribute.attgenerated::TEXT = 's' AS generated
+            FROM pg_attribute
+            LEFT JOIN pg_attrdef
+                ON pg_attrdef.adrelid = pg_attribute.attrelid AND pg_attrdef.adnum = pg_attribute.attnum
+            WHERE pg_attribute.attrelid = 'public.\"{table}\"'::regclass
+                AND pg_attribute.attnum > 0
+                AND NOT pg_attribute.attisdropped
+            ORDER BY pg_attribute.attnum
+            ",
+            table = table,
+        ))
+        .context("failed to get columns")?
+        .iter()
+        .map(|row| TableColumn {
+            name: row.get("name"),
+            data_type: row.get("data_type"),
+            not_null: row.get("not_null"),
+            default: row.get("default"),
+            identity: row.get("identity"),
+            generated: row.get("generated"),
+        })
+        .collect();
+
+    Ok(columns)
+}
+
+struct TableIndex {
+    name: String,
+    oid: u32,
+    unique: bool,
+    // Everything after the index name and table in the index definition, starting
+    // with `USING`
+    definition: String,
+    constraint: Option<(String, String)>,
+}
+
+fn get_indices(db: &mut dyn Conn, table: &str) -> anyhow::Result<Vec<TableIndex>> {
+    let indices = db
+        .query(&format!(
+            "
+            SELECT
+                index_class.relname::TEXT AS name,
+                index_class.oid AS oid,
+                pg_index.indisunique AS unique,
+                substring(pg_get_indexdef(pg_index.indexrelid) from ' USING .*$') AS definition,
+                pg_constraint.conname::TEXT AS constraint_name,
+                pg_constraint.contype::TEXT AS constraint_type
+            FROM pg_index
+            JOIN pg_class index_class ON index_class.oid = pg_index.indexrelid
+            LEFT JOIN pg_constraint ON pg_constraint.conindid = pg_index.indexrelid
+                AND pg_constraint.conrelid = pg_index.indrelid
+            WHERE pg_index.indrelid = 'public.\"{table}\"'::regclass
+            ",
+            table = table,
+        ))
+        .context("failed to get indices")?
+        .iter()
+        .map(|row| {
+            let constraint_name: Option<String> = row.get("constraint_name");
+            TableIndex {
+                name: row.get("name"),
+                oid: row.get("oid"),
+                unique: row.get("unique"),
+                definition: row.get("definition"),
+                constraint: constraint_name
+                    .map(|name| (name, row.get::<'_, _, String>("constraint_type"))),
+            }
+        })
+        .collect();
+
+    Ok(indices)
+}
+
+// Objects which can't be moved to the shadow table are rejected before any changes
+// are made, as the migration can't be aborted once completion has started
+fn check_supported(db: &mut dyn Conn, table: &str) -> anyhow::Result<()> {
+    let checks = [
+        (
+            "
+            SELECT conname::TEXT AS name FROM pg_constraint
+            WHERE confrelid = 'public.\"{table}\"'::regclass AND conrelid <> confrelid
+            ",
+            "foreign key {name} references the table",
+        ),
+        (
+            "
+            SELECT conname::TEXT AS name FROM pg_constraint
+            WHERE conrelid = 'public.\"{table}\"'::regclass
+                AND (contype IN ('x', 't') OR (contype IN ('p', 'u') AND condeferrable))
+            ",
+            "constraint {name} can't be copied",
+        ),
+        (
+            "
+            SELECT tgname::TEXT AS name FROM pg_trigger
+            WHERE tgrelid = 'public.\"{table}\"'::regclass
+                AND NOT tgisinternal
+                AND tgname NOT LIKE '\\_\\_reshape%'
+                AND tgname NOT LIKE 'reshape\\_auto\\_updated\\_at\\_%'
+            ",
+            "trigger {name} can't be copied",
+        ),
+        (
+            "
+            SELECT column_name::TEXT AS name FROM information_schema.columns
+            WHERE table_schema = 'public' AND table_name = '{table}'
+                AND column_name LIKE '\\_\\_reshape%'
+            ",
+            "column {name} is being changed by another action in the migration",
+        ),
+    ];
+
+    for (query, problem) in checks {
+        let rows = db.query(&query.replace("{table}", table))?;
+        if let Some(row) = rows.first() {
+            let name: String = row.get("name");
+            bail!(
+                "can't rewrite table \"{}\" as {}",
+                table,
+                problem.replace("{name}", &format!("\"{}\"", name))
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[typetag::serde(name = "rewrite_table")]
+impl Action for RewriteTable {
+    fn describe(&self) -> String {
+        format!("Rewriting table \"{}\"", self.table)
+    }
+
+    fn run(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        let table = schema.get_table(db, &self.table)?;
+        check_supported(db, &table.real_name)?;
+
+        let primary_key = common::get_primary_key_columns_for_table(db, &table.real_name)?;
+        if primary_key.is_empty() {
+            bail!(
+                "can't rewrite table \"{}\" as it has no primary key",
+                self.table
+            );
+        }
+
+        let mut columns = get_columns(db, &table.real_name)?;
+        if let Some(order) = &self.columns {
+            let real_order: Vec<&String> = table.real_column_names(order).collect();
+            if real_order.len() != columns.len()
+                || columns
+                    .iter()
+                    .any(|column| !real_order.contains(&&column.name))
+            {
+                bail!(
+                    "columns must list every column of table \"{}\" exactly once",
+                    self.table
+                );
+            }
+
+            columns.sort_by_key(|column| {
+                real_order
+                    .iter()
+                    .position(|name| **name == column.name)
+                    .unwrap_or(usize::MAX)
+            });
+        }
+
+        let shadow_table = self.shadow_table_name(ctx);
+
+        // Generated columns are computed by the shadow table itself
+        let copied_columns: Vec<String> = columns
+            .iter()
+            .filter(|column| !column.generated)
+            .map(|column| format!("\"{}\"", column.name))
+            .collect();
+        let new_values: Vec<String> = columns
+            .iter()
+            .filter(|column| !column.generated)
+            .map(|column| format!("NEW.\"{}\"", column.name))
+            .collect();
+        let primary_key_columns: Vec<String> = primary_key
+            .iter()
+            .map(|column| format!("\"{}\"", column))
+            .collect();
+        let old_primary_key: Vec<String> = primary_key
+            .iter()
+            .map(|column| format!("OLD.\"{}\"", column))
+            .collect();
+
+        // The shadow table is set up in a single transaction, so the trigger mirroring
+        // writes only exists once the table it writes to is complete
+        db::atomically(db, |db| {
+            let definitions: Vec<String> = columns.iter().map(TableColumn::definition).collect();
+            db.run(&format!(
+                r#"
+                CREATE TABLE IF NOT EXISTS public."{shadow_table}" (
+                    {definitions}
+                )
+                "#,
+                definitions = definitions.join(",\n"),
+            ))
+            .context("failed to create shadow table")?;
+
+            // Check and foreign key constraints are named per table so they can be copied
+            // with their names straight away
+            let constraints = db
+                .query(&format!(
+                    "
+                    SELECT
+                        conname::TEXT AS name,
+                        pg_get_constraintdef(oid) AS definition,
+                        EXISTS (
+                            SELECT 1 FROM pg_constraint shadow
+                            WHERE shadow.conrelid = 'public.\"{shadow_table}\"'::regclass
+                                AND shadow.conname = pg_constraint.conname
+                        ) AS copied
+                    FROM pg_constraint
+                    WHERE conrelid = 'public.\"{table}\"'::regclass AND contype IN ('c', 'f')
+                    ",
+                    table = table.real_name,
+                ))
+                .context("failed to get constraints")?;
+            for constraint in constraints {
+                if constraint.get("copied") {
+                    continue;
+                }
+
+                let name: String = constraint.get("name");
+                let definition: String = constraint.get("definition");
+                db.run(&format!(
+                    r#"
+                    ALTER TABLE "{shadow_table}"
+                    ADD CONSTRAINT "{name}" {definition}
+                    "#,
+                ))
+                .with_context(|| format!("failed to copy constraint {}", name))?;
+            }
+
+            // Indices are named per schema so they are created with temporary names and
+            // renamed when the migration is completed. They are created before any rows
+            // are copied as the unique indices are needed to skip rows which have already
+            // been copied.
+            for index in get_indices(db, &table.real_name)? {
+                let unique = if index.unique { "UNIQUE" } else { "" };
+                db.run(&format!(
+                    r#"
+                    CREATE {unique} INDEX IF NOT EXISTS "{temp_index}" ON public."{shadow_table}" {definition}
+                    "#,
+                    temp_index = self.temp_index_name(ctx, index.oid),
+                    definition = index.definition,
+                ))
+                .with_context(|| format!("failed to copy index {}", index.name))?;
+            }
+
+            // Mirror all writes to the shadow table. Updates replace the row entirely so
+            // changes to the primary key are handled as well.
+            let query = format!(
+                r#"
+                CREATE OR REPLACE FUNCTION {trigger_name}()
+                RETURNS TRIGGER AS $$
+                BEGIN
+                    IF TG_OP = 'TRUNCATE' THEN
+                        TRUNCATE public."{shadow_table}";
+                        RETURN NULL;
+                    END IF;
+
+                    IF TG_OP = 'UPDATE' OR TG_OP = 'DELETE' THEN
+                        DELETE FROM public."{shadow_table}"
+                        WHERE ({primary_key_columns}) = ({old_primary_key});
+                    END IF;
+
+                    IF TG_OP = 'UPDATE' OR TG_OP = 'INSERT' THEN
+                        INSERT INTO public."{shadow_table}" ({copied_columns})
+                        OVERRIDING SYSTEM VALUE
+                        VALUES ({new_values});
+                    END IF;
+
+                    RETURN NULL;
+                END
+                $$ language 'plpgsql';
+
+                DROP TRIGGER IF EXISTS "{trigger_name}" ON public."{table}";
+                CREATE TRIGGER "{trigger_name}" AFTER INSERT OR UPDATE OR DELETE ON public."{table}" FOR EACH ROW EXECUTE PROCEDURE {trigger_name}();
+
+                DROP TRIGGER IF EXISTS "{truncate_trigger_name}" ON public."{table}";
+                CREATE TRIGGER "{truncate_trigger_name}" AFTER TRUNCATE ON public."{table}" FOR EACH STATEMENT EXECUTE PROCEDURE {trigger_name}();
+                "#,
+                trigger_name = self.trigger_name(ctx),
+                truncate_trigger_name = self.truncate_trigger_name(ctx),
+                table = table.real_name,
+                primary_key_columns = primary_key_columns.join(", "),
+                old_primary_key = old_primary_key.join(", "),
+                copied_columns = copied_columns.join(", "),
+                new_values = new_values.join(", "),
+            );
+            db.run(&query).context("failed to create sync trigger")?;
+
+            Ok(())
+        })?;
+
+        // Copy existing rows in batches. The rows are locked while they are copied so
+        // they can't be deleted by a concurrent transaction before the trigger is able
+        // to remove them from the shadow table. Rows which have already been copied by
+        // the trigger are skipped.
+        let primary_key_where: Vec<String> = primary_key
+            .iter()
+            .map(|column| format!("source.\"{column}\" = rows.\"{column}\"", column = column))
+            .collect();
+        let statement = format!(
+            r#"
+            INSERT INTO public."{shadow_table}" ({copied_columns})
+            OVERRIDING SYSTEM VALUE
+            SELECT {source_columns}
+            FROM public."{table}" source
+            JOIN rows ON {primary_key_where}
+            ON CONFLICT DO NOTHING
+            RETURNING 1
+            "#,
+            table = table.real_name,
+            copied_columns = copied_columns.join(", "),
+            source_columns = copied_columns
+                .iter()
+                .map(|column| format!("source.{}", column))
+                .collect::<Vec<String>>()
+                .join(", "),
+            primary_key_where = primary_key_where.join(" AND "),
+        );
+        common::batch_rows_by_primary_key(
+            db,
+            &table.real_name,
+            &primary_key,
+            true,
+            &statement,
+            None,
+        )
+        .context("failed to copy rows to shadow table")?;
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+
+        // Writes must be blocked while the tables are swapped. Completion must be
+        // idempotent so there's nothing to do if the swap has already happened.
+        let shadow_table = self.shadow_table_name(ctx);
+        let shadow_exists = !transaction
+            .query(&format!(
+                "SELECT 1 FROM pg_class WHERE oid = to_regclass('public.\"{}\"')",
+                shadow_table
+            ))?
+            .is_empty();
+        if !shadow_exists {
+            return Ok(Some(transaction));
+        }
+
+        transaction
+            .run(&format!(
+                r#"
+                SET LOCAL search_path TO public;
+                LOCK TABLE public."{table}" IN ACCESS EXCLUSIVE MODE;
+                DROP FUNCTION IF EXISTS "{trigger_name}" CASCADE;
+                "#,
+                table = self.table,
+                trigger_name = self.trigger_name(ctx),
+            ))
+            .context("failed to drop sync trigger")?;
+
+        // Views reference the table they select from directly, so any views using the
+        // table are recreated to point at the shadow table instead. The definitions
+        // are read before renaming so they refer to the table by its current name.
+        let views = transaction
+            .query(&format!(
+                "
+                SELECT DISTINCT
+                    dependent.oid::regclass::TEXT AS name,
+                    pg_get_viewdef(dependent.oid) AS definition
+                FROM pg_depend
+                JOIN pg_rewrite ON pg_rewrite.oid = pg_depend.objid
+                JOIN pg_class dependent ON dependent.oid = pg_rewrite.ev_class
+                WHERE pg_depend.refobjid = 'public.\"{table}\"'::regclass
+                    AND dependent.oid <> pg_depend.refobjid
+                    AND dependent.relkind = 'v'
+                ",
+                table = self.table,
+            ))
+            .context("failed to get dependent views")?;
+
+        let old_table = self.old_table_name(ctx);
+        transaction
+            .run(&format!(
+                r#"
+                ALTER TABLE public."{table}" RENAME TO "{old_table}";
+                ALTER TABLE public."{shadow_table}" RENAME TO "{table}";
+                "#,
+                table = self.table,
+            ))
+            .context("failed to swap tables")?;
+
+        for view in views {
+            let name: String = view.get("name");
+            let definition: String = view.get("definition");
+            transaction
+                .run(&format!(
+                    "CREATE OR REPLACE VIEW {} AS {}",
+                    name, definition
+                ))
+                .with_context(|| format!("failed to recreate view {}", name))?;
+        }
+
+        // Sequences owned by the old table would be dropped along with it. Identity
+        // columns get a new sequence which continues from the old one.
+        for column in get_columns(&mut transaction, &old_table)? {
+            let sequence: Option<String> = transaction
+                .query(&format!(
+                    "SELECT pg_get_serial_sequence('public.\"{}\"', '{}') AS sequence",
+                    old_table, column.name
+                ))?
+                .first()
+                .and_then(|row| row.get("sequence"));
+            let sequence = match sequence {
+                Some(sequence) => sequence,
+                None => continue,
+            };
+
+            let query = if column.identity.is_empty() {
+                format!(
+                    r#"ALTER SEQUENCE {sequence} OWNED BY public."{table}"."{column}""#,
+                    table = self.table,
+                    column = column.name,
+                )
+            } else {
+                format!(
+                    r#"
+                    SELECT setval(pg_get_serial_sequence('public."{table}"', '{column}'), last_value, is_called)
+                    FROM {sequence}
+                    "#,
+                    table = self.table,
+                    column = column.name,
+                )
+            };
+            transaction
+                .run(&query)
+                .with_context(|| format!("failed to move sequence {}", sequence))?;
+        }
+
+        let indices = get_indices(&mut transaction, &old_table)?;
+        let publications = common::get_publications_for_table(&mut transaction, &old_table)?;
+        let auto_updated_at_columns =
+            common::get_auto_updated_at_columns(&mut transaction, &old_table)?;
+
+        transaction
+            .run(&format!(r#"DROP TABLE public."{}""#, old_table))
+            .context("failed to drop old table")?;
+
+        // Dropping the old table removed it from any publications
+        for published in publications {
+            common::set_publication_table(
+                &mut transaction,
+                &published.publication,
+                &self.table,
+                published.columns.as_deref(),
+                published.row_filter.as_deref(),
+            )?;
+        }
+
+        for column in auto_updated_at_columns {
+            common::create_auto_updated_at_trigger(
+                &mut transaction,
+                &self.table,
+                &column,
+                &column,
+            )?;
+        }
+
+        // Give the indices their original names, turning them back into constraints
+        // where needed
+        for index in indices {
+            let temp_index = self.temp_index_name(ctx, index.oid);
+            let query = match &index.constraint {
+                Some((name, constraint_type)) => {
+                    let constraint_type = match constraint_type.as_str() {
+                        "p" => "PRIMARY KEY",
+                        "u" => "UNIQUE",
+                        _ => return Err(anyhow!("unexpected constraint {}", name)),
+                    };
+                    format!(
+                        r#"ALTER TABLE "{table}" ADD CONSTRAINT "{name}" {constraint_type} USING INDEX "{temp_index}""#,
+                        table = self.table,
+                    )
+                }
+                None => format!(
+                    r#"ALTER INDEX "{temp_index}" RENAME TO "{name}""#,
+                    name = index.name,
+                ),
+            };
+            transaction
+                .run(&query)
+                .with_context(|| format!("failed to rename index {}", index.name))?;
+        }
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, ctx: &MigrationContext, schema: &mut Schema) {
+        // The shadow table shouldn't be exposed in the new schema
+        schema.change_table(&self.shadow_table_name(ctx), |table_changes| {
+            table_changes.set_removed();
+        });
+
+        if let Some(columns) = &self.columns {
+            schema.change_table(&self.table, |table_changes| {
+                table_changes.set_column_order(columns);
+            });
+        }
+    }
+
+    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        db.run(&format!(
+            r#"
+            DROP FUNCTION IF EXISTS "{trigger_name}" CASCADE;
+            DROP TABLE IF EXISTS public."{shadow_table}";
+            "#,
+            trigger_name = self.trigger_name(ctx),
+            shadow_table = self.shadow_table_name(ctx),
+        ))
+        .context("failed to drop shadow table")?;
+
+        Ok(())
+    }
+
+    fn rewritten_tables(&self) -> Vec<&str> {
+        vec![self.table.as_str()]
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        match &self.columns {
+            Some(columns) => schema.reorder_columns(&self.table, columns),
+            None => {
+                schema.require_table(&self.table);
+            }
+        }
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
+}
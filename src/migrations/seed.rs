@@ -0,0 +1,370 @@
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+    state::{self, SeedProgress},
+};
+use anyhow::{anyhow, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Seed inserts data which environments need to be initialized with, like lookup
+// tables, either from CSV into a table or by running SQL. Migration files can point
+// `file` at a `.csv` or `.sql` file relative to the migration, which is read when the
+// migration is loaded, so the data is stored and hashed along with the migration.
+//
+// CSV rows are inserted in batches with `ON CONFLICT DO NOTHING`. The number of rows
+// inserted is saved in the state along with each batch, so an interrupted seed
+// continues after the rows it already inserted. Empty fields are inserted as NULL.
+// Seeds which run when starting are recorded as done in the same transaction as
+// their data, so retrying or resuming the migration doesn't run them twice.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct Seed {
+    // Table to insert CSV rows into, using the column names in the header
+    #[serde(default)]
+    pub table: Option<String>,
+
+    #[serde(default)]
+    pub file: Option<String>,
+
+    #[serde(default)]
+    pub csv: Option<String>,
+
+    #[serde(default)]
+    pub sql: Option<String>,
+
+    // When to insert the data. Seeding when starting makes the data available to the
+    // new schema straight away, but it isn't removed if the migration is aborted.
+    #[serde(default)]
+    pub phase: SeedPhase,
+
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedPhase {
+    Start,
+    #[default]
+    Complete,
+}
+
+fn default_batch_size() -> usize {
+    1000
+}
+
+impl Default for Seed {
+    fn default() -> Self {
+        Seed {
+            table: None,
+            file: None,
+            csv: None,
+            sql: None,
+            phase: SeedPhase::default(),
+            batch_size: default_batch_size(),
+        }
+    }
+}
+
+impl Seed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Insert CSV rows into `table`
+    pub fn with_csv(mut self, table: impl Into<String>, csv: impl Into<String>) -> Self {
+        self.table = Some(table.into());
+        self.csv = Some(csv.into());
+        self
+    }
+
+    pub fn with_sql(mut self, sql: impl Into<String>) -> Self {
+        self.sql = Some(sql.into());
+        self
+    }
+
+    pub fn with_phase(mut self, phase: SeedPhase) -> Self {
+        self.phase = phase;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    fn header(&self) -> anyhow::Result<Vec<String>> {
+        let csv = self.csv.as_deref().unwrap_or_default();
+        let header = csv::Reader::from_reader(csv.as_bytes())
+            .headers()
+            .context("failed to read CSV header")?
+            .iter()
+            .map(str::to_string)
+            .collect();
+        Ok(header)
+    }
+
+    // Insert the data, committing all but the last batch. The last batch is left in the
+    // returned transaction along with removing the progress, so completion can save its
+    // state in the same transaction.
+    fn seed<'a>(
+        &self,
+        ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+        table: &str,
+        columns: &[String],
+    ) -> anyhow::Result<Transaction<'a>> {
+        if let Some(sql) = &self.sql {
+            let mut transaction = db.transaction().context("failed to create transaction")?;
+            transaction.run(sql).context("failed to run seed")?;
+            return Ok(transaction);
+        }
+
+        let csv = self.csv.as_deref().ok_or_else(|| self.missing_data())?;
+        let rows = csv::Reader::from_reader(csv.as_bytes())
+            .records()
+            .collect::<Result<Vec<csv::StringRecord>, csv::Error>>()
+            .context("failed to read CSV")?;
+
+        let remaining = self.insert_batches(ctx, db, table, columns, &rows)?;
+
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+        if remaining < rows.len() {
+            transaction
+                .run(&insert_query(table, columns, &rows[remaining..]))
+                .with_context(|| format!("failed to insert seed rows into \"{}\"", table))?;
+        }
+        state::save_seed_progress(&mut transaction, None)?;
+        Ok(transaction)
+    }
+
+    // Insert and commit every batch but the last, continuing after the rows inserted
+    // before an interruption. Returns the index of the first row which is left.
+    fn insert_batches(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        table: &str,
+        columns: &[String],
+        rows: &[csv::StringRecord],
+    ) -> anyhow::Result<usize> {
+        let mut progress = SeedProgress {
+            migration: ctx.migration_name.to_string(),
+            action_index: ctx.action_index,
+            rows: 0,
+        };
+        if let Some(saved) = state::load_seed_progress(db)? {
+            if saved.migration == progress.migration && saved.action_index == progress.action_index
+            {
+                progress.rows = saved.rows;
+            }
+        }
+
+        let batch_size = self.batch_size.max(1);
+        while progress.rows + batch_size < rows.len() {
+            let batch_end = progress.rows + batch_size;
+            let mut transaction = db.transaction().context("failed to create transaction")?;
+            transaction
+                .run(&insert_query(
+                    table,
+                    columns,
+                    &rows[progress.rows..batch_end],
+                ))
+                .with_context(|| format!("failed to insert seed rows into \"{}\"", table))?;
+
+            progress.rows = batch_end;
+            state::save_seed_progress(&mut transaction, Some(&progress))?;
+            transaction.commit()?;
+        }
+
+        Ok(progress.rows.min(rows.len()))
+    }
+
+    // Seed when the migration is started, unless an earlier attempt already did
+    fn seed_on_start(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        table: &str,
+        columns: &[String],
+    ) -> anyhow::Result<()> {
+        if state::seeded_on_start(db, &ctx.migration_name, ctx.action_index)? {
+            return Ok(());
+        }
+
+        let mut transaction = self.seed(ctx, db, table, columns)?;
+        state::save_seeded_on_start(
+            &mut transaction,
+            &ctx.migration_name,
+            ctx.action_index,
+            true,
+        )?;
+        transaction.commit()
+    }
+
+    fn missing_data(&self) -> anyhow::Error {
+        match &self.file {
+            Some(file) => anyhow!("seed file {} hasn't been loaded", file),
+            None => anyhow!("seed must have either csv or sql"),
+        }
+    }
+}
+
+fn insert_query(table: &str, columns: &[String], rows: &[csv::StringRecord]) -> String {
+    let columns: Vec<String> = columns
+        .iter()
+        .map(|column| format!(r#""{}""#, column))
+        .collect();
+    let values: Vec<String> = rows
+        .iter()
+        .map(|row| {
+            let values: Vec<String> = row
+                .iter()
+                .map(|value| {
+                    if value.is_empty() {
+                        "NULL".to_string()
+                    } else {
+                        common::quote_literal(value)
+                    }
+                })
+                .collect();
+            format!("({})", values.join(", "))
+        })
+        .collect();
+
+    format!(
+        r#"INSERT INTO "{}" ({}) VALUES {} ON CONFLICT DO NOTHING"#,
+        table,
+        columns.join(", "),
+        values.join(", ")
+    )
+}
+
+#[typetag::serde(name = "seed")]
+impl Action for Seed {
+    fn describe(&self) -> String {
+        match (&self.table, &self.file) {
+            (Some(table), Some(file)) => format!("Seeding \"{}\" from {}", table, file),
+            (Some(table), None) => format!("Seeding \"{}\"", table),
+            (None, Some(file)) => format!("Running seed {}", file),
+            (None, None) => "Running seed".to_string(),
+        }
+    }
+
+    fn run(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        if self.csv.is_none() && self.sql.is_none() {
+            return Err(self.missing_data());
+        }
+        if self.csv.is_none() {
+            if self.phase == SeedPhase::Start {
+                self.seed_on_start(ctx, db, "", &[])?;
+            }
+            return Ok(());
+        }
+
+        let table_name = self
+            .table
+            .as_ref()
+            .ok_or_else(|| anyhow!("CSV seeds must have a table"))?;
+        let table = schema.get_table(db, table_name)?;
+
+        // Check up front so completion doesn't fail
+        let columns = self
+            .header()?
+            .iter()
+            .map(|name| {
+                table
+                    .get_column(name)
+                    .map(|column| column.real_name.to_string())
+                    .ok_or_else(|| {
+                        anyhow!("no column \"{}\" exists on table \"{}\"", name, table.name)
+                    })
+            })
+            .collect::<anyhow::Result<Vec<String>>>()?;
+
+        if self.phase == SeedPhase::Start {
+            self.seed_on_start(ctx, db, &table.real_name, &columns)?;
+        }
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        if self.phase != SeedPhase::Complete {
+            state::save_seeded_on_start(db, &ctx.migration_name, ctx.action_index, false)?;
+            return Ok(None);
+        }
+
+        // Earlier actions in the migration have been completed by now, so the table
+        // and columns are referenced by the names they have at this point in the migration
+        let table = self.table.as_deref().unwrap_or_default();
+        let columns = if self.csv.is_some() {
+            self.header()?
+        } else {
+            Vec::new()
+        };
+        Ok(Some(self.seed(ctx, db, table, &columns)?))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        // Progress of an interrupted seed doesn't apply if the migration is started again
+        if let Some(progress) = state::load_seed_progress(db)? {
+            if progress.migration == ctx.migration_name && progress.action_index == ctx.action_index
+            {
+                state::save_seed_progress(db, None)?;
+            }
+        }
+        state::save_seeded_on_start(db, &ctx.migration_name, ctx.action_index, false)?;
+
+        Ok(())
+    }
+
+    fn can_run_atomically(&self) -> bool {
+        true
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        if self.csv.is_none() && self.sql.is_none() {
+            schema.problem(self.missing_data().to_string());
+            return;
+        }
+        if self.csv.is_none() {
+            return;
+        }
+
+        let table = match &self.table {
+            Some(table) => table,
+            None => {
+                schema.problem("CSV seeds must have a table");
+                return;
+            }
+        };
+        match self.header() {
+            Ok(header) => schema.require_columns(table, header.iter()),
+            Err(err) => schema.problem(format!("{:#}", err)),
+        }
+    }
+
+    // SQL seed data might do anything
+    fn max_lock_level(&self) -> LockLevel {
+        if self.csv.is_some() {
+            LockLevel::RowExclusive
+        } else {
+            LockLevel::AccessExclusive
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::{anyhow, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct SetColumnComment {
+    pub table: String,
+    pub column: String,
+
+    // Leave out to remove the comment
+    pub comment: Option<String>,
+}
+
+impl SetColumnComment {
+    pub fn new(
+        table: impl Into<String>,
+        column: impl Into<String>,
+        comment: Option<impl Into<String>>,
+    ) -> Self {
+        SetColumnComment {
+            table: table.into(),
+            column: column.into(),
+            comment: comment.map(Into::into),
+        }
+    }
+}
+
+#[typetag::serde(name = "set_column_comment")]
+impl Action for SetColumnComment {
+    fn describe(&self) -> String {
+        format!(
+            "Setting comment on column \"{}\" on \"{}\"",
+            self.column, self.table
+        )
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        // Check up front so completion doesn't fail
+        let table = schema.get_table(db, &self.table)?;
+        if table.get_column(&self.column).is_none() {
+            return Err(anyhow!(
+                "no column \"{}\" exists on table \"{}\"",
+                self.column,
+                self.table
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // Earlier actions in the migration have been completed by now, so the column
+        // is referenced by the name it has at this point in the migration
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+        common::set_comment(
+            &mut transaction,
+            &format!(r#"COLUMN "{}"."{}""#, self.table, self.column),
+            self.comment.as_deref(),
+        )?;
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn can_run_atomically(&self) -> bool {
+        true
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_columns(&self.table, [&self.column]);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::ShareUpdateExclusive
+    }
+}
@@ -0,0 +1,161 @@
+use super::{Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::{anyhow, bail, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct SetReplicaIdentity {
+    pub table: String,
+    pub identity: ReplicaIdentity,
+
+    // The unique index which identifies rows, required when `identity` is "index"
+    pub index: Option<String>,
+}
+
+impl SetReplicaIdentity {
+    pub fn new(table: impl Into<String>, identity: ReplicaIdentity) -> Self {
+        SetReplicaIdentity {
+            table: table.into(),
+            identity,
+            index: None,
+        }
+    }
+
+    // Identify rows using a unique index
+    pub fn using_index(table: impl Into<String>, index: impl Into<String>) -> Self {
+        SetReplicaIdentity {
+            table: table.into(),
+            identity: ReplicaIdentity::Index,
+            index: Some(index.into()),
+        }
+    }
+}
+
+// Which columns of the old row are written to the WAL on updates and deletes, which
+// logical decoding consumers use to find the row that changed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicaIdentity {
+    // The primary key, if there is one
+    Default,
+    // Every column
+    Full,
+    // No columns, updates and deletes can't be replicated
+    Nothing,
+    // The columns of a unique index
+    Index,
+}
+
+impl SetReplicaIdentity {
+    // Postgres only accepts unique, non-partial and non-deferrable indices on the table
+    // itself. Nullable columns are rejected too, but aren't checked here as an earlier
+    // `alter_column` in the same migration might only make them NOT NULL on completion.
+    fn check_index(&self, db: &mut dyn Conn, table: &str, index: &str) -> anyhow::Result<()> {
+        let rows = db
+            .query_with_params(
+                "
+                SELECT
+                    ix.indrelid = to_regclass(format('public.%I', $2::TEXT)) AS on_table,
+                    ix.indisunique
+                        AND ix.indimmediate
+                        AND ix.indpred IS NULL
+                        AND ix.indexprs IS NULL AS usable
+                FROM pg_index ix
+                WHERE ix.indexrelid = to_regclass(format('public.%I', $1::TEXT))
+                ",
+                &[&index, &table],
+            )
+            .context("failed to get index")?;
+        let row = rows
+            .first()
+            .ok_or_else(|| anyhow!("index \"{}\" doesn't exist", index))?;
+
+        if !row.get::<_, bool>("on_table") {
+            bail!("index \"{}\" isn't on table \"{}\"", index, self.table);
+        }
+        if !row.get::<_, bool>("usable") {
+            bail!(
+                "index \"{}\" can't be used as replica identity, it must be unique, non-partial, non-deferrable and only contain columns",
+                index
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[typetag::serde(name = "set_replica_identity")]
+impl Action for SetReplicaIdentity {
+    fn describe(&self) -> String {
+        format!("Setting replica identity of table \"{}\"", self.table)
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        // Like publications, the replica identity only matters to subscribers and is
+        // changed once the migration is completed. Everything is checked up front so
+        // completion doesn't fail halfway.
+        match (self.identity, &self.index) {
+            (ReplicaIdentity::Index, Some(index)) => {
+                let table = schema.get_table(db, &self.table)?;
+                self.check_index(db, &table.real_name, index)
+            }
+            (ReplicaIdentity::Index, None) => {
+                bail!("an index must be set when identity is \"index\"")
+            }
+            (_, Some(_)) => bail!("an index can only be set when identity is \"index\""),
+            (_, None) => Ok(()),
+        }
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let identity = match (self.identity, &self.index) {
+            (ReplicaIdentity::Default, _) => "DEFAULT".to_string(),
+            (ReplicaIdentity::Full, _) => "FULL".to_string(),
+            (ReplicaIdentity::Nothing, _) => "NOTHING".to_string(),
+            (ReplicaIdentity::Index, Some(index)) => format!(r#"USING INDEX "{}""#, index),
+            (ReplicaIdentity::Index, None) => {
+                bail!("an index must be set when identity is \"index\"")
+            }
+        };
+
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+        transaction
+            .run(&format!(
+                r#"ALTER TABLE "{table}" REPLICA IDENTITY {identity}"#,
+                table = self.table,
+                identity = identity,
+            ))
+            .context("failed to set replica identity")?;
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_table(&self.table);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
+}
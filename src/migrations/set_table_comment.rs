@@ -0,0 +1,78 @@
+use super::{common, Action, LockLevel, LogicalSchema, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct SetTableComment {
+    pub table: String,
+
+    // Leave out to remove the comment
+    pub comment: Option<String>,
+}
+
+impl SetTableComment {
+    pub fn new(table: impl Into<String>, comment: Option<impl Into<String>>) -> Self {
+        SetTableComment {
+            table: table.into(),
+            comment: comment.map(Into::into),
+        }
+    }
+}
+
+#[typetag::serde(name = "set_table_comment")]
+impl Action for SetTableComment {
+    fn describe(&self) -> String {
+        format!("Setting comment on table \"{}\"", self.table)
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // Comments don't affect the application, so they are only set once the
+        // migration is completed and there is nothing to undo when aborting
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+        common::set_comment(
+            &mut transaction,
+            &format!(r#"TABLE "{}""#, self.table),
+            self.comment.as_deref(),
+        )?;
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn can_run_atomically(&self) -> bool {
+        true
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_table(&self.table);
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::ShareUpdateExclusive
+    }
+}
@@ -0,0 +1,73 @@
+use super::{common, Action, LockLevel, MigrationContext};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Sets the comment on an enum, domain or composite type
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct SetTypeComment {
+    pub name: String,
+
+    // Leave out to remove the comment
+    pub comment: Option<String>,
+}
+
+impl SetTypeComment {
+    pub fn new(name: impl Into<String>, comment: Option<impl Into<String>>) -> Self {
+        SetTypeComment {
+            name: name.into(),
+            comment: comment.map(Into::into),
+        }
+    }
+}
+
+#[typetag::serde(name = "set_type_comment")]
+impl Action for SetTypeComment {
+    fn describe(&self) -> String {
+        format!("Setting comment on type \"{}\"", self.name)
+    }
+
+    fn run(
+        &self,
+        _ctx: &MigrationContext,
+        _db: &mut dyn Conn,
+        _schema: &Schema,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        _ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+        common::set_comment(
+            &mut transaction,
+            &format!(r#"TYPE "{}""#, self.name),
+            self.comment.as_deref(),
+        )?;
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, _ctx: &MigrationContext, _schema: &mut Schema) {}
+
+    fn abort(&self, _ctx: &MigrationContext, _db: &mut dyn Conn) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn can_run_atomically(&self) -> bool {
+        true
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::None
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashSet;
+
+use super::{
+    common, Action, AddColumn, Backfill, Column, LockLevel, LogicalSchema, MigrationContext,
+    RemoveColumn, VersionRequirement,
+};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::{anyhow, bail, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Splits a column into several new ones, for example `name` into `first_name` and
+// `last_name`. It's made up of the same steps as writing the migration by hand: an
+// `add_column` for each new column, with `up` computing it from the original, followed
+// by a `remove_column` for the original, with `down` computing it from the new columns.
+// Writes through the old schema fill in the new columns and writes through the new
+// schema fill in the original, so both schemas keep working while the migration is in
+// progress.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct SplitColumn {
+    pub table: String,
+    pub column: String,
+    pub columns: Vec<SplitTarget>,
+
+    // SQL expression over the new columns which the original column is set to when
+    // writing through the new schema. Required when the original is removed.
+    pub down: Option<String>,
+
+    // Keep the original column around in the new schema by setting this to false, in
+    // which case it's no longer kept in sync with the new columns
+    #[serde(default = "default_remove_original")]
+    pub remove_original: bool,
+
+    // How existing rows are filled in, the same as for `add_column`
+    #[serde(default, deserialize_with = "common::deserialize_backfill")]
+    #[schemars(with = "common::BackfillSetting")]
+    pub backfill: Backfill,
+}
+
+// A column split off from the original, where `up` is an SQL expression over the
+// columns of the table computing its value
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct SplitTarget {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub data_type: String,
+    #[serde(default = "default_nullable")]
+    pub nullable: bool,
+    pub default: Option<String>,
+    pub up: String,
+}
+
+fn default_remove_original() -> bool {
+    true
+}
+
+fn default_nullable() -> bool {
+    true
+}
+
+impl SplitTarget {
+    pub fn new(
+        name: impl Into<String>,
+        data_type: impl Into<String>,
+        up: impl Into<String>,
+    ) -> Self {
+        SplitTarget {
+            name: name.into(),
+            data_type: data_type.into(),
+            nullable: default_nullable(),
+            default: None,
+            up: up.into(),
+        }
+    }
+
+    pub fn with_nullable(mut self, nullable: bool) -> Self {
+        self.nullable = nullable;
+        self
+    }
+
+    pub fn with_default(mut self, default: impl Into<String>) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+}
+
+impl SplitColumn {
+    pub fn new(table: impl Into<String>, column: impl Into<String>) -> Self {
+        SplitColumn {
+            table: table.into(),
+            column: column.into(),
+            columns: Vec::new(),
+            down: None,
+            remove_original: default_remove_original(),
+            backfill: Backfill::default(),
+        }
+    }
+
+    pub fn with_column(mut self, column: SplitTarget) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    pub fn with_down(mut self, down: impl Into<String>) -> Self {
+        self.down = Some(down.into());
+        self
+    }
+
+    pub fn with_remove_original(mut self, remove_original: bool) -> Self {
+        self.remove_original = remove_original;
+        self
+    }
+
+    pub fn with_backfill(mut self, backfill: Backfill) -> Self {
+        self.backfill = backfill;
+        self
+    }
+
+    // One `add_column` for each new column, in order. Each step is given its own
+    // context so the temporary objects don't clash.
+    fn add_steps(&self) -> Vec<AddColumn> {
+        self.columns
+            .iter()
+            .map(|target| {
+                let mut column =
+                    Column::new(&target.name, &target.data_type).with_nullable(target.nullable);
+                if let Some(default) = &target.default {
+                    column = column.with_default(default);
+                }
+
+                AddColumn::new(&self.table, column)
+                    .with_up(&target.up)
+                    .with_backfill(self.backfill)
+            })
+            .collect()
+    }
+
+    // The original column is removed last, so its `down` can use the new columns
+    fn remove_step(&self) -> Option<RemoveColumn> {
+        if !self.remove_original {
+            return None;
+        }
+
+        let mut step = RemoveColumn::new(&self.table, &self.column);
+        if let Some(down) = &self.down {
+            step = step.with_down(down);
+        }
+        Some(step)
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.columns.is_empty() {
+            problems.push("at least one column to split into must be given".to_string());
+        }
+
+        let mut names = HashSet::new();
+        for target in &self.columns {
+            if target.name == self.column {
+                problems.push(format!(
+                    "column \"{}\" can't be split into a column with the same name",
+                    self.column
+                ));
+            } else if !names.insert(target.name.as_str()) {
+                problems.push(format!(
+                    "column \"{}\" is split into more than once",
+                    target.name
+                ));
+            }
+        }
+
+        match (self.remove_original, &self.down) {
+            (true, None) => problems.push(format!(
+                "down must be set to keep \"{}\" up to date for the old schema",
+                self.column
+            )),
+            (false, Some(_)) => {
+                problems.push("down is only used when the original column is removed".to_string())
+            }
+            _ => {}
+        }
+
+        problems
+    }
+}
+
+#[typetag::serde(name = "split_column")]
+impl Action for SplitColumn {
+    fn describe(&self) -> String {
+        let names: Vec<String> = self
+            .columns
+            .iter()
+            .map(|target| format!("\"{}\"", target.name))
+            .collect();
+        format!(
+            "Splitting column \"{}\" on \"{}\" into {}",
+            self.column,
+            self.table,
+            names.join(", ")
+        )
+    }
+
+    fn run(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        if let Some(problem) = self.problems().into_iter().next() {
+            bail!(problem);
+        }
+
+        let table = schema.get_table(db, &self.table)?;
+        table
+            .get_column(&self.column)
+            .ok_or_else(|| anyhow!("no column \"{}\" exists on \"{}\"", self.column, self.table))?;
+
+        // Later steps see the columns added by earlier ones, the same as if they were
+        // separate actions
+        let mut schema = schema.clone();
+        let add_steps = self.add_steps();
+        for (index, step) in add_steps.iter().enumerate() {
+            let ctx = ctx.for_step(index);
+            step.run(&ctx, db, &schema)?;
+            step.update_schema(&ctx, &mut schema);
+        }
+
+        if let Some(step) = self.remove_step() {
+            step.run(&ctx.for_step(add_steps.len()), db, &schema)?;
+        }
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // The new columns are completed first, skipping any which were completed before
+        // an interruption. The original is removed last, in the transaction which the
+        // state is saved in.
+        let add_steps = self.add_steps();
+        for (index, step) in add_steps.iter().enumerate() {
+            let ctx = ctx.for_step(index);
+            if step.is_completed(&ctx, db)? {
+                continue;
+            }
+            if let Some(transaction) = step.complete(&ctx, db)? {
+                transaction.commit()?;
+            }
+        }
+
+        match self.remove_step() {
+            Some(step) => step
+                .complete(&ctx.for_step(add_steps.len()), db)
+                .context("failed to remove original column"),
+            None => Ok(None),
+        }
+    }
+
+    fn update_schema(&self, ctx: &MigrationContext, schema: &mut Schema) {
+        let add_steps = self.add_steps();
+        for (index, step) in add_steps.iter().enumerate() {
+            step.update_schema(&ctx.for_step(index), schema);
+        }
+        if let Some(step) = self.remove_step() {
+            step.update_schema(&ctx.for_step(add_steps.len()), schema);
+        }
+    }
+
+    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        let add_steps = self.add_steps();
+        if let Some(step) = self.remove_step() {
+            step.abort(&ctx.for_step(add_steps.len()), db)?;
+        }
+        for (index, step) in add_steps.iter().enumerate().rev() {
+            step.abort(&ctx.for_step(index), db)?;
+        }
+
+        Ok(())
+    }
+
+    fn version_requirements(&self) -> Vec<VersionRequirement> {
+        let mut requirements: Vec<VersionRequirement> = Vec::new();
+        for requirement in self
+            .add_steps()
+            .iter()
+            .flat_map(|step| step.version_requirements())
+        {
+            let duplicate = requirements.iter().any(|existing| {
+                existing.min_version_num == requirement.min_version_num
+                    && existing.feature == requirement.feature
+            });
+            if !duplicate {
+                requirements.push(requirement);
+            }
+        }
+        requirements
+    }
+
+    fn destructive(&self) -> bool {
+        self.remove_original
+    }
+
+    fn created_names(&self) -> Vec<&str> {
+        self.columns
+            .iter()
+            .map(|target| target.name.as_str())
+            .collect()
+    }
+
+    fn rewritten_tables(&self) -> Vec<&str> {
+        if self.backfill != Backfill::None {
+            vec![self.table.as_str()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        for problem in self.problems() {
+            schema.problem(problem);
+        }
+
+        schema.require_columns(&self.table, [&self.column]);
+        for target in &self.columns {
+            schema.add_column(&self.table, &target.name);
+        }
+        if self.remove_original {
+            schema.remove_column(&self.table, &self.column);
+        }
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
+}
@@ -0,0 +1,468 @@
+use super::{
+    Action, AlterColumn, ColumnChanges, LockLevel, LogicalSchema, MigrationContext,
+    VersionRequirement,
+};
+use crate::{
+    db::{Conn, Transaction},
+    schema::Schema,
+};
+use anyhow::{anyhow, bail, Context};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// Changes an integer primary key to BIGINT, together with the columns in other tables
+// which reference it through foreign keys. Each column is altered the same way as with
+// `alter_column`: a BIGINT column is added and kept in sync using triggers, existing
+// rows are backfilled and indices are built for it. When the migration is completed,
+// the new columns replace the old ones, the primary key is moved over to the new index
+// and the foreign keys are recreated and validated without blocking writes.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct WidenPrimaryKey {
+    pub table: String,
+    pub column: String,
+
+    // Every column referencing the primary key must be included so none of them are
+    // left unable to hold the new values
+    #[serde(default)]
+    pub referencing_columns: Vec<ReferencingColumn>,
+
+    // Turns the column into an identity column when set. A serial column continues
+    // from where its sequence was and any other column from the highest existing value.
+    pub identity: Option<IdentityGeneration>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReferencingColumn {
+    pub table: String,
+    pub column: String,
+}
+
+impl ReferencingColumn {
+    pub fn new(table: impl Into<String>, column: impl Into<String>) -> Self {
+        ReferencingColumn {
+            table: table.into(),
+            column: column.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IdentityGeneration {
+    Always,
+    ByDefault,
+}
+
+impl IdentityGeneration {
+    fn sql(&self) -> &'static str {
+        match self {
+            IdentityGeneration::Always => "ALWAYS",
+            IdentityGeneration::ByDefault => "BY DEFAULT",
+        }
+    }
+}
+
+impl WidenPrimaryKey {
+    pub fn new(table: impl Into<String>, column: impl Into<String>) -> Self {
+        WidenPrimaryKey {
+            table: table.into(),
+            column: column.into(),
+            referencing_columns: Vec::new(),
+            identity: None,
+        }
+    }
+
+    pub fn with_referencing_column(
+        mut self,
+        table: impl Into<String>,
+        column: impl Into<String>,
+    ) -> Self {
+        self.referencing_columns
+            .push(ReferencingColumn::new(table, column));
+        self
+    }
+
+    pub fn with_identity(mut self, identity: IdentityGeneration) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    // The primary key comes first, followed by the referencing columns. Each step is
+    // given its own context so the temporary objects don't clash.
+    fn steps(&self) -> Vec<AlterColumn> {
+        let columns = [(self.table.as_str(), self.column.as_str())]
+            .into_iter()
+            .chain(
+                self.referencing_columns
+                    .iter()
+                    .map(|referencing| (referencing.table.as_str(), referencing.column.as_str())),
+            );
+
+        columns
+            .map(|(table, column)| {
+                AlterColumn::new(table, column)
+                    .with_changes(ColumnChanges::new().with_data_type("BIGINT"))
+            })
+            .collect()
+    }
+
+    fn check_primary_key(
+        &self,
+        db: &mut dyn Conn,
+        table: &str,
+        column: &str,
+    ) -> anyhow::Result<()> {
+        let key_columns: Vec<(String, String)> = db
+            .query_with_params(
+                "
+                SELECT
+                    pg_attribute.attname::TEXT AS name,
+                    format_type(pg_attribute.atttypid, NULL) AS data_type
+                FROM pg_index
+                JOIN pg_attribute
+                    ON pg_attribute.attrelid = pg_index.indrelid
+                    AND pg_attribute.attnum = ANY(pg_index.indkey)
+                WHERE pg_index.indrelid = to_regclass(format('public.%I', $1::TEXT))
+                    AND pg_index.indisprimary
+                ",
+                &[&table],
+            )
+            .context("failed to get primary key")?
+            .iter()
+            .map(|row| (row.get("name"), row.get("data_type")))
+            .collect();
+
+        match key_columns.as_slice() {
+            [(name, data_type)] if name == column => {
+                if !["smallint", "integer", "bigint"].contains(&data_type.as_str()) {
+                    bail!(
+                        "primary key \"{}\" on \"{}\" has type {}, only integer keys can be widened",
+                        self.column,
+                        self.table,
+                        data_type,
+                    );
+                }
+            }
+            [] => bail!("table \"{}\" has no primary key", self.table),
+            [_] => bail!(
+                "column \"{}\" isn't the primary key of \"{}\"",
+                self.column,
+                self.table
+            ),
+            _ => bail!(
+                "primary key of \"{}\" has multiple columns, only single column keys can be widened",
+                self.table
+            ),
+        }
+
+        // An identity column is created without a default, so any other default
+        // would have to be removed by hand first
+        if self.identity.is_some() {
+            let has_other_default = !db
+                .query_with_params(
+                    "
+                    SELECT 1
+                    FROM pg_attribute
+                    JOIN pg_attrdef
+                        ON pg_attrdef.adrelid = pg_attribute.attrelid
+                        AND pg_attrdef.adnum = pg_attribute.attnum
+                    WHERE pg_attribute.attrelid = to_regclass(format('public.%I', $1::TEXT))
+                        AND pg_attribute.attname = $2
+                        AND pg_attribute.attidentity = ''
+                        AND pg_get_serial_sequence(format('public.%I', $1::TEXT), $2) IS NULL
+                    ",
+                    &[&table, &column],
+                )
+                .context("failed to get default for primary key")?
+                .is_empty();
+            if has_other_default {
+                bail!(
+                    "primary key \"{}\" on \"{}\" has a default which isn't from a serial sequence and can't be made an identity column",
+                    self.column,
+                    self.table,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_referencing_columns(
+        &self,
+        db: &mut dyn Conn,
+        schema: &Schema,
+        table: &str,
+        column: &str,
+    ) -> anyhow::Result<()> {
+        // Columns in other tables which reference the primary key, together with
+        // whether they need to be widened as well
+        let referencing: Vec<(ReferencingColumn, bool)> = db
+            .query_with_params(
+                "
+                SELECT DISTINCT
+                    referencing_table.relname::TEXT AS table,
+                    pg_attribute.attname::TEXT AS column,
+                    format_type(pg_attribute.atttypid, NULL) <> 'bigint' AS needs_widening
+                FROM pg_constraint
+                JOIN pg_class referencing_table ON referencing_table.oid = pg_constraint.conrelid
+                JOIN pg_namespace ON pg_namespace.oid = referencing_table.relnamespace
+                CROSS JOIN LATERAL unnest(pg_constraint.conkey, pg_constraint.confkey)
+                    AS keys(referencing_attnum, referenced_attnum)
+                JOIN pg_attribute
+                    ON pg_attribute.attrelid = pg_constraint.conrelid
+                    AND pg_attribute.attnum = keys.referencing_attnum
+                WHERE pg_constraint.contype = 'f'
+                    AND pg_constraint.confrelid = to_regclass(format('public.%I', $1::TEXT))
+                    AND pg_namespace.nspname = 'public'
+                    AND keys.referenced_attnum = (
+                        SELECT attnum
+                        FROM pg_attribute
+                        WHERE attrelid = pg_constraint.confrelid AND attname = $2
+                    )
+                ",
+                &[&table, &column],
+            )
+            .context("failed to get columns referencing primary key")?
+            .iter()
+            .map(|row| {
+                (
+                    ReferencingColumn::new(
+                        row.get::<_, String>("table"),
+                        row.get::<_, String>("column"),
+                    ),
+                    row.get("needs_widening"),
+                )
+            })
+            .collect();
+
+        // The listed columns may have been renamed earlier in the migration
+        let mut listed = Vec::new();
+        for referencing_column in &self.referencing_columns {
+            let table = schema.get_table(db, &referencing_column.table)?;
+            let column = table
+                .get_column(&referencing_column.column)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no column \"{}\" exists on table \"{}\"",
+                        referencing_column.column,
+                        referencing_column.table
+                    )
+                })?;
+            listed.push(ReferencingColumn::new(
+                table.real_name.to_string(),
+                column.real_name.to_string(),
+            ));
+        }
+
+        for (listed_column, referencing_column) in listed.iter().zip(&self.referencing_columns) {
+            if !referencing
+                .iter()
+                .any(|(column, _)| column == listed_column)
+            {
+                bail!(
+                    "column \"{}\" on \"{}\" doesn't reference primary key \"{}\" on \"{}\"",
+                    referencing_column.column,
+                    referencing_column.table,
+                    self.column,
+                    self.table,
+                );
+            }
+        }
+
+        let missing: Vec<String> = referencing
+            .iter()
+            .filter(|(column, needs_widening)| *needs_widening && !listed.contains(column))
+            .map(|(column, _)| format!("\"{}\".\"{}\"", column.table, column.column))
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "columns referencing primary key \"{}\" on \"{}\" must be widened as well, add them to referencing_columns: {}",
+                self.column,
+                self.table,
+                missing.join(", "),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn apply_identity(&self, db: &mut dyn Conn) -> anyhow::Result<()> {
+        let row = db
+            .query_with_params(
+                "
+                SELECT
+                    attidentity <> '' AS is_identity,
+                    pg_get_serial_sequence(format('public.%I', $1::TEXT), $2) AS sequence
+                FROM pg_attribute
+                WHERE attrelid = to_regclass(format('public.%I', $1::TEXT))
+                    AND attname = $2
+                ",
+                &[&self.table, &self.column],
+            )
+            .context("failed to get sequence for primary key")?
+            .pop()
+            .ok_or_else(|| anyhow!("no column \"{}\" exists on \"{}\"", self.column, self.table))?;
+        let is_identity: bool = row.get("is_identity");
+        let sequence: Option<String> = row.get("sequence");
+
+        let query = match (self.identity, is_identity, sequence) {
+            (Some(identity), true, _) => format!(
+                r#"ALTER TABLE "{table}" ALTER COLUMN "{column}" SET GENERATED {generated}"#,
+                table = self.table,
+                column = self.column,
+                generated = identity.sql(),
+            ),
+            // The serial sequence is replaced by an identity sequence which starts
+            // where the serial one left off
+            (Some(identity), false, Some(sequence)) => {
+                let (last_value, is_called): (i64, bool) = db
+                    .query(&format!("SELECT last_value, is_called FROM {}", sequence))
+                    .context("failed to get sequence position")?
+                    .first()
+                    .map(|row| (row.get("last_value"), row.get("is_called")))
+                    .ok_or_else(|| anyhow!("sequence {} has no position", sequence))?;
+
+                format!(
+                    r#"
+                    ALTER TABLE "{table}" ALTER COLUMN "{column}" DROP DEFAULT;
+                    DROP SEQUENCE {sequence};
+                    ALTER TABLE "{table}" ALTER COLUMN "{column}" ADD GENERATED {generated} AS IDENTITY;
+                    SELECT setval(pg_get_serial_sequence('public."{table}"', '{column}'), {last_value}, {is_called});
+                    "#,
+                    table = self.table,
+                    column = self.column,
+                    sequence = sequence,
+                    generated = identity.sql(),
+                    last_value = last_value,
+                    is_called = is_called,
+                )
+            }
+            (Some(identity), false, None) => format!(
+                r#"
+                ALTER TABLE "{table}" ALTER COLUMN "{column}" ADD GENERATED {generated} AS IDENTITY;
+                SELECT setval(
+                    pg_get_serial_sequence('public."{table}"', '{column}'),
+                    COALESCE(MAX("{column}"), 0) + 1,
+                    false
+                ) FROM "{table}";
+                "#,
+                table = self.table,
+                column = self.column,
+                generated = identity.sql(),
+            ),
+            // Serial sequences are created as INTEGER, so they have to be widened too.
+            // Identity sequences are recreated along with the column.
+            (None, false, Some(sequence)) => format!("ALTER SEQUENCE {} AS BIGINT", sequence),
+            (None, _, _) => return Ok(()),
+        };
+
+        db.run(&query)
+            .context("failed to update primary key sequence")
+    }
+}
+
+#[typetag::serde(name = "widen_primary_key")]
+impl Action for WidenPrimaryKey {
+    fn describe(&self) -> String {
+        format!(
+            "Widening primary key \"{}\" on \"{}\" to BIGINT",
+            self.column, self.table
+        )
+    }
+
+    fn run(
+        &self,
+        ctx: &MigrationContext,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<()> {
+        let table = schema.get_table(db, &self.table)?;
+        let column = table
+            .get_column(&self.column)
+            .ok_or_else(|| anyhow!("no column \"{}\" exists on \"{}\"", self.column, self.table))?;
+
+        self.check_primary_key(db, &table.real_name, &column.real_name)?;
+        self.check_referencing_columns(db, schema, &table.real_name, &column.real_name)?;
+
+        for (index, step) in self.steps().iter().enumerate() {
+            step.run(&ctx.for_step(index), db, schema)?;
+        }
+
+        Ok(())
+    }
+
+    fn complete<'a>(
+        &self,
+        ctx: &MigrationContext,
+        db: &'a mut dyn Conn,
+    ) -> anyhow::Result<Option<Transaction<'a>>> {
+        // The primary key is replaced first and the foreign keys on the referencing
+        // columns are recreated as those are replaced in turn
+        for (index, step) in self.steps().iter().enumerate() {
+            if let Some(transaction) = step.complete(&ctx.for_step(index), db)? {
+                transaction.commit()?;
+            }
+        }
+
+        let mut transaction = db.transaction().context("failed to create transaction")?;
+        self.apply_identity(&mut transaction)?;
+
+        Ok(Some(transaction))
+    }
+
+    fn update_schema(&self, ctx: &MigrationContext, schema: &mut Schema) {
+        for (index, step) in self.steps().iter().enumerate() {
+            step.update_schema(&ctx.for_step(index), schema);
+        }
+    }
+
+    fn abort(&self, ctx: &MigrationContext, db: &mut dyn Conn) -> anyhow::Result<()> {
+        for (index, step) in self.steps().iter().enumerate().rev() {
+            step.abort(&ctx.for_step(index), db)?;
+        }
+
+        Ok(())
+    }
+
+    fn version_requirements(&self) -> Vec<VersionRequirement> {
+        self.steps()
+            .first()
+            .map(|step| step.version_requirements())
+            .unwrap_or_default()
+    }
+
+    fn schema_version_requirements(
+        &self,
+        db: &mut dyn Conn,
+        schema: &Schema,
+    ) -> anyhow::Result<Vec<VersionRequirement>> {
+        match self.steps().first() {
+            Some(step) => step.schema_version_requirements(db, schema),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn rewritten_tables(&self) -> Vec<&str> {
+        let mut tables = vec![self.table.as_str()];
+        for referencing in &self.referencing_columns {
+            if !tables.contains(&referencing.table.as_str()) {
+                tables.push(&referencing.table);
+            }
+        }
+        tables
+    }
+
+    fn simulate(&self, schema: &mut LogicalSchema) {
+        schema.require_columns(&self.table, [&self.column]);
+        for referencing in &self.referencing_columns {
+            schema.require_columns(&referencing.table, [&referencing.column]);
+        }
+    }
+
+    fn max_lock_level(&self) -> LockLevel {
+        LockLevel::AccessExclusive
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::bail;
+
+use crate::state::stable_hash;
+
+// A Namespace lets several independent sets of migrations share one database, for
+// example when multiple services keep their tables in the same database. Everything
+// Reshape creates is kept apart per namespace: the schema holding the state, the
+// schemas for each migration, the temporary objects created while a migration is in
+// progress and the advisory lock. The namespaces should manage disjoint sets of
+// tables, as nothing stops two of them from changing the same table.
+//
+// The default namespace uses the same names as Reshape always has, so existing
+// databases keep working without a namespace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Namespace {
+    name: Option<String>,
+}
+
+impl Namespace {
+    const MAX_LENGTH: usize = 16;
+
+    // These would make the names for the namespace collide with the names for the
+    // default namespace
+    const RESERVED: [&'static str; 4] = ["reshape", "migration", "initial", "archive"];
+
+    pub fn new(name: &str) -> anyhow::Result<Self> {
+        // Only lowercase letters and digits are allowed so the names derived from the
+        // namespace never need to be quoted and can't be mistaken for one another
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+        {
+            bail!("namespace may only contain lowercase letters and digits");
+        }
+
+        if name.len() > Self::MAX_LENGTH {
+            bail!(
+                "namespace can't be longer than {} characters",
+                Self::MAX_LENGTH
+            );
+        }
+
+        if Self::RESERVED.contains(&name) {
+            bail!("namespace can't be named {}", name);
+        }
+
+        Ok(Namespace {
+            name: Some(name.to_string()),
+        })
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    // Schema holding the state, the completed migrations and the helper functions
+    pub(crate) fn state_schema(&self) -> String {
+        match &self.name {
+            Some(name) => format!("reshape_{}", name),
+            None => "reshape".to_string(),
+        }
+    }
+
+    pub fn schema_name_for_migration(&self, migration_name: &str) -> String {
+        match &self.name {
+            Some(name) => format!("{}_migration_{}", name, migration_name),
+            None => format!("migration_{}", migration_name),
+        }
+    }
+
+    pub fn schema_query_for_migration(&self, migration_name: &str) -> String {
+        self.schema_query_with_fallback(migration_name, &[])
+    }
+
+    // Like `schema_query_for_migration`, but with further schemas to search after the
+    // migration's schema. Objects Reshape doesn't manage, like extensions installed in
+    // `public`, can then still be used without qualifying them.
+    pub fn schema_query_with_fallback(&self, migration_name: &str, fallback: &[&str]) -> String {
+        format!(
+            "SET search_path TO {}",
+            self.search_path_for_migration(migration_name, fallback)
+        )
+    }
+
+    // Like `schema_query_with_fallback`, but only for the current transaction. Behind a
+    // pooler in transaction pooling mode, a regular `SET` would stay on the server
+    // connection for other clients instead, so this must be run at the start of every
+    // transaction.
+    pub fn local_schema_query_with_fallback(
+        &self,
+        migration_name: &str,
+        fallback: &[&str],
+    ) -> String {
+        format!(
+            "SET LOCAL search_path TO {}",
+            self.search_path_for_migration(migration_name, fallback)
+        )
+    }
+
+    // The value for `search_path` on its own, for drivers and frameworks which take it
+    // as a connection setting rather than a query
+    pub fn search_path_for_migration(&self, migration_name: &str, fallback: &[&str]) -> String {
+        std::iter::once(self.schema_name_for_migration(migration_name))
+            .chain(fallback.iter().map(|schema| quote_schema(schema)))
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+
+    // Pattern for `LIKE` which matches the schemas of all migrations in the namespace
+    pub(crate) fn migration_schema_pattern(&self) -> String {
+        format!("{}%", escape_like(&self.schema_name_for_migration("")))
+    }
+
+    // Schema with views for the tables as they were before the first migration, used
+    // when previous schema views are enabled
+    pub(crate) fn initial_schema(&self) -> String {
+        match &self.name {
+            Some(name) => format!("reshape_{}_initial", name),
+            None => "reshape_initial".to_string(),
+        }
+    }
+
+    // Schema holding copies of data removed by actions with `archive` set. It's never
+    // cleaned up automatically, the archived tables are meant to be dropped by hand
+    // once they are no longer needed.
+    pub(crate) fn archive_schema(&self) -> String {
+        match &self.name {
+            Some(name) => format!("reshape_{}_archive", name),
+            None => "reshape_archive".to_string(),
+        }
+    }
+
+    // Prefix for all temporary objects created while a migration is in progress
+    pub(crate) fn object_prefix(&self) -> String {
+        match &self.name {
+            Some(name) => format!("__{}_reshape", name),
+            None => "__reshape".to_string(),
+        }
+    }
+
+    // Pattern for `LIKE` which matches all temporary objects in the namespace
+    pub(crate) fn object_pattern(&self) -> String {
+        format!("{}%", escape_like(&self.object_prefix()))
+    }
+
+    // Advisory lock keys in Postgres are 64-bit integers. The key for the default
+    // namespace was chosen randomly and the keys for other namespaces are derived
+    // from it using a hash of the name.
+    pub(crate) fn lock_key(&self) -> i64 {
+        const DEFAULT_LOCK_KEY: i64 = 4036779288569897133;
+
+        let name = match &self.name {
+            Some(name) => name,
+            None => return DEFAULT_LOCK_KEY,
+        };
+
+        DEFAULT_LOCK_KEY ^ stable_hash(name.as_bytes()) as i64
+    }
+}
+
+// Schemas other than plain lowercase identifiers, like `$user`, must be quoted in
+// `search_path`
+fn quote_schema(schema: &str) -> String {
+    let plain = schema.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && schema
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if plain {
+        schema.to_string()
+    } else {
+        format!(r#""{}""#, schema.replace('"', r#""""#))
+    }
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('_', "\\_")
+}
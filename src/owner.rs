@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Context};
+
+use crate::db::Conn;
+
+// Objects are owned by the role Reshape connects as, which often differs between
+// environments or even between the people running migrations. With an owner set,
+// everything Reshape manages is handed over to that role using `ALTER ... OWNER TO`
+// after each migration is started and completed:
+//
+//   - Tables, views, sequences and types in the `public` schema. Sequences owned by a
+//     column and indices follow their table. Tables Reshape doesn't manage are skipped.
+//   - Temporary functions used by triggers, prefixed with `__reshape`.
+//   - The migration schemas and their views, the state schema with its tables and
+//     helper functions and the archive schema with any archived tables.
+//
+// Objects which are already owned by the role are left alone. The connected role must
+// be allowed to `SET ROLE` to the owner, which in turn needs `CREATE` on the schemas.
+pub fn check_owner_exists(db: &mut dyn Conn, owner: &str) -> anyhow::Result<()> {
+    let exists = !db
+        .query_with_params("SELECT 1 FROM pg_roles WHERE rolname = $1", &[&owner])
+        .context("failed to check for owner role")?
+        .is_empty();
+
+    if !exists {
+        return Err(anyhow!("owner role \"{}\" doesn't exist", owner));
+    }
+
+    Ok(())
+}
+
+pub fn apply_owner(db: &mut dyn Conn, owner: &str) -> anyhow::Result<()> {
+    let namespace = db.namespace().clone();
+    let tables = db.tables().clone();
+    let managed_schemas = vec![
+        namespace.state_schema(),
+        namespace.archive_schema(),
+        namespace.initial_schema(),
+    ];
+
+    let objects: Vec<(String, String)> = db
+        .query_with_params(
+            "
+            WITH
+                owner AS (SELECT oid FROM pg_roles WHERE rolname = $1),
+                managed AS (
+                    SELECT oid, nspname, nspowner
+                    FROM pg_namespace
+                    WHERE nspname LIKE $2 OR nspname = ANY($3)
+                )
+            SELECT 1 AS priority, 'SCHEMA' AS kind, format('%I', nspname) AS name, NULL AS table_name
+            FROM managed
+            WHERE nspowner <> (SELECT oid FROM owner)
+            UNION ALL
+            SELECT
+                2,
+                CASE relkind
+                    WHEN 'v' THEN 'VIEW'
+                    WHEN 'm' THEN 'MATERIALIZED VIEW'
+                    WHEN 'S' THEN 'SEQUENCE'
+                    ELSE 'TABLE'
+                END,
+                format('%I.%I', pg_namespace.nspname, relname),
+                CASE WHEN pg_namespace.nspname = 'public' AND relkind IN ('r', 'p')
+                    THEN relname::TEXT
+                END
+            FROM pg_class
+            JOIN pg_namespace ON pg_namespace.oid = pg_class.relnamespace
+            WHERE (pg_namespace.nspname = 'public' OR pg_namespace.oid IN (SELECT oid FROM managed))
+                AND relkind IN ('r', 'p', 'v', 'm', 'S')
+                AND relowner <> (SELECT oid FROM owner)
+                AND NOT EXISTS (
+                    SELECT 1 FROM pg_depend
+                    WHERE pg_depend.classid = 'pg_class'::regclass
+                        AND pg_depend.objid = pg_class.oid
+                        AND pg_depend.deptype IN ('a', 'i')
+                )
+            UNION ALL
+            SELECT
+                3,
+                CASE typtype WHEN 'd' THEN 'DOMAIN' ELSE 'TYPE' END,
+                format('%I.%I', pg_namespace.nspname, typname),
+                NULL
+            FROM pg_type
+            JOIN pg_namespace ON pg_namespace.oid = pg_type.typnamespace
+            LEFT JOIN pg_class ON pg_class.oid = pg_type.typrelid
+            WHERE pg_namespace.nspname = 'public'
+                AND (typtype IN ('e', 'd') OR (typtype = 'c' AND pg_class.relkind = 'c'))
+                AND typowner <> (SELECT oid FROM owner)
+            UNION ALL
+            SELECT 4, 'FUNCTION', pg_proc.oid::regprocedure::TEXT, NULL
+            FROM pg_proc
+            JOIN pg_namespace ON pg_namespace.oid = pg_proc.pronamespace
+            WHERE ((pg_namespace.nspname = 'public' AND proname LIKE $4)
+                    OR pg_namespace.oid IN (SELECT oid FROM managed))
+                AND proowner <> (SELECT oid FROM owner)
+            ORDER BY priority, name
+            ",
+            &[
+                &owner,
+                &namespace.migration_schema_pattern(),
+                &managed_schemas,
+                &namespace.object_pattern(),
+            ],
+        )
+        .context("failed to find objects to change owner of")?
+        .iter()
+        .filter(|row| {
+            row.get::<_, Option<&str>>("table_name")
+                .map_or(true, |table| tables.manages(table))
+        })
+        .map(|row| (row.get("kind"), row.get("name")))
+        .collect();
+
+    for (kind, name) in objects {
+        db.run(&format!(r#"ALTER {} {} OWNER TO "{}""#, kind, name, owner))
+            .with_context(|| format!("failed to change owner of {} {}", kind, name))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use anyhow::bail;
+
+use crate::migrations::{action_types, LockLevel, Migration};
+
+// Policy restricts which actions new migrations may use, so that migrations which
+// would drop data or run arbitrary SQL can't be applied by mistake, for example in
+// production. It's checked before a migration is started and only applies to the
+// migrations which haven't been applied yet.
+//
+// The default policy allows everything.
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    denied_actions: Vec<String>,
+    require_allow_destructive: bool,
+    allow_destructive: bool,
+    max_lock_level: Option<LockLevel>,
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Refuse to apply migrations using any of these action types, like "custom"
+    pub fn with_denied_actions(mut self, denied_actions: Vec<String>) -> Self {
+        self.denied_actions = denied_actions;
+        self
+    }
+
+    // Refuse to apply migrations with actions which drop data, like `remove_table`,
+    // unless they have been allowed using `with_allow_destructive`
+    pub fn with_require_allow_destructive(mut self, require_allow_destructive: bool) -> Self {
+        self.require_allow_destructive = require_allow_destructive;
+        self
+    }
+
+    pub fn with_allow_destructive(mut self, allow_destructive: bool) -> Self {
+        self.allow_destructive = allow_destructive;
+        self
+    }
+
+    // Refuse to apply migrations with actions which take a stronger lock than this,
+    // for example to only allow concurrent index builds during business hours
+    pub fn with_max_lock_level(mut self, max_lock_level: LockLevel) -> Self {
+        self.max_lock_level = Some(max_lock_level);
+        self
+    }
+
+    pub(crate) fn check(&self, migrations: &[Migration]) -> anyhow::Result<()> {
+        // A misspelled action type would otherwise silently allow the action
+        let known_types = action_types();
+        for denied in &self.denied_actions {
+            if !known_types.contains(denied) {
+                bail!("policy denies unknown action type \"{}\"", denied);
+            }
+        }
+
+        for migration in migrations {
+            for action in &migration.actions {
+                let action_type = action.typetag_name();
+                if self
+                    .denied_actions
+                    .iter()
+                    .any(|denied| denied == action_type)
+                {
+                    bail!(
+                        "migration {} uses action \"{}\" which isn't allowed by the policy",
+                        migration.name,
+                        action_type
+                    );
+                }
+
+                if action.destructive() && self.require_allow_destructive && !self.allow_destructive
+                {
+                    bail!(
+                        "migration {} uses action \"{}\" which drops data. Destructive actions must be allowed explicitly, for example using `--allow-destructive`.",
+                        migration.name,
+                        action_type
+                    );
+                }
+
+                if let Some(max_lock_level) = self.max_lock_level {
+                    let lock_level = action.max_lock_level();
+                    if lock_level > max_lock_level {
+                        bail!(
+                            "migration {} uses action \"{}\" which takes a lock in {} mode, but the policy allows at most {}",
+                            migration.name,
+                            action_type,
+                            lock_level,
+                            max_lock_level
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
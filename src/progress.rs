@@ -0,0 +1,295 @@
+#![cfg_attr(not(feature = "progress"), allow(dead_code))]
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Progress draws a status line after the action currently being run, showing how
+// long it has been running, how far a backfill or constraint validation has come and
+// the statement currently being run. The line is redrawn from a separate thread, as
+// the main thread is busy waiting for the database. Nothing is drawn for actions which finish within a second.
+//
+// A disabled Progress, which is the default, ignores all updates. Clones share the
+// same display, so the connection and any transactions started from it can all
+// report to it.
+#[derive(Clone, Default)]
+pub struct Progress {
+    state: Option<Arc<Mutex<ProgressState>>>,
+}
+
+#[derive(Default)]
+struct ProgressState {
+    action_started_at: Option<Instant>,
+    statement: Option<String>,
+    backfilling: bool,
+    rows_backfilled: u64,
+    estimated_rows: Option<u64>,
+    validating: bool,
+    pages_validated: u64,
+    total_pages: u64,
+    violations: u64,
+    drawn: bool,
+}
+
+impl Progress {
+    const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+    const SHOW_AFTER: Duration = Duration::from_secs(1);
+    const BAR_WIDTH: usize = 20;
+    const STATEMENT_WIDTH: usize = 40;
+
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(ProgressState::default()));
+
+        // The thread only holds a weak reference, so it stops once all clones
+        // of the Progress have been dropped
+        let weak = Arc::downgrade(&state);
+        thread::spawn(move || Self::redraw_until_dropped(weak));
+
+        Progress { state: Some(state) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.is_some()
+    }
+
+    pub fn action_started(&self) {
+        self.update(|state| {
+            *state = ProgressState {
+                action_started_at: Some(Instant::now()),
+                ..ProgressState::default()
+            };
+        });
+    }
+
+    // Clear the status line so the result of the action can be printed in its place
+    pub fn action_finished(&self) {
+        self.update(|state| {
+            if state.drawn {
+                print!("\x1b[K");
+                let _ = std::io::stdout().flush();
+            }
+            *state = ProgressState::default();
+        });
+    }
+
+    pub fn statement(&self, query: &str) {
+        self.update(|state| state.statement = Some(query.to_string()));
+    }
+
+    pub fn backfill_started(&self, estimated_rows: Option<u64>) {
+        self.update(|state| {
+            state.backfilling = true;
+            state.rows_backfilled = 0;
+            state.estimated_rows = estimated_rows;
+        });
+    }
+
+    pub fn rows_backfilled(&self, rows: u64) {
+        self.update(|state| state.rows_backfilled += rows);
+    }
+
+    pub fn validation_started(&self, total_pages: u64) {
+        self.update(|state| {
+            state.validating = true;
+            state.pages_validated = 0;
+            state.total_pages = total_pages;
+            state.violations = 0;
+        });
+    }
+
+    pub fn pages_validated(&self, pages: u64, violations: u64) {
+        self.update(|state| {
+            state.pages_validated += pages;
+            state.violations += violations;
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ProgressState)) {
+        if let Some(state) = &self.state {
+            if let Ok(mut state) = state.lock() {
+                f(&mut state);
+            }
+        }
+    }
+
+    fn redraw_until_dropped(state: Weak<Mutex<ProgressState>>) {
+        loop {
+            thread::sleep(Self::REDRAW_INTERVAL);
+
+            let state = match state.upgrade() {
+                Some(state) => state,
+                None => return,
+            };
+            let mut state = match state.lock() {
+                Ok(state) => state,
+                Err(_) => return,
+            };
+
+            if let Some(line) = Self::render(&state) {
+                // Save the cursor position before drawing and restore it afterwards,
+                // so anything printed next ends up right after the action description
+                print!("\x1b7{}\x1b[K\x1b8", line);
+                let _ = std::io::stdout().flush();
+                state.drawn = true;
+            }
+        }
+    }
+
+    fn render(state: &ProgressState) -> Option<String> {
+        let elapsed = state.action_started_at?.elapsed();
+        if elapsed < Self::SHOW_AFTER {
+            return None;
+        }
+
+        let seconds = elapsed.as_secs();
+        let mut line = format!(
+            "[{:02}:{:02}:{:02}]",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+
+        match (state.backfilling, state.estimated_rows) {
+            (true, Some(estimated_rows)) => {
+                // The estimate might be stale, so the total is never allowed to be lower
+                // than the number of rows already backfilled
+                let total = estimated_rows.max(state.rows_backfilled).max(1);
+                let filled = (state.rows_backfilled * Self::BAR_WIDTH as u64 / total) as usize;
+                line.push_str(&format!(
+                    " [{}{}] {}/{} rows ({}%)",
+                    "=".repeat(filled),
+                    " ".repeat(Self::BAR_WIDTH - filled),
+                    state.rows_backfilled,
+                    total,
+                    state.rows_backfilled * 100 / total,
+                ));
+            }
+            (true, None) => line.push_str(&format!(" {} rows", state.rows_backfilled)),
+            (false, _) => {}
+        }
+
+        if state.validating {
+            let total = state.total_pages.max(1);
+            let validated = state.pages_validated.min(total);
+            let filled = (validated * Self::BAR_WIDTH as u64 / total) as usize;
+            line.push_str(&format!(
+                " [{}{}] {}/{} pages checked, {} violations",
+                "=".repeat(filled),
+                " ".repeat(Self::BAR_WIDTH - filled),
+                validated,
+                total,
+                state.violations,
+            ));
+        }
+
+        if let Some(statement) = &state.statement {
+            let statement = statement
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ");
+            let mut truncated: String = statement.chars().take(Self::STATEMENT_WIDTH).collect();
+            if truncated.len() < statement.len() {
+                truncated.push_str("...");
+            }
+            line.push_str(&format!(" {}", truncated));
+        }
+
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Progress, ProgressState};
+    use std::time::{Duration, Instant};
+
+    fn started_seconds_ago(seconds: u64) -> ProgressState {
+        ProgressState {
+            action_started_at: Instant::now().checked_sub(Duration::from_secs(seconds)),
+            ..ProgressState::default()
+        }
+    }
+
+    #[test]
+    fn nothing_is_rendered_within_a_second() {
+        let state = ProgressState {
+            action_started_at: Some(Instant::now()),
+            statement: Some("SELECT 1".to_string()),
+            ..ProgressState::default()
+        };
+        assert_eq!(None, Progress::render(&state));
+        assert_eq!(None, Progress::render(&ProgressState::default()));
+    }
+
+    #[test]
+    fn backfill_with_estimate() {
+        let state = ProgressState {
+            backfilling: true,
+            rows_backfilled: 250,
+            estimated_rows: Some(1000),
+            ..started_seconds_ago(65)
+        };
+        assert_eq!(
+            Some("[00:01:05] [=====               ] 250/1000 rows (25%)".to_string()),
+            Progress::render(&state)
+        );
+    }
+
+    #[test]
+    fn backfill_with_stale_estimate() {
+        let state = ProgressState {
+            backfilling: true,
+            rows_backfilled: 1500,
+            estimated_rows: Some(1000),
+            ..started_seconds_ago(2)
+        };
+        assert_eq!(
+            Some("[00:00:02] [====================] 1500/1500 rows (100%)".to_string()),
+            Progress::render(&state)
+        );
+    }
+
+    #[test]
+    fn backfill_without_estimate() {
+        let state = ProgressState {
+            backfilling: true,
+            rows_backfilled: 42,
+            ..started_seconds_ago(3661)
+        };
+        assert_eq!(
+            Some("[01:01:01] 42 rows".to_string()),
+            Progress::render(&state)
+        );
+    }
+
+    #[test]
+    fn statement_is_truncated() {
+        let state = ProgressState {
+            statement: Some(
+                "
+                UPDATE users
+                SET __reshape_0000_0001_e198_name = UPPER(name)
+                WHERE id > 100
+                "
+                .to_string(),
+            ),
+            ..started_seconds_ago(2)
+        };
+        assert_eq!(
+            Some("[00:00:02] UPDATE users SET __reshape_0000_0001_e19...".to_string()),
+            Progress::render(&state)
+        );
+
+        let state = ProgressState {
+            statement: Some("SELECT 1".to_string()),
+            ..started_seconds_ago(2)
+        };
+        assert_eq!(
+            Some("[00:00:02] SELECT 1".to_string()),
+            Progress::render(&state)
+        );
+    }
+}
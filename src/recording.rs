@@ -0,0 +1,302 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize, Serializer};
+use version::version;
+
+use crate::{migrations::common::ViolationsError, redact};
+
+// A Recording captures everything Reshape did against the database during one or
+// more commands: every statement with its timing and outcome, and a snapshot of the
+// state whenever it's loaded or saved. Recordings are meant to be shared when
+// reporting issues, so data from the database and migrations is left out:
+//
+// - Query parameters and returned rows are only recorded as counts
+// - String and numeric literals in statements are replaced with placeholders
+// - State snapshots only keep the type of each action, as actions can contain data
+//   like seeded rows and column defaults
+// - Errors leave out the details Postgres adds, like the failing row of a violated
+//   constraint, and the rows sampled when validating constraints
+// - Passwords in statements and errors are redacted
+//
+// Statements are kept as they ran and only redacted when the recording is written or
+// rendered, as other consumers like `export_sql` need the exact SQL.
+//
+// A recording can be replayed offline using `reshape replay`, which renders the
+// events in the order they happened.
+#[derive(Serialize, Deserialize, Debug)]
+#[non_exhaustive]
+pub struct Recording {
+    pub version: String,
+    pub events: Vec<Event>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Event {
+    Command {
+        name: String,
+    },
+    Statement {
+        #[serde(serialize_with = "serialize_statement")]
+        sql: String,
+        params: usize,
+        rows: Option<usize>,
+        duration_ms: u64,
+        #[serde(serialize_with = "serialize_error")]
+        error: Option<String>,
+    },
+    State {
+        state: serde_json::Value,
+    },
+    Backfill {
+        table: String,
+        rows_processed: u64,
+        estimated_rows: Option<u64>,
+    },
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Recording {
+            version: version!().to_string(),
+            events: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub(crate) fn command(&mut self, name: &str) {
+        self.events.push(Event::Command {
+            name: name.to_string(),
+        });
+    }
+
+    pub(crate) fn statement<T>(
+        &mut self,
+        sql: &str,
+        params: usize,
+        duration: Duration,
+        result: &Result<T, postgres::Error>,
+        rows: impl FnOnce(&T) -> Option<usize>,
+    ) {
+        let (rows, error) = match result {
+            Ok(value) => (rows(value), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        self.events.push(Event::Statement {
+            sql: sql.trim().to_string(),
+            params,
+            rows,
+            duration_ms: duration.as_millis() as u64,
+            error,
+        });
+    }
+
+    pub(crate) fn backfill(&mut self, progress: &crate::BackfillProgress) {
+        self.events.push(Event::Backfill {
+            table: progress.table.clone(),
+            rows_processed: progress.rows_processed,
+            estimated_rows: progress.estimated_rows,
+        });
+    }
+
+    pub(crate) fn state(&mut self, state: &crate::State) {
+        if let Ok(mut state) = serde_json::to_value(state) {
+            without_payloads(&mut state);
+            self.events.push(Event::State { state });
+        }
+    }
+
+    // Record the error the recorded commands failed with
+    pub fn set_error(&mut self, err: &anyhow::Error) {
+        let causes: Vec<String> = err
+            .chain()
+            .map(|cause| match cause.downcast_ref::<ViolationsError>() {
+                Some(violations) => violations.without_sample.clone(),
+                None => cause.to_string(),
+            })
+            .collect();
+        self.error = Some(without_details(&redact(&causes.join(": "))));
+    }
+}
+
+// Keep only the type of every action in a state snapshot, and drop the cursors of
+// pending backfills as they contain the key of a row
+fn without_payloads(state: &mut serde_json::Value) {
+    let migrations = state
+        .get_mut("migrations")
+        .and_then(|migrations| migrations.as_array_mut());
+    for migration in migrations.into_iter().flatten() {
+        let actions = migration
+            .get_mut("actions")
+            .and_then(|actions| actions.as_array_mut());
+        for action in actions.into_iter().flatten() {
+            *action = serde_json::json!({ "type": action.get("type") });
+        }
+    }
+
+    let backfills = state
+        .get_mut("pending_backfills")
+        .and_then(|backfills| backfills.as_array_mut());
+    for backfill in backfills.into_iter().flatten() {
+        if let Some(backfill) = backfill.as_object_mut() {
+            backfill.remove("cursor");
+        }
+    }
+}
+
+// Replace string and numeric literals in a statement with `'?'` and `?`. Quoted
+// identifiers and comments are left alone, as are digits which are part of an
+// identifier or a parameter like `$1`.
+fn without_literals(sql: &str) -> String {
+    let mut replaced = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut previous = ' ';
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // A quote is escaped by doubling it, which is the same as two literals
+                // right after each other
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                }
+                if !replaced.ends_with("'?'") {
+                    replaced.push_str("'?'");
+                }
+            }
+            '"' => {
+                replaced.push(c);
+                for c in chars.by_ref() {
+                    replaced.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+            }
+            // Comments are prose, where an apostrophe doesn't start a literal
+            '-' if chars.peek() == Some(&'-') => {
+                replaced.push(c);
+                for c in chars.by_ref() {
+                    replaced.push(c);
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            c if c.is_ascii_digit()
+                && !(previous.is_alphanumeric() || matches!(previous, '_' | '$')) =>
+            {
+                while chars
+                    .peek()
+                    .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+                {
+                    chars.next();
+                }
+                replaced.push('?');
+            }
+            c => replaced.push(c),
+        }
+        previous = replaced.chars().last().unwrap_or(' ');
+    }
+
+    replaced
+}
+
+// Leave out the details Postgres adds to an error, which can contain a row
+fn without_details(error: &str) -> String {
+    error
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("DETAIL:"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn serialize_statement<S: Serializer>(sql: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&without_literals(&redact(sql)))
+}
+
+fn serialize_error<S: Serializer>(
+    error: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    error
+        .as_deref()
+        .map(|error| without_details(&redact(error)))
+        .serialize(serializer)
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Renders the recording as a human readable log of the run
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Recorded with Reshape {}", self.version)?;
+
+        for event in &self.events {
+            match event {
+                Event::Command { name } => {
+                    writeln!(f)?;
+                    writeln!(f, "$ reshape {}", name)?;
+                }
+                Event::Statement {
+                    sql,
+                    params,
+                    rows,
+                    duration_ms,
+                    error,
+                } => {
+                    let mut details = vec![format!("{}ms", duration_ms)];
+                    if *params > 0 {
+                        details.push(format!("{} params", params));
+                    }
+                    if let Some(rows) = rows {
+                        details.push(format!("{} rows", rows));
+                    }
+
+                    writeln!(f, "  [{}]", details.join(", "))?;
+                    for line in without_literals(&redact(sql)).lines() {
+                        writeln!(f, "    {}", line.trim())?;
+                    }
+                    if let Some(error) = error {
+                        writeln!(f, "    failed: {}", without_details(&redact(error)))?;
+                    }
+                }
+                Event::State { state } => {
+                    let name = state
+                        .get("state")
+                        .and_then(|name| name.as_str())
+                        .unwrap_or("unknown");
+                    writeln!(f, "  state: {}", name)?;
+                }
+                Event::Backfill {
+                    table,
+                    rows_processed,
+                    estimated_rows,
+                } => match estimated_rows {
+                    Some(estimated_rows) => writeln!(
+                        f,
+                        "  backfill: {} rows of ~{} in {}",
+                        rows_processed, estimated_rows, table
+                    )?,
+                    None => writeln!(f, "  backfill: {} rows in {}", rows_processed, table)?,
+                },
+            }
+        }
+
+        if let Some(error) = &self.error {
+            writeln!(f)?;
+            writeln!(f, "Error: {}", error)?;
+        }
+
+        Ok(())
+    }
+}
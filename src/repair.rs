@@ -0,0 +1,341 @@
+use anyhow::{anyhow, Context};
+
+use crate::{
+    db::{Conn, DbConn},
+    migrations::{Migration, MigrationContext},
+    retention,
+    state::{self, State},
+    Error,
+};
+
+// Temporary objects created while a migration is in progress are all prefixed with
+// `__reshape`, or `__{namespace}_reshape` for namespaces. None of them should exist when no migration is in progress, so if
+// they do, the bookkeeping in the `reshape` schema has most likely been deleted or
+// modified manually.
+pub struct Leftover {
+    kind: String,
+    table: Option<String>,
+    name: String,
+}
+
+impl Leftover {
+    pub fn describe(&self) -> String {
+        match &self.table {
+            Some(table) => format!("{} {} on {}", self.kind, self.name, table),
+            None => format!("{} {}", self.kind, self.name),
+        }
+    }
+}
+
+pub fn find_leftovers(db: &mut DbConn) -> anyhow::Result<Vec<Leftover>> {
+    let pattern = db.namespace().object_pattern();
+    let leftovers = db
+        .query_with_params(
+            "
+            SELECT 1 AS priority, 'function' AS kind, NULL AS table_name, routine_name::TEXT AS name
+            FROM information_schema.routines
+            WHERE routine_schema = 'public' AND routine_name LIKE $1
+            UNION ALL
+            SELECT 2, 'constraint', table_name, constraint_name
+            FROM information_schema.table_constraints
+            WHERE constraint_schema = 'public' AND constraint_name LIKE $1
+            UNION ALL
+            SELECT 2, 'domain constraint', domain_name, constraint_name
+            FROM information_schema.domain_constraints
+            WHERE constraint_schema = 'public' AND constraint_name LIKE $1
+            UNION ALL
+            SELECT 3, 'index', tablename, indexname
+            FROM pg_indexes
+            WHERE schemaname = 'public' AND indexname LIKE $1
+            UNION ALL
+            SELECT 4, 'column', table_name, column_name
+            FROM information_schema.columns
+            WHERE table_schema = 'public' AND column_name LIKE $1
+            ORDER BY priority
+            ",
+            &[&pattern],
+        )
+        .context("failed to find leftover objects")?
+        .iter()
+        .map(|row| Leftover {
+            kind: row.get("kind"),
+            table: row.get("table_name"),
+            name: row.get("name"),
+        })
+        .collect();
+
+    Ok(leftovers)
+}
+
+// A temporary object along with the pending action which created it, see
+// `Reshape::temporary_objects`
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct TemporaryObject {
+    pub kind: String,
+    pub table: Option<String>,
+    pub name: String,
+
+    // Name of the migration and index of the action which created the object
+    pub owner: Option<(String, usize)>,
+
+    // Set when the object doesn't belong to any pending action, which means it was
+    // left behind by a migration which was edited or removed before being applied again
+    pub orphaned: bool,
+}
+
+impl TemporaryObject {
+    pub fn describe(&self) -> String {
+        match &self.table {
+            Some(table) => format!("{} {} on {}", self.kind, self.name, table),
+            None => format!("{} {}", self.kind, self.name),
+        }
+    }
+}
+
+// List every temporary object and match it up with the pending action it was named
+// after. Indices which were renamed while replacing them are kept until the
+// migration is completed and aren't named after any action.
+pub fn temporary_objects(db: &mut DbConn, state: &State) -> anyhow::Result<Vec<TemporaryObject>> {
+    let object_names = state::load_object_names(db)?;
+    let existing_schema_name = state::current_migration(db)?;
+    let mut contexts: Vec<(&str, usize, MigrationContext)> = Vec::new();
+    for (migration_index, migration) in state.pending_migrations().iter().enumerate() {
+        for action_index in 0..migration.actions.len() {
+            let ctx = MigrationContext::new(
+                migration_index,
+                action_index,
+                &migration.name,
+                existing_schema_name.clone(),
+            )
+            .with_namespace(db.namespace().clone())
+            .with_object_names(object_names);
+            contexts.push((&migration.name, action_index, ctx));
+        }
+    }
+    let old_index_prefix = format!("{}_old_", db.namespace().object_prefix());
+
+    let objects = find_leftovers(db)?
+        .into_iter()
+        .map(|leftover| {
+            let owner = contexts
+                .iter()
+                .find(|(_, _, ctx)| ctx.owns_object(&leftover.name))
+                .map(|(migration, action_index, _)| (migration.to_string(), *action_index));
+            let replaced_index = leftover.kind == "index"
+                && leftover.name.starts_with(&old_index_prefix)
+                && !matches!(state, State::Idle);
+
+            TemporaryObject {
+                orphaned: owner.is_none() && !replaced_index,
+                kind: leftover.kind,
+                table: leftover.table,
+                name: leftover.name,
+                owner,
+            }
+        })
+        .collect();
+
+    Ok(objects)
+}
+
+// Fail if there are leftovers from a migration even though none is in progress.
+// Continuing in this state is unpredictable as triggers might reference helpers in
+// the `reshape` schema which no longer exist.
+pub fn check_for_leftovers(db: &mut DbConn, state: &State) -> anyhow::Result<()> {
+    if !matches!(state, State::Idle) {
+        return Ok(());
+    }
+
+    let leftovers = find_leftovers(db)?;
+    if leftovers.is_empty() {
+        return Ok(());
+    }
+
+    let names: Vec<String> = leftovers
+        .iter()
+        .take(5)
+        .map(|leftover| format!("{} {}", leftover.kind, leftover.name))
+        .collect();
+
+    Err(Error::ConflictingState(format!(
+        "found objects left over from a migration ({}{}) but no migration is in progress. The `reshape` schema might have been deleted or modified manually. Please run `reshape repair` to clean up.",
+        names.join(", "),
+        if leftovers.len() > names.len() { ", ..." } else { "" },
+    ))
+    .into())
+}
+
+// Fail if anything is left over once a migration has been completed or aborted,
+// including the schemas of any migration other than the current one and those kept
+// using `--retain-schemas`. Unlike `check_for_leftovers`, every leftover is listed as
+// this is used to test migrations.
+pub fn check_cleaned_up(db: &mut DbConn) -> anyhow::Result<()> {
+    let mut names: Vec<String> = find_leftovers(db)?.iter().map(Leftover::describe).collect();
+
+    let kept_schemas = retention::existing_schemas(db)?;
+    names.extend(
+        migration_schemas(db)?
+            .into_iter()
+            .filter(|schema| !kept_schemas.contains(schema))
+            .map(|schema| format!("schema {}", schema)),
+    );
+
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "found objects left over from the migration: {}",
+        names.join(", ")
+    ))
+}
+
+fn migration_schemas(db: &mut DbConn) -> anyhow::Result<Vec<String>> {
+    let pattern = db.namespace().migration_schema_pattern();
+    let schemas = db
+        .query_with_params(
+            "
+            SELECT schema_name::TEXT AS name
+            FROM information_schema.schemata
+            WHERE schema_name LIKE $1
+            ",
+            &[&pattern],
+        )
+        .context("failed to get migration schemas")?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+
+    Ok(schemas)
+}
+
+// Repair brings the database back to a consistent state after the bookkeeping in
+// the `reshape` schema has been lost. All temporary objects are removed, which
+// reverts any in-progress migration the same way an abort would.
+//
+// If the migration history was lost too, it's reconstructed from the migration
+// schemas which still exist. The latest migration with a schema is considered to be
+// completed, unless there are leftovers from an in-progress migration and an earlier
+// schema exists too, in which case the latest one belonged to the aborted migration.
+pub fn repair(
+    db: &mut DbConn,
+    state: &mut State,
+    migrations: Vec<Migration>,
+) -> anyhow::Result<()> {
+    if !matches!(state, State::Idle) {
+        return Err(Error::ConflictingState(
+            "a migration is in progress and can't be repaired. Please use `reshape migration complete` or `reshape migration abort`."
+                .to_string(),
+        )
+        .into());
+    }
+
+    let leftovers = find_leftovers(db)?;
+    let had_leftovers = !leftovers.is_empty();
+
+    let namespace = db.namespace().clone();
+    let existing_schemas = migration_schemas(db)?;
+
+    if state::current_migration(db)?.is_none() {
+        let with_schema: Vec<usize> = migrations
+            .iter()
+            .enumerate()
+            .filter(|(_, migration)| {
+                existing_schemas.contains(&namespace.schema_name_for_migration(&migration.name))
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        let completed_index = match with_schema.as_slice() {
+            [] => None,
+            [.., previous, _] if had_leftovers => Some(*previous),
+            [.., last] => Some(*last),
+        };
+
+        if let Some(index) = completed_index {
+            let completed = &migrations[..=index];
+            state::save_migrations(db, completed, &[])
+                .context("failed to reconstruct migration history")?;
+
+            println!(
+                "Reconstructed migration history, {} migrations marked as completed",
+                completed.len()
+            );
+        }
+    }
+
+    // Only the schemas of completed migrations should remain, which are the current
+    // one and those kept using `--retain-schemas`. The other schemas must be removed
+    // before the leftovers as their views might depend on them.
+    let kept_schemas = retention::existing_schemas(db)?;
+    for schema in existing_schemas {
+        if kept_schemas.contains(&schema) {
+            continue;
+        }
+
+        db.run(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+            .with_context(|| format!("failed to drop schema {}", schema))?;
+        println!("Removed schema {}", schema);
+    }
+
+    remove_leftovers(db, leftovers)?;
+
+    *state = State::Idle;
+    state.save(db).context("failed to save state")?;
+
+    println!("Repair finished");
+    Ok(())
+}
+
+fn remove_leftovers(db: &mut DbConn, leftovers: Vec<Leftover>) -> anyhow::Result<()> {
+    let old_index_prefix = format!("{}_old_", db.namespace().object_prefix());
+
+    // Leftovers are ordered so that functions are removed first, as the triggers
+    // using them might write to the temporary columns
+    for leftover in leftovers {
+        let table = leftover.table.as_deref().unwrap_or_default();
+        let query = match leftover.kind.as_str() {
+            "function" => format!(r#"DROP FUNCTION IF EXISTS "{}" CASCADE"#, leftover.name),
+            "constraint" => format!(
+                r#"ALTER TABLE "{}" DROP CONSTRAINT IF EXISTS "{}""#,
+                table, leftover.name
+            ),
+            // The table of a domain constraint is the domain it's on
+            "domain constraint" => format!(
+                r#"ALTER DOMAIN "{}" DROP CONSTRAINT IF EXISTS "{}""#,
+                table, leftover.name
+            ),
+            "index" => {
+                // Existing indices are temporarily renamed with this prefix while
+                // being replaced. Restore the original name if it's still free.
+                let original = leftover.name.trim_start_matches(&old_index_prefix);
+                let original_exists = !db
+                    .query_with_params(
+                        "SELECT 1 FROM pg_indexes WHERE schemaname = 'public' AND indexname = $1",
+                        &[&original],
+                    )?
+                    .is_empty();
+
+                if original != leftover.name && !original_exists {
+                    format!(
+                        r#"ALTER INDEX IF EXISTS "{}" RENAME TO "{}""#,
+                        leftover.name, original
+                    )
+                } else {
+                    format!(r#"DROP INDEX CONCURRENTLY IF EXISTS "{}""#, leftover.name)
+                }
+            }
+            _ => format!(
+                r#"ALTER TABLE "{}" DROP COLUMN IF EXISTS "{}""#,
+                table, leftover.name
+            ),
+        };
+
+        db.run(&query)
+            .with_context(|| format!("failed to remove {} {}", leftover.kind, leftover.name))?;
+        println!("Cleaned up {} {}", leftover.kind, leftover.name);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,260 @@
+use anyhow::Context;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::db::Conn;
+
+// Schemas of earlier migrations can be kept when a migration is completed, so
+// deployments which haven't switched their search_path yet keep working for a while.
+// Only the views are kept, the tables underneath have already been changed by the
+// later migrations.
+//
+// Completing a migration drops columns and tables which views in the kept schemas
+// might depend on. Before the actions are completed, the definitions of the views to
+// keep are saved and all earlier schemas are dropped. Once completed, the views are
+// recreated from their definitions. Views which reference something that no longer
+// exists can't be recreated and are left out.
+#[derive(Serialize, Deserialize, Debug)]
+struct RetainedSchema {
+    name: String,
+    grants: Vec<String>,
+    views: Vec<RetainedView>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RetainedView {
+    name: String,
+    definition: String,
+    grants: Vec<String>,
+}
+
+const DATA_KEY: &str = "retained_schemas";
+
+// Schemas of completed migrations which still exist, oldest first. The schema of the
+// current migration is included last.
+pub fn existing_schemas(db: &mut dyn Conn) -> anyhow::Result<Vec<String>> {
+    let state_schema = db.namespace().state_schema();
+    let exists: bool = db
+        .query_with_params(
+            "SELECT to_regclass($1) IS NOT NULL AS exists",
+            &[&format!("{}.migrations", state_schema)],
+        )?
+        .first()
+        .map(|row| row.get("exists"))
+        .unwrap_or_default();
+    if !exists {
+        return Ok(Vec::new());
+    }
+
+    let schemas: Vec<String> = db
+        .query(&format!(
+            "SELECT name FROM {}.migrations ORDER BY index",
+            state_schema
+        ))
+        .context("failed to get completed migrations")?
+        .iter()
+        .map(|row| {
+            db.namespace()
+                .schema_name_for_migration(row.get::<_, &str>("name"))
+        })
+        .collect();
+
+    let existing: Vec<String> = db
+        .query_with_params(
+            "SELECT nspname::TEXT AS name FROM pg_namespace WHERE nspname::TEXT = ANY($1)",
+            &[&schemas],
+        )
+        .context("failed to get migration schemas")?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+
+    Ok(schemas
+        .into_iter()
+        .filter(|schema| existing.contains(schema))
+        .collect())
+}
+
+// Save the views of the newest `retain` schemas and drop all schemas of completed
+// migrations, including the current one. If the views have already been saved by an
+// earlier, interrupted completion, they are left as is.
+pub fn save_and_drop(db: &mut dyn Conn, retain: usize) -> anyhow::Result<()> {
+    let schemas = existing_schemas(db)?;
+
+    if load(db)?.is_none() && retain > 0 {
+        let kept = &schemas[schemas.len().saturating_sub(retain)..];
+        let mut retained = Vec::new();
+        for schema in kept {
+            retained.push(capture_schema(db, schema)?);
+        }
+
+        db.query_with_params(
+            &format!(
+                "INSERT INTO {}.data (key, value) VALUES ($1, $2)",
+                db.namespace().state_schema()
+            ),
+            &[&DATA_KEY, &serde_json::to_value(&retained)?],
+        )
+        .context("failed to save retained schemas")?;
+    }
+
+    for schema in schemas {
+        db.run(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+            .with_context(|| format!("failed to remove schema {}", schema))?;
+    }
+
+    Ok(())
+}
+
+// Recreate the schemas saved by `save_and_drop`. Each view is created separately so
+// one which can't be recreated doesn't stop the others.
+pub fn restore(db: &mut dyn Conn) -> anyhow::Result<()> {
+    let retained = match load(db)? {
+        Some(retained) => retained,
+        None => return Ok(()),
+    };
+
+    let mut transaction = db.transaction().context("failed to create transaction")?;
+    for schema in retained {
+        transaction
+            .run(&format!(
+                "DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema}",
+                schema = schema.name
+            ))
+            .with_context(|| format!("failed to create schema {}", schema.name))?;
+        for grant in &schema.grants {
+            transaction.run(grant)?;
+        }
+
+        for view in &schema.views {
+            let result = create_view(&mut transaction, &schema.name, view);
+            if let Err(err) = result {
+                println!(
+                    "{} view {}.\"{}\" couldn't be kept: {:#}",
+                    "Warning:".yellow(),
+                    schema.name,
+                    view.name,
+                    err
+                );
+            }
+        }
+    }
+
+    transaction
+        .query_with_params(
+            &format!(
+                "DELETE FROM {}.data WHERE key = $1",
+                transaction.namespace().state_schema()
+            ),
+            &[&DATA_KEY],
+        )
+        .context("failed to remove retained schemas")?;
+    transaction.commit()
+}
+
+// Schemas of completed migrations which `prune` drops, all but the current one and the
+// newest `keep` before it
+pub fn prunable(db: &mut dyn Conn, keep: usize) -> anyhow::Result<Vec<String>> {
+    let mut schemas = existing_schemas(db)?;
+
+    // The schema of the current migration is always kept
+    if let (Some(current), Some(last)) = (crate::state::current_migration(db)?, schemas.last()) {
+        if *last == db.namespace().schema_name_for_migration(&current) {
+            schemas.pop();
+        }
+    }
+
+    Ok(schemas
+        .drain(..schemas.len().saturating_sub(keep))
+        .collect())
+}
+
+// Drop all schemas of completed migrations but the current one and the newest `keep`
+// before it. Returns the names of the dropped schemas.
+pub fn prune(db: &mut dyn Conn, keep: usize) -> anyhow::Result<Vec<String>> {
+    let pruned = prunable(db, keep)?;
+    for schema in &pruned {
+        db.run(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+            .with_context(|| format!("failed to remove schema {}", schema))?;
+    }
+
+    Ok(pruned)
+}
+
+fn load(db: &mut dyn Conn) -> anyhow::Result<Option<Vec<RetainedSchema>>> {
+    let row = db
+        .query_with_params(
+            &format!(
+                "SELECT value FROM {}.data WHERE key = $1",
+                db.namespace().state_schema()
+            ),
+            &[&DATA_KEY],
+        )
+        .context("failed to load retained schemas")?
+        .pop();
+
+    match row {
+        Some(row) => Ok(Some(serde_json::from_value(row.get("value"))?)),
+        None => Ok(None),
+    }
+}
+
+fn capture_schema(db: &mut dyn Conn, schema: &str) -> anyhow::Result<RetainedSchema> {
+    let grants = db
+        .query_with_params(
+            "
+            SELECT format('GRANT %s ON SCHEMA %I TO %s', acl.privilege_type, n.nspname, CASE WHEN acl.grantee = 0 THEN 'PUBLIC' ELSE quote_ident(pg_get_userbyid(acl.grantee)) END) AS grant
+            FROM pg_namespace n, aclexplode(n.nspacl) acl
+            WHERE n.nspname::TEXT = $1 AND acl.grantee <> n.nspowner
+            ",
+            &[&schema],
+        )
+        .with_context(|| format!("failed to get privileges on schema {}", schema))?
+        .iter()
+        .map(|row| row.get("grant"))
+        .collect();
+
+    let views = db
+        .query_with_params(
+            "
+            SELECT
+                c.relname::TEXT AS name,
+                pg_get_viewdef(c.oid) AS definition,
+                ARRAY(
+                    SELECT format('GRANT %s ON %I.%I TO %s', acl.privilege_type, $1::TEXT, c.relname, CASE WHEN acl.grantee = 0 THEN 'PUBLIC' ELSE quote_ident(pg_get_userbyid(acl.grantee)) END)
+                    FROM aclexplode(c.relacl) acl
+                    WHERE acl.grantee <> c.relowner
+                ) AS grants
+            FROM pg_class c
+            WHERE c.relnamespace = $1::TEXT::regnamespace AND c.relkind = 'v'
+            ORDER BY c.relname
+            ",
+            &[&schema],
+        )
+        .with_context(|| format!("failed to get views in schema {}", schema))?
+        .iter()
+        .map(|row| RetainedView {
+            name: row.get("name"),
+            definition: row.get("definition"),
+            grants: row.get("grants"),
+        })
+        .collect();
+
+    Ok(RetainedSchema {
+        name: schema.to_string(),
+        grants,
+        views,
+    })
+}
+
+fn create_view(db: &mut dyn Conn, schema: &str, view: &RetainedView) -> anyhow::Result<()> {
+    let mut transaction = db.transaction()?;
+    transaction.run(&format!(
+        r#"CREATE VIEW {}."{}" AS {}"#,
+        schema, view.name, view.definition
+    ))?;
+    for grant in &view.grants {
+        transaction.run(grant)?;
+    }
+    transaction.commit()
+}
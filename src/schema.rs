@@ -10,6 +10,9 @@ use std::collections::{HashMap, HashSet};
 // changes are:
 //   - Changing the name which updates `current_name`.
 //   - Removing which sets the `removed` flag.
+//   - Reordering the columns which sets `column_order`.
+//   - Granting or revoking privileges which adds to `privilege_changes`. These are
+//     replicated onto the views for the migration.
 //
 // Changes to a column are tracked by a `ColumnChanges` struct which reside in
 // the corresponding `TableChanges`. The possible changes are:
@@ -21,7 +24,7 @@ use std::collections::{HashMap, HashSet};
 //
 // Schema provides some schema introspection methods, `get_tables` and `get_table`,
 // which will retrieve the current schema from the database and apply the changes.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Schema {
     table_changes: Vec<TableChanges>,
 }
@@ -58,20 +61,31 @@ impl Default for Schema {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TableChanges {
     current_name: String,
     real_name: String,
     column_changes: Vec<ColumnChanges>,
+    column_order: Option<Vec<String>>,
+    privilege_changes: Vec<PrivilegeChange>,
     removed: bool,
 }
 
+#[derive(Debug, Clone)]
+struct PrivilegeChange {
+    granted: bool,
+    role: String,
+    privileges: Vec<String>,
+}
+
 impl TableChanges {
     fn new(name: String) -> Self {
         Self {
             current_name: name.to_string(),
             real_name: name,
             column_changes: Vec::new(),
+            column_order: None,
+            privilege_changes: Vec::new(),
             removed: false,
         }
     }
@@ -101,9 +115,42 @@ impl TableChanges {
     pub fn set_removed(&mut self) {
         self.removed = true;
     }
+
+    pub fn grant(&mut self, role: &str, privileges: &[String]) {
+        self.privilege_changes.push(PrivilegeChange {
+            granted: true,
+            role: role.to_string(),
+            privileges: privileges.to_vec(),
+        });
+    }
+
+    pub fn revoke(&mut self, role: &str, privileges: &[String]) {
+        self.privilege_changes.push(PrivilegeChange {
+            granted: false,
+            role: role.to_string(),
+            privileges: privileges.to_vec(),
+        });
+    }
+
+    // Order the columns of the table by their current names. The order is stored using
+    // the backing columns so it's kept if columns are renamed later on. Columns which
+    // aren't included are placed last.
+    pub fn set_column_order(&mut self, columns: &[String]) {
+        let column_order = columns
+            .iter()
+            .map(|name| {
+                self.column_changes
+                    .iter()
+                    .find(|changes| &changes.current_name == name)
+                    .map(|changes| changes.real_name().to_string())
+                    .unwrap_or_else(|| name.to_string())
+            })
+            .collect();
+        self.column_order = Some(column_order);
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ColumnChanges {
     current_name: String,
     backing_columns: Vec<String>,
@@ -143,6 +190,23 @@ pub struct Table {
     pub name: String,
     pub real_name: String,
     pub columns: Vec<Column>,
+    // Privileges granted to other roles than the owner of the table
+    pub privileges: Vec<TablePrivilege>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TablePrivilege {
+    pub role: String,
+    pub privilege: String,
+}
+
+// Role as written in GRANT and REVOKE statements, where PUBLIC is a keyword for all roles
+pub fn quote_grantee(role: &str) -> String {
+    if role == "PUBLIC" {
+        role.to_string()
+    } else {
+        format!(r#""{}""#, role)
+    }
 }
 
 #[derive(Debug)]
@@ -152,19 +216,23 @@ pub struct Column {
     pub data_type: String,
     pub nullable: bool,
     pub default: Option<String>,
+    // Quoted name of the collation if it isn't the default for the type
+    pub collation: Option<String>,
 }
 
 impl Schema {
     pub fn get_tables(&self, db: &mut dyn Conn) -> anyhow::Result<Vec<Table>> {
+        let tables = db.tables().clone();
         db.query(
             "
             SELECT table_name
             FROM information_schema.tables
-            WHERE table_schema = 'public'
+            WHERE table_schema = 'public' AND table_type = 'BASE TABLE'
             ",
         )?
         .iter()
         .map(|row| row.get::<'_, _, String>("table_name"))
+        .filter(|real_name| tables.manages(real_name))
         .filter_map(|real_name| {
             let table_changes = self
                 .table_changes
@@ -206,24 +274,45 @@ impl Schema {
             .iter()
             .find(|changes| changes.real_name == real_table_name);
 
-        let real_columns: Vec<(String, String, bool, Option<String>)> = db
-            .query(&format!(
+        // Types are formatted the same way Postgres itself would write them, including
+        // modifiers like `character varying(10)`, arrays and the schema of types which
+        // aren't on the search path, such as types provided by extensions. Collations
+        // are only included when they differ from the default for the type.
+        let real_columns: Vec<Column> = db
+            .query_with_params(
                 "
-                SELECT column_name, CASE WHEN data_type = 'USER-DEFINED' THEN udt_name ELSE data_type END, is_nullable, column_default
-                FROM information_schema.columns
-                WHERE table_name = '{table}' AND table_schema = 'public'
-                ORDER BY ordinal_position
+                SELECT
+                    pg_attribute.attname::TEXT AS column_name,
+                    format_type(pg_attribute.atttypid, pg_attribute.atttypmod) AS data_type,
+                    NOT pg_attribute.attnotnull AS nullable,
+                    CASE WHEN pg_attribute.attgenerated = ''
+                        THEN pg_get_expr(pg_attrdef.adbin, pg_attrdef.adrelid)
+                    END AS column_default,
+                    CASE WHEN pg_attribute.attcollation <> pg_type.typcollation
+                        THEN format('%I.%I', pg_namespace.nspname, pg_collation.collname)
+                    END AS collation
+                FROM pg_attribute
+                JOIN pg_type ON pg_type.oid = pg_attribute.atttypid
+                LEFT JOIN pg_attrdef
+                    ON pg_attrdef.adrelid = pg_attribute.attrelid
+                    AND pg_attrdef.adnum = pg_attribute.attnum
+                LEFT JOIN pg_collation ON pg_collation.oid = pg_attribute.attcollation
+                LEFT JOIN pg_namespace ON pg_namespace.oid = pg_collation.collnamespace
+                WHERE pg_attribute.attrelid = to_regclass(format('public.%I', $1::TEXT))
+                    AND pg_attribute.attnum > 0
+                    AND NOT pg_attribute.attisdropped
+                ORDER BY pg_attribute.attnum
                 ",
-                table = real_table_name,
-            ))?
+                &[&real_table_name],
+            )?
             .iter()
-            .map(|row| {
-                (
-                    row.get("column_name"),
-                    row.get("data_type"),
-                    row.get::<'_, _, String>("is_nullable") == "YES",
-                    row.get("column_default"),
-                )
+            .map(|row| Column {
+                name: row.get("column_name"),
+                real_name: row.get("column_name"),
+                data_type: row.get("data_type"),
+                nullable: row.get("nullable"),
+                default: row.get("column_default"),
+                collation: row.get("collation"),
             })
             .collect();
 
@@ -254,25 +343,69 @@ impl Schema {
 
         let mut columns: Vec<Column> = Vec::new();
 
-        for (real_name, data_type, nullable, default) in real_columns {
-            if ignore_columns.contains(&*real_name) {
+        for mut column in real_columns {
+            if ignore_columns.contains(&column.real_name) {
                 continue;
             }
 
-            let name = aliases
-                .get(&real_name)
-                .map(|alias| alias.to_string())
-                .unwrap_or_else(|| real_name.to_string());
-
-            columns.push(Column {
-                name,
-                real_name,
-                data_type,
-                nullable,
-                default,
+            if let Some(alias) = aliases.get(&column.real_name) {
+                column.name = alias.to_string();
+            }
+
+            columns.push(column);
+        }
+
+        if let Some(column_order) = table_changes.and_then(|changes| changes.column_order.as_ref())
+        {
+            columns.sort_by_key(|column| {
+                column_order
+                    .iter()
+                    .position(|name| *name == column.real_name)
+                    .unwrap_or(usize::MAX)
             });
         }
 
+        // Privileges granted to PUBLIC have no grantee role
+        let mut privileges: Vec<TablePrivilege> = db
+            .query_with_params(
+                "
+                SELECT
+                    COALESCE(pg_roles.rolname::TEXT, 'PUBLIC') AS role,
+                    acl.privilege_type AS privilege
+                FROM pg_class
+                CROSS JOIN LATERAL aclexplode(pg_class.relacl) AS acl
+                LEFT JOIN pg_roles ON pg_roles.oid = acl.grantee
+                WHERE pg_class.oid = to_regclass(format('public.%I', $1::TEXT))
+                    AND acl.grantee <> pg_class.relowner
+                ORDER BY role, privilege
+                ",
+                &[&real_table_name],
+            )?
+            .iter()
+            .map(|row| TablePrivilege {
+                role: row.get("role"),
+                privilege: row.get("privilege"),
+            })
+            .collect();
+
+        for change in table_changes
+            .iter()
+            .flat_map(|changes| &changes.privilege_changes)
+        {
+            for privilege in &change.privileges {
+                let privilege = TablePrivilege {
+                    role: change.role.to_string(),
+                    privilege: privilege.to_string(),
+                };
+
+                if !change.granted {
+                    privileges.retain(|existing| *existing != privilege);
+                } else if !privileges.contains(&privilege) {
+                    privileges.push(privilege);
+                }
+            }
+        }
+
         let current_table_name = table_changes
             .map(|changes| changes.current_name.as_ref())
             .unwrap_or_else(|| real_table_name);
@@ -281,6 +414,7 @@ impl Schema {
             name: current_table_name.to_string(),
             real_name: real_table_name.to_string(),
             columns,
+            privileges,
         };
 
         Ok(table)
@@ -0,0 +1,111 @@
+use anyhow::Context;
+use postgres::{Client, Config, NoTls};
+use rand::{distributions::Alphanumeric, Rng};
+
+use crate::{Error, Reshape};
+
+// ScratchDatabase is a throwaway copy of a template database, created using
+// `CREATE DATABASE ... TEMPLATE`. Copying a prepared baseline is much faster than
+// rebuilding it for every test run and never touches a shared database, as the copy
+// is dropped again afterwards.
+//
+// The template can't have any open connections while it's being copied. The copy is
+// dropped when the ScratchDatabase is, terminating any connections still using it.
+// Use `remove` instead to find out if dropping it failed.
+pub struct ScratchDatabase {
+    config: Config,
+    name: String,
+    removed: bool,
+}
+
+impl ScratchDatabase {
+    // Create a copy of `template` on the server `config` connects to. The connection
+    // is only used to create and drop the copy, so any database on the server will do.
+    pub fn create(config: &Config, template: &str) -> anyhow::Result<ScratchDatabase> {
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(8)
+            .map(|byte| char::from(byte).to_ascii_lowercase())
+            .collect();
+
+        // Names are truncated to 63 bytes by Postgres, so the template name is
+        // shortened to keep the random suffix intact
+        let prefix: String = template.chars().take(40).collect();
+        let name = format!("{}_scratch_{}", prefix, suffix);
+
+        let mut db = connect(config)?;
+        db.batch_execute(&format!(
+            r#"CREATE DATABASE "{}" TEMPLATE "{}""#,
+            name, template
+        ))
+        .with_context(|| {
+            format!(
+                "failed to create scratch database from template {}. Make sure the template exists and has no open connections.",
+                template
+            )
+        })?;
+
+        Ok(ScratchDatabase {
+            config: config.clone(),
+            name,
+            removed: false,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Connection config for the scratch database
+    pub fn config(&self) -> Config {
+        let mut config = self.config.clone();
+        config.dbname(&self.name);
+        config
+    }
+
+    // Reshape connected to the scratch database, which is marked as such so it can be
+    // used with `Reshape::export_sql`
+    pub fn reshape(&self) -> anyhow::Result<Reshape> {
+        let mut reshape = Reshape::new_with_config(&self.config())?;
+        reshape.set_scratch_database(true);
+        Ok(reshape)
+    }
+
+    pub fn remove(mut self) -> anyhow::Result<()> {
+        self.removed = true;
+        drop_database(&self.config, &self.name)
+    }
+}
+
+impl Drop for ScratchDatabase {
+    fn drop(&mut self) {
+        if !self.removed {
+            let _ = drop_database(&self.config, &self.name);
+        }
+    }
+}
+
+fn connect(config: &Config) -> anyhow::Result<Client> {
+    config
+        .connect(NoTls)
+        .map_err(|err| Error::Connection(err.into()).into())
+}
+
+fn drop_database(config: &Config, name: &str) -> anyhow::Result<()> {
+    let mut db = connect(config)?;
+
+    // `DROP DATABASE ... WITH (FORCE)` requires Postgres 13, so any remaining
+    // connections are terminated manually
+    db.execute(
+        "
+        SELECT pg_terminate_backend(pid)
+        FROM pg_stat_activity
+        WHERE datname = $1 AND pid <> pg_backend_pid()
+        ",
+        &[&name],
+    )
+    .context("failed to close connections to scratch database")?;
+
+    db.batch_execute(&format!(r#"DROP DATABASE IF EXISTS "{}""#, name))
+        .with_context(|| format!("failed to drop scratch database {}", name))
+}
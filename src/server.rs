@@ -0,0 +1,145 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde_json::json;
+
+use crate::Reshape;
+
+// A small HTTP server which reports the state of the database, meant to run as a
+// sidecar next to deployments so orchestration and humans can check on migrations
+// without a shell. Requests are handled one at a time using the connection of the
+// `Reshape` instance, and only read the state without taking the lock.
+//
+// - `/healthz` responds with 200 if the database can be reached and 503 otherwise
+// - `/status` responds with the state, the current migration, any pending ones, the
+//   progress of the backfill currently running and any backfills left pending
+// - `/schema-query` responds with the query to select the schema to use
+pub(crate) fn serve(reshape: &mut Reshape, address: &str) -> anyhow::Result<()> {
+    // Allow only a port to be given, like ":8080", to listen on all interfaces
+    let address = match address.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => address.to_string(),
+    };
+
+    let listener =
+        TcpListener::bind(&address).with_context(|| format!("failed to listen on {}", address))?;
+    println!("Listening on {}", address);
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        // A single misbehaving client shouldn't stop the server
+        let _ = handle(reshape, &mut stream);
+    }
+
+    Ok(())
+}
+
+fn handle(reshape: &mut Reshape, stream: &mut TcpStream) -> anyhow::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Skip the headers, none of them are used
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts
+        .next()
+        .unwrap_or_default()
+        .split('?')
+        .next()
+        .unwrap_or_default();
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/healthz") => match reshape.status() {
+            Ok(_) => (200, "text/plain", "ok\n".to_string()),
+            Err(err) => (503, "text/plain", format!("{:#}\n", err)),
+        },
+        ("GET", "/status") => match reshape.status() {
+            Ok(status) => {
+                let pending: Vec<&str> = status
+                    .state
+                    .pending_migrations()
+                    .iter()
+                    .map(|migration| migration.name.as_str())
+                    .collect();
+                let pending_backfills: Vec<_> = status
+                    .state
+                    .pending_backfills()
+                    .iter()
+                    .map(|backfill| {
+                        json!({
+                            "migration": backfill.migration,
+                            "action": backfill.action,
+                            "started": backfill.is_started(),
+                        })
+                    })
+                    .collect();
+                let body = json!({
+                    "state": status.state.name(),
+                    "current_migration": status.current_migration,
+                    "pending_migrations": pending,
+                    "progress": status.state.progress(),
+                    "backfill": status.backfill,
+                    "pending_backfills": pending_backfills,
+                });
+                (200, "application/json", format!("{}\n", body))
+            }
+            Err(err) => error_response(&err),
+        },
+        ("GET", "/schema-query") => match reshape.current_schema_query() {
+            Ok(query) => (
+                200,
+                "text/plain",
+                format!("{}\n", query.unwrap_or_default()),
+            ),
+            Err(err) => error_response(&err),
+        },
+        ("GET", _) => (404, "text/plain", "not found\n".to_string()),
+        _ => (405, "text/plain", "method not allowed\n".to_string()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+fn error_response(err: &anyhow::Error) -> (u16, &'static str, String) {
+    let body = json!({ "error": format!("{:#}", err) });
+    (500, "application/json", format!("{}\n", body))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::Conn,
+    migrations::{self, Migration},
+};
+
+// SchemaSnapshot is the schema produced by applying all local migrations, meant to be
+// written to a `schema.lock` file which is checked in alongside the migrations. It
+// makes changes to the schema visible in code review and can be compared against a
+// live database to detect drift.
+//
+// Only names are tracked. Columns are compared as sets as Reshape might reorder them
+// when changing their type.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct SchemaSnapshot {
+    pub tables: BTreeMap<String, BTreeSet<String>>,
+    pub indices: BTreeMap<String, String>,
+    pub types: BTreeSet<String>,
+}
+
+impl SchemaSnapshot {
+    pub fn from_migrations(migrations: &[Migration]) -> SchemaSnapshot {
+        migrations::simulate_migrations(migrations).into_snapshot()
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<SchemaSnapshot> {
+        serde_json::from_str(json).context("failed to parse schema snapshot")
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = serde_json::to_string_pretty(self).unwrap();
+        json.push('\n');
+        json
+    }
+
+    // Describe all differences between two snapshots, using `name` and `other_name` to
+    // refer to where each snapshot came from
+    pub fn diff(&self, other: &SchemaSnapshot, name: &str, other_name: &str) -> Vec<String> {
+        let mut differences = Vec::new();
+
+        for (table, columns) in &self.tables {
+            let other_columns = match other.tables.get(table) {
+                Some(other_columns) => other_columns,
+                None => {
+                    differences.push(format!(
+                        "table \"{}\" exists in the {} but not in the {}",
+                        table, name, other_name
+                    ));
+                    continue;
+                }
+            };
+
+            for column in columns.difference(other_columns) {
+                differences.push(format!(
+                    "column \"{}\" on table \"{}\" exists in the {} but not in the {}",
+                    column, table, name, other_name
+                ));
+            }
+            for column in other_columns.difference(columns) {
+                differences.push(format!(
+                    "column \"{}\" on table \"{}\" exists in the {} but not in the {}",
+                    column, table, other_name, name
+                ));
+            }
+        }
+        for table in other.tables.keys() {
+            if !self.tables.contains_key(table) {
+                differences.push(format!(
+                    "table \"{}\" exists in the {} but not in the {}",
+                    table, other_name, name
+                ));
+            }
+        }
+
+        for (index, table) in &self.indices {
+            match other.indices.get(index) {
+                Some(other_table) if other_table == table => {}
+                Some(other_table) => differences.push(format!(
+                    "index \"{}\" is on table \"{}\" in the {} but on table \"{}\" in the {}",
+                    index, table, name, other_table, other_name
+                )),
+                None => differences.push(format!(
+                    "index \"{}\" exists in the {} but not in the {}",
+                    index, name, other_name
+                )),
+            }
+        }
+        for index in other.indices.keys() {
+            if !self.indices.contains_key(index) {
+                differences.push(format!(
+                    "index \"{}\" exists in the {} but not in the {}",
+                    index, other_name, name
+                ));
+            }
+        }
+
+        for type_name in self.types.difference(&other.types) {
+            differences.push(format!(
+                "type \"{}\" exists in the {} but not in the {}",
+                type_name, name, other_name
+            ));
+        }
+        for type_name in other.types.difference(&self.types) {
+            differences.push(format!(
+                "type \"{}\" exists in the {} but not in the {}",
+                type_name, other_name, name
+            ));
+        }
+
+        differences
+    }
+}
+
+// Build a snapshot of the `public` schema of a live database. Temporary objects from
+// an in-progress migration are skipped, as are indices backing constraints, since
+// those are created implicitly and never named in migrations. Tables which Reshape
+// doesn't manage are skipped along with their indices.
+pub fn from_database(db: &mut dyn Conn) -> anyhow::Result<SchemaSnapshot> {
+    let mut snapshot = SchemaSnapshot::default();
+    let tables = db.tables().clone();
+
+    let columns = db
+        .query(
+            r"
+            SELECT columns.table_name::TEXT AS table_name, columns.column_name::TEXT AS column_name
+            FROM information_schema.columns
+            JOIN information_schema.tables
+                ON tables.table_schema = columns.table_schema
+                AND tables.table_name = columns.table_name
+            WHERE columns.table_schema = 'public'
+                AND tables.table_type = 'BASE TABLE'
+                AND columns.column_name NOT LIKE '\_\_reshape%'
+            ",
+        )
+        .context("failed to get columns")?;
+    for row in columns {
+        if !tables.manages(row.get("table_name")) {
+            continue;
+        }
+
+        snapshot
+            .tables
+            .entry(row.get("table_name"))
+            .or_default()
+            .insert(row.get("column_name"));
+    }
+
+    let indices = db
+        .query(
+            r"
+            SELECT index_class.relname::TEXT AS index_name, table_class.relname::TEXT AS table_name
+            FROM pg_index
+            JOIN pg_class index_class ON index_class.oid = pg_index.indexrelid
+            JOIN pg_class table_class ON table_class.oid = pg_index.indrelid
+            JOIN pg_namespace ON pg_namespace.oid = table_class.relnamespace
+            WHERE pg_namespace.nspname = 'public'
+                AND index_class.relname NOT LIKE '\_\_reshape%'
+                AND NOT EXISTS (
+                    SELECT 1 FROM pg_constraint WHERE pg_constraint.conindid = pg_index.indexrelid
+                )
+            ",
+        )
+        .context("failed to get indices")?;
+    for row in indices {
+        if !tables.manages(row.get("table_name")) {
+            continue;
+        }
+
+        snapshot
+            .indices
+            .insert(row.get("index_name"), row.get("table_name"));
+    }
+
+    // Enums, domains and standalone composite types. Every table also has a composite
+    // type, which is excluded by only including types whose relation is a composite.
+    let types = db
+        .query(
+            "
+            SELECT pg_type.typname::TEXT AS type_name
+            FROM pg_type
+            JOIN pg_namespace ON pg_namespace.oid = pg_type.typnamespace
+            LEFT JOIN pg_class ON pg_class.oid = pg_type.typrelid
+            WHERE pg_namespace.nspname = 'public'
+                AND (
+                    pg_type.typtype IN ('e', 'd')
+                    OR (pg_type.typtype = 'c' AND pg_class.relkind = 'c')
+                )
+            ",
+        )
+        .context("failed to get types")?;
+    for row in types {
+        snapshot.types.insert(row.get("type_name"));
+    }
+
+    Ok(snapshot)
+}
@@ -1,49 +1,276 @@
-use crate::{db::Conn, migrations::Migration};
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    backfill::PendingBackfill,
+    db::{Conn, Counters},
+    migrations::{Migration, ObjectNames},
+};
 use anyhow::anyhow;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use version::version;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 #[serde(tag = "state")]
 pub enum State {
     #[serde(rename = "idle")]
+    #[default]
     Idle,
 
     #[serde(rename = "applying")]
     Applying { migrations: Vec<Migration> },
 
     #[serde(rename = "in_progress")]
-    InProgress { migrations: Vec<Migration> },
+    InProgress {
+        migrations: Vec<Migration>,
+        #[serde(default)]
+        stats: Vec<ActionStats>,
+        // Backfills which were skipped or stopped early, in the order they will run
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pending_backfills: Vec<PendingBackfill>,
+    },
 
     #[serde(rename = "completing")]
     Completing {
         migrations: Vec<Migration>,
-        current_migration_index: usize,
-        current_action_index: usize,
+        // Every action before this has been completed
+        progress: MigrationProgress,
+        #[serde(default)]
+        stats: Vec<ActionStats>,
     },
 
     #[serde(rename = "aborting")]
     Aborting {
         migrations: Vec<Migration>,
-        last_migration_index: usize,
-        last_action_index: usize,
+        // Every action from this onwards has been aborted
+        progress: MigrationProgress,
     },
 }
 
+// A position between two actions of the pending migrations, used to keep track of
+// how far completing or aborting has come so it can be resumed after an interruption.
+// Actions are completed front to back, so everything before the position has been
+// completed. Aborting goes back to front, so everything from the position onwards has
+// been aborted or was never applied.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MigrationProgress {
+    pub migration: usize,
+    pub action: usize,
+}
+
+impl MigrationProgress {
+    // Before the first action of the first migration
+    pub fn start() -> Self {
+        Self::before(0, 0)
+    }
+
+    // After the last action of the last migration
+    pub fn end(migrations: &[Migration]) -> Self {
+        Self::before(migrations.len(), 0)
+    }
+
+    pub fn before(migration: usize, action: usize) -> Self {
+        MigrationProgress { migration, action }
+    }
+
+    // Moves on to the next migration after its last action, so each position has a
+    // single representation
+    pub fn after(migrations: &[Migration], migration: usize, action: usize) -> Self {
+        let action_count = migrations
+            .get(migration)
+            .map(|migration| migration.actions.len())
+            .unwrap_or_default();
+
+        if action + 1 >= action_count {
+            Self::before(migration + 1, 0)
+        } else {
+            Self::before(migration, action + 1)
+        }
+    }
+
+    // Whether the action comes before this position
+    pub fn is_past(&self, migration: usize, action: usize) -> bool {
+        Self::before(migration, action) < *self
+    }
+
+    // Whether all of the migration's actions come before this position
+    pub fn is_past_migration(&self, migration: usize, action_count: usize) -> bool {
+        Self::before(migration, action_count) <= *self
+    }
+
+    // Number of actions before this position
+    pub fn actions_passed(&self, migrations: &[Migration]) -> usize {
+        migrations
+            .iter()
+            .enumerate()
+            .map(|(index, migration)| {
+                (0..migration.actions.len())
+                    .filter(|action| self.is_past(index, *action))
+                    .count()
+            })
+            .sum()
+    }
+}
+
+// Stats collected for a single action while starting or completing a migration.
+// They are kept in the state until the migration has been completed and then
+// stored alongside it in `reshape.migrations`, which helps when planning future
+// migrations for the same tables.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[non_exhaustive]
+pub struct ActionStats {
+    pub migration: String,
+    pub action: String,
+    pub phase: Phase,
+
+    // Milliseconds since the Unix epoch
+    pub started_at: u64,
+    pub finished_at: u64,
+
+    pub rows_backfilled: u64,
+    pub retries: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Start,
+    Complete,
+    Analyze,
+}
+
+impl ActionStats {
+    pub(crate) fn new(
+        migration: &str,
+        action: String,
+        phase: Phase,
+        started_at: SystemTime,
+        counters: Counters,
+    ) -> Self {
+        ActionStats {
+            migration: migration.to_string(),
+            action,
+            phase,
+            started_at: unix_millis(started_at),
+            finished_at: unix_millis(SystemTime::now()),
+            rows_backfilled: counters.rows_backfilled,
+            retries: counters.retries,
+        }
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        self.finished_at.saturating_sub(self.started_at)
+    }
+}
+
+// Earlier versions stored the progress as two separate indices. When completing, they
+// pointed one migration past the last completed action. Aborts are idempotent, so an
+// abort from an earlier version simply starts over from the end.
+fn upgrade_progress(json: &mut serde_json::Value) {
+    let Some(state) = json.as_object_mut() else {
+        return;
+    };
+
+    let index = |state: &mut serde_json::Map<String, serde_json::Value>, key: &str| {
+        state
+            .remove(key)
+            .and_then(|index| index.as_u64())
+            .map(|index| index as usize)
+    };
+
+    let progress = match state.get("state").and_then(|name| name.as_str()) {
+        Some("completing") => {
+            let migration = index(state, "current_migration_index");
+            let action = index(state, "current_action_index");
+            match (migration, action) {
+                (Some(0), _) => MigrationProgress::start(),
+                (Some(migration), Some(action)) => MigrationProgress::before(migration - 1, action),
+                _ => return,
+            }
+        }
+        Some("aborting") => {
+            if index(state, "last_migration_index").is_none() {
+                return;
+            }
+            state.remove("last_action_index");
+
+            let migrations = state
+                .get("migrations")
+                .and_then(|migrations| migrations.as_array())
+                .map(|migrations| migrations.len())
+                .unwrap_or_default();
+            MigrationProgress::before(migrations, 0)
+        }
+        _ => return,
+    };
+
+    if let Ok(progress) = serde_json::to_value(progress) {
+        state.insert("progress".to_string(), progress);
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
+
 impl State {
+    // Name of the state, the same as it's stored in the database
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Applying { .. } => "applying",
+            State::InProgress { .. } => "in_progress",
+            State::Completing { .. } => "completing",
+            State::Aborting { .. } => "aborting",
+        }
+    }
+
+    // Migrations which are being applied, completed or aborted
+    pub fn pending_migrations(&self) -> &[Migration] {
+        match self {
+            State::Idle => &[],
+            State::Applying { migrations }
+            | State::InProgress { migrations, .. }
+            | State::Completing { migrations, .. }
+            | State::Aborting { migrations, .. } => migrations,
+        }
+    }
+
     pub fn load(db: &mut impl Conn) -> anyhow::Result<State> {
         Self::ensure_schema_and_table(db)?;
+        Self::load_existing(db)
+    }
 
-        let results = db.query("SELECT value FROM reshape.data WHERE key = 'state'")?;
+    // Load the state without creating the `reshape` schema first, which makes it
+    // usable from read-only connections. If Reshape has never been run, the state
+    // is idle.
+    pub fn load_existing(db: &mut impl Conn) -> anyhow::Result<State> {
+        let schema = db.namespace().state_schema();
+        if !table_exists(db, &format!("{}.data", schema))? {
+            return Ok(Default::default());
+        }
+
+        let results = db.query(&format!(
+            "SELECT value FROM {}.data WHERE key = 'state'",
+            schema
+        ))?;
 
         let state = match results.first() {
             Some(row) => {
-                let json: serde_json::Value = row.get(0);
+                let mut json: serde_json::Value = row.get(0);
+                upgrade_progress(&mut json);
                 serde_json::from_value(json)?
             }
             None => Default::default(),
         };
+
+        db.record_state(&state);
         Ok(state)
     }
 
@@ -51,15 +278,28 @@ impl State {
         Self::ensure_schema_and_table(db)?;
 
         let json = serde_json::to_value(self)?;
-        db.query_with_params(
-            "INSERT INTO reshape.data (key, value) VALUES ('state', $1) ON CONFLICT (key) DO UPDATE SET value = $1",
-            &[&json]
-        )?;
+        let query = format!(
+            "INSERT INTO {}.data (key, value) VALUES ('state', $1) ON CONFLICT (key) DO UPDATE SET value = $1",
+            db.namespace().state_schema(),
+        );
+        db.query_with_params(&query, &[&json])?;
+
+        // The naming of temporary objects is chosen when migrations are started, and
+        // failures are only kept while the failed migration is being applied
+        if let State::Idle = self {
+            db.run(&format!(
+                "DELETE FROM {}.data WHERE key IN ('object_names', 'failed_action')",
+                db.namespace().state_schema(),
+            ))?;
+        }
+
+        db.record_state(self);
         Ok(())
     }
 
     pub fn clear(&mut self, db: &mut impl Conn) -> anyhow::Result<()> {
-        db.run("DROP SCHEMA reshape CASCADE")?;
+        let schema = db.namespace().state_schema();
+        db.run(&format!("DROP SCHEMA {} CASCADE", schema))?;
 
         *self = Self::default();
 
@@ -71,10 +311,12 @@ impl State {
         let current_state = std::mem::replace(self, Self::Idle);
 
         match current_state {
-            Self::Completing { migrations, .. } => {
+            Self::Completing {
+                migrations, stats, ..
+            } => {
                 // Add migrations and update state in a transaction to ensure atomicity
                 let mut transaction = db.transaction()?;
-                save_migrations(&mut transaction, &migrations)?;
+                save_migrations(&mut transaction, &migrations, &stats)?;
                 self.save(&mut transaction)?;
                 transaction.commit()?;
             }
@@ -97,49 +339,81 @@ impl State {
         };
     }
 
-    pub fn in_progress(&mut self, new_migrations: Vec<Migration>) {
+    pub fn in_progress(
+        &mut self,
+        new_migrations: Vec<Migration>,
+        stats: Vec<ActionStats>,
+        pending_backfills: Vec<PendingBackfill>,
+    ) {
         *self = Self::InProgress {
             migrations: new_migrations,
+            stats,
+            pending_backfills,
         };
     }
 
-    pub fn completing(
-        &mut self,
-        migrations: Vec<Migration>,
-        current_migration_index: usize,
-        current_action_index: usize,
-    ) {
+    // Stats collected so far are carried over from the current state
+    pub fn completing(&mut self, migrations: Vec<Migration>, progress: MigrationProgress) {
+        let stats = match self {
+            Self::InProgress { stats, .. } | Self::Completing { stats, .. } => {
+                std::mem::take(stats)
+            }
+            _ => Vec::new(),
+        };
+
         *self = Self::Completing {
             migrations,
-            current_migration_index,
-            current_action_index,
+            progress,
+            stats,
         }
     }
 
-    pub fn aborting(
-        &mut self,
-        migrations: Vec<Migration>,
-        last_migration_index: usize,
-        last_action_index: usize,
-    ) {
+    pub fn add_stats(&mut self, action_stats: ActionStats) {
+        if let Self::InProgress { stats, .. } | Self::Completing { stats, .. } = self {
+            stats.push(action_stats);
+        }
+    }
+
+    pub fn aborting(&mut self, migrations: Vec<Migration>, progress: MigrationProgress) {
         *self = Self::Aborting {
             migrations,
-            last_migration_index,
-            last_action_index,
+            progress,
+        }
+    }
+
+    // Backfills which must be run using `Reshape::backfill` before completing
+    pub fn pending_backfills(&self) -> &[PendingBackfill] {
+        match self {
+            Self::InProgress {
+                pending_backfills, ..
+            } => pending_backfills,
+            _ => &[],
+        }
+    }
+
+    // How far completing or aborting has come
+    pub fn progress(&self) -> Option<MigrationProgress> {
+        match self {
+            Self::Completing { progress, .. } | Self::Aborting { progress, .. } => Some(*progress),
+            _ => None,
         }
     }
 
     fn ensure_schema_and_table(db: &mut impl Conn) -> anyhow::Result<()> {
-        db.run("CREATE SCHEMA IF NOT EXISTS reshape")?;
+        let schema = db.namespace().state_schema();
+        db.run(&format!("CREATE SCHEMA IF NOT EXISTS {}", schema))?;
 
         // Create data table which will be a key-value table containing
         // the version and current state.
-        db.run("CREATE TABLE IF NOT EXISTS reshape.data (key TEXT PRIMARY KEY, value JSONB)")?;
+        db.run(&format!(
+            "CREATE TABLE IF NOT EXISTS {}.data (key TEXT PRIMARY KEY, value JSONB)",
+            schema
+        ))?;
 
         // Create migrations table which will store all completed migrations
-        db.run(
+        db.run(&format!(
             "
-            CREATE TABLE IF NOT EXISTS reshape.migrations (
+            CREATE TABLE IF NOT EXISTS {}.migrations (
                 index INTEGER GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
                 name TEXT NOT NULL,
                 description TEXT,
@@ -147,16 +421,44 @@ impl State {
                 completed_at TIMESTAMP DEFAULT NOW()
             )
             ",
-        )?;
+            schema
+        ))?;
+
+        // Stats and hashes were added after the migrations table, so it might need to
+        // be extended
+        db.run(&format!(
+            "ALTER TABLE {}.migrations ADD COLUMN IF NOT EXISTS stats JSONB, ADD COLUMN IF NOT EXISTS hash TEXT",
+            schema
+        ))?;
+
+        // Create change log table which actions with `change_log` enabled write to
+        // for every row changed while a migration is in progress. External consumers
+        // read it to reindex affected rows and delete entries once processed.
+        db.run(&format!(
+            "
+            CREATE TABLE IF NOT EXISTS {}.change_log (
+                id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+                migration TEXT NOT NULL,
+                table_name TEXT NOT NULL,
+                operation TEXT NOT NULL,
+                row_key JSONB NOT NULL,
+                changed_at TIMESTAMP DEFAULT NOW()
+            )
+            ",
+            schema
+        ))?;
 
         // Update the current version
         let encoded_version = serde_json::to_value(version!().to_string())?;
         db.query_with_params(
-            "
-            INSERT INTO reshape.data (key, value)
-            VALUES ('version', $1)
-            ON CONFLICT (key) DO UPDATE SET value = $1
-            ",
+            &format!(
+                "
+                INSERT INTO {}.data (key, value)
+                VALUES ('version', $1)
+                ON CONFLICT (key) DO UPDATE SET value = $1
+                ",
+                schema
+            ),
             &[&encoded_version],
         )?;
 
@@ -164,109 +466,711 @@ impl State {
     }
 }
 
-impl Default for State {
-    fn default() -> Self {
-        Self::Idle
+// How far the backfill currently running has come. It's saved after every batch so it
+// can be followed from other processes, for example using `reshape status`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BackfillProgress {
+    pub table: String,
+    pub rows_processed: u64,
+    // Rows in the table as estimated by Postgres, missing if the table has never been
+    // vacuumed or analyzed
+    pub estimated_rows: Option<u64>,
+}
+
+impl BackfillProgress {
+    pub fn new(table: impl Into<String>, estimated_rows: Option<u64>) -> Self {
+        BackfillProgress {
+            table: table.into(),
+            rows_processed: 0,
+            estimated_rows,
+        }
+    }
+
+    // The estimate might be stale, so the total is never lower than the number of rows
+    // processed so far
+    pub fn estimated_total(&self) -> Option<u64> {
+        self.estimated_rows
+            .map(|estimated_rows| estimated_rows.max(self.rows_processed))
+    }
+
+    pub fn percent(&self) -> Option<u64> {
+        self.estimated_total()
+            .map(|total| self.rows_processed * 100 / total.max(1))
+    }
+}
+
+// Passing `None` removes the progress once the backfill has finished
+pub(crate) fn save_backfill_progress(
+    db: &mut dyn Conn,
+    progress: Option<&BackfillProgress>,
+) -> anyhow::Result<()> {
+    let schema = db.namespace().state_schema();
+    match progress {
+        Some(progress) => {
+            let json = serde_json::to_value(progress)?;
+            db.query_with_params(
+                &format!(
+                    "INSERT INTO {}.data (key, value) VALUES ('backfill', $1) ON CONFLICT (key) DO UPDATE SET value = $1",
+                    schema,
+                ),
+                &[&json],
+            )?;
+        }
+        None => db.run(&format!(
+            "DELETE FROM {}.data WHERE key = 'backfill'",
+            schema
+        ))?,
+    }
+
+    Ok(())
+}
+
+pub fn load_backfill_progress(db: &mut dyn Conn) -> anyhow::Result<Option<BackfillProgress>> {
+    let schema = db.namespace().state_schema();
+    if !table_exists(db, &format!("{}.data", schema))? {
+        return Ok(None);
+    }
+
+    let progress = db
+        .query(&format!(
+            "SELECT value FROM {}.data WHERE key = 'backfill'",
+            schema
+        ))?
+        .first()
+        .map(|row| serde_json::from_value(row.get(0)))
+        .transpose()?;
+    Ok(progress)
+}
+
+// How many rows a seed or `load_data` action has inserted, saved along with every
+// batch so one which was interrupted continues after the rows it already inserted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SeedProgress {
+    pub migration: String,
+    pub action_index: usize,
+    pub rows: usize,
+}
+
+// Passing `None` removes the progress once the seed has finished
+pub(crate) fn save_seed_progress(
+    db: &mut dyn Conn,
+    progress: Option<&SeedProgress>,
+) -> anyhow::Result<()> {
+    let schema = db.namespace().state_schema();
+    match progress {
+        Some(progress) => {
+            let json = serde_json::to_value(progress)?;
+            db.query_with_params(
+                &format!(
+                    "INSERT INTO {}.data (key, value) VALUES ('seed', $1) ON CONFLICT (key) DO UPDATE SET value = $1",
+                    schema,
+                ),
+                &[&json],
+            )?;
+        }
+        None => db.run(&format!("DELETE FROM {}.data WHERE key = 'seed'", schema))?,
+    }
+
+    Ok(())
+}
+
+pub(crate) fn load_seed_progress(db: &mut dyn Conn) -> anyhow::Result<Option<SeedProgress>> {
+    let schema = db.namespace().state_schema();
+    let progress = db
+        .query(&format!(
+            "SELECT value FROM {}.data WHERE key = 'seed'",
+            schema
+        ))?
+        .first()
+        .map(|row| serde_json::from_value(row.get(0)))
+        .transpose()?;
+    Ok(progress)
+}
+
+// Seeds which ran when their migration was started, by migration name and action
+// index. Retrying or resuming the migration runs the actions again, so these are
+// skipped as their data has already been committed.
+pub(crate) fn seeded_on_start(
+    db: &mut dyn Conn,
+    migration: &str,
+    action_index: usize,
+) -> anyhow::Result<bool> {
+    Ok(load_seeded_on_start(db)?.contains(&(migration.to_string(), action_index)))
+}
+
+pub(crate) fn save_seeded_on_start(
+    db: &mut dyn Conn,
+    migration: &str,
+    action_index: usize,
+    seeded: bool,
+) -> anyhow::Result<()> {
+    let mut seeds = load_seeded_on_start(db)?;
+    seeds.retain(|seed| seed != &(migration.to_string(), action_index));
+    if seeded {
+        seeds.push((migration.to_string(), action_index));
+    }
+
+    let schema = db.namespace().state_schema();
+    if seeds.is_empty() {
+        db.run(&format!("DELETE FROM {}.data WHERE key = 'seeded'", schema))?;
+        return Ok(());
+    }
+
+    let json = serde_json::to_value(&seeds)?;
+    db.query_with_params(
+        &format!(
+            "INSERT INTO {}.data (key, value) VALUES ('seeded', $1) ON CONFLICT (key) DO UPDATE SET value = $1",
+            schema,
+        ),
+        &[&json],
+    )?;
+    Ok(())
+}
+
+fn load_seeded_on_start(db: &mut dyn Conn) -> anyhow::Result<Vec<(String, usize)>> {
+    let schema = db.namespace().state_schema();
+    let seeds = db
+        .query(&format!(
+            "SELECT value FROM {}.data WHERE key = 'seeded'",
+            schema
+        ))?
+        .first()
+        .map(|row| serde_json::from_value(row.get(0)))
+        .transpose()?
+        .unwrap_or_default();
+    Ok(seeds)
+}
+
+// The action which made `migrate` fail when failed migrations are kept in the
+// applying state to be retried, see `Reshape::set_retry_failed`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[non_exhaustive]
+pub struct FailedAction {
+    pub migration: String,
+    pub action: String,
+    pub error: String,
+
+    // Position of the action among the pending migrations
+    pub migration_index: usize,
+    pub action_index: usize,
+
+    // Every action before this was run, fully or partially, when the failure happened
+    pub applied: MigrationProgress,
+}
+
+// Passing `None` removes the failure once the migration is applied again
+pub(crate) fn save_failed_action(
+    db: &mut dyn Conn,
+    failed_action: Option<&FailedAction>,
+) -> anyhow::Result<()> {
+    let schema = db.namespace().state_schema();
+    match failed_action {
+        Some(failed_action) => {
+            let json = serde_json::to_value(failed_action)?;
+            db.query_with_params(
+                &format!(
+                    "INSERT INTO {}.data (key, value) VALUES ('failed_action', $1) ON CONFLICT (key) DO UPDATE SET value = $1",
+                    schema,
+                ),
+                &[&json],
+            )?;
+        }
+        None => db.run(&format!(
+            "DELETE FROM {}.data WHERE key = 'failed_action'",
+            schema
+        ))?,
+    }
+
+    Ok(())
+}
+
+pub fn load_failed_action(db: &mut dyn Conn) -> anyhow::Result<Option<FailedAction>> {
+    let schema = db.namespace().state_schema();
+    if !table_exists(db, &format!("{}.data", schema))? {
+        return Ok(None);
     }
+
+    let failed_action = db
+        .query(&format!(
+            "SELECT value FROM {}.data WHERE key = 'failed_action'",
+            schema
+        ))?
+        .first()
+        .map(|row| serde_json::from_value(row.get(0)))
+        .transpose()?;
+    Ok(failed_action)
+}
+
+pub(crate) fn save_object_names(
+    db: &mut dyn Conn,
+    object_names: ObjectNames,
+) -> anyhow::Result<()> {
+    let json = serde_json::to_value(object_names)?;
+    db.query_with_params(
+        &format!(
+            "INSERT INTO {}.data (key, value) VALUES ('object_names', $1) ON CONFLICT (key) DO UPDATE SET value = $1",
+            db.namespace().state_schema(),
+        ),
+        &[&json],
+    )?;
+    Ok(())
+}
+
+// Migrations which were started by earlier versions didn't save how their temporary
+// objects are named, and those versions only used positional names
+pub(crate) fn load_object_names(db: &mut dyn Conn) -> anyhow::Result<ObjectNames> {
+    let schema = db.namespace().state_schema();
+    if !table_exists(db, &format!("{}.data", schema))? {
+        return Ok(ObjectNames::Positional);
+    }
+
+    let object_names = db
+        .query(&format!(
+            "SELECT value FROM {}.data WHERE key = 'object_names'",
+            schema
+        ))?
+        .first()
+        .map(|row| serde_json::from_value(row.get(0)))
+        .transpose()?;
+    Ok(object_names.unwrap_or(ObjectNames::Positional))
 }
 
 pub fn current_migration(db: &mut dyn Conn) -> anyhow::Result<Option<String>> {
+    let schema = db.namespace().state_schema();
+    if !table_exists(db, &format!("{}.migrations", schema))? {
+        return Ok(None);
+    }
+
     let name: Option<String> = db
-        .query(
+        .query(&format!(
             "
             SELECT name
-            FROM reshape.migrations
+            FROM {}.migrations
             ORDER BY index DESC
             LIMIT 1
             ",
-        )?
+            schema
+        ))?
         .first()
         .map(|row| row.get("name"));
     Ok(name)
 }
 
+// Determine which of the local migrations haven't been applied yet. The local
+// migrations must start with the applied ones in the same order, otherwise it's
+// unclear which of them have been applied. Applied migrations which have been edited
+// since are rejected too, as the database won't match what the migration describes.
+// With `allow_drift`, edits only cause a warning.
 pub fn remaining_migrations(
     db: &mut impl Conn,
     new_migrations: impl IntoIterator<Item = Migration>,
+    allow_drift: bool,
 ) -> anyhow::Result<Vec<Migration>> {
-    let mut new_iter = new_migrations.into_iter();
-
-    // Ensure the new migrations match up with the existing ones
-    let mut highest_index: Option<i32> = None;
-    loop {
-        let migrations = get_migrations(db, highest_index)?;
-        if migrations.is_empty() {
-            break;
-        }
+    let new_migrations: Vec<Migration> = new_migrations.into_iter().collect();
+    let applied = applied_migrations(db)?;
 
-        for (index, existing) in migrations {
-            highest_index = Some(index);
-
-            let new = match new_iter.next() {
-                Some(migration) => migration,
-                None => {
-                    return Err(anyhow!(
-                        "existing migration {} doesn't exist in local migrations",
-                        existing
-                    ))
-                }
-            };
+    for difference in compare_migrations(&applied, &new_migrations) {
+        if difference.blocks_migration() {
+            return Err(anyhow!(
+                "local migrations don't match the applied ones, {}. Run `reshape diff-migrations` for details.",
+                difference
+            ));
+        }
 
-            if existing != new.name {
+        if let MigrationDifference::Edited { .. } = difference {
+            if !allow_drift {
                 return Err(anyhow!(
-                    "existing migration {} does not match new migration {}",
-                    existing,
-                    new.name
+                    "{}. Please revert the changes or use --allow-drift to ignore them.",
+                    difference
                 ));
             }
+
+            println!("Warning: {}", difference);
         }
     }
 
-    // Return the remaining migrations
-    let items: Vec<Migration> = new_iter.collect();
-    Ok(items)
+    Ok(new_migrations.into_iter().skip(applied.len()).collect())
 }
 
-fn get_migrations(
+// A difference between the migrations which have been applied to the database and
+// the local migrations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationDifference {
+    // Applied but doesn't exist locally
+    Missing {
+        name: String,
+    },
+    // Not applied yet and comes after all applied migrations
+    Pending {
+        name: String,
+    },
+    // Not applied yet but comes before migrations which have been, so it can't be
+    // applied
+    Inserted {
+        name: String,
+    },
+    // Applied under a different name, detected by the actions being unchanged
+    Renamed {
+        from: String,
+        to: String,
+    },
+    // Applied at a different position than it has locally. Positions start at 1.
+    Reordered {
+        name: String,
+        applied_position: usize,
+        local_position: usize,
+    },
+    // The actions have changed since the migration was applied
+    Edited {
+        name: String,
+    },
+}
+
+impl MigrationDifference {
+    // Whether starting a migration must be refused, as the local migrations can't be
+    // matched up with the applied ones. Edited migrations can be matched up, but are
+    // still refused unless drift is allowed.
+    pub fn blocks_migration(&self) -> bool {
+        !matches!(self, Self::Pending { .. } | Self::Edited { .. })
+    }
+}
+
+impl std::fmt::Display for MigrationDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing { name } => {
+                write!(
+                    f,
+                    "migration {} has been applied but doesn't exist locally",
+                    name
+                )
+            }
+            Self::Pending { name } => write!(f, "migration {} hasn't been applied yet", name),
+            Self::Inserted { name } => write!(
+                f,
+                "migration {} hasn't been applied but comes before migrations which have",
+                name
+            ),
+            Self::Renamed { from, to } => {
+                write!(f, "migration {} has been renamed to {}", from, to)
+            }
+            Self::Reordered {
+                name,
+                applied_position,
+                local_position,
+            } => write!(
+                f,
+                "migration {} was applied as number {} but is number {} locally",
+                name, applied_position, local_position
+            ),
+            Self::Edited { name } => {
+                write!(
+                    f,
+                    "migration {} has been changed since it was applied",
+                    name
+                )
+            }
+        }
+    }
+}
+
+pub fn diff_migrations(
     db: &mut impl Conn,
-    index_larger_than: Option<i32>,
-) -> anyhow::Result<Vec<(i32, String)>> {
-    let rows = if let Some(index_larger_than) = index_larger_than {
-        db.query_with_params(
+    migrations: &[Migration],
+) -> anyhow::Result<Vec<MigrationDifference>> {
+    let applied = applied_migrations(db)?;
+    Ok(compare_migrations(&applied, migrations))
+}
+
+struct AppliedMigration {
+    name: String,
+    hash: Option<String>,
+}
+
+fn applied_migrations(db: &mut impl Conn) -> anyhow::Result<Vec<AppliedMigration>> {
+    let schema = db.namespace().state_schema();
+    if !table_exists(db, &format!("{}.migrations", schema))? {
+        return Ok(Vec::new());
+    }
+
+    // The hash is read through `to_jsonb` as the column won't exist if the table was
+    // created by an earlier version and hasn't been migrated yet. Migrations applied
+    // before hashes were stored don't have one and can't be checked for edits, and
+    // neither can those hashed by a version using a different form of the actions.
+    let migrations = db
+        .query(&format!(
             "
-            SELECT index, name
-            FROM reshape.migrations
-            WHERE index > $1
+            SELECT name, to_jsonb(m) ->> 'hash' AS hash
+            FROM {}.migrations m
             ORDER BY index ASC
-            LIMIT 100
-            ",
-            &[&index_larger_than],
-        )?
-    } else {
-        db.query(
-            "
-            SELECT index, name
-            FROM reshape.migrations
-            LIMIT 100
             ",
-        )?
-    };
-
-    let migrations = rows
+            schema
+        ))?
         .iter()
-        .map(|row| (row.get("index"), row.get("name")))
+        .map(|row| AppliedMigration {
+            name: row.get("name"),
+            hash: row
+                .get::<_, Option<String>>("hash")
+                .filter(|hash| current_hash_version(hash)),
+        })
         .collect();
     Ok(migrations)
 }
 
-fn save_migrations(db: &mut impl Conn, migrations: &[Migration]) -> anyhow::Result<()> {
+fn compare_migrations(
+    applied: &[AppliedMigration],
+    local: &[Migration],
+) -> Vec<MigrationDifference> {
+    let local_hashes: Vec<String> = local.iter().map(migration_hash).collect();
+    let applied_names: HashSet<&str> = applied.iter().map(|m| m.name.as_str()).collect();
+    let mut renamed_to: HashSet<&str> = HashSet::new();
+    let mut differences = Vec::new();
+
+    for (index, applied_migration) in applied.iter().enumerate() {
+        let name = &applied_migration.name;
+        let local_migration = local.get(index);
+
+        if local_migration.is_some_and(|migration| &migration.name == name) {
+            if applied_migration
+                .hash
+                .as_ref()
+                .is_some_and(|hash| hash != &local_hashes[index])
+            {
+                differences.push(MigrationDifference::Edited { name: name.clone() });
+            }
+            continue;
+        }
+
+        if let Some(local_index) = local.iter().position(|migration| &migration.name == name) {
+            differences.push(MigrationDifference::Reordered {
+                name: name.clone(),
+                applied_position: index + 1,
+                local_position: local_index + 1,
+            });
+            continue;
+        }
+
+        // A migration in the same position with identical actions which hasn't been
+        // applied under its own name is most likely the same one renamed
+        let renamed = local_migration.filter(|migration| {
+            applied_migration.hash.as_ref() == Some(&local_hashes[index])
+                && !applied_names.contains(migration.name.as_str())
+        });
+        match renamed {
+            Some(migration) => {
+                renamed_to.insert(&migration.name);
+                differences.push(MigrationDifference::Renamed {
+                    from: name.clone(),
+                    to: migration.name.clone(),
+                });
+            }
+            None => differences.push(MigrationDifference::Missing { name: name.clone() }),
+        }
+    }
+
+    for (index, migration) in local.iter().enumerate() {
+        let name = migration.name.as_str();
+        if applied_names.contains(name) || renamed_to.contains(name) {
+            continue;
+        }
+
+        if index < applied.len() {
+            differences.push(MigrationDifference::Inserted {
+                name: name.to_string(),
+            });
+        } else {
+            differences.push(MigrationDifference::Pending {
+                name: name.to_string(),
+            });
+        }
+    }
+
+    differences
+}
+
+// Version of the form hashed by `migration_hash`, stored as a prefix of the hash.
+// It must be bumped whenever the canonical form changes, or when an action gets a
+// new field whose default `canonical_actions` doesn't leave out, so hashes stored by
+// earlier versions aren't mistaken for edits.
+const HASH_VERSION: &str = "v2";
+
+// Hash of the actions in a migration, stored when the migration is completed to
+// detect if it's changed afterwards. The name and description are left out, so
+// renaming a migration or updating its description keeps the hash.
+pub(crate) fn migration_hash(migration: &Migration) -> String {
+    let actions =
+        serde_json::to_value(&migration.actions).expect("actions should always be serializable");
+    let canonical = canonical_actions(actions).to_string();
+    format!(
+        "{}:{:016x}",
+        HASH_VERSION,
+        stable_hash(canonical.as_bytes())
+    )
+}
+
+// Hashes stored by an earlier version of Reshape were made from a different form of
+// the actions and can't be compared
+fn current_hash_version(hash: &str) -> bool {
+    hash.split_once(':')
+        .is_some_and(|(version, _)| version == HASH_VERSION)
+}
+
+// Serialized actions in a form which doesn't change when defaulted fields are added
+// to them. Fields which are null, false or empty are what `#[serde(default)]` fills
+// in for a migration written before the field existed, so they're left out. Keys are
+// sorted as well, so the order fields are serialized in doesn't matter.
+fn canonical_actions(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut fields: Vec<(String, Value)> = map
+                .into_iter()
+                .map(|(key, value)| (key, canonical_actions(value)))
+                .filter(|(_, value)| !is_default(value))
+                .collect();
+            fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(fields.into_iter().collect())
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(canonical_actions).collect()),
+        value => value,
+    }
+}
+
+fn is_default(value: &Value) -> bool {
+    match value {
+        Value::Null | Value::Bool(false) => true,
+        Value::Array(items) => items.is_empty(),
+        Value::Object(fields) => fields.is_empty(),
+        _ => false,
+    }
+}
+
+// FNV-1a, which unlike the hasher in the standard library is guaranteed to give the
+// same result across versions, so it's safe to store
+pub(crate) fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn save_migrations(
+    db: &mut impl Conn,
+    migrations: &[Migration],
+    stats: &[ActionStats],
+) -> anyhow::Result<()> {
     for migration in migrations {
         let encoded_actions = serde_json::to_value(&migration.actions)?;
+        let migration_stats: Vec<&ActionStats> = stats
+            .iter()
+            .filter(|stats| stats.migration == migration.name)
+            .collect();
+        let encoded_stats = serde_json::to_value(migration_stats)?;
+        let hash = migration_hash(migration);
+
+        let query = format!(
+            "INSERT INTO {}.migrations(name, description, actions, stats, hash) VALUES ($1, $2, $3, $4, $5)",
+            db.namespace().state_schema(),
+        );
         db.query_with_params(
-            "INSERT INTO reshape.migrations(name, description, actions) VALUES ($1, $2, $3)",
-            &[&migration.name, &migration.description, &encoded_actions],
+            &query,
+            &[
+                &migration.name,
+                &migration.description,
+                &encoded_actions,
+                &encoded_stats,
+                &hash,
+            ],
         )?;
     }
 
     Ok(())
 }
+
+// Details about a completed migration, including stats for each of its actions
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct CompletedMigration {
+    pub name: String,
+    pub description: Option<String>,
+    pub completed_at: String,
+    pub stats: Vec<ActionStats>,
+}
+
+pub fn get_completed_migration(
+    db: &mut impl Conn,
+    name: &str,
+) -> anyhow::Result<Option<CompletedMigration>> {
+    let schema = db.namespace().state_schema();
+    if !table_exists(db, &format!("{}.migrations", schema))? {
+        return Ok(None);
+    }
+
+    // The stats column is read through `to_jsonb` as it won't exist if the table
+    // was created by an earlier version and hasn't been migrated yet
+    let row = match db
+        .query_with_params(
+            &format!(
+                "
+                SELECT name, description, completed_at::TEXT AS completed_at, to_jsonb(m) -> 'stats' AS stats
+                FROM {}.migrations m
+                WHERE name = $1
+                ",
+                schema
+            ),
+            &[&name],
+        )?
+        .pop()
+    {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+
+    // Migrations completed before stats were collected don't have any
+    let stats: Option<serde_json::Value> = row.get("stats");
+    let stats = match stats {
+        Some(serde_json::Value::Null) | None => Vec::new(),
+        Some(stats) => serde_json::from_value(stats)?,
+    };
+
+    Ok(Some(CompletedMigration {
+        name: row.get("name"),
+        description: row.get("description"),
+        completed_at: row.get("completed_at"),
+        stats,
+    }))
+}
+
+fn table_exists(db: &mut (impl Conn + ?Sized), name: &str) -> anyhow::Result<bool> {
+    let exists = db
+        .query_with_params("SELECT to_regclass($1) IS NOT NULL AS exists", &[&name])?
+        .first()
+        .map(|row| row.get("exists"))
+        .unwrap_or_default();
+    Ok(exists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::canonical_actions;
+    use serde_json::json;
+
+    #[test]
+    fn defaulted_fields_are_left_out_of_canonical_actions() {
+        // As serialized before `unlogged` and `checks` were added to the action
+        let before = json!([{ "type": "create_table", "name": "users", "up": null }]);
+        let after = json!([{
+            "checks": [],
+            "name": "users",
+            "type": "create_table",
+            "unlogged": false,
+            "up": null,
+        }]);
+        assert_eq!(canonical_actions(before), canonical_actions(after));
+
+        let changed = json!([{ "type": "create_table", "name": "users", "unlogged": true }]);
+        assert_ne!(
+            canonical_actions(json!([{ "type": "create_table", "name": "users" }])),
+            canonical_actions(changed)
+        );
+    }
+}
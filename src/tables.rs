@@ -0,0 +1,82 @@
+// TableFilter decides which tables in the `public` schema Reshape manages, so it can
+// share the schema with tables it never created, like `spatial_ref_sys` from PostGIS
+// or tables belonging to other tools. Tables which aren't managed get no views in the
+// migration schemas, are left out of snapshots and ownership changes and are never
+// dropped by `Reshape::remove`.
+//
+// Patterns are matched against the name of the table in the database and may use `*`
+// for any number of characters and `?` for a single character. A table is managed if
+// it matches any include pattern, or there are none, and doesn't match any exclude
+// pattern. The default filter manages every table.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl TableFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Only manage tables matching any of these patterns
+    pub fn with_include(mut self, include: Vec<String>) -> Self {
+        self.include = include;
+        self
+    }
+
+    // Never manage tables matching any of these patterns
+    pub fn with_exclude(mut self, exclude: Vec<String>) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    pub fn manages(&self, table: &str) -> bool {
+        let included = self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| matches_pattern(pattern, table));
+
+        included
+            && !self
+                .exclude
+                .iter()
+                .any(|pattern| matches_pattern(pattern, table))
+    }
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Classic wildcard matching, backtracking to the last `*` on a mismatch
+    let (mut p, mut n) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                last_star = Some((p, n));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+            }
+            Some(c) if *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match last_star {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    last_star = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
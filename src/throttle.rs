@@ -0,0 +1,100 @@
+use std::{thread, time::Duration};
+
+use anyhow::Context;
+
+use crate::db::Conn;
+
+// Throttle slows down backfills to protect the rest of the system, most importantly
+// read replicas which can fall behind when a large table is rewritten. Before every
+// batch, the replication lag and the optional probe are checked. If any threshold is
+// exceeded, the backfill pauses and checks again every `check_interval` until it's
+// safe to continue.
+#[derive(Clone, Debug)]
+pub struct Throttle {
+    max_replication_lag: Option<Duration>,
+    probe: Option<String>,
+    check_interval: Duration,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Throttle {
+            max_replication_lag: None,
+            probe: None,
+            check_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Pause while any replica connected to the database is further behind than this.
+    // The lag is read from `replay_lag` in `pg_stat_replication`.
+    pub fn with_max_replication_lag(mut self, max_replication_lag: Duration) -> Self {
+        self.max_replication_lag = Some(max_replication_lag);
+        self
+    }
+
+    // Pause while this query returns true. The query must return a single boolean,
+    // which makes it possible to throttle on anything which can be read from the
+    // database, like the number of active connections or the lag of a logical
+    // replication slot.
+    pub fn with_probe(mut self, probe: impl Into<String>) -> Self {
+        self.probe = Some(probe.into());
+        self
+    }
+
+    pub fn with_check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+        self
+    }
+
+    // Block until no threshold is exceeded. Every check runs through the connection,
+    // so a cancelled migration stops waiting straight away.
+    pub(crate) fn wait(&self, db: &mut dyn Conn) -> anyhow::Result<()> {
+        while self.exceeded(db)? {
+            thread::sleep(self.check_interval);
+        }
+
+        Ok(())
+    }
+
+    fn exceeded(&self, db: &mut dyn Conn) -> anyhow::Result<bool> {
+        if let Some(max_replication_lag) = self.max_replication_lag {
+            let lag: f64 = db
+                .query(
+                    "
+                    SELECT COALESCE(MAX(EXTRACT(EPOCH FROM replay_lag)), 0)::FLOAT8 AS lag
+                    FROM pg_stat_replication
+                    ",
+                )
+                .context("failed to check replication lag")?
+                .first()
+                .map(|row| row.get("lag"))
+                .unwrap_or_default();
+
+            if lag > max_replication_lag.as_secs_f64() {
+                return Ok(true);
+            }
+        }
+
+        if let Some(probe) = &self.probe {
+            let exceeded: Option<bool> = db
+                .query(probe)
+                .context("failed to run throttle probe")?
+                .first()
+                .map(|row| row.try_get(0))
+                .transpose()
+                .context("throttle probe must return a single boolean")?;
+
+            if exceeded.unwrap_or(false) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
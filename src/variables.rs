@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+
+// Variables parameterize migration files so the same migrations can be used in every
+// environment. Strings in a migration file can refer to a variable using
+// `{{ var("NAME") }}` or to an environment variable using `{{ env("NAME") }}`, which
+// are replaced when the file is loaded. Anything else between double braces is left
+// alone, as it's valid SQL, for example in array literals like `'{{1,2},{3,4}}'`.
+//
+// Values end up in the actions as if they were written in the file, so they are
+// stored in the database along with the migration and are part of its hash. They
+// must never be secrets, which is why environment variables with names like
+// `PGPASSWORD` or `API_TOKEN` can't be referred to, see `is_secret`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Variables {
+    vars: HashMap<String, String>,
+}
+
+impl Variables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    // Replace all references to variables in `text`. Referring to a variable which
+    // isn't set is an error, so mistakes don't end up in the database.
+    pub fn interpolate(&self, text: &str) -> anyhow::Result<String> {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find("{{") {
+            result.push_str(&rest[..start]);
+            let after_braces = &rest[start + 2..];
+
+            let (function, args) = match parse_function(after_braces) {
+                Some(parsed) => parsed,
+                None => {
+                    result.push_str("{{");
+                    rest = after_braces;
+                    continue;
+                }
+            };
+
+            let (name, remaining) = parse_reference(args).ok_or_else(|| {
+                anyhow!(
+                    "invalid reference in \"{}\", expected {}(\"NAME\") }}}}",
+                    text,
+                    function
+                )
+            })?;
+
+            let value = match function {
+                "var" => self
+                    .vars
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("variable \"{}\" isn't set", name))?,
+                _ if is_secret(name) => {
+                    return Err(anyhow!(
+                        "environment variable \"{}\" looks like a secret and can't be used in migrations, as they are stored in the database",
+                        name
+                    ))
+                }
+                _ => std::env::var(name)
+                    .map_err(|_| anyhow!("environment variable \"{}\" isn't set", name))?,
+            };
+            result.push_str(&value);
+            rest = remaining;
+        }
+
+        result.push_str(rest);
+        Ok(result)
+    }
+}
+
+// The function called after the opening braces, if any, and what follows its name
+fn parse_function(text: &str) -> Option<(&'static str, &str)> {
+    let text = text.trim_start();
+    ["var", "env"].into_iter().find_map(|function| {
+        text.strip_prefix(function)
+            .and_then(|rest| rest.trim_start().strip_prefix('('))
+            .map(|rest| (function, rest))
+    })
+}
+
+// The quoted name and closing parenthesis and braces, returning the name and the text
+// after the reference
+fn parse_reference(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    let quote = text.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let text = &text[1..];
+    let end = text.find(quote)?;
+    let name = &text[..end];
+
+    let rest = text[end + 1..].trim_start().strip_prefix(')')?;
+    let rest = rest.trim_start().strip_prefix("}}")?;
+    Some((name, rest))
+}
+
+// Whether an environment variable likely holds a password, token or a connection URL
+// with a password in it
+fn is_secret(name: &str) -> bool {
+    let name = name.to_uppercase();
+    [
+        "PASSWORD",
+        "PASSWD",
+        "SECRET",
+        "TOKEN",
+        "CREDENTIAL",
+        "PRIVATE_KEY",
+    ]
+    .iter()
+    .any(|part| name.contains(part))
+        || name.ends_with("_URL")
+        || name.ends_with("_DSN")
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+
+use crate::{db::Conn, migrations::common, schema::Table};
+
+// Views can't have constraints, so ORMs and other tools which introspect the catalogs
+// don't find any primary or foreign keys on the views in the migration schemas. With
+// view metadata enabled, the constraints of each table are described in the comment on
+// its view instead, using the smart comment format understood by PostGraphile:
+//
+//   @primaryKey id
+//   @unique email
+//   @foreignKey (author_id) references users (id)
+//   The table's own comment, if it has one
+//
+// Column names are translated to the names used in the view, so columns which are
+// being replaced or renamed by the migration refer to the right view columns.
+// Constraints on columns which aren't in the view, like the temporary column an index
+// is rebuilt on, are left out. Comments on columns are copied onto the view's columns.
+//
+// The comments reflect the constraints when the schema is created and are written
+// again for the schema of the last migration once it has been completed. Schemas kept
+// using `Reshape::set_retained_schemas` don't get comments.
+pub fn annotate_views(db: &mut dyn Conn, schema: &str, tables: &[Table]) -> anyhow::Result<()> {
+    let constraints = get_constraints(db)?;
+    let table_comments = get_table_comments(db)?;
+    let column_comments = get_column_comments(db)?;
+
+    // Real table name to the table, for finding the views referenced by foreign keys
+    let tables_by_real_name: HashMap<&str, &Table> = tables
+        .iter()
+        .map(|table| (table.real_name.as_str(), table))
+        .collect();
+
+    for table in tables {
+        let mut tags: Vec<String> = Vec::new();
+        for constraint in constraints
+            .iter()
+            .filter(|constraint| constraint.table == table.real_name)
+        {
+            let Some(columns) = view_columns(table, &constraint.columns) else {
+                continue;
+            };
+
+            match constraint.kind.as_str() {
+                "p" => tags.push(format!("@primaryKey {}", columns.join(","))),
+                "u" => tags.push(format!("@unique {}", columns.join(","))),
+                _ => {
+                    let Some(foreign_table) = constraint
+                        .foreign_table
+                        .as_deref()
+                        .and_then(|name| tables_by_real_name.get(name))
+                    else {
+                        continue;
+                    };
+                    let Some(foreign_columns) =
+                        view_columns(foreign_table, &constraint.foreign_columns)
+                    else {
+                        continue;
+                    };
+
+                    tags.push(format!(
+                        "@foreignKey ({}) references {} ({})",
+                        columns.join(","),
+                        foreign_table.name,
+                        foreign_columns.join(","),
+                    ));
+                }
+            }
+        }
+
+        let mut lines = tags;
+        if let Some(comment) = table_comments.get(&table.real_name) {
+            lines.push(comment.to_string());
+        }
+        let comment = if lines.is_empty() {
+            None
+        } else {
+            Some(lines.join("\n"))
+        };
+
+        common::set_comment(
+            db,
+            &format!(r#"VIEW {}."{}""#, schema, table.name),
+            comment.as_deref(),
+        )
+        .with_context(|| format!("failed to annotate view for table {}", table.name))?;
+
+        for column in &table.columns {
+            let Some(comment) =
+                column_comments.get(&(table.real_name.clone(), column.real_name.clone()))
+            else {
+                continue;
+            };
+
+            common::set_comment(
+                db,
+                &format!(r#"COLUMN {}."{}"."{}""#, schema, table.name, column.name),
+                Some(comment),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// Names of the view columns for the real columns, or None if any of them isn't in the view
+fn view_columns(table: &Table, real_names: &[String]) -> Option<Vec<String>> {
+    real_names
+        .iter()
+        .map(|real_name| {
+            table
+                .columns
+                .iter()
+                .find(|column| &column.real_name == real_name)
+                .map(|column| column.name.to_string())
+        })
+        .collect()
+}
+
+struct Constraint {
+    // "p" for primary keys, "u" for unique constraints and "f" for foreign keys
+    kind: String,
+    table: String,
+    columns: Vec<String>,
+    foreign_table: Option<String>,
+    foreign_columns: Vec<String>,
+}
+
+fn get_constraints(db: &mut dyn Conn) -> anyhow::Result<Vec<Constraint>> {
+    let constraints = db
+        .query(
+            "
+            SELECT
+                con.contype::TEXT AS kind,
+                rel.relname::TEXT AS table_name,
+                ARRAY(
+                    SELECT attname::TEXT
+                    FROM unnest(con.conkey) WITH ORDINALITY AS key(attnum, position)
+                    JOIN pg_attribute ON attrelid = con.conrelid AND pg_attribute.attnum = key.attnum
+                    ORDER BY position
+                ) AS columns,
+                foreign_rel.relname::TEXT AS foreign_table_name,
+                ARRAY(
+                    SELECT attname::TEXT
+                    FROM unnest(con.confkey) WITH ORDINALITY AS key(attnum, position)
+                    JOIN pg_attribute ON attrelid = con.confrelid AND pg_attribute.attnum = key.attnum
+                    ORDER BY position
+                ) AS foreign_columns
+            FROM pg_constraint con
+            JOIN pg_class rel ON rel.oid = con.conrelid
+            JOIN pg_namespace ON pg_namespace.oid = rel.relnamespace
+            LEFT JOIN pg_class foreign_rel ON foreign_rel.oid = con.confrelid
+            WHERE pg_namespace.nspname = 'public' AND con.contype IN ('p', 'u', 'f')
+            ORDER BY array_position(ARRAY['p', 'u', 'f'], con.contype::TEXT), con.conname
+            ",
+        )
+        .context("failed to get constraints")?
+        .iter()
+        .map(|row| Constraint {
+            kind: row.get("kind"),
+            table: row.get("table_name"),
+            columns: row.get("columns"),
+            foreign_table: row.get("foreign_table_name"),
+            foreign_columns: row.get("foreign_columns"),
+        })
+        .collect();
+
+    Ok(constraints)
+}
+
+fn get_table_comments(db: &mut dyn Conn) -> anyhow::Result<HashMap<String, String>> {
+    let comments = db
+        .query(
+            "
+            SELECT relname::TEXT AS table_name, obj_description(pg_class.oid, 'pg_class') AS comment
+            FROM pg_class
+            JOIN pg_namespace ON pg_namespace.oid = pg_class.relnamespace
+            WHERE pg_namespace.nspname = 'public'
+                AND relkind IN ('r', 'p')
+                AND obj_description(pg_class.oid, 'pg_class') IS NOT NULL
+            ",
+        )
+        .context("failed to get table comments")?
+        .iter()
+        .map(|row| (row.get("table_name"), row.get("comment")))
+        .collect();
+
+    Ok(comments)
+}
+
+fn get_column_comments(db: &mut dyn Conn) -> anyhow::Result<HashMap<(String, String), String>> {
+    let comments = db
+        .query(
+            "
+            SELECT
+                relname::TEXT AS table_name,
+                attname::TEXT AS column_name,
+                col_description(attrelid, attnum) AS comment
+            FROM pg_attribute
+            JOIN pg_class ON pg_class.oid = pg_attribute.attrelid
+            JOIN pg_namespace ON pg_namespace.oid = pg_class.relnamespace
+            WHERE pg_namespace.nspname = 'public'
+                AND relkind IN ('r', 'p')
+                AND attnum > 0
+                AND NOT attisdropped
+                AND col_description(attrelid, attnum) IS NOT NULL
+            ",
+        )
+        .context("failed to get column comments")?
+        .iter()
+        .map(|row| {
+            (
+                (row.get("table_name"), row.get("column_name")),
+                row.get("comment"),
+            )
+        })
+        .collect();
+
+    Ok(comments)
+}
@@ -0,0 +1,209 @@
+use std::{sync::mpsc, thread, time::Duration};
+
+use postgres::{Client, NoTls};
+use reshape::{migrations::Migration, testing, ActiveClients, Reshape};
+
+#[test]
+fn completion_is_refused_while_previous_schema_is_in_use() {
+    let (mut reshape, connection_string) = set_up();
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations()).unwrap();
+
+    // An old deployment still reading through the previous schema
+    let mut old_client = Client::connect(&connection_string, NoTls).unwrap();
+    old_client
+        .simple_query("BEGIN; SELECT * FROM migration_create_users.users")
+        .unwrap();
+
+    reshape.set_active_clients(ActiveClients::Refuse);
+    let err = reshape.complete().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::ConflictingState(_))
+    ));
+    assert_eq!("in_progress", reshape.status().unwrap().state.name());
+
+    // Once the deployment has moved on, completing goes ahead
+    old_client.simple_query("COMMIT").unwrap();
+    reshape.complete().unwrap();
+    assert_eq!("idle", reshape.status().unwrap().state.name());
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn search_path_and_application_name_count_as_using_the_schema() {
+    let (mut reshape, connection_string) = set_up();
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations()).unwrap();
+    reshape.set_active_clients(ActiveClients::Refuse);
+
+    let mut old_client = Client::connect(&connection_string, NoTls).unwrap();
+    old_client
+        .simple_query("SET search_path TO migration_create_users")
+        .unwrap();
+    assert!(reshape.complete().is_err());
+    drop(old_client);
+
+    let mut named_client = Client::connect(
+        &format!(
+            "{}?application_name=api-migration_create_users",
+            connection_string
+        ),
+        NoTls,
+    )
+    .unwrap();
+    named_client.simple_query("SELECT 1").unwrap();
+    assert!(reshape.complete().is_err());
+    drop(named_client);
+
+    // Other sessions which don't refer to the schema don't stop completion
+    let mut other_client = Client::connect(&connection_string, NoTls).unwrap();
+    other_client.simple_query("SELECT 1").unwrap();
+    reshape.complete().unwrap();
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn completion_waits_for_sessions_to_stop_using_previous_schema() {
+    let (mut reshape, connection_string) = set_up();
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations()).unwrap();
+
+    let (in_use, in_use_signal) = mpsc::channel();
+    let old_client = thread::spawn(move || {
+        let mut db = Client::connect(&connection_string, NoTls).unwrap();
+        db.simple_query("BEGIN; SELECT * FROM migration_create_users.users")
+            .unwrap();
+        in_use.send(()).unwrap();
+
+        thread::sleep(Duration::from_millis(1500));
+        db.simple_query("COMMIT").unwrap();
+    });
+    in_use_signal.recv().unwrap();
+
+    reshape.set_active_clients(ActiveClients::Wait(Duration::from_secs(30)));
+    reshape.complete().unwrap();
+    old_client.join().unwrap();
+
+    assert_eq!("idle", reshape.status().unwrap().state.name());
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn waiting_for_sessions_times_out() {
+    let (mut reshape, connection_string) = set_up();
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations()).unwrap();
+
+    let mut old_client = Client::connect(&connection_string, NoTls).unwrap();
+    old_client
+        .simple_query("BEGIN; SELECT * FROM migration_create_users.users")
+        .unwrap();
+
+    reshape.set_active_clients(ActiveClients::Wait(Duration::from_secs(1)));
+    let err = reshape.complete().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::ConflictingState(_))
+    ));
+
+    old_client.simple_query("COMMIT").unwrap();
+    reshape.abort().unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn atomic_migrations_are_refused_while_previous_schema_is_in_use() {
+    let (mut reshape, connection_string) = set_up();
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+
+    let create_posts: Migration = toml::from_str(
+        r#"
+        name = "create_posts"
+        atomic = true
+
+        [[actions]]
+        type = "create_table"
+        name = "posts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+    let atomic_migrations = vec![migrations().remove(0), create_posts];
+
+    let mut old_client = Client::connect(&connection_string, NoTls).unwrap();
+    old_client
+        .simple_query("SET search_path TO migration_create_users")
+        .unwrap();
+
+    reshape.set_active_clients(ActiveClients::Refuse);
+    let err = reshape.migrate(atomic_migrations.clone()).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::ConflictingState(_))
+    ));
+    assert_eq!("idle", reshape.status().unwrap().state.name());
+    assert_eq!(
+        Some("create_users".to_string()),
+        reshape.status().unwrap().current_migration
+    );
+
+    drop(old_client);
+    reshape.migrate(atomic_migrations).unwrap();
+    assert_eq!(
+        Some("create_posts".to_string()),
+        reshape.status().unwrap().current_migration
+    );
+
+    reshape.remove().unwrap();
+}
+
+fn set_up() -> (Reshape, String) {
+    let connection_string = testing::connection_string();
+    let mut reshape = Reshape::new(&connection_string).unwrap();
+    reshape.remove().unwrap();
+
+    (reshape, connection_string)
+}
+
+fn migrations() -> Vec<Migration> {
+    [
+        r#"
+        name = "create_users"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+        r#"
+        name = "add_name"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "name"
+            type = "TEXT"
+        "#,
+    ]
+    .iter()
+    .map(|migration| toml::from_str(migration).unwrap())
+    .collect()
+}
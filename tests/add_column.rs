@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::{self, Test};
 
 #[test]
 fn add_column() {
@@ -108,7 +107,7 @@ fn add_column() {
             .eq(expected));
     });
 
-    test.run()
+    test.crash_at_every_statement().run()
 }
 
 #[test]
@@ -170,7 +169,7 @@ fn add_column_nullable() {
             .unwrap();
         let name: Option<String> = new_db
             .query_one("SELECT name from users WHERE id = 3", &[])
-            .map(|row| (row.get("name")))
+            .map(|row| row.get("name"))
             .unwrap();
         assert_eq!(None, name);
 
@@ -359,3 +358,883 @@ fn add_column_with_complex_up() {
 
     test.run();
 }
+
+#[test]
+fn add_column_with_auto_updated_at() {
+    let mut test = Test::new("Add column with auto_updated_at");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_updated_at_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        auto_updated_at = true
+
+            [actions.column]
+            name = "updated_at"
+            type = "TIMESTAMP"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'John')")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Updates through either schema should set the new column
+        old_db
+            .simple_query("UPDATE users SET name = 'Jane' WHERE id = 1")
+            .unwrap();
+
+        let updated_at: Option<std::time::SystemTime> = new_db
+            .query_one("SELECT updated_at FROM users WHERE id = 1", &[])
+            .unwrap()
+            .get("updated_at");
+        assert!(updated_at.is_some());
+    });
+
+    test.after_completion(|db| {
+        db.simple_query("UPDATE users SET updated_at = NULL, name = 'John' WHERE id = 1")
+            .unwrap();
+
+        let updated_at: Option<std::time::SystemTime> = db
+            .query_one("SELECT updated_at FROM users WHERE id = 1", &[])
+            .unwrap()
+            .get("updated_at");
+        assert!(updated_at.is_some());
+    });
+
+    test.after_abort(|db| {
+        // The old schema must keep working once the trigger has been removed
+        db.simple_query("UPDATE users SET name = 'John' WHERE id = 1")
+            .unwrap();
+    });
+
+    test.run();
+}
+
+#[test]
+fn add_multiple_columns_to_same_table() {
+    let mut test = Test::new("Add multiple columns to same table");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    // These statements are combined into a single ALTER TABLE
+    test.second_migration(
+        r#"
+        name = "add_name_and_email_columns"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "name"
+            type = "TEXT"
+            nullable = false
+            default = "'unknown'"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id) VALUES (1)")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        old_db
+            .simple_query("INSERT INTO users (id) VALUES (2)")
+            .unwrap();
+
+        let rows: Vec<(String, Option<String>)> = new_db
+            .query("SELECT name, email FROM users ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| (row.get("name"), row.get("email")))
+            .collect();
+        assert_eq!(
+            vec![("unknown".to_string(), None), ("unknown".to_string(), None)],
+            rows
+        );
+
+        // The NOT NULL constraint must be in place for the new schema
+        assert!(new_db
+            .simple_query("INSERT INTO users (id, name) VALUES (3, NULL)")
+            .is_err());
+    });
+
+    test.after_completion(|db| {
+        let nullable: Vec<String> = db
+            .query(
+                "
+                SELECT is_nullable
+                FROM information_schema.columns
+                WHERE table_schema = 'public'
+                AND table_name = 'users'
+                AND column_name IN ('name', 'email')
+                ORDER BY column_name
+                ",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(vec!["YES", "NO"], nullable);
+    });
+
+    test.run();
+}
+
+// The backfill touches rows in batches using a cursor over the primary key, which must
+// work for any key type. More rows than fit in a single batch are inserted.
+const KEY_TEST_SECOND_MIGRATION: &str = r#"
+    name = "add_doubled_column"
+
+    [[actions]]
+    type = "add_column"
+    table = "items"
+    up = "value * 2"
+
+        [actions.column]
+        name = "doubled"
+        type = "INTEGER"
+        nullable = false
+    "#;
+
+fn assert_all_items_backfilled(_old_db: &mut postgres::Client, new_db: &mut postgres::Client) {
+    let (total, backfilled): (i64, i64) = new_db
+        .query_one(
+            "SELECT COUNT(*), COUNT(*) FILTER (WHERE doubled = value * 2) FROM items",
+            &[],
+        )
+        .map(|row| (row.get(0), row.get(1)))
+        .unwrap();
+    assert_eq!(2500, total);
+    assert_eq!(total, backfilled);
+}
+
+#[test]
+fn add_column_with_composite_primary_key() {
+    let mut test = Test::new("Add column with composite primary key");
+
+    test.first_migration(
+        r#"
+        name = "create_items_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "items"
+        primary_key = ["category", "number"]
+
+            [[actions.columns]]
+            name = "category"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "number"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "value"
+            type = "INTEGER"
+        "#,
+    );
+    test.second_migration(KEY_TEST_SECOND_MIGRATION);
+
+    test.after_first(|db| {
+        db.simple_query(
+            "
+            INSERT INTO items (category, number, value)
+            SELECT 'category_' || (i % 3), i, i FROM generate_series(1, 2500) AS i
+            ",
+        )
+        .unwrap();
+    });
+    test.intermediate(assert_all_items_backfilled);
+
+    test.run();
+}
+
+#[test]
+fn add_column_with_uuid_primary_key() {
+    let mut test = Test::new("Add column with UUID primary key");
+
+    test.first_migration(
+        r#"
+        name = "create_items_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "items"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "UUID"
+
+            [[actions.columns]]
+            name = "value"
+            type = "INTEGER"
+        "#,
+    );
+    test.second_migration(KEY_TEST_SECOND_MIGRATION);
+
+    test.after_first(|db| {
+        db.simple_query(
+            "
+            INSERT INTO items (id, value)
+            SELECT md5(i::TEXT)::UUID, i FROM generate_series(1, 2500) AS i
+            ",
+        )
+        .unwrap();
+    });
+    test.intermediate(assert_all_items_backfilled);
+
+    test.run();
+}
+
+#[test]
+fn add_column_with_text_primary_key() {
+    let mut test = Test::new("Add column with text primary key");
+
+    test.first_migration(
+        r#"
+        name = "create_items_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "items"
+        primary_key = ["name"]
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "value"
+            type = "INTEGER"
+        "#,
+    );
+    test.second_migration(KEY_TEST_SECOND_MIGRATION);
+
+    test.after_first(|db| {
+        db.simple_query(
+            "
+            INSERT INTO items (name, value)
+            SELECT 'Item ' || i, i FROM generate_series(1, 2500) AS i
+            ",
+        )
+        .unwrap();
+    });
+    test.intermediate(assert_all_items_backfilled);
+
+    test.run();
+}
+
+#[test]
+fn add_column_without_primary_key() {
+    let mut test = Test::new("Add column without primary key");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+    test.second_migration(KEY_TEST_SECOND_MIGRATION);
+
+    test.after_first(|db| {
+        // Tables without a primary key can't be created using Reshape but might
+        // exist from before it was adopted
+        db.simple_query(
+            "
+            CREATE TABLE public.items (value INTEGER);
+            INSERT INTO public.items (value) SELECT i FROM generate_series(1, 2500) AS i;
+            ",
+        )
+        .unwrap();
+    });
+    test.intermediate(assert_all_items_backfilled);
+
+    test.run();
+}
+
+#[test]
+fn add_column_with_backfill_order() {
+    let mut test = Test::new("Add column with backfill order");
+
+    test.first_migration(
+        r#"
+        name = "create_items_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "items"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "UUID"
+
+            [[actions.columns]]
+            name = "created_at"
+            type = "TIMESTAMP"
+            nullable = false
+
+            [[actions.columns]]
+            name = "value"
+            type = "INTEGER"
+        "#,
+    );
+    test.second_migration(
+        r#"
+        name = "add_doubled_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "items"
+        up = "value * 2"
+        backfill_order = ["created_at"]
+
+            [actions.column]
+            name = "doubled"
+            type = "INTEGER"
+            nullable = false
+        "#,
+    );
+
+    test.after_first(|db| {
+        // Many rows share a timestamp, so ties are broken by the primary key across batches
+        db.simple_query(
+            "
+            CREATE INDEX items_created_at_idx ON public.items (created_at);
+            INSERT INTO items (id, created_at, value)
+            SELECT md5(i::TEXT)::UUID, '2024-01-01'::TIMESTAMP + (i % 7) * INTERVAL '1 day', i
+            FROM generate_series(1, 2500) AS i
+            ",
+        )
+        .unwrap();
+    });
+    test.intermediate(assert_all_items_backfilled);
+
+    test.run();
+}
+
+#[test]
+fn add_column_with_ctid_backfill_order() {
+    let mut test = Test::new("Add column with ctid backfill order");
+
+    test.first_migration(
+        r#"
+        name = "create_items_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "items"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "UUID"
+
+            [[actions.columns]]
+            name = "value"
+            type = "INTEGER"
+        "#,
+    );
+    test.second_migration(
+        r#"
+        name = "add_doubled_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "items"
+        up = "value * 2"
+        backfill_order = "ctid"
+
+            [actions.column]
+            name = "doubled"
+            type = "INTEGER"
+            nullable = false
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "
+            INSERT INTO items (id, value)
+            SELECT md5(i::TEXT)::UUID, i FROM generate_series(1, 2500) AS i
+            ",
+        )
+        .unwrap();
+    });
+    test.intermediate(assert_all_items_backfilled);
+
+    test.run();
+}
+
+#[test]
+fn add_column_without_backfill() {
+    let mut test = Test::new("Add column without backfill");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_status_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "'active'"
+        backfill = false
+
+            [actions.column]
+            name = "status"
+            type = "TEXT"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id) VALUES (1)")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Existing rows are left untouched
+        let status: Option<String> = new_db
+            .query_one("SELECT status FROM users WHERE id = 1", &[])
+            .unwrap()
+            .get("status");
+        assert_eq!(None, status);
+
+        // New rows are still filled in by the trigger
+        old_db
+            .simple_query("INSERT INTO users (id) VALUES (2)")
+            .unwrap();
+        let status: Option<String> = new_db
+            .query_one("SELECT status FROM users WHERE id = 2", &[])
+            .unwrap()
+            .get("status");
+        assert_eq!(Some("active".to_string()), status);
+    });
+
+    test.run();
+}
+
+#[test]
+fn add_column_with_backfill_priority() {
+    let mut test = Test::new("Add column with backfill priority");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_columns"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "id * 2"
+        backfill_priority = 1
+
+            [actions.column]
+            name = "doubled"
+            type = "INTEGER"
+            nullable = false
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "id * 3"
+        backfill = "immediate"
+        backfill_priority = 2
+
+            [actions.column]
+            name = "tripled"
+            type = "INTEGER"
+            nullable = false
+
+        [[actions]]
+        type = "rename_table"
+        table = "users"
+        new_name = "customers"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id) SELECT i FROM generate_series(1, 1500) AS i")
+            .unwrap();
+    });
+
+    test.intermediate(|_old_db, new_db| {
+        let backfilled: i64 = new_db
+            .query_one(
+                "SELECT COUNT(*) FROM customers WHERE doubled = id * 2 AND tripled = id * 3",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(1500, backfilled);
+    });
+
+    test.run();
+}
+
+#[test]
+fn add_not_null_column_with_backfill_value() {
+    let mut test = Test::new("Add NOT NULL column with backfill value");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_status_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        backfill_value = "'active'"
+
+            [actions.column]
+            name = "status"
+            type = "TEXT"
+            nullable = false
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id) VALUES (1)")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Rows written using the old schema get the value as well
+        old_db
+            .simple_query("INSERT INTO users (id) VALUES (2)")
+            .unwrap();
+
+        let statuses: Vec<String> = new_db
+            .query("SELECT status FROM users ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get("status"))
+            .collect();
+        assert_eq!(vec!["active", "active"], statuses);
+    });
+
+    test.run();
+}
+
+#[test]
+fn add_not_null_column_without_value_to_non_empty_table() {
+    let mut test = Test::new("Add NOT NULL column without value to non-empty table");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_status_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "status"
+            type = "TEXT"
+            nullable = false
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id) VALUES (1)")
+            .unwrap();
+    });
+
+    // Fails when the migration is started rather than on completion
+    test.expect_failure();
+    test.run();
+}
+
+#[test]
+fn add_column_with_foreign_key() {
+    let mut test = Test::new("Add column with foreign key");
+
+    test.first_migration(
+        r#"
+        name = "create_tables"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+        [[actions]]
+        type = "create_table"
+        name = "items"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_user_id_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "items"
+        up = "1"
+
+            [actions.column]
+            name = "user_id"
+            type = "INTEGER"
+
+            [actions.foreign_key]
+            referenced_table = "users"
+            referenced_column = "id"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "
+            INSERT INTO users (id) VALUES (1), (2);
+            INSERT INTO items (id) VALUES (1);
+            ",
+        )
+        .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // The foreign key is enforced for new writes while the migration is in progress
+        new_db
+            .simple_query("INSERT INTO items (id, user_id) VALUES (2, 2)")
+            .unwrap();
+        assert!(new_db
+            .simple_query("INSERT INTO items (id, user_id) VALUES (3, 3)")
+            .is_err());
+
+        old_db
+            .simple_query("INSERT INTO items (id) VALUES (4)")
+            .unwrap();
+    });
+
+    test.after_completion(|db| {
+        let foreign_keys: Vec<(String, bool)> = db
+            .query(
+                "
+                SELECT conname::TEXT AS name, convalidated AS validated
+                FROM pg_catalog.pg_constraint
+                WHERE conrelid = 'public.items'::regclass AND contype = 'f'
+                ",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| (row.get("name"), row.get("validated")))
+            .collect();
+        assert_eq!(vec![("items_user_id_fkey".to_string(), true)], foreign_keys);
+    });
+
+    test.after_abort(|db| {
+        let foreign_keys: i64 = db
+            .query_one(
+                "
+                SELECT COUNT(*)
+                FROM pg_catalog.pg_constraint
+                WHERE conrelid = 'public.items'::regclass AND contype = 'f'
+                ",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(0, foreign_keys);
+    });
+
+    test.run();
+}
+
+#[test]
+fn columns_added_by_separate_actions_are_batched() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let migrations: Vec<reshape::migrations::Migration> = vec![
+        toml::from_str(
+            r#"
+            name = "create_users_table"
+
+            [[actions]]
+            type = "create_table"
+            name = "users"
+            primary_key = ["id"]
+
+                [[actions.columns]]
+                name = "id"
+                type = "INTEGER"
+            "#,
+        )
+        .unwrap(),
+        toml::from_str(
+            r#"
+            name = "add_name_and_email_columns"
+
+            [[actions]]
+            type = "add_column"
+            table = "users"
+
+                [actions.column]
+                name = "name"
+                type = "TEXT"
+
+            [[actions]]
+            type = "add_column"
+            table = "users"
+
+                [actions.column]
+                name = "email"
+                type = "TEXT"
+            "#,
+        )
+        .unwrap(),
+    ];
+    reshape.migrate(migrations.iter().take(1).cloned()).unwrap();
+    reshape.complete().unwrap();
+
+    reshape.start_recording();
+    reshape.migrate(migrations).unwrap();
+    let recording = reshape.take_recording().unwrap();
+
+    // Both columns are added by the same statement, so the table is only locked once
+    let alter_tables: Vec<&str> = recording
+        .events
+        .iter()
+        .filter_map(|event| match event {
+            reshape::recording::Event::Statement { sql, .. }
+                if sql.starts_with(r#"ALTER TABLE "users""#) =>
+            {
+                Some(sql.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(1, alter_tables.len(), "{:?}", alter_tables);
+    assert!(alter_tables[0].contains("temp_column_users_name"));
+    assert!(alter_tables[0].contains("temp_column_users_email"));
+
+    reshape.abort().unwrap();
+    reshape.remove().unwrap();
+}
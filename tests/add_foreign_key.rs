@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::{self, Test};
 
 #[test]
 fn add_foreign_key() {
@@ -105,7 +104,7 @@ fn add_foreign_key() {
         assert!(fk_does_not_exist);
     });
 
-    test.run()
+    test.crash_at_every_statement().run()
 }
 
 #[test]
@@ -164,3 +163,139 @@ fn add_invalid_foreign_key() {
     test.expect_failure();
     test.run()
 }
+
+#[test]
+fn add_foreign_key_validated_on_completion() {
+    let mut test = Test::new("Add foreign key validated on completion");
+
+    test.first_migration(
+        r#"
+        name = "create_user_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+        [[actions]]
+        type = "create_table"
+        name = "items"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "user_id"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_foreign_key"
+
+        [[actions]]
+        type = "add_foreign_key"
+        table = "items"
+        validate = "complete"
+
+            [actions.foreign_key]
+            columns = ["user_id"]
+            referenced_table = "users"
+            referenced_columns = ["id"]
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id) VALUES (1)")
+            .unwrap();
+        db.simple_query("INSERT INTO items (id, user_id) VALUES (1, 1)")
+            .unwrap();
+    });
+
+    test.intermediate(|db, _| {
+        // The foreign key is enforced for new rows but not yet validated
+        let result = db.simple_query("INSERT INTO items (id, user_id) VALUES (2, 2)");
+        assert!(result.is_err(), "expected insert to fail");
+
+        let validated: bool = db
+            .query_one(
+                "SELECT convalidated FROM pg_constraint WHERE contype = 'f' AND conrelid = 'public.items'::regclass",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert!(!validated);
+    });
+
+    test.after_completion(|db| {
+        let validated: bool = db
+            .query_one(
+                "SELECT convalidated FROM pg_constraint WHERE contype = 'f' AND conrelid = 'public.items'::regclass",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert!(validated);
+    });
+
+    test.run()
+}
+
+#[test]
+fn add_invalid_foreign_key_samples_rows() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let first_migration: reshape::migrations::Migration = toml::from_str(
+        r#"
+        name = "create_tables"
+
+        [[actions]]
+        type = "custom"
+        start = """
+            CREATE TABLE users (id INTEGER PRIMARY KEY);
+            CREATE TABLE items (id INTEGER PRIMARY KEY, user_id INTEGER);
+            INSERT INTO users (id) VALUES (1);
+            INSERT INTO items (id, user_id) VALUES (1, 1), (2, 2), (3, NULL);
+        """
+        "#,
+    )
+    .unwrap();
+    let second_migration: reshape::migrations::Migration = toml::from_str(
+        r#"
+        name = "add_foreign_key"
+
+        [[actions]]
+        type = "add_foreign_key"
+        table = "items"
+
+            [actions.foreign_key]
+            columns = ["user_id"]
+            referenced_table = "users"
+            referenced_columns = ["id"]
+        "#,
+    )
+    .unwrap();
+
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    let err = reshape
+        .migrate(vec![first_migration, second_migration])
+        .unwrap_err();
+    let message = format!("{:#}", err);
+    assert!(
+        message.contains(r#"rows in "items" with (user_id) = (2) have no matching row in "users""#),
+        "unexpected error: {}",
+        message
+    );
+
+    reshape.remove().unwrap();
+}
@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn add_index() {
@@ -80,7 +79,7 @@ fn add_index() {
         assert!(is_valid, "expected index to be valid");
     });
 
-    test.run();
+    test.crash_at_every_statement().run();
 }
 
 #[test]
@@ -221,3 +220,178 @@ fn add_index_with_type() {
 
     test.run();
 }
+
+#[test]
+fn add_index_replaces_invalid_index() {
+    let mut test = Test::new("Add index replacing invalid index");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_name_index"
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "name_idx"
+            columns = ["name"]
+            unique = true
+        "#,
+    );
+
+    test.after_first(|db| {
+        // A failed concurrent build leaves an invalid index behind, as if an earlier
+        // attempt at the migration had failed without being aborted
+        db.simple_query("INSERT INTO public.users (id, name) VALUES (1, 'John'), (2, 'John')")
+            .unwrap();
+        assert!(db
+            .simple_query("CREATE UNIQUE INDEX CONCURRENTLY name_idx ON public.users (name)")
+            .is_err());
+        db.simple_query("UPDATE public.users SET name = 'Jane' WHERE id = 2")
+            .unwrap();
+    });
+
+    test.intermediate(|db, _| {
+        let is_valid: bool = db
+            .query_one(
+                "
+                SELECT pg_index.indisvalid
+                FROM pg_catalog.pg_index
+                JOIN pg_catalog.pg_class ON pg_index.indexrelid = pg_class.oid
+                WHERE pg_class.relname = 'name_idx'
+                ",
+                &[],
+            )
+            .unwrap()
+            .get("indisvalid");
+        assert!(is_valid, "expected index to be rebuilt");
+    });
+
+    test.run();
+}
+
+#[test]
+fn add_index_on_renamed_column() {
+    let mut test = Test::new("Add index on column renamed in same migration");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "rename_and_index_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+
+            [actions.changes]
+            name = "full_name"
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "full_name_id_idx"
+            columns = ["full_name", "id"]
+        "#,
+    );
+
+    test.after_completion(|db| {
+        // Columns are indexed in the order they were listed
+        let definition: String = db
+            .query_one(
+                "SELECT pg_get_indexdef('public.full_name_id_idx'::regclass)",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert!(
+            definition.ends_with("(full_name, id)"),
+            "unexpected index definition: {}",
+            definition
+        );
+    });
+
+    test.run();
+}
+
+#[test]
+fn add_index_fails_for_unknown_column() {
+    let mut test = Test::new("Add index with unknown column");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_misspelled_index"
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "name_id_idx"
+            columns = ["nmae", "id"]
+        "#,
+    );
+
+    // Previously the index was silently created on only the columns which exist
+    test.expect_failure();
+    test.run();
+}
@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::{self, Test};
 
 #[test]
 fn alter_column_data() {
@@ -78,7 +77,7 @@ fn alter_column_data() {
         assert_eq!("test testsson", result.get::<_, &str>("name"));
     });
 
-    test.run();
+    test.crash_at_every_statement().run();
 }
 
 #[test]
@@ -323,7 +322,7 @@ fn alter_column_rename() {
             .eq(expected));
     });
 
-    test.run();
+    test.crash_at_every_statement().run();
 }
 
 #[test]
@@ -402,7 +401,7 @@ fn alter_column_multiple() {
             .unwrap()
             .iter()
             .map(|row| row.get("counter"))
-            .nth(0)
+            .next()
             .unwrap();
         assert_eq!(52, result);
 
@@ -415,7 +414,7 @@ fn alter_column_multiple() {
             .unwrap()
             .iter()
             .map(|row| row.get("counter"))
-            .nth(0)
+            .next()
             .unwrap();
         assert_eq!(48, result);
     });
@@ -732,3 +731,953 @@ fn alter_column_with_hash_index() {
 
     test.run();
 }
+
+#[test]
+fn alter_column_with_change_log() {
+    let mut test = Test::new("Alter column with change log");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "uppercase_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+        change_log = true
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'john'), (2, 'jane')")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // The backfill should not be recorded
+        let count: i64 = new_db
+            .query_one("SELECT COUNT(*) FROM reshape.change_log", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(0, count);
+
+        // Writes through both schemas should be recorded
+        old_db
+            .simple_query("UPDATE users SET name = 'jim' WHERE id = 1")
+            .unwrap();
+        new_db
+            .simple_query("INSERT INTO users (id, name) VALUES (3, 'JACK')")
+            .unwrap();
+
+        let entries: Vec<(String, String, String, serde_json::Value)> = new_db
+            .query(
+                "
+                SELECT migration, table_name, operation, row_key
+                FROM reshape.change_log
+                ORDER BY id
+                ",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3)))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "uppercase_name".to_string(),
+                    "users".to_string(),
+                    "UPDATE".to_string(),
+                    serde_json::json!({ "id": 1 })
+                ),
+                (
+                    "uppercase_name".to_string(),
+                    "users".to_string(),
+                    "INSERT".to_string(),
+                    serde_json::json!({ "id": 3 })
+                ),
+            ],
+            entries
+        );
+    });
+
+    test.after_completion(|db| {
+        // Entries are kept for consumers but no new ones are recorded
+        db.simple_query("UPDATE users SET name = 'JOHN' WHERE id = 1")
+            .unwrap();
+
+        let count: i64 = db
+            .query_one("SELECT COUNT(*) FROM reshape.change_log", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(2, count);
+    });
+
+    test.after_abort(|db| {
+        let count: i64 = db
+            .query_one("SELECT COUNT(*) FROM reshape.change_log", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(0, count);
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_column_with_dependent_objects() {
+    let mut test = Test::new("Alter column with dependent objects");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "age"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "age_changes"
+            type = "INTEGER"
+            default = "0"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "change_age_type"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "age"
+
+            [actions.changes]
+            name = "years"
+            type = "BIGINT"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "
+            INSERT INTO public.users (id, age) VALUES (1, 20), (2, 40);
+
+            ALTER TABLE public.users ADD CONSTRAINT users_age_check CHECK (age >= 0);
+
+            CREATE OR REPLACE FUNCTION public.count_age_changes() RETURNS TRIGGER AS $$
+            BEGIN
+                NEW.age_changes = NEW.age_changes + 1;
+                RETURN NEW;
+            END
+            $$ LANGUAGE plpgsql;
+            CREATE TRIGGER count_age_changes BEFORE UPDATE OF age ON public.users
+            FOR EACH ROW EXECUTE FUNCTION public.count_age_changes();
+
+            CREATE VIEW public.adult_users AS SELECT id, age FROM public.users WHERE age >= 18;
+            ",
+        )
+        .unwrap();
+    });
+
+    test.after_completion(|db| {
+        // The view keeps its column names but selects from the altered column
+        let ages: Vec<i64> = db
+            .query("SELECT age FROM public.adult_users ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(vec![20, 40], ages);
+
+        // The trigger now fires on updates to the altered column
+        let before: i32 = db
+            .query_one("SELECT age_changes FROM public.users WHERE id = 1", &[])
+            .unwrap()
+            .get(0);
+        let after: i32 = db
+            .query_one(
+                "UPDATE public.users SET years = 21 WHERE id = 1 RETURNING age_changes",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(before + 1, after);
+
+        // The check constraint is recreated and validated
+        let validated: bool = db
+            .query_one(
+                "SELECT convalidated FROM pg_constraint WHERE conname = 'users_age_check'",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert!(validated);
+
+        let result = db.simple_query("UPDATE public.users SET years = -1 WHERE id = 1");
+        assert!(result.is_err(), "expected check constraint to be recreated");
+
+        // Views aren't removed together with the tables between test runs
+        db.simple_query("DROP VIEW public.adult_users CASCADE")
+            .unwrap();
+    });
+
+    test.after_abort(|db| {
+        let ages: Vec<i32> = db
+            .query("SELECT age FROM public.adult_users ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(vec![20, 40], ages);
+
+        db.simple_query("DROP VIEW public.adult_users CASCADE")
+            .unwrap();
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_column_with_unsupported_dependent_object() {
+    let mut test = Test::new("Alter column with unsupported dependent object");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "age"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "change_age_type"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "age"
+
+            [actions.changes]
+            type = "BIGINT"
+        "#,
+    );
+
+    test.after_first(|db| {
+        // Materialized views can't be recreated without repopulating them
+        db.simple_query(
+            "CREATE MATERIALIZED VIEW public.user_ages AS SELECT age FROM public.users",
+        )
+        .unwrap();
+    });
+
+    test.expect_failure();
+    test.run();
+}
+
+#[test]
+fn alter_column_with_identity() {
+    let mut test = Test::new("Alter identity column");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+            generated = "ALWAYS AS IDENTITY"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "change_id_type"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "id"
+
+            [actions.changes]
+            type = "BIGINT"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (name) VALUES ('John')")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Both schemas get a value for the identity column when it's left out
+        old_db
+            .simple_query("INSERT INTO users (name) VALUES ('Jane')")
+            .unwrap();
+        new_db
+            .simple_query("INSERT INTO users (name) VALUES ('Jack')")
+            .unwrap();
+
+        let new_ids: Vec<i64> = new_db
+            .query("SELECT id FROM users ORDER BY name", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get("id"))
+            .collect();
+        let old_ids: Vec<i64> = old_db
+            .query("SELECT id::BIGINT FROM users ORDER BY name", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get("id"))
+            .collect();
+        assert_eq!(3, new_ids.len());
+        assert_eq!(old_ids, new_ids);
+    });
+
+    test.after_completion(|db| {
+        // The column is still an identity column and continues after the existing rows
+        db.simple_query("INSERT INTO users (name) VALUES ('Jill')")
+            .unwrap();
+        let (id, max_id): (i64, i64) = db
+            .query_one(
+                "
+                SELECT
+                    (SELECT id FROM users WHERE name = 'Jill'),
+                    (SELECT MAX(id) FROM users WHERE name <> 'Jill')
+                ",
+                &[],
+            )
+            .map(|row| (row.get(0), row.get(1)))
+            .unwrap();
+        assert!(id > max_id);
+
+        let identity: String = db
+            .query_one(
+                "
+                SELECT identity_generation::TEXT
+                FROM information_schema.columns
+                WHERE table_schema = 'public' AND table_name = 'users' AND column_name = 'id'
+                ",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!("ALWAYS", identity);
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_column_referenced_by_foreign_key() {
+    let mut test = Test::new("Alter serial column referenced by foreign key");
+
+    test.first_migration(
+        r#"
+        name = "create_tables"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "SERIAL"
+
+        [[actions]]
+        type = "create_table"
+        name = "posts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "user_id"
+            type = "INTEGER"
+
+            [[actions.foreign_keys]]
+            columns = ["user_id"]
+            referenced_table = "users"
+            referenced_columns = ["id"]
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "change_id_type"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "id"
+
+            [actions.changes]
+            type = "BIGINT"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users DEFAULT VALUES").unwrap();
+        db.simple_query("INSERT INTO posts (id, user_id) VALUES (1, 1)")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        old_db
+            .simple_query("INSERT INTO users DEFAULT VALUES")
+            .unwrap();
+        new_db
+            .simple_query("INSERT INTO users DEFAULT VALUES")
+            .unwrap();
+
+        let count: i64 = new_db
+            .query_one("SELECT COUNT(DISTINCT id) FROM users", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(3, count);
+    });
+
+    test.after_completion(|db| {
+        // The primary key and the foreign key referencing it are both kept
+        let constraints: Vec<String> = db
+            .query(
+                "
+                SELECT conname::TEXT
+                FROM pg_constraint
+                WHERE conrelid IN ('public.users'::regclass, 'public.posts'::regclass)
+                    AND convalidated
+                ORDER BY conname
+                ",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(
+            vec!["posts_pkey", "posts_user_id_fkey", "users_pkey"],
+            constraints
+        );
+
+        let result = db.simple_query("INSERT INTO posts (id, user_id) VALUES (2, 100)");
+        assert!(result.is_err());
+
+        // The sequence is still used for new rows
+        db.simple_query("INSERT INTO users DEFAULT VALUES").unwrap();
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_column_rename_with_default() {
+    let mut test = Test::new("Rename column with default");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+            nullable = false
+            default = "'unknown'"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "rename_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+
+            [actions.changes]
+            name = "full_name"
+        "#,
+    );
+
+    test.intermediate(|old_db, new_db| {
+        // The aliased column in the new schema still gets the default from the table
+        new_db
+            .simple_query("INSERT INTO users (id) VALUES (1)")
+            .unwrap();
+        new_db
+            .simple_query("INSERT INTO users (id, full_name) VALUES (2, DEFAULT)")
+            .unwrap();
+        old_db
+            .simple_query("INSERT INTO users (id) VALUES (3)")
+            .unwrap();
+
+        let names: Vec<String> = new_db
+            .query("SELECT full_name FROM users ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get("full_name"))
+            .collect();
+        assert_eq!(vec!["unknown", "unknown", "unknown"], names);
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_column_set_not_null_samples_null_rows() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    let first_migration: reshape::migrations::Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "custom"
+        start = """
+            CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);
+            INSERT INTO users (id, name) VALUES (1, 'John'), (2, NULL), (3, NULL);
+        """
+        "#,
+    )
+    .unwrap();
+    let second_migration: reshape::migrations::Migration = toml::from_str(
+        r#"
+        name = "set_name_not_null"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+
+            [actions.changes]
+            nullable = false
+        "#,
+    )
+    .unwrap();
+
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    // The existing NULL values are found before the constraint is validated
+    reshape
+        .migrate(vec![first_migration, second_migration])
+        .unwrap();
+    let message = format!("{:#}", reshape.complete().unwrap_err());
+    assert!(
+        message.contains(r#"2 rows in "users" have a NULL value"#),
+        "unexpected error: {}",
+        message
+    );
+    assert!(message.contains("(2,"), "unexpected error: {}", message);
+
+    // Completing succeeds once the offending rows have been fixed
+    db.simple_query("UPDATE public.users SET name = 'Unknown' WHERE name IS NULL")
+        .unwrap();
+    reshape.complete().unwrap();
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn alter_column_keeps_type_details() {
+    let mut test = Test::new("Alter column keeps type details");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "custom"
+        start = """
+            CREATE SCHEMA IF NOT EXISTS extensions;
+            CREATE EXTENSION IF NOT EXISTS citext SCHEMA extensions;
+            CREATE TABLE users (
+                id INTEGER PRIMARY KEY,
+                email extensions.citext,
+                name VARCHAR(10) COLLATE "C",
+                tags INTEGER[]
+            );
+        """
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "trim_columns"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "email"
+        up = "TRIM(email)"
+        down = "email"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "TRIM(name)"
+        down = "name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "tags"
+        up = "tags[1:3]"
+        down = "tags"
+        "#,
+    );
+
+    test.clear(|db| {
+        db.simple_query("DROP SCHEMA IF EXISTS extensions CASCADE")
+            .unwrap();
+    });
+
+    test.after_completion(|db| {
+        let columns: Vec<(String, String, Option<String>)> = db
+            .query(
+                "
+                SELECT
+                    attname::TEXT,
+                    format_type(atttypid, atttypmod),
+                    (SELECT collname::TEXT FROM pg_collation WHERE oid = attcollation)
+                FROM pg_attribute
+                WHERE attrelid = 'public.users'::regclass AND attnum > 0 AND NOT attisdropped
+                ORDER BY attnum
+                ",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect();
+
+        let column = |name: &str| {
+            columns
+                .iter()
+                .find(|column| column.0 == name)
+                .map(|column| (column.1.as_str(), column.2.as_deref()))
+                .unwrap()
+        };
+        assert_eq!(("extensions.citext", Some("default")), column("email"));
+        assert_eq!(("character varying(10)", Some("C")), column("name"));
+        assert_eq!(("integer[]", None), column("tags"));
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_column_with_comment() {
+    let mut test = Test::new("Alter column with comment");
+
+    test.first_migration(
+        r#"
+        name = "create_user_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+        [[actions]]
+        type = "set_column_comment"
+        table = "users"
+        column = "name"
+        comment = "Full name"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "uppercase_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+        "#,
+    );
+
+    test.after_completion(|db| {
+        // The comment is kept when the temporary column replaces the old one
+        let comment: Option<String> = db
+            .query_one(
+                "
+                SELECT col_description(attrelid, attnum)
+                FROM pg_attribute
+                WHERE attrelid = 'public.users'::regclass AND attname = 'name'
+                ",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(Some("Full name".to_string()), comment);
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_column_with_column_settings() {
+    let mut test = Test::new("Alter column with column settings");
+
+    test.first_migration(
+        r#"
+        name = "create_user_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "age"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "alter_columns"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "age"
+        up = "age::INTEGER"
+        down = "age::TEXT"
+
+            [actions.changes]
+            type = "INTEGER"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "
+            ALTER TABLE public.users
+                ALTER COLUMN name SET STATISTICS 500,
+                ALTER COLUMN name SET STORAGE EXTERNAL,
+                ALTER COLUMN name SET (n_distinct = 100),
+                ALTER COLUMN age SET STORAGE MAIN;
+            ",
+        )
+        .unwrap();
+    });
+
+    test.after_completion(|db| {
+        let settings = |db: &mut postgres::Client, column: &str| -> (i32, String, Vec<String>) {
+            let row = db
+                .query_one(
+                    "
+                    SELECT
+                        COALESCE((to_jsonb(pg_attribute) ->> 'attstattarget')::INTEGER, -1),
+                        attstorage::TEXT,
+                        COALESCE(attoptions, '{}')
+                    FROM pg_attribute
+                    WHERE attrelid = 'public.users'::regclass AND attname = $1
+                    ",
+                    &[&column],
+                )
+                .unwrap();
+            (row.get(0), row.get(1), row.get(2))
+        };
+
+        // The settings are carried over to the column which replaces the old one
+        assert_eq!(
+            (500, "e".to_string(), vec!["n_distinct=100".to_string()]),
+            settings(db, "name")
+        );
+
+        // Storage can't be changed for integers, so it's left as the default
+        assert_eq!((-1, "p".to_string(), vec![]), settings(db, "age"));
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_generated_column() {
+    let mut test = Test::new("Alter generated column");
+
+    test.first_migration(
+        r#"
+        name = "create_user_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "upper_name"
+            type = "TEXT"
+            generated = "ALWAYS AS (UPPER(name)) STORED"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "alter_generated_column"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "upper_name"
+        up = "LOWER(upper_name)"
+        down = "UPPER(upper_name)"
+        "#,
+    );
+
+    test.expect_failure();
+    test.run();
+}
+
+#[test]
+fn alter_column_with_nullable_backfill_order() {
+    let mut test = Test::new("Nullable backfill order");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "created_at"
+            type = "TIMESTAMP"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "uppercase_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+        backfill_order = ["created_at"]
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'alice')")
+            .unwrap();
+    });
+
+    // Rows with a NULL `created_at` would never be backfilled
+    test.expect_failure();
+    test.run();
+}
@@ -0,0 +1,118 @@
+use reshape::testing::Test;
+
+#[test]
+fn alter_composite_type() {
+    let mut test = Test::new("Alter composite type");
+
+    test.first_migration(
+        r#"
+		name = "create_composite_type_and_table"
+
+		[[actions]]
+		type = "create_composite_type"
+		name = "address"
+
+			[[actions.attributes]]
+			name = "street"
+			type = "TEXT"
+
+			[[actions.attributes]]
+			name = "city"
+			type = "TEXT"
+
+		[[actions]]
+		type = "create_table"
+		name = "users"
+		primary_key = ["id"]
+
+			[[actions.columns]]
+			name = "id"
+			type = "INTEGER"
+
+			[[actions.columns]]
+			name = "address"
+			type = "address"
+		"#,
+    );
+
+    test.second_migration(
+        r#"
+		name = "alter_address"
+
+		[[actions]]
+		type = "alter_composite_type"
+		composite_type = "address"
+		remove_attributes = ["city"]
+
+			[[actions.add_attributes]]
+			name = "zip_code"
+			type = "TEXT"
+		"#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "INSERT INTO users (id, address) VALUES (1, ROW('Main Street', 'Springfield'))",
+        )
+        .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Both the new and removed attributes should be available until completion
+        let (city, zip_code): (String, Option<String>) = old_db
+            .query(
+                "SELECT (address).city, (address).zip_code FROM users WHERE id = 1",
+                &[],
+            )
+            .unwrap()
+            .first()
+            .map(|row| (row.get("city"), row.get("zip_code")))
+            .unwrap();
+        assert_eq!("Springfield", city);
+        assert!(zip_code.is_none());
+
+        new_db
+            .simple_query("UPDATE users SET address.zip_code = '12345' WHERE id = 1")
+            .unwrap();
+    });
+
+    test.after_completion(|db| {
+        let attributes: Vec<String> = db
+            .query(
+                "
+                SELECT a.attname
+                FROM pg_catalog.pg_attribute a
+                JOIN pg_catalog.pg_type t ON t.typrelid = a.attrelid
+                WHERE t.typname = 'address' AND NOT a.attisdropped
+                ORDER BY a.attnum
+                ",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get("attname"))
+            .collect();
+        assert_eq!(vec!["street", "zip_code"], attributes);
+    });
+
+    test.after_abort(|db| {
+        let attributes: Vec<String> = db
+            .query(
+                "
+                SELECT a.attname
+                FROM pg_catalog.pg_attribute a
+                JOIN pg_catalog.pg_type t ON t.typrelid = a.attrelid
+                WHERE t.typname = 'address' AND NOT a.attisdropped
+                ORDER BY a.attnum
+                ",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get("attname"))
+            .collect();
+        assert_eq!(vec!["street", "city"], attributes);
+    });
+
+    test.crash_at_every_statement().run();
+}
@@ -0,0 +1,271 @@
+use reshape::testing::{self, Test};
+
+#[test]
+fn alter_domain_add_constraint() {
+    let mut test = Test::new("Alter domain add constraint");
+
+    test.first_migration(
+        r#"
+		name = "create_domain_and_table"
+
+		[[actions]]
+		type = "create_domain"
+		name = "email"
+		data_type = "TEXT"
+
+		[[actions]]
+		type = "create_table"
+		name = "users"
+		primary_key = ["id"]
+
+			[[actions.columns]]
+			name = "id"
+			type = "INTEGER"
+
+			[[actions.columns]]
+			name = "email"
+			type = "email"
+		"#,
+    );
+
+    test.second_migration(
+        r#"
+		name = "add_email_constraint"
+
+		[[actions]]
+		type = "alter_domain"
+		domain = "email"
+
+			[[actions.add_constraints]]
+			name = "contains_at"
+			check = "VALUE LIKE '%@%'"
+		"#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, email) VALUES (1, 'test@example.com')")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Invalid values should be rejected in both schemas
+        let result = old_db.simple_query("INSERT INTO users (id, email) VALUES (2, 'invalid')");
+        assert!(result.is_err(), "expected insert to fail in old schema");
+
+        let result = new_db.simple_query("INSERT INTO users (id, email) VALUES (2, 'invalid')");
+        assert!(result.is_err(), "expected insert to fail in new schema");
+
+        // Ensure constraint has been validated. It keeps a temporary name until the
+        // migration is completed.
+        let is_valid: bool = new_db
+            .query(
+                "SELECT convalidated FROM pg_catalog.pg_constraint WHERE conname LIKE '\\_\\_reshape%contains_at'",
+                &[],
+            )
+            .unwrap()
+            .first()
+            .map(|row| row.get("convalidated"))
+            .unwrap();
+        assert!(is_valid, "expected constraint to be validated");
+    });
+
+    test.after_completion(|db| {
+        let names: Vec<String> = db
+            .query(
+                "SELECT conname::TEXT FROM pg_catalog.pg_constraint WHERE contypid = 'public.email'::regtype",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(vec!["contains_at"], names);
+    });
+
+    test.after_abort(|db| {
+        // Constraint should have been removed after abort
+        db.simple_query("INSERT INTO users (id, email) VALUES (2, 'invalid')")
+            .unwrap();
+    });
+
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn alter_domain_remove_constraint() {
+    let mut test = Test::new("Alter domain remove constraint");
+
+    test.first_migration(
+        r#"
+		name = "create_domain"
+
+		[[actions]]
+		type = "create_domain"
+		name = "positive_integer"
+		data_type = "INTEGER"
+
+			[[actions.constraints]]
+			name = "positive"
+			check = "VALUE > 0"
+
+		[[actions]]
+		type = "create_table"
+		name = "items"
+		primary_key = ["id"]
+
+			[[actions.columns]]
+			name = "id"
+			type = "INTEGER"
+
+			[[actions.columns]]
+			name = "quantity"
+			type = "positive_integer"
+		"#,
+    );
+
+    test.second_migration(
+        r#"
+		name = "remove_positive_constraint"
+
+		[[actions]]
+		type = "alter_domain"
+		domain = "positive_integer"
+		remove_constraints = ["positive"]
+		"#,
+    );
+
+    test.intermediate(|old_db, _| {
+        // Constraint should remain until completion
+        let result = old_db.simple_query("INSERT INTO items (id, quantity) VALUES (1, 0)");
+        assert!(result.is_err(), "expected constraint to still be enforced");
+    });
+
+    test.after_completion(|db| {
+        db.simple_query("INSERT INTO items (id, quantity) VALUES (1, 0)")
+            .unwrap();
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_domain_with_invalid_values() {
+    let mut test = Test::new("Alter domain with invalid existing values");
+
+    test.first_migration(
+        r#"
+		name = "create_domain_and_table"
+
+		[[actions]]
+		type = "create_domain"
+		name = "email"
+		data_type = "TEXT"
+
+		[[actions]]
+		type = "create_table"
+		name = "users"
+		primary_key = ["id"]
+
+			[[actions.columns]]
+			name = "id"
+			type = "INTEGER"
+
+			[[actions.columns]]
+			name = "email"
+			type = "email"
+		"#,
+    );
+
+    test.second_migration(
+        r#"
+		name = "add_email_constraint"
+
+		[[actions]]
+		type = "alter_domain"
+		domain = "email"
+
+			[[actions.add_constraints]]
+			name = "contains_at"
+			check = "VALUE LIKE '%@%'"
+		"#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, email) VALUES (1, 'invalid')")
+            .unwrap();
+    });
+
+    test.expect_failure();
+    test.run();
+}
+
+#[test]
+fn alter_domain_add_existing_constraint() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    let first_migration: reshape::migrations::Migration = toml::from_str(
+        r#"
+        name = "create_domain"
+
+        [[actions]]
+        type = "create_domain"
+        name = "email"
+        data_type = "TEXT"
+
+            [[actions.constraints]]
+            name = "contains_at"
+            check = "VALUE LIKE '%@%'"
+        "#,
+    )
+    .unwrap();
+    let add_constraint = |check: &str| -> reshape::migrations::Migration {
+        toml::from_str(&format!(
+            r#"
+            name = "add_email_constraint"
+
+            [[actions]]
+            type = "alter_domain"
+            domain = "email"
+
+                [[actions.add_constraints]]
+                name = "contains_at"
+                check = "{}"
+            "#,
+            check
+        ))
+        .unwrap()
+    };
+    let constraint_enforced =
+        |db: &mut postgres::Client| db.simple_query("SELECT 'invalid'::email").is_err();
+
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    // A different constraint with the same name can't be added, and the existing one
+    // is left alone when the migration is aborted
+    let err = reshape
+        .migrate(vec![
+            first_migration.clone(),
+            add_constraint("VALUE LIKE '%.%'"),
+        ])
+        .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("a different constraint \"contains_at\" already exists"),
+        "unexpected error: {:#}",
+        err
+    );
+    assert!(constraint_enforced(&mut db));
+
+    // The same constraint is kept as it is, including when aborting
+    reshape
+        .migrate(vec![
+            first_migration.clone(),
+            add_constraint("VALUE LIKE '%@%'"),
+        ])
+        .unwrap();
+    reshape.abort().unwrap();
+    assert!(constraint_enforced(&mut db));
+
+    reshape.remove().unwrap();
+}
@@ -0,0 +1,488 @@
+// These tests guard the stable library API exposed through `reshape::prelude`.
+// They only use the prelude and check that migrations built using the
+// constructors serialize to the same format as migration files. If any of these
+// tests need to be changed, the change is most likely a breaking one.
+use reshape::prelude::*;
+use serde_json::json;
+
+#[test]
+fn build_migration_with_prelude() {
+    let migration = Migration::new("create_users_table", Some("Add users".to_string()))
+        .with_action(
+            CreateTable::new("users", vec!["id".to_string()])
+                .with_column(Column::new("id", "INTEGER").with_nullable(false))
+                .with_column(Column::new("name", "TEXT").with_default("'unknown'")),
+        )
+        .with_action(AddIndex::new(
+            "users",
+            Index::new("name_idx", vec!["name".to_string()]).with_unique(true),
+        ));
+
+    let encoded = serde_json::to_value(&migration).unwrap();
+    let expected = json!({
+        "name": "create_users_table",
+        "description": "Add users",
+        "atomic": false,
+        "actions": [
+            {
+                "type": "create_table",
+                "name": "users",
+                "primary_key": ["id"],
+                "foreign_keys": [],
+                "unique": [],
+                "checks": [],
+                "up": null,
+                "auto_updated_at": false,
+                "unlogged": false,
+                "columns": [
+                    {
+                        "name": "id",
+                        "type": "INTEGER",
+                        "nullable": false,
+                        "default": null,
+                        "generated": null,
+                        "unique": false,
+                        "check": null,
+                    },
+                    {
+                        "name": "name",
+                        "type": "TEXT",
+                        "nullable": true,
+                        "default": "'unknown'",
+                        "generated": null,
+                        "unique": false,
+                        "check": null,
+                    },
+                ],
+            },
+            {
+                "type": "add_index",
+                "table": "users",
+                "index": {
+                    "name": "name_idx",
+                    "columns": ["name"],
+                    "unique": true,
+                    "type": null,
+                },
+            },
+        ],
+    });
+
+    assert_eq!(expected, encoded);
+}
+
+#[test]
+fn build_all_actions_with_prelude() {
+    let actions: Vec<Box<dyn Action>> = vec![
+        Box::new(AddColumn::new("users", Column::new("email", "TEXT")).with_up("'unknown'")),
+        Box::new(
+            AlterColumn::new("users", "email").with_changes(
+                ColumnChanges::new()
+                    .with_name("email_address")
+                    .with_nullable(false),
+            ),
+        ),
+        Box::new(RemoveColumn::new("users", "name").with_down("'unknown'")),
+        Box::new(RemoveIndex::new("name_idx")),
+        Box::new(RenameTable::new("users", "customers")),
+        Box::new(RemoveTable::new("customers")),
+        Box::new(CreateEnum::new(
+            "mood",
+            vec!["happy".to_string(), "sad".to_string()],
+        )),
+        Box::new(RemoveEnum::new("mood")),
+        Box::new(
+            CreateDomain::new("positive_integer", "INTEGER")
+                .with_constraint(DomainConstraint::new("positive", "VALUE > 0")),
+        ),
+        Box::new(AlterDomain::new("positive_integer").with_removed_constraint("positive")),
+        Box::new(RemoveDomain::new("positive_integer")),
+        Box::new(
+            CreateCompositeType::new("address")
+                .with_attribute(CompositeAttribute::new("street", "TEXT")),
+        ),
+        Box::new(AlterCompositeType::new("address").with_removed_attribute("street")),
+        Box::new(RemoveCompositeType::new("address")),
+        Box::new(AddForeignKey::new(
+            "items",
+            ForeignKey::new(vec!["user_id".to_string()], "users", vec!["id".to_string()]),
+        )),
+        Box::new(RemoveForeignKey::new("items", "items_user_id_fkey")),
+        Box::new(Custom::new().with_start("SELECT 1").with_abort("SELECT 2")),
+        Box::new(
+            Seed::new()
+                .with_csv("countries", "code,name\nse,Sweden\n")
+                .with_phase(SeedPhase::Start),
+        ),
+        Box::new(
+            LoadData::new("users", "users.tsv")
+                .with_columns(["id", "name"])
+                .with_header(false)
+                .with_on_error(LoadErrors::Ignore),
+        ),
+        Box::new(
+            SplitColumn::new("users", "name")
+                .with_column(SplitTarget::new(
+                    "first_name",
+                    "TEXT",
+                    "split_part(name, ' ', 1)",
+                ))
+                .with_column(
+                    SplitTarget::new("last_name", "TEXT", "split_part(name, ' ', 2)")
+                        .with_nullable(false),
+                )
+                .with_down("first_name || ' ' || last_name"),
+        ),
+        Box::new(
+            MergeColumns::new(
+                "users",
+                Column::new("name", "TEXT"),
+                "first_name || ' ' || last_name",
+            )
+            .with_column(MergeSource::new("first_name").with_down("split_part(name, ' ', 1)"))
+            .with_column(MergeSource::new("last_name").with_down("split_part(name, ' ', 2)")),
+        ),
+    ];
+
+    let types: Vec<String> = actions
+        .iter()
+        .map(|action| {
+            let encoded = serde_json::to_value(action).unwrap();
+            encoded["type"].as_str().unwrap().to_string()
+        })
+        .collect();
+
+    assert_eq!(
+        vec![
+            "add_column",
+            "alter_column",
+            "remove_column",
+            "remove_index",
+            "rename_table",
+            "remove_table",
+            "create_enum",
+            "remove_enum",
+            "create_domain",
+            "alter_domain",
+            "remove_domain",
+            "create_composite_type",
+            "alter_composite_type",
+            "remove_composite_type",
+            "add_foreign_key",
+            "remove_foreign_key",
+            "custom",
+            "seed",
+            "load_data",
+            "split_column",
+            "merge_columns",
+        ],
+        types
+    );
+
+    // Actions must survive a round trip through the migration file format
+    let encoded = serde_json::to_string(&actions).unwrap();
+    let decoded: Vec<Box<dyn Action>> = serde_json::from_str(&encoded).unwrap();
+    assert_eq!(actions.len(), decoded.len());
+}
+
+#[test]
+fn build_transformations_with_prelude() {
+    let create_table = CreateTable::new("profiles", vec!["user_id".to_string()])
+        .with_up("users", [("user_id".to_string(), "id".to_string())].into())
+        .with_upsert_constraint("profiles_user_id_key");
+    assert_eq!(
+        json!({
+            "table": "users",
+            "values": { "user_id": "id" },
+            "upsert_constraint": "profiles_user_id_key",
+        }),
+        serde_json::to_value(&create_table).unwrap()["up"]
+    );
+
+    let add_column = AddColumn::new("users", Column::new("account_name", "TEXT")).with_up_update(
+        "accounts",
+        "accounts.name",
+        "accounts.user_id = users.id",
+    );
+    assert_eq!(
+        json!({
+            "table": "accounts",
+            "value": "accounts.name",
+            "where": "accounts.user_id = users.id",
+        }),
+        serde_json::to_value(&add_column).unwrap()["up"]
+    );
+
+    let remove_column = RemoveColumn::new("users", "account_name").with_down_update(
+        "accounts",
+        "accounts.name",
+        "accounts.user_id = users.id",
+    );
+    assert_eq!(
+        json!({
+            "table": "accounts",
+            "value": "accounts.name",
+            "where": "accounts.user_id = users.id",
+        }),
+        serde_json::to_value(&remove_column).unwrap()["down"]
+    );
+}
+
+#[test]
+fn build_with_string_slices() {
+    // Lists of names can be passed as string slices instead of owned strings
+    let migration = Migration::new("1_create_tables", None)
+        .with_description("Create tables")
+        .with_action(
+            CreateTable::new("items", ["id"])
+                .with_column(Column::new("id", "INTEGER"))
+                .with_column(Column::new("user_id", "INTEGER"))
+                .with_unique(["id", "user_id"])
+                .with_foreign_key(ForeignKey::new(["user_id"], "users", ["id"])),
+        )
+        .with_actions(vec![
+            Box::new(AddIndex::new(
+                "items",
+                Index::new("user_id_idx", ["user_id"]),
+            )) as Box<dyn Action>,
+            Box::new(CreateEnum::new("mood", ["happy", "sad"])),
+        ]);
+
+    let expected = Migration::new("1_create_tables", Some("Create tables".to_string()))
+        .with_action(
+            CreateTable::new("items", vec!["id".to_string()])
+                .with_column(Column::new("id", "INTEGER"))
+                .with_column(Column::new("user_id", "INTEGER"))
+                .with_unique(vec!["id".to_string(), "user_id".to_string()])
+                .with_foreign_key(ForeignKey::new(
+                    vec!["user_id".to_string()],
+                    "users",
+                    vec!["id".to_string()],
+                )),
+        )
+        .with_action(AddIndex::new(
+            "items",
+            Index::new("user_id_idx", vec!["user_id".to_string()]),
+        ))
+        .with_action(CreateEnum::new(
+            "mood",
+            vec!["happy".to_string(), "sad".to_string()],
+        ));
+
+    assert_eq!(
+        serde_json::to_value(&expected).unwrap(),
+        serde_json::to_value(&migration).unwrap()
+    );
+}
+
+#[test]
+fn unknown_fields_are_rejected() {
+    let migration: Result<Migration, _> = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+            nullible = false
+        "#,
+    );
+    assert!(migration.is_err());
+
+    let action: Result<Box<dyn Action>, _> = serde_json::from_value(json!({
+        "type": "remove_column",
+        "table": "users",
+        "column": "name",
+        "dwon": "'unknown'",
+    }));
+    assert!(action.is_err());
+}
+
+#[test]
+fn backfill_settings() {
+    let backfill = |value: serde_json::Value| -> serde_json::Value {
+        let action: Box<dyn Action> = serde_json::from_value(json!({
+            "type": "alter_column",
+            "table": "users",
+            "column": "name",
+            "up": "UPPER(name)",
+            "backfill": value,
+        }))
+        .unwrap();
+        serde_json::to_value(&action).unwrap()["backfill"].clone()
+    };
+    assert_eq!(json!("none"), backfill(json!(false)));
+    assert_eq!(json!("batched"), backfill(json!(true)));
+    assert_eq!(json!("immediate"), backfill(json!("immediate")));
+
+    let add_column = AddColumn::new("users", Column::new("name", "TEXT"))
+        .with_up("'unknown'")
+        .with_backfill(Backfill::None)
+        .with_backfill_priority(10);
+    let encoded = serde_json::to_value(&add_column).unwrap();
+    assert_eq!(json!("none"), encoded["backfill"]);
+    assert_eq!(json!(10), encoded["backfill_priority"]);
+    assert_eq!(json!("primary_key"), encoded["backfill_order"]);
+
+    // The backfill order is either a strategy or a list of columns
+    let backfill_order = |value| {
+        let action: AlterColumn = serde_json::from_value(json!({
+            "table": "users",
+            "column": "name",
+            "backfill_order": value,
+        }))
+        .unwrap();
+        action.backfill_order
+    };
+    assert_eq!(BackfillOrder::Ctid, backfill_order(json!("ctid")));
+    assert_eq!(
+        BackfillOrder::Columns(vec!["created_at".to_string()]),
+        backfill_order(json!(["created_at"]))
+    );
+    let alter_column = AlterColumn::new("users", "name")
+        .with_backfill_order(BackfillOrder::Columns(vec!["created_at".to_string()]));
+    assert_eq!(
+        json!(["created_at"]),
+        serde_json::to_value(&alter_column).unwrap()["backfill_order"]
+    );
+
+    let add_column = AddColumn::new("items", Column::new("user_id", "INTEGER"))
+        .with_foreign_key(ColumnForeignKey::new("users", "id"));
+    assert_eq!(
+        json!({ "referenced_table": "users", "referenced_column": "id" }),
+        serde_json::to_value(&add_column).unwrap()["foreign_key"]
+    );
+
+    let action: Result<Box<dyn Action>, _> = serde_json::from_value(json!({
+        "type": "add_column",
+        "table": "users",
+        "column": { "name": "name", "type": "TEXT" },
+        "backfill": "eventually",
+    }));
+    assert!(action.is_err());
+}
+
+#[test]
+fn schema_versions() {
+    let mut action = json!({ "type": "remove_table", "table": "users" });
+    upgrade_action(SCHEMA_VERSION, &mut action).unwrap();
+    assert_eq!(json!({ "type": "remove_table", "table": "users" }), action);
+
+    assert!(upgrade_action(0, &mut action).is_err());
+    assert!(upgrade_action(SCHEMA_VERSION + 1, &mut action).is_err());
+}
+
+#[test]
+fn error_exit_codes_are_stable() {
+    let errors = [
+        (Error::Connection(anyhow::anyhow!("test")), "connection", 3),
+        (Error::Validation(anyhow::anyhow!("test")), "validation", 4),
+        (
+            Error::ConflictingState("test".to_string()),
+            "conflicting_state",
+            5,
+        ),
+        (
+            Error::MigrationFailed(anyhow::anyhow!("test")),
+            "migration_failed",
+            6,
+        ),
+        (
+            Error::AbortRequired("test".to_string()),
+            "abort_required",
+            7,
+        ),
+        (Error::Cancelled("test".to_string()), "cancelled", 8),
+    ];
+
+    for (error, kind, exit_code) in errors {
+        assert_eq!(kind, error.kind());
+        assert_eq!(exit_code, error.exit_code());
+    }
+}
+
+#[test]
+fn version_requirements() {
+    let add_column = AddColumn::new("users", Column::new("email", "TEXT").with_nullable(false));
+    let requirements = add_column.version_requirements();
+    assert_eq!(1, requirements.len());
+    assert_eq!(120000, requirements[0].min_version_num);
+
+    let rename_column = AlterColumn::new("users", "email")
+        .with_changes(ColumnChanges::new().with_name("email_address"));
+    assert!(rename_column.version_requirements().is_empty());
+
+    // Only columns which end up NOT NULL need Postgres 12, columns keeping their current
+    // nullability are resolved from the schema before migrating
+    let change_type = AlterColumn::new("users", "email")
+        .with_changes(ColumnChanges::new().with_data_type("VARCHAR(100)"));
+    assert!(change_type.version_requirements().is_empty());
+
+    let make_not_null =
+        AlterColumn::new("users", "email").with_changes(ColumnChanges::new().with_nullable(false));
+    assert_eq!(1, make_not_null.version_requirements().len());
+
+    let make_nullable =
+        AlterColumn::new("users", "email").with_changes(ColumnChanges::new().with_nullable(true));
+    assert!(make_nullable.version_requirements().is_empty());
+}
+
+#[test]
+fn schema_query() {
+    assert_eq!(
+        "SET search_path TO migration_1_initial",
+        schema_query_for_migration("1_initial")
+    );
+}
+
+#[test]
+fn schema_query_with_fallback_schemas() {
+    assert_eq!(
+        r#"SET search_path TO migration_1_initial,public,"$user""#,
+        schema_query_with_fallback("1_initial", &["public", "$user"])
+    );
+}
+
+#[test]
+fn schema_override_query() {
+    assert_eq!(
+        "SET LOCAL reshape.schema = 'new'",
+        SchemaOverride::New.query()
+    );
+    assert_eq!(
+        "SET LOCAL reshape.schema = 'old'",
+        SchemaOverride::Old.query()
+    );
+}
+
+#[test]
+fn build_migrations_with_macro() {
+    let migrations = reshape::migrations![
+        create_users => [
+            CreateTable::new("users", ["id"]).with_column(Column::new("id", "INTEGER")),
+        ],
+        [],
+        add_name => [
+            AddColumn::new("users", Column::new("name", "TEXT")),
+            AddIndex::new("users", Index::new("users_name_idx", ["name"])),
+        ],
+    ];
+
+    let names: Vec<&str> = migrations.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(vec!["1_create_users", "2", "3_add_name"], names);
+    assert_eq!(
+        vec![1, 0, 2],
+        migrations
+            .iter()
+            .map(|m| m.actions.len())
+            .collect::<Vec<_>>()
+    );
+
+    let empty: Vec<Migration> = reshape::migrations![];
+    assert!(empty.is_empty());
+}
@@ -0,0 +1,225 @@
+use postgres::Client;
+use reshape::{migrations::Migration, testing::connect, State};
+
+fn schema_exists(db: &mut Client, schema: &str) -> bool {
+    db.query_one(
+        "SELECT EXISTS (SELECT 1 FROM pg_namespace WHERE nspname = $1)",
+        &[&schema],
+    )
+    .unwrap()
+    .get(0)
+}
+
+#[test]
+fn atomic_migration() {
+    let (mut reshape, mut db) = connect();
+    reshape.remove().unwrap();
+
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+        atomic = true
+
+        [[actions]]
+        type = "create_enum"
+        name = "mood"
+        values = ["happy", "sad"]
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "users_name_idx"
+            columns = ["name"]
+        "#,
+    )
+    .unwrap();
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+
+    // The migration is completed straight away
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::Idle));
+    assert_eq!(
+        Some("create_users_table".to_string()),
+        status.current_migration
+    );
+    assert!(schema_exists(&mut db, "migration_create_users_table"));
+
+    let index_exists: bool = db
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM pg_indexes WHERE indexname = 'users_name_idx')",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert!(index_exists);
+
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "create_posts_table"
+        atomic = true
+
+        [[actions]]
+        type = "create_table"
+        name = "posts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+    reshape
+        .migrate(vec![first_migration, second_migration])
+        .unwrap();
+
+    // The previous schema is replaced by the one for the new migration
+    assert!(!schema_exists(&mut db, "migration_create_users_table"));
+    assert!(schema_exists(&mut db, "migration_create_posts_table"));
+
+    db.simple_query("SET search_path TO migration_create_posts_table")
+        .unwrap();
+    db.simple_query("INSERT INTO posts (id) VALUES (1)")
+        .unwrap();
+    db.simple_query("INSERT INTO users (id, name) VALUES (1, 'John')")
+        .unwrap();
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn atomic_migration_with_index_on_non_empty_table() {
+    let (mut reshape, mut db) = connect();
+    reshape.remove().unwrap();
+
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    db.simple_query("INSERT INTO public.users (id, name) VALUES (1, 'John')")
+        .unwrap();
+
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "add_users_name_index"
+        atomic = true
+
+        [[actions]]
+        type = "create_table"
+        name = "posts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "users_name_idx"
+            columns = ["name"]
+        "#,
+    )
+    .unwrap();
+    let err = reshape
+        .migrate(vec![first_migration, second_migration])
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::MigrationFailed(_))
+    ));
+
+    // Nothing is left behind as the whole migration is rolled back
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::Idle));
+    assert_eq!(
+        Some("create_users_table".to_string()),
+        status.current_migration
+    );
+
+    let posts_exists: bool = db
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM pg_tables WHERE tablename = 'posts')",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert!(!posts_exists);
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn atomic_migration_with_unsupported_action() {
+    let (mut reshape, _) = connect();
+    reshape.remove().unwrap();
+
+    let migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+        atomic = true
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+
+    let err = reshape.migrate(vec![migration]).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::Validation(_))
+    ));
+
+    let status = reshape.status().unwrap();
+    assert_eq!(None, status.current_migration);
+}
@@ -0,0 +1,225 @@
+use postgres::Client;
+use reshape::{migrations::Migration, testing::connect, Reshape, State};
+
+fn migrations() -> Vec<Migration> {
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "uppercase_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+        "#,
+    )
+    .unwrap();
+
+    vec![first_migration, second_migration]
+}
+
+fn set_up(rows: i32) -> (Reshape, Client) {
+    let (mut reshape, mut db) = connect();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations()[..1].to_vec()).unwrap();
+    reshape.complete().unwrap();
+    db.simple_query(&format!(
+        "INSERT INTO public.users (id, name) SELECT i, 'user' || i FROM generate_series(1, {}) AS i",
+        rows
+    ))
+    .unwrap();
+
+    (reshape, db)
+}
+
+fn backfilled_rows(db: &mut Client) -> i64 {
+    db.query_one(
+        "SELECT COUNT(*) FROM migration_uppercase_name.users WHERE name = UPPER('user' || id)",
+        &[],
+    )
+    .unwrap()
+    .get(0)
+}
+
+fn pending_backfills(reshape: &mut Reshape) -> Vec<(String, bool)> {
+    reshape
+        .status()
+        .unwrap()
+        .state
+        .pending_backfills()
+        .iter()
+        .map(|backfill| (backfill.migration.to_string(), backfill.is_started()))
+        .collect()
+}
+
+#[test]
+fn skipped_backfill_is_run_later() {
+    let (mut reshape, mut db) = set_up(3);
+
+    reshape.set_skip_backfill(true);
+    reshape.migrate(migrations()).unwrap();
+    assert_eq!(0, backfilled_rows(&mut db));
+    assert_eq!(
+        vec![("uppercase_name".to_string(), false)],
+        pending_backfills(&mut reshape)
+    );
+
+    // Writes are kept in sync by the triggers while the backfill is pending
+    db.simple_query("INSERT INTO public.users (id, name) VALUES (4, 'user4')")
+        .unwrap();
+    assert_eq!(1, backfilled_rows(&mut db));
+
+    // Completing would lose the values of the rows which haven't been backfilled
+    let err = reshape.complete().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::ConflictingState(_))
+    ));
+
+    reshape.backfill(None).unwrap();
+    assert_eq!(4, backfilled_rows(&mut db));
+    assert!(pending_backfills(&mut reshape).is_empty());
+
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn limited_backfill_continues_where_it_stopped() {
+    let (mut reshape, mut db) = set_up(2500);
+
+    reshape.set_max_backfill_rows(Some(1000));
+    reshape.migrate(migrations()).unwrap();
+    assert_eq!(1000, backfilled_rows(&mut db));
+    assert_eq!(
+        vec![("uppercase_name".to_string(), true)],
+        pending_backfills(&mut reshape)
+    );
+
+    // Every run continues from the last row backfilled by the previous one
+    reshape.backfill(None).unwrap();
+    assert_eq!(2000, backfilled_rows(&mut db));
+    assert!(matches!(
+        reshape.status().unwrap().state,
+        State::InProgress { .. }
+    ));
+
+    reshape.set_max_backfill_rows(None);
+    reshape.backfill(None).unwrap();
+    assert_eq!(2500, backfilled_rows(&mut db));
+    assert!(pending_backfills(&mut reshape).is_empty());
+
+    reshape.complete().unwrap();
+    let names: Vec<String> = db
+        .query(
+            "SELECT name FROM public.users WHERE id IN (1, 2500) ORDER BY id",
+            &[],
+        )
+        .unwrap()
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+    assert_eq!(vec!["USER1", "USER2500"], names);
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn migration_with_pending_backfill_can_be_aborted() {
+    let (mut reshape, mut db) = set_up(3);
+
+    reshape.set_skip_backfill(true);
+    reshape.migrate(migrations()).unwrap();
+    reshape.abort().unwrap();
+    assert!(matches!(reshape.status().unwrap().state, State::Idle));
+
+    let name: String = db
+        .query_one("SELECT name FROM public.users WHERE id = 1", &[])
+        .unwrap()
+        .get(0);
+    assert_eq!("user1", name);
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn backfills_can_be_run_for_a_single_migration() {
+    let (mut reshape, mut db) = set_up(3);
+
+    let mut migrations = migrations();
+    migrations.push(
+        toml::from_str(
+            r#"
+            name = "add_name_length"
+
+            [[actions]]
+            type = "add_column"
+            table = "users"
+            up = "LENGTH(name)"
+
+                [actions.column]
+                name = "name_length"
+                type = "INTEGER"
+            "#,
+        )
+        .unwrap(),
+    );
+
+    reshape.set_skip_backfill(true);
+    reshape.migrate(migrations).unwrap();
+    assert_eq!(
+        vec![
+            ("uppercase_name".to_string(), false),
+            ("add_name_length".to_string(), false)
+        ],
+        pending_backfills(&mut reshape)
+    );
+
+    reshape.backfill(Some("add_name_length")).unwrap();
+    let lengths: i64 = db
+        .query_one(
+            "SELECT COUNT(*) FROM migration_add_name_length.users WHERE name_length = 5",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(3, lengths);
+    assert_eq!(
+        vec![("uppercase_name".to_string(), false)],
+        pending_backfills(&mut reshape)
+    );
+
+    let err = reshape.backfill(Some("unknown")).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::Validation(_))
+    ));
+
+    reshape.backfill(None).unwrap();
+    assert!(pending_backfills(&mut reshape).is_empty());
+
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
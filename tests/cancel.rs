@@ -0,0 +1,82 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use reshape::{migrations::Migration, testing, CancellationToken, State};
+
+#[test]
+fn cancel_migration() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    // Cancel while the first action is running, which stops the migration before
+    // the table is created
+    let token = CancellationToken::new();
+    let canceller = {
+        let token = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            token.cancel();
+        })
+    };
+
+    let err = reshape
+        .migrate_with(migrations(), &token, None)
+        .unwrap_err();
+    canceller.join().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::Cancelled(_))
+    ));
+
+    // The migration is left to be continued
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::Applying { .. }));
+
+    reshape.migrate(migrations()).unwrap();
+
+    // A deadline which has already passed cancels before anything is done
+    let err = reshape
+        .complete_with(&CancellationToken::new(), Some(Instant::now()))
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::Cancelled(_))
+    ));
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::InProgress { .. }));
+
+    reshape.complete().unwrap();
+    let status = reshape.status().unwrap();
+    assert_eq!(
+        Some("create_users_table".to_string()),
+        status.current_migration
+    );
+
+    reshape.remove().unwrap();
+}
+
+fn migrations() -> Vec<Migration> {
+    let migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "custom"
+        start = "SELECT pg_sleep(0.5)"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+
+    vec![migration]
+}
@@ -0,0 +1,134 @@
+use postgres::{Client, NoTls};
+use reshape::{migrations::Migration, testing::connection_string, CascadeDrops, Reshape};
+
+fn migrations() -> Vec<Migration> {
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "add_name_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+
+    vec![first_migration, second_migration]
+}
+
+fn view_exists(db: &mut Client, name: &str) -> bool {
+    !db.query(
+        "SELECT 1 FROM pg_views WHERE schemaname = 'public' AND viewname = $1",
+        &[&name],
+    )
+    .unwrap()
+    .is_empty()
+}
+
+fn assert_conflicting_state(err: anyhow::Error, dependent: &str) {
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::ConflictingState(_))
+    ));
+    let message = format!("{:#}", err);
+    assert!(message.contains(dependent), "unexpected error: {}", message);
+}
+
+#[test]
+fn complete_refuses_to_drop_dependent_objects() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    reshape.remove().unwrap();
+    reshape.set_cascade_drops(CascadeDrops::Refuse);
+
+    // Nothing but Reshape's own views depend on the previous schema
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+
+    // A view built on the previous schema would be dropped along with it
+    reshape.migrate(migrations()).unwrap();
+    db.simple_query(
+        "
+        CREATE VIEW public.user_count AS SELECT COUNT(*) FROM migration_create_users_table.users;
+        CREATE VIEW public.user_report AS SELECT * FROM public.user_count;
+        ",
+    )
+    .unwrap();
+
+    let err = reshape.complete().unwrap_err();
+    assert_conflicting_state(err, "view public.user_count");
+    assert_eq!("in_progress", reshape.status().unwrap().state.name());
+    assert!(view_exists(&mut db, "user_count"));
+
+    reshape.set_cascade_drops(CascadeDrops::Warn);
+    reshape.complete().unwrap();
+    assert!(!view_exists(&mut db, "user_count"));
+    assert!(!view_exists(&mut db, "user_report"));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn abort_refuses_to_drop_dependent_objects() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    reshape.remove().unwrap();
+    reshape.set_cascade_drops(CascadeDrops::Refuse);
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations()).unwrap();
+    db.simple_query(
+        "CREATE FUNCTION public.user_name(users migration_add_name_column.users) RETURNS TEXT AS 'SELECT users.name' LANGUAGE SQL",
+    )
+    .unwrap();
+
+    let err = reshape.abort().unwrap_err();
+    assert_conflicting_state(err, "function public.user_name");
+    assert_eq!("in_progress", reshape.status().unwrap().state.name());
+
+    db.simple_query("DROP FUNCTION public.user_name").unwrap();
+    reshape.abort().unwrap();
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn remove_refuses_to_drop_dependent_objects() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    let mut db = Client::connect(&connection_string(), NoTls).unwrap();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations()).unwrap();
+    reshape.complete().unwrap();
+    db.simple_query("CREATE VIEW public.user_names AS SELECT name FROM public.users")
+        .unwrap();
+
+    reshape.set_cascade_drops(CascadeDrops::Refuse);
+    let err = reshape.remove().unwrap_err();
+    assert_conflicting_state(err, "view public.user_names");
+    assert!(view_exists(&mut db, "user_names"));
+
+    reshape.set_cascade_drops(CascadeDrops::Warn);
+    reshape.remove().unwrap();
+    assert!(!view_exists(&mut db, "user_names"));
+}
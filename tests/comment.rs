@@ -0,0 +1,213 @@
+use postgres::Client;
+use reshape::testing::Test;
+
+fn table_comment(db: &mut Client, table: &str) -> Option<String> {
+    db.query_one(
+        "SELECT obj_description(to_regclass($1), 'pg_class')",
+        &[&table],
+    )
+    .unwrap()
+    .get(0)
+}
+
+fn column_comment(db: &mut Client, table: &str, column: &str) -> Option<String> {
+    db.query_one(
+        "
+        SELECT col_description(attrelid, attnum)
+        FROM pg_attribute
+        WHERE attrelid = to_regclass($1) AND attname = $2
+        ",
+        &[&table, &column],
+    )
+    .unwrap()
+    .get(0)
+}
+
+fn type_comment(db: &mut Client, name: &str) -> Option<String> {
+    db.query_one(
+        "SELECT obj_description(to_regtype($1), 'pg_type')",
+        &[&name],
+    )
+    .unwrap()
+    .get(0)
+}
+
+#[test]
+fn set_comments() {
+    let mut test = Test::new("Set comments");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_enum"
+        name = "mood"
+        values = ["happy", "sad"]
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "set_comments"
+
+        [[actions]]
+        type = "set_table_comment"
+        table = "users"
+        comment = "People using the app"
+
+        [[actions]]
+        type = "set_column_comment"
+        table = "users"
+        column = "name"
+        comment = "Full name, it's not split up"
+
+        [[actions]]
+        type = "set_type_comment"
+        name = "mood"
+        comment = "How a user is feeling"
+        "#,
+    );
+
+    test.intermediate(|db, _| {
+        // Comments are only set once the migration is completed
+        assert_eq!(None, table_comment(db, "public.users"));
+        assert_eq!(None, column_comment(db, "public.users", "name"));
+        assert_eq!(None, type_comment(db, "public.mood"));
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(
+            Some("People using the app".to_string()),
+            table_comment(db, "public.users")
+        );
+        assert_eq!(
+            Some("Full name, it's not split up".to_string()),
+            column_comment(db, "public.users", "name")
+        );
+        assert_eq!(
+            Some("How a user is feeling".to_string()),
+            type_comment(db, "public.mood")
+        );
+    });
+
+    test.after_abort(|db| {
+        assert_eq!(None, table_comment(db, "public.users"));
+        assert_eq!(None, column_comment(db, "public.users", "name"));
+        assert_eq!(None, type_comment(db, "public.mood"));
+    });
+
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn remove_comments() {
+    let mut test = Test::new("Remove comments");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+        [[actions]]
+        type = "set_table_comment"
+        table = "users"
+        comment = "People using the app"
+
+        [[actions]]
+        type = "set_column_comment"
+        table = "users"
+        column = "id"
+        comment = "Identifier"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "remove_comments"
+
+        [[actions]]
+        type = "set_table_comment"
+        table = "users"
+
+        [[actions]]
+        type = "set_column_comment"
+        table = "users"
+        column = "id"
+        "#,
+    );
+
+    test.after_first(|db| {
+        assert_eq!(
+            Some("People using the app".to_string()),
+            table_comment(db, "public.users")
+        );
+        assert_eq!(
+            Some("Identifier".to_string()),
+            column_comment(db, "public.users", "id")
+        );
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(None, table_comment(db, "public.users"));
+        assert_eq!(None, column_comment(db, "public.users", "id"));
+    });
+
+    test.run();
+}
+
+#[test]
+fn set_column_comment_on_missing_column() {
+    let mut test = Test::new("Set column comment on missing column");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "set_comment"
+
+        [[actions]]
+        type = "set_column_comment"
+        table = "users"
+        column = "name"
+        comment = "Full name"
+        "#,
+    );
+
+    test.expect_failure();
+    test.run();
+}
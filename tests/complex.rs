@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn move_column_between_tables() {
@@ -157,7 +156,7 @@ fn move_column_between_tables() {
         assert_eq!("test4+updated@test.com", old_email);
     });
 
-    test.run();
+    test.crash_at_every_statement().run();
 }
 
 #[test]
@@ -223,7 +222,6 @@ fn extract_relation_into_new_table() {
             [actions.up]
             table = "users"
             values = { user_id = "id", account_id = "account_id", role = "UPPER(account_role)" }
-            where = "user_account_connections.user_id = users.id"
 
         [[actions]]
         type = "remove_column"
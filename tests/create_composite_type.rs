@@ -0,0 +1,54 @@
+use reshape::testing::Test;
+
+#[test]
+fn create_composite_type() {
+    let mut test = Test::new("Create composite type");
+
+    test.first_migration(
+        r#"
+		name = "create_composite_type_and_table"
+
+		[[actions]]
+		type = "create_composite_type"
+		name = "address"
+
+			[[actions.attributes]]
+			name = "street"
+			type = "TEXT"
+
+			[[actions.attributes]]
+			name = "city"
+			type = "TEXT"
+
+		[[actions]]
+		type = "create_table"
+		name = "users"
+		primary_key = ["id"]
+
+			[[actions.columns]]
+			name = "id"
+			type = "INTEGER"
+
+			[[actions.columns]]
+			name = "address"
+			type = "address"
+		"#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "INSERT INTO users (id, address) VALUES (1, ROW('Main Street', 'Springfield'))",
+        )
+        .unwrap();
+
+        let city: String = db
+            .query("SELECT (address).city FROM users WHERE id = 1", &[])
+            .unwrap()
+            .first()
+            .map(|row| row.get("city"))
+            .unwrap();
+        assert_eq!("Springfield", city);
+    });
+
+    test.run();
+}
@@ -0,0 +1,46 @@
+use reshape::testing::Test;
+
+#[test]
+fn create_domain() {
+    let mut test = Test::new("Create domain");
+
+    test.first_migration(
+        r#"
+		name = "create_domain_and_table"
+
+		[[actions]]
+		type = "create_domain"
+		name = "positive_integer"
+		data_type = "INTEGER"
+
+			[[actions.constraints]]
+			name = "positive"
+			check = "VALUE > 0"
+
+		[[actions]]
+		type = "create_table"
+		name = "items"
+		primary_key = ["id"]
+
+			[[actions.columns]]
+			name = "id"
+			type = "INTEGER"
+
+			[[actions.columns]]
+			name = "quantity"
+			type = "positive_integer"
+		"#,
+    );
+
+    test.after_first(|db| {
+        // Valid values should succeed
+        db.simple_query("INSERT INTO items (id, quantity) VALUES (1, 1), (2, 10)")
+            .unwrap();
+
+        // Values violating the domain constraint should fail
+        let result = db.simple_query("INSERT INTO items (id, quantity) VALUES (3, 0)");
+        assert!(result.is_err(), "expected insert of invalid value to fail");
+    });
+
+    test.run();
+}
@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn create_table() {
@@ -198,3 +197,285 @@ fn create_table_with_foreign_keys() {
 
     test.run();
 }
+
+#[test]
+fn create_table_with_auto_updated_at() {
+    let mut test = Test::new("Create table with auto_updated_at");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+        auto_updated_at = true
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "updated_at"
+            type = "TIMESTAMP"
+            nullable = false
+            default = "NOW()"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "INSERT INTO users (id, name, updated_at) VALUES (1, 'John', '2000-01-01')",
+        )
+        .unwrap();
+
+        // Updating the row should bump updated_at
+        db.simple_query("UPDATE users SET name = 'Jane' WHERE id = 1")
+            .unwrap();
+
+        let outdated: bool = db
+            .query_one(
+                "SELECT updated_at < '2001-01-01' AS outdated FROM users WHERE id = 1",
+                &[],
+            )
+            .unwrap()
+            .get("outdated");
+        assert!(!outdated);
+    });
+
+    test.run();
+}
+
+#[test]
+fn auto_updated_at_is_kept_when_backfilling() {
+    let mut test = Test::new("Backfill table with auto_updated_at");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+        auto_updated_at = true
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "updated_at"
+            type = "TIMESTAMP"
+            nullable = false
+            default = "NOW()"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "add_and_alter_columns"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "UPPER(name)"
+
+            [actions.column]
+            name = "display_name"
+            type = "TEXT"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "LOWER(name)"
+        down = "name"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "INSERT INTO users (id, name, updated_at) VALUES (1, 'John', '2000-01-01'), (2, 'Jane', '2000-01-01')",
+        )
+        .unwrap();
+    });
+
+    // Backfills touch every row, which mustn't count as the rows being updated
+    test.intermediate(|old_db, _| {
+        assert_eq!(0, rows_updated_since_2001(old_db));
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(0, rows_updated_since_2001(db));
+
+        // Changes made by the application still bump updated_at
+        db.simple_query("UPDATE users SET name = 'jim' WHERE id = 1")
+            .unwrap();
+        assert_eq!(1, rows_updated_since_2001(db));
+    });
+
+    test.after_abort(|db| {
+        assert_eq!(0, rows_updated_since_2001(db));
+    });
+
+    fn rows_updated_since_2001(db: &mut postgres::Client) -> i64 {
+        db.query_one(
+            "SELECT COUNT(*) FROM public.users WHERE updated_at > '2001-01-01'",
+            &[],
+        )
+        .unwrap()
+        .get(0)
+    }
+
+    test.run();
+}
+
+#[test]
+fn create_table_with_unique_and_check_constraints() {
+    let mut test = Test::new("Create table with unique and check constraints");
+
+    test.first_migration(
+        r#"
+        name = "create_products_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "products"
+        primary_key = ["id"]
+        unique = [["category", "name"]]
+        checks = ["price > discount"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "sku"
+            type = "TEXT"
+            unique = true
+
+            [[actions.columns]]
+            name = "category"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "price"
+            type = "INTEGER"
+            check = "price > 0"
+
+            [[actions.columns]]
+            name = "discount"
+            type = "INTEGER"
+            default = "0"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "INSERT INTO products (id, sku, category, name, price) VALUES (1, 'A1', 'shoes', 'Boot', 100)",
+        )
+        .unwrap();
+
+        // Column unique constraint
+        assert!(db
+            .simple_query(
+                "INSERT INTO products (id, sku, category, name, price) VALUES (2, 'A1', 'shoes', 'Sandal', 50)",
+            )
+            .is_err());
+
+        // Table unique constraint
+        assert!(db
+            .simple_query(
+                "INSERT INTO products (id, sku, category, name, price) VALUES (3, 'A2', 'shoes', 'Boot', 50)",
+            )
+            .is_err());
+
+        // Column check constraint
+        assert!(db
+            .simple_query(
+                "INSERT INTO products (id, sku, category, name, price) VALUES (4, 'A3', 'hats', 'Cap', 0)",
+            )
+            .is_err());
+
+        // Table check constraint
+        assert!(db
+            .simple_query(
+                "INSERT INTO products (id, sku, category, name, price, discount) VALUES (5, 'A4', 'hats', 'Beanie', 10, 20)",
+            )
+            .is_err());
+    });
+
+    test.run();
+}
+
+#[test]
+fn create_unlogged_table() {
+    let mut test = Test::new("Create unlogged table");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "create_events_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "events"
+        primary_key = ["id"]
+        unlogged = true
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    fn persistence(db: &mut postgres::Client) -> String {
+        let persistence: i8 = db
+            .query_one(
+                "SELECT relpersistence FROM pg_class WHERE oid = 'public.events'::regclass",
+                &[],
+            )
+            .unwrap()
+            .get("relpersistence");
+        (persistence as u8 as char).to_string()
+    }
+
+    test.intermediate(|old_db, _new_db| {
+        // The table is unlogged while the migration is in progress
+        assert_eq!("u", persistence(old_db));
+    });
+
+    test.after_completion(|db| {
+        assert_eq!("p", persistence(db));
+    });
+
+    test.crash_at_every_statement().run();
+}
@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn custom_enable_extension() {
@@ -124,5 +123,5 @@ fn custom_enable_extension() {
         assert!(!btree_gist_activated);
     });
 
-    test.run();
+    test.crash_at_every_statement().run();
 }
@@ -0,0 +1,112 @@
+use reshape::{migrations::Migration, testing::connection_string, Reshape};
+
+fn migrations() -> Vec<Migration> {
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_tables"
+
+        [[actions]]
+        type = "create_enum"
+        name = "mood"
+        values = ["happy", "sad"]
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "BIGINT"
+
+            [[actions.columns]]
+            name = "name"
+            type = "VARCHAR(50)"
+            nullable = false
+
+            [[actions.columns]]
+            name = "type"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "moods"
+            type = "mood[]"
+
+        [[actions]]
+        type = "create_table"
+        name = "posts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "user_id"
+            type = "BIGINT"
+            nullable = false
+
+            [[actions.foreign_keys]]
+            columns = ["user_id"]
+            referenced_table = "users"
+            referenced_columns = ["id"]
+        "#,
+    )
+    .unwrap();
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "rename_posts_table"
+
+        [[actions]]
+        type = "rename_table"
+        table = "posts"
+        new_name = "articles"
+        "#,
+    )
+    .unwrap();
+
+    vec![first_migration, second_migration]
+}
+
+#[test]
+fn diesel_schema_describes_latest_migration() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+
+    let schema = reshape.diesel_schema().unwrap();
+    assert!(schema.contains("migration 'create_tables'"));
+    assert!(schema.contains("#[diesel(postgres_type(name = \"mood\"))]\n    pub struct Mood;"));
+    assert!(schema.contains("    use super::sql_types::Mood;\n\n    users (id) {"));
+    assert!(schema.contains("        id -> Int8,\n        name -> Text,"));
+    assert!(schema.contains("        #[sql_name = \"type\"]\n        type_ -> Nullable<Text>,"));
+    assert!(schema.contains("        moods -> Nullable<Array<Mood>>,"));
+    assert!(schema.contains("diesel::joinable!(posts -> users (user_id));"));
+    assert!(schema
+        .contains("diesel::allow_tables_to_appear_in_same_query!(\n    posts,\n    users,\n);"));
+
+    // The new names are used while the migration is in progress
+    reshape.migrate(migrations()).unwrap();
+    let schema = reshape.diesel_schema().unwrap();
+    assert!(schema.contains("migration 'rename_posts_table'"));
+    assert!(schema.contains("    articles (id) {"));
+    assert!(!schema.contains("    posts (id) {"));
+    assert!(schema.contains("diesel::joinable!(articles -> users (user_id));"));
+
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn diesel_schema_requires_applied_migration() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let err = reshape.diesel_schema().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::ConflictingState(_))
+    ));
+}
@@ -0,0 +1,257 @@
+use reshape::{prelude::*, testing, MigrationDifference, Reshape};
+
+fn connect() -> Reshape {
+    Reshape::new(&testing::connection_string()).unwrap()
+}
+
+fn create_table(migration_name: &str, table: &str) -> Migration {
+    Migration::new(migration_name, None)
+        .with_action(CreateTable::new(table, ["id"]).with_column(Column::new("id", "INTEGER")))
+}
+
+#[test]
+fn diff_migrations_reports_drift() {
+    let mut reshape = connect();
+    reshape.remove().unwrap();
+
+    let applied = vec![
+        create_table("create_users", "users"),
+        create_table("create_products", "products"),
+    ];
+    reshape.migrate(applied.clone()).unwrap();
+    reshape.complete().unwrap();
+    assert!(reshape.diff_migrations(&applied).unwrap().is_empty());
+
+    let pending = vec![
+        applied[0].clone(),
+        applied[1].clone(),
+        create_table("create_orders", "orders"),
+    ];
+    assert_eq!(
+        vec![MigrationDifference::Pending {
+            name: "create_orders".to_string()
+        }],
+        reshape.diff_migrations(&pending).unwrap()
+    );
+
+    let edited = vec![applied[0].clone(), create_table("create_products", "items")];
+    assert_eq!(
+        vec![MigrationDifference::Edited {
+            name: "create_products".to_string()
+        }],
+        reshape.diff_migrations(&edited).unwrap()
+    );
+
+    let renamed = vec![create_table("add_users", "users"), applied[1].clone()];
+    assert_eq!(
+        vec![MigrationDifference::Renamed {
+            from: "create_users".to_string(),
+            to: "add_users".to_string(),
+        }],
+        reshape.diff_migrations(&renamed).unwrap()
+    );
+
+    let reordered = vec![applied[1].clone(), applied[0].clone()];
+    assert_eq!(
+        vec![
+            MigrationDifference::Reordered {
+                name: "create_users".to_string(),
+                applied_position: 1,
+                local_position: 2,
+            },
+            MigrationDifference::Reordered {
+                name: "create_products".to_string(),
+                applied_position: 2,
+                local_position: 1,
+            },
+        ],
+        reshape.diff_migrations(&reordered).unwrap()
+    );
+
+    let inserted = vec![
+        applied[0].clone(),
+        create_table("create_orders", "orders"),
+        applied[1].clone(),
+    ];
+    assert_eq!(
+        vec![
+            MigrationDifference::Reordered {
+                name: "create_products".to_string(),
+                applied_position: 2,
+                local_position: 3,
+            },
+            MigrationDifference::Inserted {
+                name: "create_orders".to_string()
+            },
+        ],
+        reshape.diff_migrations(&inserted).unwrap()
+    );
+
+    let missing = vec![applied[0].clone()];
+    assert_eq!(
+        vec![MigrationDifference::Missing {
+            name: "create_products".to_string()
+        }],
+        reshape.diff_migrations(&missing).unwrap()
+    );
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn edited_migrations_require_allow_drift() {
+    let mut reshape = connect();
+    reshape.remove().unwrap();
+
+    reshape
+        .migrate(vec![create_table("create_users", "users")])
+        .unwrap();
+    reshape.complete().unwrap();
+
+    let edited = vec![
+        create_table("create_users", "accounts"),
+        create_table("create_products", "products"),
+    ];
+    let err = reshape.migrate(edited.clone()).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::Validation(_))
+    ));
+
+    // Edited migrations have already been applied and aren't run again
+    reshape.set_allow_drift(true);
+    reshape.migrate(edited).unwrap();
+    reshape.complete().unwrap();
+    assert_eq!(
+        Some("create_products".to_string()),
+        reshape.status().unwrap().current_migration
+    );
+
+    // Renamed migrations can't be matched up with the applied ones, even when
+    // drift is allowed
+    let err = reshape
+        .migrate(vec![
+            create_table("add_users", "users"),
+            create_table("create_products", "products"),
+        ])
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::Validation(_))
+    ));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn migration_hash_is_stable_across_processes() {
+    let mut reshape = connect();
+    reshape.remove().unwrap();
+
+    let dir = std::env::temp_dir().join(format!("reshape-{}-hash", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("1_create_users.toml"),
+        r#"
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+    // The values of an `up` transformation are a map, which mustn't affect the
+    // hash depending on the order it happens to be serialized in
+    std::fs::write(
+        dir.join("2_create_accounts.toml"),
+        r#"
+        [[actions]]
+        type = "create_table"
+        name = "accounts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "display_name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "handle"
+            type = "TEXT"
+
+            [actions.up]
+            table = "users"
+            values = { id = "id", name = "name", display_name = "INITCAP(name)", handle = "LOWER(name)" }
+        "#,
+    )
+    .unwrap();
+
+    let connection_string = testing::connection_string();
+    let run = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_reshape"))
+            .args(args)
+            .args(["--url", &connection_string, "--dirs", dir.to_str().unwrap()])
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["migration", "start", "--complete"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Each process seeds its hash maps differently, so comparing several times
+    // would catch a hash which depends on the iteration order
+    for _ in 0..5 {
+        let output = run(&["diff-migrations"]);
+        assert!(
+            output.status.success(),
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn earlier_hashes_are_not_edits() {
+    let mut reshape = connect();
+    reshape.remove().unwrap();
+
+    let applied = vec![create_table("create_users", "users")];
+    reshape.migrate(applied.clone()).unwrap();
+    reshape.complete().unwrap();
+
+    // Hashes stored by an earlier version were made from a different form of the
+    // actions and can't be checked for edits
+    let connection_string = testing::connection_string();
+    let mut db = postgres::Client::connect(&connection_string, postgres::NoTls).unwrap();
+    db.simple_query("UPDATE reshape.migrations SET hash = '0123456789abcdef'")
+        .unwrap();
+    assert!(reshape
+        .diff_migrations(&[create_table("create_users", "accounts")])
+        .unwrap()
+        .is_empty());
+
+    reshape.remove().unwrap();
+}
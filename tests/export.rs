@@ -0,0 +1,333 @@
+use postgres::Client;
+use reshape::{
+    migrations::Migration,
+    testing::{self, connection_string},
+    Reshape, State,
+};
+
+// The test database is reset by every test, so it's used as the scratch database
+fn connect() -> (Reshape, Client) {
+    let (mut reshape, db) = testing::connect();
+    reshape.set_scratch_database(true);
+    (reshape, db)
+}
+
+fn migrations() -> Vec<Migration> {
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "add_upper_name_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "upper(name)"
+
+            [actions.column]
+            name = "upper_name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+
+    vec![first_migration, second_migration]
+}
+
+// Reset the database to only have the first migration applied, with some rows to backfill
+fn apply_first_migration(reshape: &mut Reshape, db: &mut Client) {
+    reshape.remove().unwrap();
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+
+    // Rows beyond the first batch make sure the backfill isn't batched
+    db.simple_query(
+        "INSERT INTO public.users (id, name) SELECT id, 'user ' || id FROM generate_series(1, 2500) AS id",
+    )
+    .unwrap();
+}
+
+// Scripts are meant for psql, which handles the meta-commands
+fn run_script(db: &mut Client, script: &str) {
+    let statements: String = script
+        .lines()
+        .filter(|line| !line.starts_with('\\'))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    db.batch_execute(&statements).unwrap();
+}
+
+#[test]
+fn exported_scripts_start_and_complete_migrations() {
+    let (mut reshape, mut db) = connect();
+
+    apply_first_migration(&mut reshape, &mut db);
+    let scripts = reshape.export_sql(migrations()).unwrap();
+    assert!(scripts
+        .start
+        .contains("Start migrations 'add_upper_name_column'"));
+    assert!(!scripts.start.contains("pg_try_advisory_lock"));
+
+    // Run the scripts against a database which hasn't been migrated yet
+    apply_first_migration(&mut reshape, &mut db);
+    run_script(&mut db, &scripts.start);
+
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::InProgress { .. }));
+    let backfilled: i64 = db
+        .query_one(
+            "SELECT COUNT(*) FROM migration_add_upper_name_column.users WHERE upper_name = upper(name)",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(2500, backfilled);
+
+    run_script(&mut db, &scripts.complete);
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::Idle));
+    assert_eq!(
+        Some("add_upper_name_column".to_string()),
+        status.current_migration
+    );
+    assert!(reshape.diff_migrations(&migrations()).unwrap().is_empty());
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn exported_scripts_abort_migrations() {
+    let (mut reshape, mut db) = connect();
+
+    apply_first_migration(&mut reshape, &mut db);
+    let scripts = reshape.export_sql(migrations()).unwrap();
+
+    apply_first_migration(&mut reshape, &mut db);
+    run_script(&mut db, &scripts.start);
+    run_script(&mut db, &scripts.abort);
+
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::Idle));
+    assert_eq!(
+        Some("create_users_table".to_string()),
+        status.current_migration
+    );
+
+    // The database is left as if the migration was never started, so it can be
+    // applied the regular way
+    reshape.migrate(migrations()).unwrap();
+    reshape.complete().unwrap();
+
+    reshape.remove().unwrap();
+}
+
+// Migrations on a table with a column named password, which must make it into the
+// scripts as is
+fn password_migrations() -> Vec<Migration> {
+    let create_table: Migration = toml::from_str(
+        r#"
+        name = "create_accounts_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "accounts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "password"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+    let add_column: Migration = toml::from_str(
+        r#"
+        name = "add_password_hash_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "accounts"
+        up = "CASE WHEN password = '' THEN NULL ELSE md5(password) END"
+
+            [actions.column]
+            name = "password_hash"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+
+    vec![create_table, add_column]
+}
+
+fn apply_first_password_migration(reshape: &mut Reshape, db: &mut Client) {
+    reshape.remove().unwrap();
+    reshape
+        .migrate(password_migrations().into_iter().take(1))
+        .unwrap();
+    reshape.complete().unwrap();
+
+    db.simple_query("INSERT INTO public.accounts (id, password) VALUES (1, 'secret'), (2, '')")
+        .unwrap();
+}
+
+#[test]
+fn exported_scripts_keep_password_columns() {
+    let (mut reshape, mut db) = connect();
+
+    apply_first_password_migration(&mut reshape, &mut db);
+    let scripts = reshape.export_sql(password_migrations()).unwrap();
+    assert!(scripts
+        .start
+        .contains("CASE WHEN password = '' THEN NULL ELSE md5(password) END"));
+    assert!(!scripts.start.contains("********"));
+
+    apply_first_password_migration(&mut reshape, &mut db);
+    run_script(&mut db, &scripts.start);
+    run_script(&mut db, &scripts.complete);
+
+    let hash: String = db
+        .query_one(
+            "SELECT password_hash FROM public.accounts WHERE id = 1",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!("5ebe2294ecd0e0f08eab7690d2a6ee69", hash);
+    let empty_hash: Option<String> = db
+        .query_one(
+            "SELECT password_hash FROM public.accounts WHERE id = 2",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(None, empty_hash);
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn export_requires_pending_migrations() {
+    let (mut reshape, _) = connect();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations()).unwrap();
+    reshape.complete().unwrap();
+
+    let err = reshape.export_sql(migrations()).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::Validation(_))
+    ));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn export_requires_scratch_database() {
+    let (mut reshape, mut db) = connect();
+    apply_first_migration(&mut reshape, &mut db);
+
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    let err = reshape.export_sql(migrations()).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::ConflictingState(_))
+    ));
+
+    // Nothing has been applied
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::Idle));
+    assert_eq!(
+        Some("create_users_table".to_string()),
+        status.current_migration
+    );
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn export_refuses_actions_which_send_data() {
+    let (mut reshape, mut db) = connect();
+    apply_first_migration(&mut reshape, &mut db);
+
+    let mut migrations = migrations();
+    migrations[1] = toml::from_str(
+        r#"
+        name = "load_users"
+
+        [[actions]]
+        type = "load_data"
+        table = "users"
+        file = "users.csv"
+        "#,
+    )
+    .unwrap();
+
+    let err = reshape.export_sql(migrations).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::Validation(_))
+    ));
+    assert!(matches!(reshape.status().unwrap().state, State::Idle));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn failed_export_is_rolled_back() {
+    let (mut reshape, mut db) = connect();
+    apply_first_migration(&mut reshape, &mut db);
+
+    // A failed migration is left in place when retrying is enabled, which the export
+    // must still clean up
+    reshape.set_retry_failed(true);
+    let mut migrations = migrations();
+    migrations[1] = toml::from_str(
+        r#"
+        name = "add_upper_name_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "upper(nme)"
+
+            [actions.column]
+            name = "upper_name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+    reshape.export_sql(migrations).unwrap_err();
+
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::Idle));
+    assert_eq!(
+        Some("create_users_table".to_string()),
+        status.current_migration
+    );
+
+    // Exporting works again once the migration is fixed
+    reshape.export_sql(self::migrations()).unwrap();
+
+    reshape.remove().unwrap();
+}
@@ -0,0 +1,234 @@
+use reshape::testing::Test;
+
+// PostGIS isn't available everywhere the tests run, so `cube` and `pg_trgm` stand in
+// for it. Like PostGIS, their types, functions and operator classes are installed in
+// `public`, which isn't on the search_path of deployments using a migration schema.
+
+#[test]
+fn alter_column_with_extension_type() {
+    let mut test = Test::new("Alter extension type");
+
+    test.clear(|db| {
+        db.simple_query(
+            "CREATE EXTENSION IF NOT EXISTS cube; CREATE EXTENSION IF NOT EXISTS pg_trgm",
+        )
+        .unwrap();
+    });
+
+    test.first_migration(
+        r#"
+        name = "create_places"
+
+        [[actions]]
+        type = "create_table"
+        name = "places"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "location"
+            type = "cube"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+        [[actions]]
+        type = "add_index"
+        table = "places"
+
+            [actions.index]
+            name = "places_location_idx"
+            columns = ["location"]
+            type = "gist"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "enlarge_locations"
+
+        [[actions]]
+        type = "alter_column"
+        table = "places"
+        column = "location"
+        up = "cube_enlarge(location, 1, 2)"
+        down = "location"
+
+        [[actions]]
+        type = "alter_column"
+        table = "places"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+        "#,
+    );
+
+    test.after_first(|db| {
+        // Indices using an operator class which isn't the default for the type
+        db.simple_query(
+            "
+            CREATE INDEX places_name_trgm_idx ON public.places USING gin (name public.gin_trgm_ops);
+            CREATE INDEX places_name_desc_idx ON public.places (name DESC NULLS LAST) INCLUDE (id);
+            ",
+        )
+        .unwrap();
+        db.simple_query("INSERT INTO places (id, location, name) VALUES (1, '(1, 2)', 'home')")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Functions from the extension can be used in up and down, also for writes
+        // from deployments which only have the migration schema on the search_path
+        let location: String = new_db
+            .query_one("SELECT location::TEXT FROM places WHERE id = 1", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!("(0, 1),(2, 3)", location);
+
+        old_db
+            .simple_query("INSERT INTO places (id, location, name) VALUES (2, '(3, 4)', 'work')")
+            .unwrap();
+        let location: String = new_db
+            .query_one("SELECT location::TEXT FROM places WHERE id = 2", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!("(2, 3),(4, 5)", location);
+
+        // Deployments using public as a fallback still use the new schema
+        let schema: String = new_db
+            .query_one("SELECT current_setting('search_path')", &[])
+            .unwrap()
+            .get(0);
+        new_db
+            .simple_query(&format!("SET search_path TO {}, public", schema))
+            .unwrap();
+        new_db
+            .simple_query("INSERT INTO places (id, location, name) VALUES (3, '(5, 6)', 'GYM')")
+            .unwrap();
+        let name: String = old_db
+            .query_one("SELECT name FROM places WHERE id = 3", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!("gym", name);
+    });
+
+    test.after_completion(|db| {
+        let indices: Vec<String> = db
+            .query(
+                "SELECT indexdef FROM pg_indexes WHERE tablename = 'places' ORDER BY indexname",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(
+            vec![
+                "CREATE INDEX places_location_idx ON public.places USING gist (location)",
+                "CREATE INDEX places_name_desc_idx ON public.places USING btree (name DESC NULLS LAST) INCLUDE (id)",
+                "CREATE INDEX places_name_trgm_idx ON public.places USING gin (name gin_trgm_ops)",
+                "CREATE UNIQUE INDEX places_pkey ON public.places USING btree (id)",
+            ],
+            indices
+        );
+    });
+
+    test.run();
+}
+
+#[test]
+fn add_and_remove_column_with_extension_type() {
+    let mut test = Test::new("Extension type columns");
+
+    test.clear(|db| {
+        db.simple_query("CREATE EXTENSION IF NOT EXISTS cube")
+            .unwrap();
+    });
+
+    test.first_migration(
+        r#"
+        name = "create_places"
+
+        [[actions]]
+        type = "create_table"
+        name = "places"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "x"
+            type = "FLOAT"
+
+            [[actions.columns]]
+            name = "y"
+            type = "FLOAT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "use_cube"
+
+        [[actions]]
+        type = "add_column"
+        table = "places"
+        up = "cube(ARRAY[x, y])"
+
+            [actions.column]
+            name = "location"
+            type = "cube"
+
+        [[actions]]
+        type = "remove_column"
+        table = "places"
+        column = "x"
+        down = "cube_ll_coord(location, 1)"
+
+        [[actions]]
+        type = "remove_column"
+        table = "places"
+        column = "y"
+        down = "cube_ll_coord(location, 2)"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO places (id, x, y) VALUES (1, 1, 2)")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        let location: String = new_db
+            .query_one("SELECT location::TEXT FROM places WHERE id = 1", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!("(1, 2)", location);
+
+        old_db
+            .simple_query("INSERT INTO places (id, x, y) VALUES (2, 3, 4)")
+            .unwrap();
+        let location: String = new_db
+            .query_one("SELECT location::TEXT FROM places WHERE id = 2", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!("(3, 4)", location);
+
+        new_db
+            .simple_query("INSERT INTO places (id, location) VALUES (3, '(5, 6)')")
+            .unwrap();
+        let (x, y): (f64, f64) = old_db
+            .query_one("SELECT x, y FROM places WHERE id = 3", &[])
+            .map(|row| (row.get(0), row.get(1)))
+            .unwrap();
+        assert_eq!((5.0, 6.0), (x, y));
+    });
+
+    test.crash_at_every_statement().run();
+}
@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::{self, Test};
 
 #[test]
 fn invalid_migration() {
@@ -49,3 +48,356 @@ fn invalid_migration() {
     test.expect_failure();
     test.run();
 }
+
+#[test]
+fn failed_migration_error_kind() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let migration: reshape::migrations::Migration = toml::from_str(
+        r#"
+        name = "invalid_custom"
+
+        [[actions]]
+        type = "custom"
+        start = "INVALID SQL"
+        "#,
+    )
+    .unwrap();
+
+    let err = reshape.migrate(vec![migration]).unwrap_err();
+    let err = err
+        .downcast_ref::<reshape::Error>()
+        .expect("expected a reshape::Error");
+
+    assert!(matches!(err, reshape::Error::MigrationFailed(_)));
+    assert_eq!(6, err.exit_code());
+}
+
+#[test]
+fn failed_statement_details() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let migration: reshape::migrations::Migration = toml::from_str(
+        r#"
+        name = "invalid_custom"
+
+        [[actions]]
+        type = "custom"
+        start = """
+            CREATE TABLE users (id INTEGER);
+            SELECT idd FROM users;
+        """
+        "#,
+    )
+    .unwrap();
+
+    let err = reshape.migrate(vec![migration]).unwrap_err();
+    let query_error = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<reshape::QueryError>())
+        .expect("expected a QueryError in the error chain");
+
+    assert_eq!(Some("42703"), query_error.code());
+    assert!(query_error.statement().contains("SELECT idd FROM users"));
+    assert!(format!("{:#}", err).contains("column \"idd\" does not exist (SQLSTATE 42703)"));
+
+    // The error position points at the unknown column
+    let (line, column) = query_error.error_line().unwrap();
+    assert_eq!("            SELECT idd FROM users;", line);
+    assert_eq!("idd", &line[column..column + 3]);
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn crash_while_starting_leaves_no_partial_setup() {
+    let (mut reshape, mut db) = testing::connect();
+
+    // Crash at every statement when starting the second migration. The temporary
+    // column must never exist without the triggers keeping it in sync.
+    for crash_after in 0.. {
+        reshape.remove().unwrap();
+        reshape
+            .migrate(uppercase_migrations().into_iter().take(1))
+            .unwrap();
+        reshape.complete().unwrap();
+
+        reshape.simulate_crash_after(Some(crash_after));
+        let result = reshape.migrate(uppercase_migrations());
+        let crashed = reshape.has_crashed();
+        reshape.simulate_crash_after(None);
+        if !crashed {
+            result.unwrap();
+            reshape.abort().unwrap();
+            break;
+        }
+
+        let has_column = !db
+            .query(
+                "
+                SELECT 1 FROM information_schema.columns
+                WHERE table_schema = 'public' AND table_name = 'users' AND column_name LIKE '%_new_name'
+                ",
+                &[],
+            )
+            .unwrap()
+            .is_empty();
+        let triggers = db
+            .query(
+                "
+                SELECT 1 FROM pg_trigger
+                WHERE tgrelid = 'public.users'::regclass AND tgname LIKE '%_alter_column_%_trigger'
+                ",
+                &[],
+            )
+            .unwrap()
+            .len();
+        assert_eq!(
+            if has_column { 2 } else { 0 },
+            triggers,
+            "partial setup left after crashing after {} statements",
+            crash_after
+        );
+
+        if !matches!(reshape.status().unwrap().state, reshape::State::Idle) {
+            reshape.abort().unwrap();
+        }
+    }
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn retry_failed_migration() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    reshape
+        .migrate(uppercase_migrations().into_iter().take(1))
+        .unwrap();
+    reshape.complete().unwrap();
+    db.simple_query("INSERT INTO public.users (id, name) VALUES (1, 'john')")
+        .unwrap();
+
+    let migrations = |up: &str, email_up: &str| {
+        let mut migrations = uppercase_migrations();
+        migrations[1] = toml::from_str(&format!(
+            r#"
+            name = "uppercase_name"
+
+            [[actions]]
+            type = "add_column"
+            table = "users"
+            up = "{email_up}"
+
+                [actions.column]
+                name = "email"
+                type = "TEXT"
+
+            [[actions]]
+            type = "alter_column"
+            table = "users"
+            column = "name"
+            up = "{up}"
+            down = "LOWER(name)"
+            "#,
+        ))
+        .unwrap();
+        migrations
+    };
+
+    // The failed migration is left in place along with the error
+    reshape.set_retry_failed(true);
+    let err = reshape
+        .migrate(migrations("UPPER(nme)", "'unknown'"))
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::MigrationFailed(_))
+    ));
+    let status = reshape.status().unwrap();
+    assert_eq!("applying", status.state.name());
+    let failed_action = status.failed_action.unwrap();
+    assert_eq!("uppercase_name", failed_action.migration);
+    assert_eq!(1, failed_action.action_index);
+    assert!(failed_action.error.contains("nme"));
+
+    // Retrying after editing requires the flag
+    reshape.set_retry_failed(false);
+    let err = reshape
+        .migrate(migrations("UPPER(name)", "'unknown'"))
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::AbortRequired(_))
+    ));
+
+    // Actions before the failed one can't be edited
+    reshape.set_retry_failed(true);
+    let err = reshape
+        .migrate(migrations("UPPER(name)", "'none'"))
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::Validation(_))
+    ));
+
+    reshape
+        .migrate(migrations("UPPER(name)", "'unknown'"))
+        .unwrap();
+    let status = reshape.status().unwrap();
+    assert_eq!("in_progress", status.state.name());
+    assert!(status.failed_action.is_none());
+
+    reshape.complete().unwrap();
+    let row = db
+        .query_one("SELECT name, email FROM public.users WHERE id = 1", &[])
+        .unwrap();
+    assert_eq!("JOHN", row.get::<_, String>("name"));
+    assert_eq!("unknown", row.get::<_, String>("email"));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn failed_batched_statement_is_blamed_on_its_action() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    reshape
+        .migrate(uppercase_migrations().into_iter().take(1))
+        .unwrap();
+    reshape.complete().unwrap();
+
+    // Adding the column is held back to be batched, but must fail as part of its own
+    // action rather than the one after it
+    let mut migrations = uppercase_migrations();
+    migrations[1] = toml::from_str(
+        r#"
+        name = "add_age"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "age"
+            type = "no_such_type"
+
+        [[actions]]
+        type = "create_table"
+        name = "posts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+
+    reshape.set_retry_failed(true);
+    reshape.migrate(migrations).unwrap_err();
+    let failed_action = reshape.status().unwrap().failed_action.unwrap();
+    assert_eq!("add_age", failed_action.migration);
+    assert_eq!(0, failed_action.action_index);
+    assert!(failed_action.error.contains("no_such_type"));
+
+    reshape.abort().unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn failed_statement_in_batch_of_several_actions_is_blamed_on_its_action() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    reshape
+        .migrate(uppercase_migrations().into_iter().take(1))
+        .unwrap();
+    reshape.complete().unwrap();
+
+    // Both columns are added by a single statement, which fails because of the
+    // second action
+    let mut migrations = uppercase_migrations();
+    migrations[1] = toml::from_str(
+        r#"
+        name = "add_nickname_and_age"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "nickname"
+            type = "TEXT"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "age"
+            type = "no_such_type"
+
+        [[actions]]
+        type = "create_table"
+        name = "posts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+
+    reshape.set_retry_failed(true);
+    reshape.migrate(migrations).unwrap_err();
+    let failed_action = reshape.status().unwrap().failed_action.unwrap();
+    assert_eq!("add_nickname_and_age", failed_action.migration);
+    assert_eq!(1, failed_action.action_index);
+    assert!(failed_action.error.contains("no_such_type"));
+
+    reshape.abort().unwrap();
+    reshape.remove().unwrap();
+}
+
+fn uppercase_migrations() -> Vec<reshape::migrations::Migration> {
+    vec![
+        toml::from_str(
+            r#"
+            name = "create_users_table"
+
+            [[actions]]
+            type = "create_table"
+            name = "users"
+            primary_key = ["id"]
+
+                [[actions.columns]]
+                name = "id"
+                type = "INTEGER"
+
+                [[actions.columns]]
+                name = "name"
+                type = "TEXT"
+            "#,
+        )
+        .unwrap(),
+        toml::from_str(
+            r#"
+            name = "uppercase_name"
+
+            [[actions]]
+            type = "alter_column"
+            table = "users"
+            column = "name"
+            up = "UPPER(name)"
+            down = "LOWER(name)"
+            "#,
+        )
+        .unwrap(),
+    ]
+}
@@ -0,0 +1,153 @@
+use postgres::Client;
+use reshape::testing::Test;
+
+fn create_role(db: &mut Client) {
+    db.simple_query(
+        "
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'reshape_test_app') THEN
+                CREATE ROLE reshape_test_app;
+            END IF;
+        END
+        $$
+        ",
+    )
+    .unwrap();
+}
+
+fn has_privilege(db: &mut Client, object: &str, privilege: &str) -> bool {
+    db.query_one(
+        "SELECT has_table_privilege('reshape_test_app', $1, $2)",
+        &[&object, &privilege],
+    )
+    .unwrap()
+    .get(0)
+}
+
+const CREATE_USERS_TABLE: &str = r#"
+    name = "create_users_table"
+
+    [[actions]]
+    type = "create_table"
+    name = "users"
+    primary_key = ["id"]
+
+        [[actions.columns]]
+        name = "id"
+        type = "INTEGER"
+"#;
+
+#[test]
+fn grant() {
+    let mut test = Test::new("Grant");
+    test.clear(create_role);
+    test.first_migration(CREATE_USERS_TABLE);
+    test.second_migration(
+        r#"
+        name = "grant_select"
+
+        [[actions]]
+        type = "grant"
+        table = "users"
+        role = "reshape_test_app"
+        privileges = ["select", "INSERT"]
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("GRANT UPDATE ON public.users TO reshape_test_app")
+            .unwrap();
+    });
+
+    test.intermediate(|db, _| {
+        // Only the views for the new schema have the privileges until completion
+        let new_view = "migration_grant_select.users";
+        assert!(has_privilege(db, new_view, "SELECT"));
+        assert!(has_privilege(db, new_view, "INSERT"));
+        assert!(!has_privilege(db, "public.users", "SELECT"));
+        assert!(!has_privilege(
+            db,
+            "migration_create_users_table.users",
+            "SELECT"
+        ));
+
+        // Existing privileges on the table are replicated onto the views
+        assert!(has_privilege(db, new_view, "UPDATE"));
+        assert!(!has_privilege(db, new_view, "DELETE"));
+    });
+
+    test.after_completion(|db| {
+        assert!(has_privilege(db, "public.users", "SELECT"));
+        assert!(has_privilege(db, "public.users", "INSERT"));
+        assert!(has_privilege(db, "migration_grant_select.users", "SELECT"));
+    });
+
+    test.after_abort(|db| {
+        assert!(!has_privilege(db, "public.users", "SELECT"));
+        assert!(!has_privilege(db, "public.users", "INSERT"));
+        assert!(has_privilege(db, "public.users", "UPDATE"));
+    });
+
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn revoke() {
+    let mut test = Test::new("Revoke");
+    test.clear(create_role);
+    test.first_migration(CREATE_USERS_TABLE);
+    test.second_migration(
+        r#"
+        name = "revoke_all"
+
+        [[actions]]
+        type = "revoke"
+        table = "users"
+        role = "reshape_test_app"
+        privileges = ["ALL"]
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("GRANT SELECT, INSERT ON public.users TO reshape_test_app")
+            .unwrap();
+    });
+
+    test.intermediate(|db, _| {
+        // The privileges are kept on the table until completion
+        assert!(!has_privilege(db, "migration_revoke_all.users", "SELECT"));
+        assert!(has_privilege(db, "public.users", "SELECT"));
+    });
+
+    test.after_completion(|db| {
+        assert!(!has_privilege(db, "public.users", "SELECT"));
+        assert!(!has_privilege(db, "public.users", "INSERT"));
+    });
+
+    test.after_abort(|db| {
+        assert!(has_privilege(db, "public.users", "SELECT"));
+        assert!(has_privilege(db, "public.users", "INSERT"));
+    });
+
+    test.run();
+}
+
+#[test]
+fn grant_to_unknown_role() {
+    let mut test = Test::new("Grant to unknown role");
+    test.first_migration(CREATE_USERS_TABLE);
+    test.second_migration(
+        r#"
+        name = "grant_to_unknown_role"
+
+        [[actions]]
+        type = "grant"
+        table = "users"
+        role = "reshape_test_missing_role"
+        privileges = ["SELECT"]
+        "#,
+    );
+    test.expect_failure();
+    test.run();
+}
@@ -0,0 +1,88 @@
+use reshape::{
+    migrations::{ImpactKind, LockLevel, Object},
+    prelude::*,
+};
+
+#[test]
+fn migration_impact() {
+    let migration = Migration::new("2_change_users", None)
+        .with_action(AddColumn::new("users", Column::new("name", "TEXT")))
+        .with_action(AddIndex::new(
+            "users",
+            Index::new("users_name_idx", ["name"]),
+        ))
+        .with_action(
+            AlterColumn::new("users", "email")
+                .with_up("LOWER(email)")
+                .with_down("email"),
+        )
+        .with_action(RemoveColumn::new("items", "price").with_down("0"))
+        .with_action(Custom::new().with_start("SELECT 1"));
+
+    let impact = migration.impact();
+    let kinds: Vec<ImpactKind> = impact.iter().map(|action| action.kind).collect();
+    assert_eq!(
+        vec![
+            ImpactKind::Additive,
+            ImpactKind::Additive,
+            ImpactKind::Rewriting,
+            ImpactKind::Destructive,
+            ImpactKind::Additive,
+        ],
+        kinds
+    );
+    assert_eq!(
+        Some(ImpactKind::Destructive),
+        impact.iter().map(|action| action.kind).max()
+    );
+
+    let lock_levels: Vec<LockLevel> = impact.iter().map(|action| action.lock_level).collect();
+    assert_eq!(
+        vec![
+            LockLevel::AccessExclusive,
+            LockLevel::ShareUpdateExclusive,
+            LockLevel::AccessExclusive,
+            LockLevel::AccessExclusive,
+            LockLevel::AccessExclusive,
+        ],
+        lock_levels
+    );
+    assert_eq!(LockLevel::AccessExclusive, migration.max_lock_level());
+
+    assert_eq!("add_index", impact[1].action_type);
+    assert_eq!(
+        vec![
+            Object::Table("users".to_string()),
+            Object::Column("users".to_string(), "name".to_string()),
+            Object::Index("users_name_idx".to_string()),
+        ],
+        impact[1].objects
+    );
+    assert_eq!(
+        vec![
+            Object::Column("items".to_string(), "price".to_string()),
+            Object::Table("items".to_string())
+        ],
+        impact[3].objects
+    );
+    assert!(impact[4].objects.is_empty());
+
+    assert_eq!(vec!["users", "items"], migration.affected_tables());
+}
+
+#[test]
+fn renamed_table_impact() {
+    let migration = Migration::new("2_rename_users", None)
+        .with_action(RenameTable::new("users", "customers"))
+        .with_action(RemoveIndex::new("users_name_idx"));
+
+    let impact = migration.impact();
+    assert!(impact
+        .iter()
+        .all(|action| action.kind == ImpactKind::Additive));
+    assert_eq!(
+        vec![Object::Index("users_name_idx".to_string())],
+        impact[1].objects
+    );
+    assert_eq!(vec!["users", "customers"], migration.affected_tables());
+}
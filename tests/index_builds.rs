@@ -0,0 +1,164 @@
+use postgres::Client;
+use reshape::{migrations::Migration, recording::Event, testing::connect, Reshape, State};
+
+fn create_tables_migration() -> Migration {
+    toml::from_str(
+        r#"
+        name = "create_tables"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+
+        [[actions]]
+        type = "create_table"
+        name = "posts"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "title"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap()
+}
+
+fn set_up() -> (Reshape, Client) {
+    let (mut reshape, mut db) = connect();
+    reshape.remove().unwrap();
+
+    reshape.migrate(vec![create_tables_migration()]).unwrap();
+    reshape.complete().unwrap();
+
+    db.simple_query(
+        "
+        INSERT INTO public.users (id, name, email) SELECT i, 'user' || i, 'user@example.com' FROM generate_series(1, 1000) AS i;
+        INSERT INTO public.posts (id, title) SELECT i, 'post' || i FROM generate_series(1, 1000) AS i;
+        ",
+    )
+    .unwrap();
+
+    (reshape, db)
+}
+
+fn migrations(unique_email: bool) -> Vec<Migration> {
+    let index_migration = toml::from_str(&format!(
+        r#"
+        name = "add_indices"
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "users_name_idx"
+            columns = ["name"]
+
+        [[actions]]
+        type = "add_index"
+        table = "posts"
+
+            [actions.index]
+            name = "posts_title_idx"
+            columns = ["title"]
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "users_email_idx"
+            columns = ["email"]
+            unique = {unique_email}
+
+        # Runs once the indices above have been built
+        [[actions]]
+        type = "custom"
+        start = """
+            DO $$
+            BEGIN
+                IF (SELECT COUNT(*) FROM pg_index WHERE indexrelid::regclass::TEXT LIKE '%_idx' AND indisvalid) != 3 THEN
+                    RAISE EXCEPTION 'indices have not been built';
+                END IF;
+            END
+            $$
+        """
+        "#,
+    ))
+    .unwrap();
+
+    vec![create_tables_migration(), index_migration]
+}
+
+fn indices(db: &mut Client) -> Vec<String> {
+    db.query(
+        "
+        SELECT indexrelid::regclass::TEXT AS name
+        FROM pg_index
+        WHERE indexrelid::regclass::TEXT LIKE '%_idx'
+        ORDER BY 1
+        ",
+        &[],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get("name"))
+    .collect()
+}
+
+#[test]
+fn indices_are_built_in_parallel() {
+    let (mut reshape, mut db) = set_up();
+
+    reshape.set_max_parallel_index_builds(2);
+    reshape.start_recording();
+    reshape.migrate(migrations(false)).unwrap();
+    assert_eq!(
+        vec!["posts_title_idx", "users_email_idx", "users_name_idx"],
+        indices(&mut db)
+    );
+
+    // Statements run by the extra connections are recorded as well
+    let recording = reshape.take_recording().unwrap();
+    let builds = recording
+        .events
+        .iter()
+        .filter(|event| {
+            matches!(event, Event::Statement { sql, .. } if sql.contains("INDEX CONCURRENTLY"))
+        })
+        .count();
+    assert_eq!(3, builds);
+
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn failed_parallel_index_build_is_aborted() {
+    let (mut reshape, mut db) = set_up();
+
+    // All users have the same email, so the unique index can't be built
+    reshape.set_max_parallel_index_builds(2);
+    assert!(reshape.migrate(migrations(true)).is_err());
+    assert!(matches!(reshape.status().unwrap().state, State::Idle));
+    assert!(indices(&mut db).is_empty());
+
+    reshape.remove().unwrap();
+}
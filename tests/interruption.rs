@@ -0,0 +1,212 @@
+use postgres::Client;
+use reshape::{
+    migrations::{Custom, Migration},
+    testing, MigrationProgress, Reshape, State,
+};
+
+// Two migrations with two custom actions each. Every action logs when it's started,
+// completed and aborted, and can be made to fail by adding it to `interruptions`.
+// The log entry and the failure are part of the same statement, so a failed action
+// doesn't leave an entry behind.
+const ACTIONS: usize = 4;
+
+#[test]
+fn complete_resumes_after_interruption() {
+    for interrupted in 0..ACTIONS {
+        let (mut reshape, mut db) = set_up();
+
+        reshape.migrate(migrations()).unwrap();
+        interrupt(&mut db, "complete", interrupted);
+        assert!(reshape.complete().is_err());
+
+        let state = reshape.status().unwrap().state;
+        assert_eq!(
+            Some(MigrationProgress::before(interrupted / 2, interrupted % 2)),
+            state.progress()
+        );
+
+        resume(&mut db);
+        reshape.complete().unwrap();
+        assert_eq!(vec![0, 1, 2, 3], logged(&mut db, "complete"));
+
+        reshape.remove().unwrap();
+    }
+}
+
+#[test]
+fn abort_resumes_after_interruption() {
+    for interrupted in 0..ACTIONS {
+        let (mut reshape, mut db) = set_up();
+
+        reshape.migrate(migrations()).unwrap();
+        interrupt(&mut db, "abort", interrupted);
+        assert!(reshape.abort().is_err());
+
+        let state = reshape.status().unwrap().state;
+        assert_eq!(
+            Some(MigrationProgress::after(
+                &migrations(),
+                interrupted / 2,
+                interrupted % 2
+            )),
+            state.progress()
+        );
+
+        resume(&mut db);
+        reshape.abort().unwrap();
+        assert_eq!(vec![3, 2, 1, 0], logged(&mut db, "abort"));
+
+        reshape.remove().unwrap();
+    }
+}
+
+#[test]
+fn failed_start_aborts_applied_actions() {
+    for interrupted in 0..ACTIONS {
+        let (mut reshape, mut db) = set_up();
+
+        // The failed action is aborted too as it might have been partially applied
+        interrupt(&mut db, "start", interrupted);
+        assert!(reshape.migrate(migrations()).is_err());
+
+        let expected: Vec<i32> = (0..=interrupted as i32).rev().collect();
+        assert_eq!(expected, logged(&mut db, "abort"));
+        assert!(matches!(reshape.status().unwrap().state, State::Idle));
+
+        reshape.remove().unwrap();
+    }
+}
+
+#[test]
+fn abort_resumes_after_interrupted_automatic_abort() {
+    let (mut reshape, mut db) = set_up();
+
+    interrupt(&mut db, "start", 2);
+    interrupt(&mut db, "abort", 1);
+    assert!(reshape.migrate(migrations()).is_err());
+    assert_eq!(vec![2], logged(&mut db, "abort"));
+
+    resume(&mut db);
+    reshape.abort().unwrap();
+    assert_eq!(vec![2, 1, 0], logged(&mut db, "abort"));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn progress_stored_by_earlier_versions() {
+    let (mut reshape, mut db) = set_up();
+
+    reshape.migrate(migrations()).unwrap();
+    interrupt(&mut db, "complete", 3);
+    assert!(reshape.complete().is_err());
+
+    // Earlier versions pointed one migration past the last completed action
+    db.simple_query(
+        "
+        UPDATE reshape.data
+        SET value = value - 'progress' || '{\"current_migration_index\": 2, \"current_action_index\": 1}'
+        WHERE key = 'state'
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        Some(MigrationProgress::before(1, 1)),
+        reshape.status().unwrap().state.progress()
+    );
+
+    resume(&mut db);
+    reshape.complete().unwrap();
+    assert_eq!(vec![0, 1, 2, 3], logged(&mut db, "complete"));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn simulated_crash_stops_all_statements() {
+    let (mut reshape, mut db) = set_up();
+
+    reshape.migrate(migrations()).unwrap();
+    reshape.simulate_crash_after(Some(1));
+    assert!(reshape.complete().is_err());
+    assert!(reshape.has_crashed());
+
+    // Nothing runs after the crash, not even the first action
+    reshape.simulate_crash_after(None);
+    assert!(logged(&mut db, "complete").is_empty());
+    assert_eq!("in_progress", reshape.status().unwrap().state.name());
+
+    reshape.complete().unwrap();
+    assert_eq!(vec![0, 1, 2, 3], logged(&mut db, "complete"));
+
+    reshape.remove().unwrap();
+}
+
+fn set_up() -> (Reshape, Client) {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    db.simple_query(
+        "
+        CREATE TABLE log (phase TEXT, action INTEGER, id INTEGER GENERATED ALWAYS AS IDENTITY);
+        CREATE TABLE interruptions (phase TEXT, action INTEGER);
+        CREATE OR REPLACE FUNCTION interrupt_if_requested(phase TEXT, action INTEGER) RETURNS VOID AS $$
+        BEGIN
+            IF EXISTS (SELECT FROM interruptions i WHERE i.phase = $1 AND i.action = $2) THEN
+                RAISE EXCEPTION 'interrupted while running % for action %', $1, $2;
+            END IF;
+        END
+        $$ LANGUAGE plpgsql;
+        ",
+    )
+    .unwrap();
+
+    (reshape, db)
+}
+
+fn migrations() -> Vec<Migration> {
+    let logged = |phase: &str, action: usize| {
+        format!(
+            "INSERT INTO log (phase, action) VALUES ('{phase}', {action}); SELECT interrupt_if_requested('{phase}', {action})"
+        )
+    };
+
+    (0..ACTIONS / 2)
+        .map(|migration| {
+            Migration::new(format!("migration_{}", migration), None).with_actions((0..2).map(
+                |index| {
+                    let action = migration * 2 + index;
+                    Box::new(
+                        Custom::new()
+                            .with_start(logged("start", action))
+                            .with_complete(logged("complete", action))
+                            .with_abort(logged("abort", action)),
+                    ) as _
+                },
+            ))
+        })
+        .collect()
+}
+
+fn interrupt(db: &mut Client, phase: &str, action: usize) {
+    db.execute(
+        "INSERT INTO interruptions (phase, action) VALUES ($1, $2)",
+        &[&phase, &(action as i32)],
+    )
+    .unwrap();
+}
+
+fn resume(db: &mut Client) {
+    db.simple_query("DELETE FROM interruptions").unwrap();
+}
+
+fn logged(db: &mut Client, phase: &str) -> Vec<i32> {
+    db.query(
+        "SELECT action FROM log WHERE phase = $1 ORDER BY id",
+        &[&phase],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
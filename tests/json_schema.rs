@@ -0,0 +1,30 @@
+use reshape::migrations::{migration_file_schema, Action};
+use serde_json::json;
+
+#[test]
+fn json_schema_covers_all_actions() {
+    let schema = migration_file_schema();
+    assert_eq!(json!("object"), schema["type"]);
+    assert!(schema["definitions"]["Column"].is_object());
+
+    let actions = schema["properties"]["actions"]["items"]["oneOf"]
+        .as_array()
+        .unwrap();
+    assert_eq!(35, actions.len());
+
+    for action in actions {
+        let action_type = action["properties"]["type"]["const"].as_str().unwrap();
+        assert_eq!(json!("type"), action["required"][0]);
+
+        // Every type in the schema must be known when decoding actions. Decoding
+        // fails because of missing fields, but not because of an unknown type.
+        if let Err(err) = serde_json::from_value::<Box<dyn Action>>(json!({ "type": action_type }))
+        {
+            assert!(
+                !err.to_string().contains("unknown variant"),
+                "unknown action type {}",
+                action_type
+            );
+        }
+    }
+}
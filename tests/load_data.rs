@@ -0,0 +1,153 @@
+use std::{fs, path::PathBuf};
+
+use postgres::Client;
+use reshape::testing::Test;
+
+fn write_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("reshape-{}-{}", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+fn users(db: &mut Client) -> Vec<(i32, String, Option<String>)> {
+    db.query("SELECT id, name, bio FROM users ORDER BY id", &[])
+        .unwrap()
+        .iter()
+        .map(|row| (row.get("id"), row.get("name"), row.get("bio")))
+        .collect()
+}
+
+fn user(id: i32, name: &str, bio: Option<&str>) -> (i32, String, Option<String>) {
+    (id, name.to_string(), bio.map(str::to_string))
+}
+
+fn user_count(db: &mut Client) -> i64 {
+    db.query_one("SELECT COUNT(*) FROM users", &[])
+        .unwrap()
+        .get(0)
+}
+
+fn tags(db: &mut Client) -> Vec<(String, Option<String>)> {
+    db.query("SELECT name, color FROM tags ORDER BY name", &[])
+        .unwrap()
+        .iter()
+        .map(|row| (row.get("name"), row.get("color")))
+        .collect()
+}
+
+fn load_progress(db: &mut Client) -> i64 {
+    db.query_one("SELECT COUNT(*) FROM reshape.data WHERE key = 'seed'", &[])
+        .unwrap()
+        .get(0)
+}
+
+#[test]
+fn load_data() {
+    let mut test = Test::new("Load data");
+
+    let users_file = write_file(
+        "users.csv",
+        "id,name,bio\n1,Alice,\n2,Bob,\"Likes \"\"quotes\"\",\nand newlines\"\n3,Carol,\"\"\n4,Dave,Hi\n5,Eve,\n",
+    );
+    let tags_file = write_file("tags.tsv", "red\t#f00\nblue\t\\N\n");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "full_name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "bio"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(&format!(
+        r#"
+        name = "load_data"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "full_name"
+
+            [actions.changes]
+            name = "name"
+
+        [[actions]]
+        type = "load_data"
+        table = "users"
+        file = "{}"
+        batch_size = 2
+
+        [[actions]]
+        type = "create_table"
+        name = "tags"
+        primary_key = ["name"]
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "color"
+            type = "TEXT"
+
+        [[actions]]
+        type = "load_data"
+        table = "tags"
+        file = "{}"
+        header = false
+        columns = ["name", "color"]
+        "#,
+        users_file.display(),
+        tags_file.display(),
+    ));
+
+    test.intermediate(|_old_db, new_db| {
+        // The header uses the name from earlier in the migration, mapped to the real column
+        assert_eq!(
+            vec![
+                user(1, "Alice", None),
+                user(2, "Bob", Some("Likes \"quotes\",\nand newlines")),
+                user(3, "Carol", Some("")),
+                user(4, "Dave", Some("Hi")),
+                user(5, "Eve", None),
+            ],
+            users(new_db)
+        );
+        assert_eq!(
+            vec![
+                ("blue".to_string(), None),
+                ("red".to_string(), Some("#f00".to_string())),
+            ],
+            tags(new_db)
+        );
+        assert_eq!(0, load_progress(new_db));
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(5, users(db).len());
+        assert_eq!(0, load_progress(db));
+    });
+
+    test.after_abort(|db| {
+        // Loaded rows are kept, but never loaded twice
+        assert_eq!(5, user_count(db));
+        assert_eq!(0, load_progress(db));
+    });
+
+    test.crash_at_every_statement().run();
+}
@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+use postgres::{Client, NoTls};
+use reshape::{
+    migrations::Migration, testing::connection_string, CancellationToken, Reshape, State,
+};
+
+fn migration() -> Migration {
+    toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap()
+}
+
+// Simulate another instance of Reshape holding the lock, returning its pid
+fn hold_lock() -> (Client, i32) {
+    let mut other = Client::connect(
+        &format!("{}?application_name=lock_holder", connection_string()),
+        NoTls,
+    )
+    .unwrap();
+    let pid = other
+        .query_one("SELECT pg_backend_pid()", &[])
+        .unwrap()
+        .get(0);
+    other
+        .simple_query("SELECT pg_advisory_lock(4036779288569897133)")
+        .unwrap();
+    (other, pid)
+}
+
+#[test]
+fn lock_holder_is_reported() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let (mut other, pid) = hold_lock();
+
+    let err = reshape.migrate(vec![migration()]).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::ConflictingState(_))
+    ));
+    let message = format!("{:#}", err);
+    assert!(
+        message.contains(&format!("held by process {}", pid)),
+        "unexpected error: {}",
+        message
+    );
+    assert!(message.contains(r#"application "lock_holder""#));
+    assert!(message.contains(&format!("pg_terminate_backend({})", pid)));
+
+    other
+        .simple_query("SELECT pg_advisory_unlock(4036779288569897133)")
+        .unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn wait_for_lock() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+    reshape.set_wait_for_lock(true);
+
+    let (mut other, _) = hold_lock();
+    let started_at = Instant::now();
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_secs(2));
+        other
+            .simple_query("SELECT pg_advisory_unlock(4036779288569897133)")
+            .unwrap();
+    });
+
+    reshape.migrate(vec![migration()]).unwrap();
+    assert!(started_at.elapsed() >= Duration::from_secs(2));
+    assert!(matches!(
+        reshape.status().unwrap().state,
+        State::InProgress { .. }
+    ));
+
+    release.join().unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn waiting_for_lock_stops_at_deadline() {
+    let mut reshape = Reshape::new(&connection_string()).unwrap();
+    reshape.remove().unwrap();
+    reshape.set_wait_for_lock(true);
+
+    let (mut other, _) = hold_lock();
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let err = reshape
+        .migrate_with(vec![migration()], &CancellationToken::new(), Some(deadline))
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::Cancelled(_))
+    ));
+    assert!(matches!(reshape.status().unwrap().state, State::Idle));
+
+    other
+        .simple_query("SELECT pg_advisory_unlock(4036779288569897133)")
+        .unwrap();
+    reshape.remove().unwrap();
+}
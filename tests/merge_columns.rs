@@ -0,0 +1,153 @@
+use postgres::Client;
+use reshape::testing::Test;
+
+fn columns(db: &mut Client) -> Vec<String> {
+    db.query(
+        "
+        SELECT column_name::TEXT
+        FROM information_schema.columns
+        WHERE table_schema = 'public' AND table_name = 'users'
+        ORDER BY ordinal_position
+        ",
+        &[],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
+
+#[test]
+fn merge_columns() {
+    let mut test = Test::new("Merge columns");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "first_name"
+            type = "TEXT"
+            nullable = false
+
+            [[actions.columns]]
+            name = "last_name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "merge_names"
+
+        [[actions]]
+        type = "merge_columns"
+        table = "users"
+        up = "concat_ws(' ', first_name, last_name)"
+
+            [actions.column]
+            name = "name"
+            type = "TEXT"
+            nullable = false
+
+            [[actions.columns]]
+            name = "first_name"
+            down = "split_part(name, ' ', 1)"
+
+            [[actions.columns]]
+            name = "last_name"
+            down = "NULLIF(split_part(name, ' ', 2), '')"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "INSERT INTO users (id, first_name, last_name) VALUES (1, 'John', 'Doe'), (2, 'Jane', NULL)",
+        )
+        .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Existing rows have been backfilled
+        let names: Vec<String> = new_db
+            .query("SELECT name FROM users ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(vec!["John Doe", "Jane"], names);
+
+        // Writes through the old schema fill in the new column
+        old_db
+            .simple_query(
+                "INSERT INTO users (id, first_name, last_name) VALUES (3, 'Bob', 'Smith')",
+            )
+            .unwrap();
+        let name: String = new_db
+            .query_one("SELECT name FROM users WHERE id = 3", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!("Bob Smith", name);
+
+        // Writes through the new schema fill in the originals
+        new_db
+            .simple_query("INSERT INTO users (id, name) VALUES (4, 'Alice Jones')")
+            .unwrap();
+        new_db
+            .simple_query("UPDATE users SET name = 'Jane Doe' WHERE id = 2")
+            .unwrap();
+        let users: Vec<(String, Option<String>)> = old_db
+            .query("SELECT first_name, last_name FROM users ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        assert_eq!(
+            vec![
+                ("John".to_string(), Some("Doe".to_string())),
+                ("Jane".to_string(), Some("Doe".to_string())),
+                ("Bob".to_string(), Some("Smith".to_string())),
+                ("Alice".to_string(), Some("Jones".to_string())),
+            ],
+            users
+        );
+
+        // The originals aren't part of the new schema
+        assert!(new_db.simple_query("SELECT first_name FROM users").is_err());
+        assert!(new_db.simple_query("SELECT last_name FROM users").is_err());
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(vec!["id", "name"], columns(db));
+
+        let nullable: String = db
+            .query_one(
+                "
+                SELECT is_nullable::TEXT
+                FROM information_schema.columns
+                WHERE table_schema = 'public'
+                    AND table_name = 'users'
+                    AND column_name = 'name'
+                ",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!("NO", nullable);
+    });
+
+    test.after_abort(|db| {
+        assert_eq!(vec!["id", "first_name", "last_name"], columns(db));
+    });
+
+    test.crash_at_every_statement().run();
+}
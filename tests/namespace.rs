@@ -0,0 +1,210 @@
+use postgres::Client;
+use reshape::{migrations::Migration, testing, Namespace, Reshape};
+
+fn connect(namespace: &str) -> (Reshape, Client) {
+    let (mut reshape, db) = testing::connect();
+    reshape.set_namespace(Namespace::new(namespace).unwrap());
+    (reshape, db)
+}
+
+fn migrations(table: &str) -> Vec<Migration> {
+    let first_migration: Migration = toml::from_str(&format!(
+        r#"
+        name = "create_{table}_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "{table}"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#
+    ))
+    .unwrap();
+
+    let second_migration: Migration = toml::from_str(&format!(
+        r#"
+        name = "uppercase_{table}_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "{table}"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+        "#
+    ))
+    .unwrap();
+
+    vec![first_migration, second_migration]
+}
+
+fn schema_exists(db: &mut Client, name: &str) -> bool {
+    db.query_one(
+        "SELECT EXISTS (SELECT FROM information_schema.schemata WHERE schema_name = $1)",
+        &[&name],
+    )
+    .unwrap()
+    .get(0)
+}
+
+#[test]
+fn namespaces_have_separate_state() {
+    let (mut users, mut db) = connect("users");
+    let (mut products, _) = connect("products");
+    users.remove().unwrap();
+    products.remove().unwrap();
+
+    let users_migrations = migrations("users");
+    let products_migrations = migrations("products");
+
+    users.migrate(users_migrations[..1].to_vec()).unwrap();
+    users.complete().unwrap();
+    products.migrate(products_migrations[..1].to_vec()).unwrap();
+    products.complete().unwrap();
+    db.simple_query(
+        "
+        INSERT INTO public.users (id, name) VALUES (1, 'john');
+        INSERT INTO public.products (id, name) VALUES (1, 'chair');
+        ",
+    )
+    .unwrap();
+
+    // A migration in progress in one namespace doesn't stop the other from migrating
+    users.migrate(users_migrations.clone()).unwrap();
+    products.migrate(products_migrations.clone()).unwrap();
+
+    assert!(schema_exists(&mut db, "reshape_users"));
+    assert!(schema_exists(&mut db, "reshape_products"));
+    assert!(schema_exists(
+        &mut db,
+        "users_migration_uppercase_users_name"
+    ));
+    assert!(schema_exists(
+        &mut db,
+        "products_migration_uppercase_products_name"
+    ));
+
+    // Temporary objects are prefixed with the namespace
+    let temporary_columns: Vec<String> = db
+        .query(
+            "
+            SELECT column_name::TEXT
+            FROM information_schema.columns
+            WHERE table_schema = 'public' AND column_name LIKE '\\_\\_%'
+            ORDER BY column_name
+            ",
+            &[],
+        )
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    assert_eq!(
+        vec![
+            "__products_reshape_0000_0000_ee0e_new_name",
+            "__users_reshape_0000_0000_1b7a_new_name"
+        ],
+        temporary_columns
+    );
+
+    // Aborting one namespace leaves the other one in progress
+    products.abort().unwrap();
+    users.complete().unwrap();
+
+    let name: String = db
+        .query_one(
+            "SELECT name FROM users_migration_uppercase_users_name.users WHERE id = 1",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!("JOHN", name);
+    let name: String = db
+        .query_one(
+            "SELECT name FROM products_migration_create_products_table.products WHERE id = 1",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!("chair", name);
+
+    assert_eq!(
+        Some("create_products_table".to_string()),
+        products.status().unwrap().current_migration
+    );
+    assert_eq!(
+        Some("uppercase_users_name".to_string()),
+        users.status().unwrap().current_migration
+    );
+
+    users.remove().unwrap();
+    products.remove().unwrap();
+}
+
+#[test]
+fn namespaces_use_separate_locks() {
+    let (mut reshape, mut db) = connect("locks");
+    reshape.remove().unwrap();
+
+    // Hold the lock for the default namespace from another connection
+    let (_, mut other) = connect("locks");
+    other
+        .simple_query("SELECT pg_advisory_lock(4036779288569897133)")
+        .unwrap();
+
+    reshape.migrate(migrations("users")).unwrap();
+    reshape.complete().unwrap();
+    assert!(schema_exists(
+        &mut db,
+        "locks_migration_uppercase_users_name"
+    ));
+
+    other
+        .simple_query("SELECT pg_advisory_unlock(4036779288569897133)")
+        .unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn invalid_namespaces_are_rejected() {
+    for name in [
+        "",
+        "Users",
+        "my_app",
+        "my-app",
+        "reshape",
+        "a".repeat(17).as_str(),
+    ] {
+        assert!(
+            Namespace::new(name).is_err(),
+            "{:?} should be rejected",
+            name
+        );
+    }
+
+    assert!(Namespace::new("app2").is_ok());
+}
+
+#[test]
+fn search_path_uses_namespaced_schema() {
+    let namespace = Namespace::new("billing").unwrap();
+    assert_eq!(
+        "billing_migration_create_users,public",
+        namespace.search_path_for_migration("create_users", &["public"])
+    );
+    assert_eq!(
+        "SET search_path TO billing_migration_create_users",
+        namespace.schema_query_for_migration("create_users")
+    );
+    assert_eq!(
+        "SET LOCAL search_path TO billing_migration_create_users,public",
+        namespace.local_schema_query_with_fallback("create_users", &["public"])
+    );
+}
@@ -0,0 +1,138 @@
+use postgres::Client;
+use reshape::prelude::*;
+use reshape::testing;
+
+fn relation_owner(db: &mut Client, relation: &str) -> String {
+    db.query_one(
+        "SELECT pg_get_userbyid(relowner)::TEXT FROM pg_class WHERE oid = to_regclass($1)",
+        &[&relation],
+    )
+    .unwrap()
+    .get(0)
+}
+
+fn schema_owner(db: &mut Client, schema: &str) -> String {
+    db.query_one(
+        "SELECT pg_get_userbyid(nspowner)::TEXT FROM pg_namespace WHERE nspname = $1",
+        &[&schema],
+    )
+    .unwrap()
+    .get(0)
+}
+
+fn function_owners(db: &mut Client, pattern: &str) -> Vec<String> {
+    db.query(
+        "SELECT pg_get_userbyid(proowner)::TEXT FROM pg_proc WHERE proname LIKE $1",
+        &[&pattern],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
+
+#[test]
+fn owner_is_applied_to_managed_objects() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    db.simple_query(
+        "
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'reshape_test_owner') THEN
+                CREATE ROLE reshape_test_owner;
+            END IF;
+        END
+        $$
+        ",
+    )
+    .unwrap();
+
+    let create_tables = Migration::new("1_create_tables", None)
+        .with_action(
+            CreateTable::new("users", ["id"])
+                .with_column(Column::new("id", "INTEGER").with_generated("ALWAYS AS IDENTITY"))
+                .with_column(Column::new("name", "TEXT")),
+        )
+        .with_action(CreateTable::new("sessions", ["id"]).with_column(Column::new("id", "INTEGER")))
+        .with_action(CreateEnum::new("mood", ["happy", "sad"]));
+    let alter_name = Migration::new("2_alter_name", None).with_action(
+        AlterColumn::new("users", "name")
+            .with_up("UPPER(name)")
+            .with_down("LOWER(name)"),
+    );
+    let remove_sessions = Migration::new("3_remove_sessions", None)
+        .with_action(RemoveTable::new("sessions").with_archive(true));
+
+    // The owner must exist
+    reshape.set_owner("reshape_test_missing_owner");
+    let err = reshape.migrate(vec![create_tables.clone()]).unwrap_err();
+    assert_eq!(Some("validation"), err.downcast_ref().map(Error::kind));
+
+    reshape.set_owner("reshape_test_owner");
+    reshape.migrate(vec![create_tables.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    assert_eq!(
+        "reshape_test_owner",
+        relation_owner(&mut db, "public.users")
+    );
+    assert_eq!(
+        "reshape_test_owner",
+        relation_owner(&mut db, "public.users_id_seq")
+    );
+    assert_eq!("reshape_test_owner", schema_owner(&mut db, "reshape"));
+    assert_eq!(
+        "reshape_test_owner",
+        schema_owner(&mut db, "migration_1_create_tables")
+    );
+    assert_eq!(
+        "reshape_test_owner",
+        relation_owner(&mut db, "migration_1_create_tables.users")
+    );
+    let type_owner: String = db
+        .query_one(
+            "SELECT pg_get_userbyid(typowner)::TEXT FROM pg_type WHERE typname = 'mood'",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!("reshape_test_owner", type_owner);
+
+    // Temporary objects are handed over while the migration is in progress
+    reshape
+        .migrate(vec![create_tables.clone(), alter_name.clone()])
+        .unwrap();
+    let trigger_functions = function_owners(&mut db, "__reshape%");
+    assert!(!trigger_functions.is_empty());
+    assert!(trigger_functions
+        .iter()
+        .all(|owner| owner == "reshape_test_owner"));
+    assert_eq!(
+        vec!["reshape_test_owner".to_string()],
+        function_owners(&mut db, "is_new_schema")
+    );
+    reshape.complete().unwrap();
+
+    // Archived tables are created when completing
+    reshape
+        .migrate(vec![create_tables, alter_name, remove_sessions])
+        .unwrap();
+    reshape.complete().unwrap();
+    let archived_table: String = db
+        .query_one(
+            "SELECT format('%I.%I', schemaname, tablename) FROM pg_tables WHERE schemaname = 'reshape_archive'",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(
+        "reshape_test_owner",
+        relation_owner(&mut db, &archived_table)
+    );
+
+    reshape.remove().unwrap();
+    db.simple_query("REASSIGN OWNED BY reshape_test_owner TO postgres")
+        .unwrap();
+}
@@ -0,0 +1,91 @@
+use reshape::{migrations::LockLevel, prelude::*, testing, Policy};
+
+#[test]
+fn policy_restricts_new_migrations() {
+    let mut reshape = Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let create_users = Migration::new("1_create_users", None)
+        .with_action(CreateTable::new("users", ["id"]).with_column(Column::new("id", "INTEGER")));
+    let custom = Migration::new("2_custom", None).with_action(Custom::new().with_start("SELECT 1"));
+    let remove_users =
+        Migration::new("2_remove_users", None).with_action(RemoveTable::new("users"));
+
+    // Migrations which have already been applied aren't checked against the policy
+    reshape.migrate(vec![create_users.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    reshape.set_policy(
+        Policy::new()
+            .with_denied_actions(vec!["custom".to_string()])
+            .with_require_allow_destructive(true),
+    );
+
+    let err = reshape
+        .migrate(vec![create_users.clone(), custom])
+        .unwrap_err();
+    assert_eq!(Some("validation"), err.downcast_ref().map(Error::kind));
+    assert!(format!("{:#}", err).contains(r#"uses action "custom""#));
+
+    let err = reshape
+        .migrate(vec![create_users.clone(), remove_users.clone()])
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("drops data"));
+
+    reshape.set_policy(
+        Policy::new()
+            .with_require_allow_destructive(true)
+            .with_allow_destructive(true),
+    );
+    reshape
+        .migrate(vec![create_users.clone(), remove_users.clone()])
+        .unwrap();
+    reshape.complete().unwrap();
+
+    // Misspelled action types are rejected instead of being ignored
+    reshape.set_policy(Policy::new().with_denied_actions(vec!["remove_tabel".to_string()]));
+    let err = reshape
+        .migrate(vec![
+            create_users,
+            remove_users,
+            Migration::new("3_empty", None),
+        ])
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("unknown action type"));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn policy_limits_lock_level() {
+    let mut reshape = Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let create_users = Migration::new("1_create_users", None).with_action(
+        CreateTable::new("users", ["id"])
+            .with_column(Column::new("id", "INTEGER"))
+            .with_column(Column::new("name", "TEXT")),
+    );
+    let add_index = Migration::new("2_add_index", None).with_action(AddIndex::new(
+        "users",
+        Index::new("users_name_idx", ["name"]),
+    ));
+    let add_column = Migration::new("3_add_column", None)
+        .with_action(AddColumn::new("users", Column::new("email", "TEXT")));
+
+    reshape.set_policy(Policy::new().with_max_lock_level(LockLevel::ShareUpdateExclusive));
+
+    // Indices are built concurrently and don't block writes
+    reshape
+        .migrate(vec![create_users.clone(), add_index.clone()])
+        .unwrap();
+    reshape.complete().unwrap();
+
+    let err = reshape
+        .migrate(vec![create_users, add_index, add_column])
+        .unwrap_err();
+    assert_eq!(Some("validation"), err.downcast_ref().map(Error::kind));
+    assert!(format!("{:#}", err).contains("ACCESS EXCLUSIVE"));
+
+    reshape.remove().unwrap();
+}
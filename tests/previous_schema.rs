@@ -0,0 +1,155 @@
+use postgres::Client;
+use reshape::migrations::Migration;
+use reshape::testing;
+
+#[test]
+fn previous_schema_views() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+    reshape.set_previous_schema_views(true);
+
+    // The table exists before Reshape is adopted
+    db.simple_query("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)")
+        .unwrap();
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+
+    // Existing deployments can use views for the initial schema, which don't
+    // include the temporary column
+    assert_eq!(
+        vec!["id", "name"],
+        view_columns(&mut db, "reshape_initial", "users")
+    );
+    db.simple_query(
+        "SET search_path TO reshape_initial; INSERT INTO users (id, name) VALUES (1, 'John'); SET search_path TO public",
+    )
+    .unwrap();
+
+    reshape.complete().unwrap();
+    assert!(view_columns(&mut db, "reshape_initial", "users").is_empty());
+
+    // For later migrations, the previous migration's schema is recreated
+    reshape.migrate(migrations()).unwrap();
+    assert_eq!(
+        vec!["id", "name", "email"],
+        view_columns(&mut db, "migration_add_email_column", "users")
+    );
+    assert_eq!(
+        vec!["id", "name", "email"],
+        view_columns(&mut db, "migration_add_age_column", "users")
+            .into_iter()
+            .take(3)
+            .collect::<Vec<String>>()
+    );
+
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn previous_schema_views_are_updated_in_place() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+    reshape.set_previous_schema_views(true);
+
+    db.simple_query("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)")
+        .unwrap();
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+
+    // Objects and grants made outside of Reshape on the schema deployments are using
+    db.simple_query(
+        "
+        DO $$
+        BEGIN
+            IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = 'reshape_test_reader') THEN
+                CREATE ROLE reshape_test_reader;
+            END IF;
+        END
+        $$;
+        CREATE VIEW public.user_emails AS SELECT email FROM migration_add_email_column.users;
+        GRANT USAGE ON SCHEMA migration_add_email_column TO reshape_test_reader;
+        GRANT SELECT ON migration_add_email_column.users TO reshape_test_reader;
+        CREATE VIEW migration_add_email_column.removed AS SELECT 1 AS id;
+        ",
+    )
+    .unwrap();
+
+    reshape.migrate(migrations()).unwrap();
+
+    assert_eq!(
+        vec!["id", "name", "email"],
+        view_columns(&mut db, "migration_add_email_column", "users")
+    );
+    assert!(view_columns(&mut db, "migration_add_email_column", "removed").is_empty());
+
+    let row = db
+        .query_one(
+            "
+            SELECT
+                to_regclass('public.user_emails') IS NOT NULL AS dependent_view_exists,
+                has_schema_privilege('reshape_test_reader', 'migration_add_email_column', 'USAGE') AS schema_granted,
+                has_table_privilege('reshape_test_reader', 'migration_add_email_column.users', 'SELECT') AS view_granted
+            ",
+            &[],
+        )
+        .unwrap();
+    assert!(row.get::<_, bool>("dependent_view_exists"));
+    assert!(row.get::<_, bool>("schema_granted"));
+    assert!(row.get::<_, bool>("view_granted"));
+
+    db.simple_query("DROP VIEW public.user_emails").unwrap();
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
+
+fn view_columns(db: &mut Client, schema: &str, view: &str) -> Vec<String> {
+    db.query(
+        "
+        SELECT column_name::TEXT
+        FROM information_schema.columns
+        WHERE table_schema = $1 AND table_name = $2
+        ORDER BY ordinal_position
+        ",
+        &[&schema, &view],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
+
+fn migrations() -> Vec<Migration> {
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "add_email_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "'unknown'"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+            nullable = false
+        "#,
+    )
+    .unwrap();
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "add_age_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "age"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+
+    vec![first_migration, second_migration]
+}
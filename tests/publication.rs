@@ -0,0 +1,267 @@
+use reshape::testing::Test;
+
+fn create_publication(db: &mut postgres::Client) {
+    db.simple_query(
+        "
+        DROP PUBLICATION IF EXISTS app_publication;
+        CREATE PUBLICATION app_publication;
+        ",
+    )
+    .unwrap();
+}
+
+fn published_columns(db: &mut postgres::Client) -> Option<Vec<String>> {
+    db.query(
+        "
+        SELECT attnames::TEXT[]
+        FROM pg_publication_tables
+        WHERE pubname = 'app_publication' AND tablename = 'users'
+        ",
+        &[],
+    )
+    .unwrap()
+    .first()
+    .map(|row| row.get(0))
+}
+
+#[test]
+fn add_table_to_publication() {
+    let mut test = Test::new("Add table to publication");
+    test.clear(create_publication);
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "publish_users_table"
+
+        [[actions]]
+        type = "add_table_to_publication"
+        publication = "app_publication"
+        table = "users"
+        columns = ["id", "name"]
+        "#,
+    );
+
+    test.intermediate(|db, _| {
+        // The table is only published once the migration is completed
+        assert_eq!(None, published_columns(db));
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(
+            Some(vec!["id".to_string(), "name".to_string()]),
+            published_columns(db)
+        );
+    });
+
+    test.after_abort(|db| {
+        assert_eq!(None, published_columns(db));
+    });
+
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn remove_table_from_publication() {
+    let mut test = Test::new("Remove table from publication");
+    test.clear(create_publication);
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+        [[actions]]
+        type = "add_table_to_publication"
+        publication = "app_publication"
+        table = "users"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "unpublish_users_table"
+
+        [[actions]]
+        type = "remove_table_from_publication"
+        publication = "app_publication"
+        table = "users"
+        "#,
+    );
+
+    test.intermediate(|db, _| {
+        assert_eq!(Some(vec!["id".to_string()]), published_columns(db));
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(None, published_columns(db));
+    });
+
+    test.after_abort(|db| {
+        assert_eq!(Some(vec!["id".to_string()]), published_columns(db));
+    });
+
+    test.run();
+}
+
+#[test]
+fn change_columns_of_published_table() {
+    let mut test = Test::new("Change columns of published table");
+    test.clear(create_publication);
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "password"
+            type = "TEXT"
+
+        [[actions]]
+        type = "add_table_to_publication"
+        publication = "app_publication"
+        table = "users"
+        columns = ["id", "name", "email"]
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "change_users_columns"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+
+        [[actions]]
+        type = "remove_column"
+        table = "users"
+        column = "email"
+        down = "'unknown'"
+        "#,
+    );
+
+    test.after_completion(|db| {
+        // The altered column must stay published and the removed one must be left out,
+        // without removing the table from the publication
+        assert_eq!(
+            Some(vec!["id".to_string(), "name".to_string()]),
+            published_columns(db)
+        );
+    });
+
+    test.after_abort(|db| {
+        assert_eq!(
+            Some(vec![
+                "id".to_string(),
+                "name".to_string(),
+                "email".to_string()
+            ]),
+            published_columns(db)
+        );
+    });
+
+    test.run();
+}
+
+#[test]
+fn rewrite_published_table() {
+    let mut test = Test::new("Rewrite published table");
+    test.clear(create_publication);
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+        [[actions]]
+        type = "add_table_to_publication"
+        publication = "app_publication"
+        table = "users"
+        columns = ["id", "name"]
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "rewrite_users_table"
+
+        [[actions]]
+        type = "rewrite_table"
+        table = "users"
+        columns = ["name", "id"]
+        "#,
+    );
+
+    test.after_completion(|db| {
+        // The new table replaces the old one in the publication
+        assert_eq!(
+            Some(vec!["name".to_string(), "id".to_string()]),
+            published_columns(db)
+        );
+    });
+
+    test.run();
+}
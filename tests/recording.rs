@@ -0,0 +1,124 @@
+use reshape::recording::{Event, Recording};
+use reshape::testing;
+
+#[test]
+fn record_migration() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let migration: reshape::migrations::Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+
+    reshape.start_recording();
+    reshape.migrate(vec![migration]).unwrap();
+    reshape.complete().unwrap();
+    let recording = reshape.take_recording().unwrap();
+
+    let commands: Vec<&str> = recording
+        .events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Command { name } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(vec!["migrate", "complete"], commands);
+
+    let created_table = recording.events.iter().any(|event| match event {
+        Event::Statement { sql, error, .. } => {
+            sql.contains(r#"CREATE TABLE "users""#) && error.is_none()
+        }
+        _ => false,
+    });
+    assert!(created_table, "expected CREATE TABLE to be recorded");
+
+    let states: Vec<&str> = recording
+        .events
+        .iter()
+        .filter_map(|event| match event {
+            Event::State { state } => state["state"].as_str(),
+            _ => None,
+        })
+        .collect();
+    assert!(states.contains(&"in_progress"));
+    assert_eq!(Some(&"idle"), states.last());
+
+    // Recordings must survive being written to and read from a file
+    let encoded = serde_json::to_string(&recording).unwrap();
+    let decoded: Recording = serde_json::from_str(&encoded).unwrap();
+    assert_eq!(recording.events.len(), decoded.events.len());
+    assert!(decoded.to_string().contains("$ reshape migrate"));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn recording_leaves_out_data() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    let dir = std::env::temp_dir().join(format!("reshape-{}-recording", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("1_create_users.toml"),
+        r#"
+        [[actions]]
+        type = "custom"
+        start = """
+            CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT);
+            INSERT INTO users (id, name, email) VALUES (1, NULL, 'seeded@example.com');
+        """
+        "#,
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("2_set_name_not_null.toml"),
+        r#"
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+
+            [actions.changes]
+            nullable = false
+        "#,
+    )
+    .unwrap();
+
+    // The seeded value ends up in a statement and the state, and the row violates
+    // the NOT NULL constraint when completing, which samples it in the error
+    let path = dir.join("recording.json");
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_reshape"))
+        .args(["--record", path.to_str().unwrap()])
+        .args(["migration", "start", "--complete"])
+        .args(["--url", &testing::connection_string()])
+        .args(["--dirs", dir.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("seeded@example.com"));
+
+    let recording = std::fs::read_to_string(&path).unwrap();
+    assert!(recording.contains("have a NULL value"), "{}", recording);
+    assert!(!recording.contains("seeded@example.com"), "{}", recording);
+
+    db.simple_query("UPDATE public.users SET name = 'Unknown'")
+        .unwrap();
+    reshape.complete().unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    reshape.remove().unwrap();
+}
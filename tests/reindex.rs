@@ -0,0 +1,150 @@
+use reshape::testing::Test;
+
+const CREATE_USERS: &str = r#"
+    name = "create_users_table"
+
+    [[actions]]
+    type = "create_table"
+    name = "users"
+    primary_key = ["id"]
+
+        [[actions.columns]]
+        name = "id"
+        type = "INTEGER"
+
+        [[actions.columns]]
+        name = "name"
+        type = "TEXT"
+
+    [[actions]]
+    type = "add_index"
+    table = "users"
+
+        [actions.index]
+        name = "name_idx"
+        columns = ["name"]
+    "#;
+
+#[test]
+fn reindex_index() {
+    let mut test = Test::new("Reindex index");
+
+    test.first_migration(CREATE_USERS);
+
+    test.second_migration(
+        r#"
+        name = "reindex_name_index"
+
+        [[actions]]
+        type = "reindex_index"
+        index = "name_idx"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'Alice'), (2, 'Bob')")
+            .unwrap();
+
+        // Leftover from an earlier rebuild which was interrupted
+        db.simple_query(
+            "
+            CREATE INDEX name_idx_ccnew ON public.users (name);
+            UPDATE pg_index SET indisvalid = false WHERE indexrelid = 'public.name_idx_ccnew'::regclass;
+            ",
+        )
+        .unwrap();
+
+        remember_files(db);
+    });
+
+    test.intermediate(|db, _| {
+        // The index is rebuilt when the migration is started, and the leftover is removed
+        assert_eq!(vec!["name_idx", "users_pkey"], indices(db));
+        assert!(rebuilt(db, "name_idx"));
+        assert!(!rebuilt(db, "users_pkey"));
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(vec!["name_idx", "users_pkey"], indices(db));
+    });
+
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn reindex_table() {
+    let mut test = Test::new("Reindex table");
+
+    test.first_migration(CREATE_USERS);
+
+    test.second_migration(
+        r#"
+        name = "reindex_users"
+
+        [[actions]]
+        type = "reindex_table"
+        table = "users"
+        concurrently = false
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'Alice'), (2, 'Bob')")
+            .unwrap();
+        remember_files(db);
+    });
+
+    test.intermediate(|db, _| {
+        assert_eq!(vec!["name_idx", "users_pkey"], indices(db));
+        assert!(rebuilt(db, "name_idx"));
+        assert!(rebuilt(db, "users_pkey"));
+    });
+
+    test.run();
+}
+
+// The files backing the indices are stored in a comment on the table, as the
+// steps of a test can't share any state
+fn remember_files(db: &mut postgres::Client) {
+    db.simple_query(
+        "
+        DO $$ BEGIN
+            EXECUTE format(
+                'COMMENT ON TABLE public.users IS %L',
+                pg_relation_filenode('public.name_idx') || ',' || pg_relation_filenode('public.users_pkey')
+            );
+        END $$
+        ",
+    )
+    .unwrap();
+}
+
+fn rebuilt(db: &mut postgres::Client, index: &str) -> bool {
+    db.query_one(
+        "
+        SELECT NOT (pg_relation_filenode(format('public.%I', $1::TEXT)::regclass)::TEXT
+            = ANY(string_to_array(obj_description('public.users'::regclass, 'pg_class'), ',')))
+        ",
+        &[&index],
+    )
+    .unwrap()
+    .get(0)
+}
+
+// All indices on the users table, including invalid ones
+fn indices(db: &mut postgres::Client) -> Vec<String> {
+    db.query(
+        "
+        SELECT i.relname::TEXT
+        FROM pg_index ix
+        JOIN pg_class i ON i.oid = ix.indexrelid
+        WHERE ix.indrelid = 'public.users'::regclass
+        ORDER BY i.relname
+        ",
+        &[],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
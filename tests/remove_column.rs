@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn remove_column() {
@@ -63,7 +62,7 @@ fn remove_column() {
         );
     });
 
-    test.run();
+    test.crash_at_every_statement().run();
 }
 
 #[test]
@@ -132,6 +131,85 @@ fn remove_column_with_index() {
     test.run();
 }
 
+#[test]
+fn remove_column_with_archive() {
+    let mut test = Test::new("Remove column with archive");
+
+    test.first_migration(
+        r#"
+        name = "create_user_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "remove_name_column"
+
+        [[actions]]
+        type = "remove_column"
+        table = "users"
+        column = "name"
+        archive = true
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "INSERT INTO users (id, name, email) VALUES (1, 'John', 'john@example.com')",
+        )
+        .unwrap();
+    });
+
+    test.after_completion(|db| {
+        // Only the primary key and the removed column are archived
+        let table: String = db
+            .query_one(
+                "
+                SELECT table_name::TEXT
+                FROM information_schema.tables
+                WHERE table_schema = 'reshape_archive'
+                ",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert!(
+            table.starts_with("users_name_"),
+            "unexpected table: {}",
+            table
+        );
+
+        let row = db
+            .query_one(
+                &format!(r#"SELECT * FROM reshape_archive."{}""#, table),
+                &[],
+            )
+            .unwrap();
+        assert_eq!(2, row.len());
+        assert_eq!(1, row.get::<_, i32>("id"));
+        assert_eq!("John", row.get::<_, String>("name"));
+    });
+
+    test.crash_at_every_statement().run();
+}
+
 #[test]
 fn remove_column_with_complex_down() {
     let mut test = Test::new("Remove column complex");
@@ -215,3 +293,106 @@ fn remove_column_with_complex_down() {
 
     test.run();
 }
+
+#[test]
+fn remove_not_null_column_with_complex_down() {
+    let mut test = Test::new("Remove NOT NULL column complex");
+
+    test.first_migration(
+        r#"
+        name = "create_tables"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+            nullable = false
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+        [[actions]]
+        type = "create_table"
+        name = "profiles"
+        primary_key = ["user_id"]
+
+            [[actions.columns]]
+            name = "user_id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "remove_users_email_column"
+
+        [[actions]]
+        type = "remove_column"
+        table = "users"
+        column = "email"
+
+            [actions.down]
+            table = "profiles"
+            value = "profiles.email"
+            where = "users.id = profiles.user_id"
+
+        [[actions]]
+        type = "remove_column"
+        table = "users"
+        column = "name"
+
+            [actions.down]
+            table = "profiles"
+            value = "profiles.email"
+            where = "users.id = profiles.user_id"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, email) VALUES (1, 'test@example.com')")
+            .unwrap();
+        db.simple_query("INSERT INTO profiles (user_id, email) VALUES (1, 'test@example.com')")
+            .unwrap();
+    });
+
+    test.after_abort(|db| {
+        // NOT NULL is only reinstated for the column which had it to begin with
+        let nullable: Vec<(String, String)> = db
+            .query(
+                "
+                SELECT column_name::TEXT, is_nullable::TEXT
+                FROM information_schema.columns
+                WHERE table_schema = 'public' AND table_name = 'users'
+                ORDER BY column_name
+                ",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        assert_eq!(
+            vec![
+                ("email".to_string(), "NO".to_string()),
+                ("id".to_string(), "NO".to_string()),
+                ("name".to_string(), "YES".to_string()),
+            ],
+            nullable
+        );
+    });
+
+    test.crash_at_every_statement().run();
+}
@@ -0,0 +1,58 @@
+use reshape::testing::Test;
+
+#[test]
+fn remove_composite_type() {
+    let mut test = Test::new("Remove composite type");
+
+    test.first_migration(
+        r#"
+		name = "create_composite_type"
+
+		[[actions]]
+		type = "create_composite_type"
+		name = "address"
+
+			[[actions.attributes]]
+			name = "street"
+			type = "TEXT"
+		"#,
+    );
+
+    test.second_migration(
+        r#"
+		name = "remove_composite_type"
+
+		[[actions]]
+		type = "remove_composite_type"
+		composite_type = "address"
+		"#,
+    );
+
+    test.intermediate(|db, _| {
+        // Type should remain until completion
+        let type_exists = !db
+            .query(
+                "SELECT typname FROM pg_catalog.pg_type WHERE typname = 'address'",
+                &[],
+            )
+            .unwrap()
+            .is_empty();
+        assert!(type_exists, "expected composite type to exist");
+    });
+
+    test.after_completion(|db| {
+        let type_does_not_exist = db
+            .query(
+                "SELECT typname FROM pg_catalog.pg_type WHERE typname = 'address'",
+                &[],
+            )
+            .unwrap()
+            .is_empty();
+        assert!(
+            type_does_not_exist,
+            "expected composite type to have been removed"
+        );
+    });
+
+    test.crash_at_every_statement().run();
+}
@@ -0,0 +1,68 @@
+use reshape::testing::Test;
+
+#[test]
+fn remove_domain() {
+    let mut test = Test::new("Remove domain");
+
+    test.first_migration(
+        r#"
+		name = "create_domain"
+
+		[[actions]]
+		type = "create_domain"
+		name = "positive_integer"
+		data_type = "INTEGER"
+		"#,
+    );
+
+    test.second_migration(
+        r#"
+		name = "remove_domain"
+
+		[[actions]]
+		type = "remove_domain"
+		domain = "positive_integer"
+		"#,
+    );
+
+    test.after_first(|db| {
+        // Ensure domain was created
+        let domain_exists = !db
+            .query(
+                "
+				SELECT typname
+				FROM pg_catalog.pg_type
+				WHERE typtype = 'd'
+				AND typname = 'positive_integer'
+				",
+                &[],
+            )
+            .unwrap()
+            .is_empty();
+
+        assert!(domain_exists, "expected domain to have been created");
+    });
+
+    test.after_completion(|db| {
+        // Ensure domain was removed after completion
+        let domain_does_not_exist = db
+            .query(
+                "
+				SELECT typname
+				FROM pg_catalog.pg_type
+				WHERE typtype = 'd'
+				AND typname = 'positive_integer'
+				",
+                &[],
+            )
+            .unwrap()
+            .is_empty();
+
+        assert!(
+            domain_does_not_exist,
+            "expected domain to have been removed"
+        );
+    });
+
+    test.crash_at_every_statement().run();
+}
@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn remove_enum() {
@@ -65,5 +64,5 @@ fn remove_enum() {
         );
     });
 
-    test.run();
+    test.crash_at_every_statement().run();
 }
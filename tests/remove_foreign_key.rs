@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn remove_foreign_key() {
@@ -120,5 +119,5 @@ fn remove_foreign_key() {
         assert!(fk_exists);
     });
 
-    test.run()
+    test.crash_at_every_statement().run()
 }
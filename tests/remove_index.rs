@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn remove_index() {
@@ -82,5 +81,5 @@ fn remove_index() {
         assert_eq!(0, count, "expected index to not exist");
     });
 
-    test.run();
+    test.crash_at_every_statement().run();
 }
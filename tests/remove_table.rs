@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn remove_table() {
@@ -40,5 +39,126 @@ fn remove_table() {
         assert!(new_db.query("SELECT id FROM users", &[]).is_err());
     });
 
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn remove_table_with_auto_updated_at() {
+    let mut test = Test::new("Remove table with auto_updated_at");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+        auto_updated_at = true
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "updated_at"
+            type = "TIMESTAMP"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "remove_users_table"
+
+        [[actions]]
+        type = "remove_table"
+        table = "users"
+        "#,
+    );
+
+    test.after_completion(|db| {
+        // The trigger function must be removed along with the table
+        let functions: i64 = db
+            .query_one(
+                "SELECT COUNT(*) FROM pg_proc WHERE proname LIKE 'reshape_auto_updated_at%'",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(0, functions);
+    });
+
     test.run();
 }
+
+#[test]
+fn remove_table_with_archive() {
+    let mut test = Test::new("Remove table with archive");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "remove_users_table"
+
+        [[actions]]
+        type = "remove_table"
+        table = "users"
+        archive = true
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'John'), (2, 'Jane')")
+            .unwrap();
+    });
+
+    test.after_completion(|db| {
+        // The removed rows are kept in a timestamped table in the archive schema
+        let table: String = db
+            .query_one(
+                "
+                SELECT table_name::TEXT
+                FROM information_schema.tables
+                WHERE table_schema = 'reshape_archive'
+                ",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert!(table.starts_with("users_"), "unexpected table: {}", table);
+
+        let names: Vec<String> = db
+            .query(
+                &format!(
+                    r#"SELECT name FROM reshape_archive."{}" ORDER BY id"#,
+                    table
+                ),
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get("name"))
+            .collect();
+        assert_eq!(vec!["John", "Jane"], names);
+    });
+
+    test.crash_at_every_statement().run();
+}
@@ -1,5 +1,4 @@
-mod common;
-use common::Test;
+use reshape::testing::Test;
 
 #[test]
 fn rename_table() {
@@ -66,5 +65,5 @@ fn rename_table() {
         assert!(new_db.simple_query("SELECT id FROM users").is_err());
     });
 
-    test.run();
+    test.crash_at_every_statement().run();
 }
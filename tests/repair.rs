@@ -0,0 +1,257 @@
+use reshape::migrations::{Custom, Migration};
+use reshape::testing;
+
+#[test]
+fn repair_after_reshape_schema_deleted() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations()).unwrap();
+
+    // Lose all bookkeeping while the second migration is in progress
+    db.simple_query("DROP SCHEMA reshape CASCADE").unwrap();
+
+    let err = reshape.migrate(migrations()).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::ConflictingState(_))
+    ));
+
+    reshape.repair(migrations()).unwrap();
+    let leftovers = db
+        .query(
+            "SELECT column_name FROM information_schema.columns WHERE column_name LIKE '\\_\\_reshape%'",
+            &[],
+        )
+        .unwrap();
+    assert!(
+        leftovers.is_empty(),
+        "expected temporary columns to be removed"
+    );
+
+    // The first migration was completed, the second one had only been started
+    let completed: Vec<String> = db
+        .query("SELECT name FROM reshape.migrations ORDER BY index", &[])
+        .unwrap()
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+    assert_eq!(vec!["create_users_table"], completed);
+
+    let schemas: Vec<String> = db
+        .query(
+            "SELECT schema_name::TEXT FROM information_schema.schemata WHERE schema_name LIKE 'migration\\_%'",
+            &[],
+        )
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    assert_eq!(vec!["migration_create_users_table"], schemas);
+
+    // Migrating works as normal after a repair
+    reshape.migrate(migrations()).unwrap();
+    reshape.complete().unwrap();
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn force_abort_after_objects_dropped_manually() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    let migrations = || {
+        let mut migrations = migrations();
+        migrations[1].actions.push(Box::new(
+            Custom::new()
+                .with_start("CREATE TABLE audit (id INTEGER); ALTER TABLE users ADD COLUMN __reshape_audit INTEGER")
+                .with_abort("DROP TABLE audit; ALTER TABLE users DROP COLUMN __reshape_audit"),
+        ));
+        migrations
+    };
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations()).unwrap();
+
+    // Someone cleans up by hand, which makes the custom action's abort fail
+    db.simple_query("DROP TABLE audit CASCADE").unwrap();
+    assert!(reshape.abort().is_err());
+    assert_eq!("aborting", reshape.status().unwrap().state.name());
+
+    let failures = reshape.force_abort().unwrap();
+    assert_eq!(2, failures.len(), "unexpected failures: {:?}", failures);
+    assert!(failures[0].contains("failed to abort action"));
+    assert_eq!("column __reshape_audit on users was left over", failures[1]);
+    assert_eq!("idle", reshape.status().unwrap().state.name());
+
+    // The add_column action was still aborted even though a later action failed
+    let columns: Vec<String> = db
+        .query(
+            "SELECT column_name::TEXT FROM information_schema.columns WHERE table_schema = 'public' AND table_name = 'users' ORDER BY column_name",
+            &[],
+        )
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    assert_eq!(vec!["__reshape_audit", "id"], columns);
+
+    // The leftover column can be cleaned up using repair
+    reshape.repair(migrations()).unwrap();
+    reshape.migrate(migrations()).unwrap();
+    reshape.complete().unwrap();
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn reset_state() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations()).unwrap();
+    assert_eq!("in_progress", reshape.status().unwrap().state.name());
+
+    // Nothing is cleaned up, so the temporary objects block the next migration
+    reshape.reset_state().unwrap();
+    assert_eq!("idle", reshape.status().unwrap().state.name());
+    assert!(reshape.migrate(migrations()).is_err());
+
+    reshape.repair(migrations()).unwrap();
+    reshape.migrate(migrations()).unwrap();
+    reshape.complete().unwrap();
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn temporary_objects_are_matched_to_actions() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations()).unwrap();
+
+    // Left behind by a different migration which was in the same position
+    db.simple_query("ALTER TABLE public.users ADD COLUMN __reshape_0000_0000_abcd_stale INTEGER")
+        .unwrap();
+
+    let objects = reshape.temporary_objects().unwrap();
+    assert!(objects.len() > 1);
+    for object in &objects {
+        if object.name == "__reshape_0000_0000_abcd_stale" {
+            assert!(object.orphaned);
+            assert_eq!(None, object.owner);
+        } else {
+            assert!(!object.orphaned, "unexpected orphan: {}", object.describe());
+            assert!(object.name.starts_with("__reshape_0000_0000_f346_"));
+            assert_eq!(Some(("add_email_column".to_string(), 0)), object.owner);
+        }
+    }
+
+    // Only the objects of the pending action are removed by the abort
+    reshape.abort().unwrap();
+    let names: Vec<String> = reshape
+        .temporary_objects()
+        .unwrap()
+        .iter()
+        .map(|object| object.name.to_string())
+        .collect();
+    assert_eq!(vec!["__reshape_0000_0000_abcd_stale"], names);
+
+    reshape.repair(migrations()).unwrap();
+    assert!(reshape.temporary_objects().unwrap().is_empty());
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn migration_started_with_positional_names() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+
+    // Simulate an earlier version which failed while applying the second migration,
+    // before it created any objects. Earlier versions didn't save how objects are named.
+    reshape.migrate(migrations()).unwrap();
+    db.simple_query(
+        "CREATE TABLE public.saved_state AS SELECT value FROM reshape.data WHERE key = 'state'",
+    )
+    .unwrap();
+    reshape.abort().unwrap();
+    db.simple_query(
+        r#"
+        UPDATE reshape.data
+        SET value = (SELECT value || '{"state": "applying"}' FROM public.saved_state)
+        WHERE key = 'state';
+        DROP TABLE public.saved_state;
+        "#,
+    )
+    .unwrap();
+
+    // Retrying keeps using positional names
+    reshape.migrate(migrations()).unwrap();
+    let objects = reshape.temporary_objects().unwrap();
+    assert!(!objects.is_empty());
+    for object in &objects {
+        assert!(!object.orphaned, "unexpected orphan: {}", object.describe());
+        assert!(object.name.starts_with("__reshape_0000_0000_"));
+        assert!(!object.name.starts_with("__reshape_0000_0000_f346_"));
+    }
+
+    reshape.complete().unwrap();
+    assert!(reshape.temporary_objects().unwrap().is_empty());
+
+    // The naming is forgotten once the migration is completed
+    let object_names = db
+        .query("SELECT 1 FROM reshape.data WHERE key = 'object_names'", &[])
+        .unwrap();
+    assert!(object_names.is_empty());
+
+    reshape.remove().unwrap();
+}
+
+fn migrations() -> Vec<Migration> {
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "add_email_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "'unknown'"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+            nullable = false
+        "#,
+    )
+    .unwrap();
+
+    vec![first_migration, second_migration]
+}
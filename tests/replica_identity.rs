@@ -0,0 +1,347 @@
+use reshape::testing::Test;
+
+// The replica identity of the `users` table and the index it uses, if any
+fn replica_identity(db: &mut postgres::Client) -> (String, Option<String>) {
+    let row = db
+        .query_one(
+            "
+            SELECT
+                pg_class.relreplident::TEXT AS identity,
+                (
+                    SELECT index.relname::TEXT
+                    FROM pg_index
+                    JOIN pg_class index ON index.oid = pg_index.indexrelid
+                    WHERE pg_index.indrelid = pg_class.oid AND pg_index.indisreplident
+                ) AS index
+            FROM pg_class
+            WHERE pg_class.oid = 'public.users'::regclass
+            ",
+            &[],
+        )
+        .unwrap();
+    (row.get("identity"), row.get("index"))
+}
+
+#[test]
+fn set_replica_identity() {
+    let mut test = Test::new("Set replica identity");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+            nullable = false
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "email_idx"
+            columns = ["email"]
+            unique = true
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "identify_users_by_email"
+
+        [[actions]]
+        type = "set_replica_identity"
+        table = "users"
+        identity = "index"
+        index = "email_idx"
+        "#,
+    );
+
+    test.intermediate(|db, _| {
+        // The replica identity is only changed once the migration is completed
+        assert_eq!(("d".to_string(), None), replica_identity(db));
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(
+            ("i".to_string(), Some("email_idx".to_string())),
+            replica_identity(db)
+        );
+    });
+
+    test.after_abort(|db| {
+        assert_eq!(("d".to_string(), None), replica_identity(db));
+    });
+
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn set_replica_identity_full() {
+    let mut test = Test::new("Set replica identity to full");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "identify_users_by_all_columns"
+
+        [[actions]]
+        type = "set_replica_identity"
+        table = "users"
+        identity = "full"
+        "#,
+    );
+
+    test.after_completion(|db| {
+        assert_eq!(("f".to_string(), None), replica_identity(db));
+    });
+
+    test.run();
+}
+
+#[test]
+fn set_replica_identity_requires_unique_index() {
+    let mut test = Test::new("Set replica identity to non-unique index");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+            nullable = false
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "email_idx"
+            columns = ["email"]
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "identify_users_by_email"
+
+        [[actions]]
+        type = "set_replica_identity"
+        table = "users"
+        identity = "index"
+        index = "email_idx"
+        "#,
+    );
+
+    test.expect_failure();
+    test.run();
+}
+
+#[test]
+fn alter_column_moves_replica_identity_to_new_index() {
+    let mut test = Test::new("Alter replica identity column");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+            nullable = false
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "email_idx"
+            columns = ["email"]
+            unique = true
+
+        [[actions]]
+        type = "set_replica_identity"
+        table = "users"
+        identity = "index"
+        index = "email_idx"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "lowercase_emails"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "email"
+        up = "LOWER(email)"
+        down = "email"
+        "#,
+    );
+
+    test.after_completion(|db| {
+        assert_eq!(
+            ("i".to_string(), Some("email_idx".to_string())),
+            replica_identity(db)
+        );
+    });
+
+    test.after_abort(|db| {
+        assert_eq!(
+            ("i".to_string(), Some("email_idx".to_string())),
+            replica_identity(db)
+        );
+    });
+
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn alter_column_moves_replica_identity_to_new_primary_key() {
+    let mut test = Test::new("Alter primary key column with replica identity index");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+        [[actions]]
+        type = "set_replica_identity"
+        table = "users"
+        identity = "index"
+        index = "users_pkey"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "widen_id"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "id"
+
+            [actions.changes]
+            type = "BIGINT"
+        "#,
+    );
+
+    test.after_completion(|db| {
+        assert_eq!(
+            ("i".to_string(), Some("users_pkey".to_string())),
+            replica_identity(db)
+        );
+    });
+
+    test.run();
+}
+
+#[test]
+fn alter_column_keeps_replica_identity_column_not_null() {
+    let mut test = Test::new("Make replica identity column nullable");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+            nullable = false
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "email_idx"
+            columns = ["email"]
+            unique = true
+
+        [[actions]]
+        type = "set_replica_identity"
+        table = "users"
+        identity = "index"
+        index = "email_idx"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "make_email_nullable"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "email"
+
+            [actions.changes]
+            nullable = true
+        "#,
+    );
+
+    test.expect_failure();
+    test.run();
+}
@@ -0,0 +1,219 @@
+use postgres::Client;
+use reshape::{migrations::Migration, testing, Reshape};
+
+#[test]
+fn schemas_are_retained_after_completion() {
+    let (mut reshape, mut db) = set_up();
+    reshape.set_retained_schemas(1);
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    db.simple_query("GRANT SELECT ON users TO PUBLIC").unwrap();
+
+    reshape.migrate(migrations().into_iter().take(2)).unwrap();
+    reshape.complete().unwrap();
+
+    // Deployments which haven't switched yet can keep using the previous schema
+    db.simple_query("INSERT INTO migration_create_users.users (id, name) VALUES (1, 'Test')")
+        .unwrap();
+    let email: Option<String> = db
+        .query_one("SELECT email FROM migration_add_email.users", &[])
+        .unwrap()
+        .get("email");
+    assert_eq!(None, email);
+
+    // Only the newest schema before the current one is kept
+    reshape.migrate(migrations()).unwrap();
+    reshape.complete().unwrap();
+    assert_eq!(
+        vec!["migration_add_email", "migration_add_phone"],
+        migration_schemas(&mut db)
+    );
+    assert!(view_exists(&mut db, "migration_add_email", "users"));
+
+    // Privileges on the views are kept too
+    let public_select: bool = db
+        .query_one(
+            "SELECT array_to_string(relacl, ',') LIKE '%=r/%' FROM pg_class WHERE oid = 'migration_add_email.users'::regclass",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert!(public_select);
+
+    // The current schema is never pruned
+    let pruned = reshape.prune_schemas(0).unwrap();
+    assert_eq!(vec!["migration_add_email"], pruned);
+    assert_eq!(vec!["migration_add_phone"], migration_schemas(&mut db));
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn views_referencing_removed_columns_are_left_out() {
+    let (mut reshape, mut db) = set_up();
+    reshape.set_retained_schemas(1);
+
+    let remove_name: Migration = toml::from_str(
+        r#"
+        name = "remove_name"
+
+        [[actions]]
+        type = "remove_column"
+        table = "users"
+        column = "name"
+        down = "'unknown'"
+        "#,
+    )
+    .unwrap();
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape
+        .migrate(migrations().into_iter().take(1).chain([remove_name]))
+        .unwrap();
+    reshape.complete().unwrap();
+
+    assert_eq!(
+        vec!["migration_create_users", "migration_remove_name"],
+        migration_schemas(&mut db)
+    );
+    assert!(!view_exists(&mut db, "migration_create_users", "users"));
+
+    reshape.remove().unwrap();
+    assert!(migration_schemas(&mut db).is_empty());
+}
+
+#[test]
+fn completion_with_retained_schemas_can_be_resumed() {
+    for crash_after in 0.. {
+        let (mut reshape, mut db) = set_up();
+        reshape.set_retained_schemas(1);
+
+        reshape.migrate(migrations().into_iter().take(1)).unwrap();
+        reshape.complete().unwrap();
+        reshape.migrate(migrations().into_iter().take(2)).unwrap();
+
+        reshape.simulate_crash_after(Some(crash_after));
+        let result = reshape.complete();
+        let crashed = reshape.has_crashed();
+        reshape.simulate_crash_after(None);
+        if crashed {
+            reshape.complete().unwrap();
+        } else {
+            result.unwrap();
+        }
+
+        assert!(
+            view_exists(&mut db, "migration_create_users", "users"),
+            "retained view missing after crashing after {} statements",
+            crash_after
+        );
+
+        reshape.remove().unwrap();
+        if !crashed {
+            break;
+        }
+    }
+}
+
+#[test]
+fn retained_schemas_are_not_leftovers() {
+    let (mut reshape, mut db) = set_up();
+    reshape.set_retained_schemas(1);
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    reshape.migrate(migrations().into_iter().take(2)).unwrap();
+    reshape.complete().unwrap();
+
+    // Repairing keeps the schemas of completed migrations
+    reshape.repair(migrations().into_iter().take(2)).unwrap();
+    assert_eq!(
+        vec!["migration_add_email", "migration_create_users"],
+        migration_schemas(&mut db)
+    );
+    assert!(view_exists(&mut db, "migration_create_users", "users"));
+
+    // Testing a migration doesn't report them as left over either
+    reshape.test(migrations()).unwrap();
+    assert_eq!(
+        vec!["migration_add_email", "migration_add_phone"],
+        migration_schemas(&mut db)
+    );
+
+    reshape.remove().unwrap();
+}
+
+fn set_up() -> (Reshape, Client) {
+    let (mut reshape, db) = testing::connect();
+    reshape.remove().unwrap();
+
+    (reshape, db)
+}
+
+fn migrations() -> Vec<Migration> {
+    [
+        r#"
+        name = "create_users"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+        r#"
+        name = "add_email"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+        "#,
+        r#"
+        name = "add_phone"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+
+            [actions.column]
+            name = "phone"
+            type = "TEXT"
+        "#,
+    ]
+    .iter()
+    .map(|migration| toml::from_str(migration).unwrap())
+    .collect()
+}
+
+fn migration_schemas(db: &mut Client) -> Vec<String> {
+    db.query(
+        "SELECT nspname::TEXT FROM pg_namespace WHERE nspname LIKE 'migration\\_%' ORDER BY nspname",
+        &[],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
+
+fn view_exists(db: &mut Client, schema: &str, view: &str) -> bool {
+    db.query_one(
+        "SELECT to_regclass(format('%I.%I', $1::TEXT, $2::TEXT)) IS NOT NULL",
+        &[&schema, &view],
+    )
+    .unwrap()
+    .get(0)
+}
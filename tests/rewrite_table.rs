@@ -0,0 +1,278 @@
+use reshape::testing::Test;
+
+#[test]
+fn rewrite_table() {
+    let mut test = Test::new("Rewrite table");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+            generated = "BY DEFAULT AS IDENTITY"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+            nullable = false
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+            unique = true
+
+            [[actions.columns]]
+            name = "age"
+            type = "INTEGER"
+            check = "age >= 0"
+
+        [[actions]]
+        type = "add_index"
+        table = "users"
+
+            [actions.index]
+            name = "users_name_idx"
+            columns = ["name"]
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "rewrite_users_table"
+
+        [[actions]]
+        type = "rewrite_table"
+        table = "users"
+        columns = ["id", "email", "name", "age"]
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "
+            INSERT INTO users (name, email, age)
+            SELECT 'User ' || i, 'user' || i || '@example.com', i
+            FROM generate_series(1, 2500) AS i
+            ",
+        )
+        .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // The new schema uses the new column order straight away
+        let columns: Vec<String> = new_db
+            .prepare("SELECT * FROM users")
+            .unwrap()
+            .columns()
+            .iter()
+            .map(|column| column.name().to_string())
+            .collect();
+        assert_eq!(vec!["id", "email", "name", "age"], columns);
+
+        // Writes during the migration must be mirrored to the shadow table
+        old_db
+            .simple_query(
+                "
+                INSERT INTO users (name, email, age) VALUES ('New user', 'new@example.com', 20);
+                UPDATE users SET name = 'Updated' WHERE id = 1;
+                UPDATE users SET id = 5000 WHERE id = 3;
+                DELETE FROM users WHERE id = 2;
+                ",
+            )
+            .unwrap();
+
+        let count: i64 = new_db
+            .query_one("SELECT COUNT(*) FROM users", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(2500, count);
+    });
+
+    test.after_completion(|db| {
+        let columns: Vec<String> = db
+            .query(
+                "
+                SELECT column_name::TEXT
+                FROM information_schema.columns
+                WHERE table_schema = 'public' AND table_name = 'users'
+                ORDER BY ordinal_position
+                ",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(vec!["id", "email", "name", "age"], columns);
+
+        // All rows must have been copied, including the changes made during the migration
+        let count: i64 = db
+            .query_one("SELECT COUNT(*) FROM users", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(2500, count);
+
+        let name: String = db
+            .query_one("SELECT name FROM users WHERE id = 1", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!("Updated", name);
+
+        let moved: i64 = db
+            .query_one("SELECT COUNT(*) FROM users WHERE id IN (2, 3, 5000)", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(1, moved);
+
+        // Indices and constraints keep their names
+        let mut indices: Vec<String> = db
+            .query(
+                "SELECT indexname::TEXT FROM pg_indexes WHERE schemaname = 'public' AND tablename = 'users'",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        indices.sort();
+        assert_eq!(
+            vec!["users_email_key", "users_name_idx", "users_pkey"],
+            indices
+        );
+
+        // The identity sequence continues from the old table
+        let id: i32 = db
+            .query_one(
+                "INSERT INTO users (name) VALUES ('Another user') RETURNING id",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(2502, id);
+
+        let result = db.simple_query("INSERT INTO users (name, age) VALUES ('Invalid', -1)");
+        assert!(result.is_err(), "expected check constraint to be copied");
+
+        let result = db.simple_query(
+            "INSERT INTO users (name, email) VALUES ('Duplicate', 'user10@example.com')",
+        );
+        assert!(result.is_err(), "expected unique constraint to be copied");
+    });
+
+    test.after_abort(|db| {
+        let count: i64 = db
+            .query_one("SELECT COUNT(*) FROM users", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(2500, count);
+
+        let tables: i64 = db
+            .query_one(
+                "SELECT COUNT(*) FROM pg_tables WHERE schemaname = 'public' AND tablename LIKE '\\_\\_reshape%'",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(0, tables);
+    });
+
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn rewrite_table_with_serial_primary_key() {
+    let mut test = Test::new("Rewrite table with serial primary key");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "SERIAL"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "rewrite_users_table"
+
+        [[actions]]
+        type = "rewrite_table"
+        table = "users"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (name) VALUES ('First'), ('Second')")
+            .unwrap();
+    });
+
+    test.after_completion(|db| {
+        // The sequence must be kept when the old table is dropped
+        let id: i32 = db
+            .query_one(
+                "INSERT INTO users (name) VALUES ('Third') RETURNING id",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(3, id);
+    });
+
+    test.run();
+}
+
+#[test]
+fn rewrite_table_with_incomplete_column_order() {
+    let mut test = Test::new("Rewrite table with incomplete column order");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "rewrite_users_table"
+
+        [[actions]]
+        type = "rewrite_table"
+        table = "users"
+        columns = ["name"]
+        "#,
+    );
+
+    test.expect_failure();
+    test.run();
+}
@@ -0,0 +1,274 @@
+use postgres::Client;
+use reshape::{testing::Test, SchemaOverride};
+
+fn helpers(db: &mut Client) -> (bool, bool) {
+    let row = db
+        .query_one(
+            "SELECT reshape.is_new_schema() AS new, reshape.is_old_schema() AS old",
+            &[],
+        )
+        .unwrap();
+    (row.get("new"), row.get("old"))
+}
+
+fn names(db: &mut Client, schema: &str) -> Vec<String> {
+    db.query(
+        &format!("SELECT name FROM {}.users ORDER BY id", schema),
+        &[],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get("name"))
+    .collect()
+}
+
+#[test]
+fn schema_helpers() {
+    let mut test = Test::new("Schema helpers");
+
+    test.first_migration(
+        r#"
+        name = "create_user_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "uppercase_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+        "#,
+    );
+
+    test.intermediate(|old_db, new_db| {
+        // The first schema in the search_path decides, regardless of fallbacks
+        assert_eq!((true, false), helpers(new_db));
+        assert_eq!((false, true), helpers(old_db));
+        new_db
+            .simple_query("SET search_path TO \"migration_uppercase_name\", public")
+            .unwrap();
+        assert_eq!((true, false), helpers(new_db));
+
+        // A background job which can't change its search_path writes to the new schema
+        // using qualified names, setting the schema it writes to for the transaction
+        let mut transaction = old_db.transaction().unwrap();
+        transaction
+            .simple_query("SET LOCAL reshape.is_new_schema = 'migration_uppercase_name'")
+            .unwrap();
+        transaction
+            .simple_query(
+                "INSERT INTO migration_uppercase_name.users (id, name) VALUES (1, 'JOHN DOE')",
+            )
+            .unwrap();
+        transaction.commit().unwrap();
+
+        assert_eq!(vec!["JOHN DOE"], names(old_db, "migration_uppercase_name"));
+        assert_eq!(
+            vec!["john doe"],
+            names(old_db, "migration_create_user_table")
+        );
+
+        // The setting only lasts for the transaction
+        assert_eq!((false, true), helpers(old_db));
+
+        // `YES` counts as the new schema for every namespace
+        let mut transaction = old_db.transaction().unwrap();
+        transaction
+            .simple_query("SET LOCAL reshape.is_new_schema = 'YES'")
+            .unwrap();
+        transaction
+            .simple_query(
+                "INSERT INTO migration_uppercase_name.users (id, name) VALUES (2, 'JANE DOE')",
+            )
+            .unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(
+            vec!["john doe", "jane doe"],
+            names(old_db, "migration_create_user_table")
+        );
+
+        // The schema of another namespace's migration doesn't count
+        let mut transaction = old_db.transaction().unwrap();
+        transaction
+            .simple_query("SET LOCAL reshape.is_new_schema = 'billing_migration_uppercase_name'")
+            .unwrap();
+        let row = transaction
+            .query_one(
+                "SELECT reshape.is_new_schema() AS new, reshape.is_old_schema() AS old",
+                &[],
+            )
+            .unwrap();
+        assert_eq!((false, true), (row.get("new"), row.get("old")));
+        transaction
+            .simple_query(
+                "INSERT INTO migration_create_user_table.users (id, name) VALUES (3, 'Bob')",
+            )
+            .unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(
+            vec!["JOHN DOE", "JANE DOE", "BOB"],
+            names(old_db, "migration_uppercase_name")
+        );
+    });
+
+    test.after_completion(|db| {
+        let helpers: i64 = db
+            .query_one(
+                "SELECT COUNT(*) FROM pg_proc WHERE proname IN ('is_new_schema', 'is_old_schema')",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(0, helpers);
+    });
+
+    test.after_abort(|db| {
+        let helpers: i64 = db
+            .query_one(
+                "SELECT COUNT(*) FROM pg_proc WHERE proname IN ('is_new_schema', 'is_old_schema')",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(0, helpers);
+    });
+
+    test.run();
+}
+
+#[test]
+fn schema_override() {
+    let mut test = Test::new("Schema override");
+
+    test.first_migration(
+        r#"
+        name = "create_user_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "uppercase_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "'unknown'"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+        "#,
+    );
+
+    test.intermediate(|old_db, new_db| {
+        // Pinned to the new schema from a connection using the old one
+        let mut transaction = old_db.transaction().unwrap();
+        transaction
+            .simple_query(&SchemaOverride::New.query())
+            .unwrap();
+        let row = transaction
+            .query_one(
+                "SELECT reshape.is_new_schema() AS new, reshape.is_old_schema() AS old",
+                &[],
+            )
+            .unwrap();
+        assert_eq!((true, false), (row.get("new"), row.get("old")));
+        transaction
+            .simple_query(
+                "INSERT INTO migration_uppercase_name.users (id, name, email) VALUES (1, 'JOHN DOE', 'john@example.com')",
+            )
+            .unwrap();
+        transaction.commit().unwrap();
+
+        // Pinned to the old schema from a connection using the new one, which takes
+        // precedence over `reshape.is_new_schema` as well
+        let mut transaction = new_db.transaction().unwrap();
+        transaction
+            .simple_query("SET LOCAL reshape.is_new_schema = 'YES'")
+            .unwrap();
+        transaction
+            .simple_query(&SchemaOverride::Old.query())
+            .unwrap();
+        transaction
+            .simple_query(
+                "INSERT INTO migration_create_user_table.users (id, name) VALUES (2, 'Jane Doe')",
+            )
+            .unwrap();
+        transaction.commit().unwrap();
+
+        // Every trigger follows the override
+        let users: Vec<(String, String)> = new_db
+            .query("SELECT name, email FROM users ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| (row.get("name"), row.get("email")))
+            .collect();
+        assert_eq!(
+            vec![
+                ("JOHN DOE".to_string(), "john@example.com".to_string()),
+                ("JANE DOE".to_string(), "unknown".to_string()),
+            ],
+            users
+        );
+        assert_eq!(
+            vec!["john doe", "Jane Doe"],
+            names(old_db, "migration_create_user_table")
+        );
+
+        // Unknown values are rejected rather than guessed
+        let mut transaction = old_db.transaction().unwrap();
+        transaction
+            .simple_query("SET LOCAL reshape.schema = 'newest'")
+            .unwrap();
+        let result = transaction.simple_query(
+            "INSERT INTO migration_create_user_table.users (id, name) VALUES (3, 'Bob')",
+        );
+        let error = result.unwrap_err();
+        assert!(error
+            .as_db_error()
+            .unwrap()
+            .message()
+            .contains("reshape.schema must be either new or old"));
+    });
+
+    test.run();
+}
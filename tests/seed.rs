@@ -0,0 +1,223 @@
+use postgres::Client;
+use reshape::testing::{self, Test};
+
+fn countries(db: &mut Client) -> Vec<(String, String, Option<String>)> {
+    db.query("SELECT code, name, note FROM countries ORDER BY code", &[])
+        .unwrap()
+        .iter()
+        .map(|row| (row.get("code"), row.get("name"), row.get("note")))
+        .collect()
+}
+
+fn seed_progress(db: &mut Client) -> i64 {
+    db.query_one("SELECT COUNT(*) FROM reshape.data WHERE key = 'seed'", &[])
+        .unwrap()
+        .get(0)
+}
+
+fn country(code: &str, name: &str, note: Option<&str>) -> (String, String, Option<String>) {
+    (code.to_string(), name.to_string(), note.map(str::to_string))
+}
+
+#[test]
+fn seed_data() {
+    let mut test = Test::new("Seed data");
+
+    test.first_migration(
+        r#"
+        name = "create_tables"
+
+        [[actions]]
+        type = "create_table"
+        name = "countries"
+        primary_key = ["code"]
+
+            [[actions.columns]]
+            name = "code"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+
+            [[actions.columns]]
+            name = "note"
+            type = "TEXT"
+
+        [[actions]]
+        type = "create_table"
+        name = "currencies"
+        primary_key = ["code"]
+
+            [[actions.columns]]
+            name = "code"
+            type = "TEXT"
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "seed_data"
+
+        [[actions]]
+        type = "seed"
+        table = "countries"
+        phase = "start"
+        batch_size = 2
+        csv = """
+code,name,note
+se,Sweden,
+no,Norway,"It's, cold"
+dk,Denmark,
+fi,Finland,
+is,Iceland,
+"""
+
+        [[actions]]
+        type = "rename_table"
+        table = "currencies"
+        new_name = "money"
+
+        [[actions]]
+        type = "seed"
+        table = "money"
+        csv = """
+code
+sek
+nok
+"""
+
+        [[actions]]
+        type = "seed"
+        sql = "UPDATE countries SET note = 'Nordic' WHERE note IS NULL"
+        "#,
+    );
+
+    test.intermediate(|old_db, new_db| {
+        // Seeds run when starting are available straight away, the others once completed
+        assert_eq!(5, countries(new_db).len());
+        assert_eq!(5, countries(old_db).len());
+        assert_eq!(
+            country("no", "Norway", Some("It's, cold")),
+            countries(new_db)[3]
+        );
+        assert_eq!(country("se", "Sweden", None), countries(new_db)[4]);
+
+        let currencies: i64 = new_db
+            .query_one("SELECT COUNT(*) FROM money", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(0, currencies);
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(
+            vec![
+                country("dk", "Denmark", Some("Nordic")),
+                country("fi", "Finland", Some("Nordic")),
+                country("is", "Iceland", Some("Nordic")),
+                country("no", "Norway", Some("It's, cold")),
+                country("se", "Sweden", Some("Nordic")),
+            ],
+            countries(db)
+        );
+
+        let currencies: Vec<String> = db
+            .query("SELECT code FROM money ORDER BY code", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get("code"))
+            .collect();
+        assert_eq!(vec!["nok", "sek"], currencies);
+        assert_eq!(0, seed_progress(db));
+    });
+
+    test.after_abort(|db| {
+        // Seeded rows are kept, but never inserted twice
+        assert_eq!(5, countries(db).len());
+        assert_eq!(0, seed_progress(db));
+    });
+
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn sql_seed_is_not_run_again_on_retry() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    let migrations = |up: &str| -> Vec<reshape::migrations::Migration> {
+        vec![
+            toml::from_str(
+                r#"
+                name = "create_events"
+
+                [[actions]]
+                type = "create_table"
+                name = "events"
+                primary_key = ["id"]
+
+                    [[actions.columns]]
+                    name = "id"
+                    type = "INTEGER"
+                    generated = "ALWAYS AS IDENTITY"
+
+                    [[actions.columns]]
+                    name = "name"
+                    type = "TEXT"
+                "#,
+            )
+            .unwrap(),
+            toml::from_str(&format!(
+                r#"
+                name = "seed_events"
+
+                [[actions]]
+                type = "seed"
+                sql = "INSERT INTO events (name) VALUES ('created')"
+                phase = "start"
+
+                [[actions]]
+                type = "alter_column"
+                table = "events"
+                column = "name"
+                up = "{up}"
+                down = "LOWER(name)"
+                "#,
+            ))
+            .unwrap(),
+        ]
+    };
+    let events = |db: &mut postgres::Client| -> i64 {
+        db.query_one("SELECT COUNT(*) FROM public.events", &[])
+            .unwrap()
+            .get(0)
+    };
+
+    reshape
+        .migrate(migrations("UPPER(name)").into_iter().take(1))
+        .unwrap();
+    reshape.complete().unwrap();
+
+    // The seed has been committed when the action after it fails
+    reshape.set_retry_failed(true);
+    reshape.migrate(migrations("UPPER(nme)")).unwrap_err();
+    assert_eq!(1, events(&mut db));
+
+    // Retrying skips the seed as it has already run
+    reshape.migrate(migrations("UPPER(name)")).unwrap();
+    assert_eq!(1, events(&mut db));
+
+    reshape.complete().unwrap();
+    assert_eq!(1, events(&mut db));
+    let seeded: i64 = db
+        .query_one(
+            "SELECT COUNT(*) FROM reshape.data WHERE key = 'seeded'",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert_eq!(0, seeded);
+
+    reshape.remove().unwrap();
+}
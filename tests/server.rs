@@ -0,0 +1,59 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+use reshape::migrations::Migration;
+use reshape::testing;
+
+#[test]
+fn serve_status_over_http() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    let first_migration = Migration::new("create_users", None);
+    let second_migration = Migration::new("create_items", None);
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+    reshape
+        .migrate(vec![first_migration, second_migration])
+        .unwrap();
+
+    // The server runs until the test process exits
+    let (mut server, _) = testing::connect();
+    thread::spawn(move || server.serve("127.0.0.1:18417"));
+
+    let healthz = get("/healthz");
+    assert!(healthz.starts_with("HTTP/1.1 200"), "{}", healthz);
+
+    let status = get("/status");
+    assert!(status.contains(r#""state":"in_progress""#), "{}", status);
+    assert!(status.contains(r#""current_migration":"create_users""#));
+    assert!(status.contains(r#""pending_migrations":["create_items"]"#));
+
+    let schema_query = get("/schema-query");
+    assert!(schema_query.ends_with("\r\n\r\nSET search_path TO migration_create_items\n"));
+
+    assert!(get("/unknown").starts_with("HTTP/1.1 404"));
+
+    reshape.abort().unwrap();
+    reshape.remove().unwrap();
+}
+
+fn get(path: &str) -> String {
+    // Wait for the server to start listening
+    let mut stream = (0..50)
+        .find_map(|_| {
+            TcpStream::connect("127.0.0.1:18417")
+                .map_err(|_| thread::sleep(Duration::from_millis(100)))
+                .ok()
+        })
+        .expect("server should be listening");
+
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
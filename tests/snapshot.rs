@@ -0,0 +1,49 @@
+use reshape::{prelude::*, snapshot::SchemaSnapshot, testing};
+
+#[test]
+fn database_matches_snapshot() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    let migrations = vec![
+        Migration::new("create_users_table", None)
+            .with_action(
+                CreateTable::new("users", ["id"])
+                    .with_column(Column::new("id", "INTEGER"))
+                    .with_column(Column::new("email", "TEXT").with_unique(true)),
+            )
+            .with_action(CreateEnum::new("mood", ["happy", "sad"])),
+        Migration::new("add_name_column", None)
+            .with_action(AddColumn::new("users", Column::new("name", "TEXT")))
+            .with_action(AddIndex::new(
+                "users",
+                Index::new("users_name_idx", ["name"]),
+            )),
+    ];
+    let snapshot = SchemaSnapshot::from_migrations(&migrations);
+    assert_eq!(
+        snapshot,
+        SchemaSnapshot::from_json(&snapshot.to_json()).unwrap()
+    );
+
+    reshape.migrate(migrations).unwrap();
+    reshape.complete().unwrap();
+
+    // Indices backing constraints aren't part of the snapshot
+    let database_snapshot = reshape.database_snapshot().unwrap();
+    assert_eq!(
+        Vec::<String>::new(),
+        snapshot.diff(&database_snapshot, "snapshot", "database")
+    );
+
+    // Changes made outside of migrations show up as drift
+    db.simple_query("ALTER TABLE users ADD COLUMN extra TEXT")
+        .unwrap();
+    let database_snapshot = reshape.database_snapshot().unwrap();
+    assert_eq!(
+        vec!["column \"extra\" on table \"users\" exists in the database but not in the snapshot"],
+        snapshot.diff(&database_snapshot, "snapshot", "database")
+    );
+
+    reshape.remove().unwrap();
+}
@@ -0,0 +1,149 @@
+use postgres::Client;
+use reshape::testing::Test;
+
+fn columns(db: &mut Client) -> Vec<String> {
+    db.query(
+        "
+        SELECT column_name::TEXT
+        FROM information_schema.columns
+        WHERE table_schema = 'public' AND table_name = 'users'
+        ORDER BY ordinal_position
+        ",
+        &[],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| row.get(0))
+    .collect()
+}
+
+#[test]
+fn split_column() {
+    let mut test = Test::new("Split column");
+
+    test.first_migration(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+            nullable = false
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "split_name"
+
+        [[actions]]
+        type = "split_column"
+        table = "users"
+        column = "name"
+        down = "first_name || ' ' || last_name"
+
+            [[actions.columns]]
+            name = "first_name"
+            type = "TEXT"
+            nullable = false
+            up = "split_part(name, ' ', 1)"
+
+            [[actions.columns]]
+            name = "last_name"
+            type = "TEXT"
+            up = "NULLIF(split_part(name, ' ', 2), '')"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, name) VALUES (1, 'John Doe'), (2, 'Jane')")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Existing rows have been backfilled
+        let users: Vec<(i32, String, Option<String>)> = new_db
+            .query(
+                "SELECT id, first_name, last_name FROM users ORDER BY id",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, "John".to_string(), Some("Doe".to_string())),
+                (2, "Jane".to_string(), None),
+            ],
+            users
+        );
+
+        // Writes through the old schema fill in the new columns
+        old_db
+            .simple_query("INSERT INTO users (id, name) VALUES (3, 'Bob Smith')")
+            .unwrap();
+        let (first_name, last_name): (String, String) = new_db
+            .query_one("SELECT first_name, last_name FROM users WHERE id = 3", &[])
+            .map(|row| (row.get(0), row.get(1)))
+            .unwrap();
+        assert_eq!(("Bob", "Smith"), (first_name.as_str(), last_name.as_str()));
+
+        // Writes through the new schema fill in the original
+        new_db
+            .simple_query(
+                "INSERT INTO users (id, first_name, last_name) VALUES (4, 'Alice', 'Jones')",
+            )
+            .unwrap();
+        new_db
+            .simple_query("UPDATE users SET last_name = 'Doe-Smith' WHERE id = 1")
+            .unwrap();
+        let names: Vec<String> = old_db
+            .query("SELECT name FROM users ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(
+            vec!["John Doe-Smith", "Jane", "Bob Smith", "Alice Jones"],
+            names
+        );
+
+        // The original isn't part of the new schema
+        assert!(new_db.simple_query("SELECT name FROM users").is_err());
+    });
+
+    test.after_completion(|db| {
+        assert_eq!(vec!["id", "first_name", "last_name"], columns(db));
+
+        let nullable: String = db
+            .query_one(
+                "
+                SELECT is_nullable::TEXT
+                FROM information_schema.columns
+                WHERE table_schema = 'public'
+                    AND table_name = 'users'
+                    AND column_name = 'first_name'
+                ",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!("NO", nullable);
+    });
+
+    test.after_abort(|db| {
+        assert_eq!(vec!["id", "name"], columns(db));
+    });
+
+    test.crash_at_every_statement().run();
+}
@@ -0,0 +1,132 @@
+use reshape::{migrations::Migration, testing, Phase};
+
+#[test]
+fn stats_are_stored_with_completed_migration() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+    db.simple_query("INSERT INTO users (id) VALUES (1), (2), (3)")
+        .unwrap();
+
+    reshape.migrate(migrations()).unwrap();
+    reshape.complete().unwrap();
+
+    let migration = reshape
+        .completed_migration("add_email_column")
+        .unwrap()
+        .unwrap();
+    assert_eq!("add_email_column", migration.name);
+
+    let phases: Vec<Phase> = migration.stats.iter().map(|stats| stats.phase).collect();
+    assert_eq!(vec![Phase::Start, Phase::Complete, Phase::Analyze], phases);
+
+    // Every existing row is backfilled when the migration is started
+    let start = &migration.stats[0];
+    assert_eq!(3, start.rows_backfilled);
+    assert!(start.finished_at >= start.started_at);
+
+    // The backfilled table is analyzed once the migration has been completed
+    assert_eq!(r#"Analyzing "users""#, migration.stats[2].action);
+    let analyzed: bool = db
+        .query_one(
+            "SELECT last_analyze IS NOT NULL FROM pg_stat_user_tables WHERE relname = 'users'",
+            &[],
+        )
+        .unwrap()
+        .get(0);
+    assert!(analyzed);
+
+    assert!(reshape.completed_migration("missing").unwrap().is_none());
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn analyze_after_completion_can_be_disabled() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+    reshape.set_analyze_after_completion(false);
+
+    reshape.migrate(migrations()).unwrap();
+    reshape.complete().unwrap();
+
+    let migration = reshape
+        .completed_migration("add_email_column")
+        .unwrap()
+        .unwrap();
+    let phases: Vec<Phase> = migration.stats.iter().map(|stats| stats.phase).collect();
+    assert_eq!(vec![Phase::Start, Phase::Complete], phases);
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn lock_timeouts_are_retried_and_counted() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    reshape.migrate(migrations().into_iter().take(1)).unwrap();
+    reshape.complete().unwrap();
+
+    // Hold a lock on the table for longer than lock_timeout. The column and its
+    // trigger are added in a single transaction, which is retried as a whole.
+    let (locked, is_locked) = std::sync::mpsc::channel();
+    let release = std::thread::spawn(move || {
+        db.simple_query("BEGIN; LOCK TABLE users IN ACCESS SHARE MODE")
+            .unwrap();
+        locked.send(()).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1500));
+        db.simple_query("COMMIT").unwrap();
+    });
+    is_locked.recv().unwrap();
+
+    reshape.migrate(migrations()).unwrap();
+    release.join().unwrap();
+    reshape.complete().unwrap();
+
+    let migration = reshape
+        .completed_migration("add_email_column")
+        .unwrap()
+        .unwrap();
+    assert!(migration.stats[0].retries > 0);
+
+    reshape.remove().unwrap();
+}
+
+fn migrations() -> Vec<Migration> {
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "add_email_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "users"
+        up = "'unknown'"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+            nullable = false
+        "#,
+    )
+    .unwrap();
+
+    vec![first_migration, second_migration]
+}
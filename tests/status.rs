@@ -0,0 +1,200 @@
+use reshape::{migrations::Migration, testing, State};
+
+#[test]
+fn status_while_locked() {
+    let (mut reshape, mut other) = testing::connect();
+    reshape.remove().unwrap();
+
+    let migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+    reshape.migrate(vec![migration]).unwrap();
+
+    // Simulate another instance of Reshape holding the lock
+    other
+        .simple_query("SELECT pg_advisory_lock(4036779288569897133)")
+        .unwrap();
+
+    let err = reshape.complete().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::ConflictingState(_))
+    ));
+
+    // Reading the status doesn't need the lock
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::InProgress { .. }));
+    assert_eq!(None, status.current_migration);
+
+    other
+        .simple_query("SELECT pg_advisory_unlock(4036779288569897133)")
+        .unwrap();
+
+    reshape.complete().unwrap();
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::Idle));
+    assert_eq!(
+        Some("create_users_table".to_string()),
+        status.current_migration
+    );
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn current_schema_name_from_database() {
+    let mut reshape = reshape::Reshape::new(&testing::connection_string()).unwrap();
+    reshape.remove().unwrap();
+
+    assert_eq!(None, reshape.current_schema_name().unwrap());
+
+    let first_migration = Migration::new("create_users", None);
+    let second_migration = Migration::new("create_items", None);
+
+    reshape.migrate(vec![first_migration.clone()]).unwrap();
+    reshape.complete().unwrap();
+    assert_eq!(
+        Some("migration_create_users".to_string()),
+        reshape.current_schema_name().unwrap()
+    );
+
+    // New deployments should use the new schema while the migration is in progress
+    reshape
+        .migrate(vec![first_migration, second_migration])
+        .unwrap();
+    assert_eq!(
+        Some("migration_create_items".to_string()),
+        reshape.current_schema_name().unwrap()
+    );
+    assert_eq!(
+        Some("SET search_path TO migration_create_items".to_string()),
+        reshape.current_schema_query().unwrap()
+    );
+
+    reshape.abort().unwrap();
+    assert_eq!(
+        Some("migration_create_users".to_string()),
+        reshape.current_schema_name().unwrap()
+    );
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn status_shows_backfill_progress() {
+    let (mut reshape, mut other) = testing::connect();
+    reshape.remove().unwrap();
+
+    reshape
+        .migrate(backfill_migrations().into_iter().take(1))
+        .unwrap();
+    reshape.complete().unwrap();
+
+    // The backfill is held up by the last row until the advisory lock is released,
+    // after the first two batches of 1000 rows have been processed
+    other
+        .simple_query(
+            "
+            INSERT INTO public.items (id, value) SELECT i, i FROM generate_series(1, 2500) AS i;
+            ANALYZE public.items;
+            CREATE OR REPLACE FUNCTION public.wait_and_double(id INTEGER, value INTEGER)
+            RETURNS INTEGER AS $$
+            BEGIN
+                IF id = 2500 THEN
+                    PERFORM pg_advisory_lock(1234);
+                    PERFORM pg_advisory_unlock(1234);
+                END IF;
+                RETURN value * 2;
+            END
+            $$ LANGUAGE plpgsql;
+            SELECT pg_advisory_lock(1234);
+            ",
+        )
+        .unwrap();
+
+    let migrating = std::thread::spawn(|| {
+        let (mut reshape, _) = testing::connect();
+        reshape.migrate(backfill_migrations()).unwrap();
+    });
+
+    let mut backfill = None;
+    for _ in 0..300 {
+        backfill = reshape.status().unwrap().backfill;
+        if backfill
+            .as_ref()
+            .is_some_and(|backfill| backfill.rows_processed == 2000)
+        {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    let backfill = backfill.expect("backfill progress should be reported");
+    assert_eq!("items", backfill.table);
+    assert_eq!(2000, backfill.rows_processed);
+    assert_eq!(Some(2500), backfill.estimated_rows);
+    assert_eq!(Some(80), backfill.percent());
+
+    other
+        .simple_query("SELECT pg_advisory_unlock(1234)")
+        .unwrap();
+    migrating.join().unwrap();
+
+    // Nothing is reported once the backfill has finished
+    let status = reshape.status().unwrap();
+    assert!(matches!(status.state, State::InProgress { .. }));
+    assert_eq!(None, status.backfill);
+
+    reshape.abort().unwrap();
+    reshape.remove().unwrap();
+    other
+        .simple_query("DROP FUNCTION public.wait_and_double")
+        .unwrap();
+}
+
+fn backfill_migrations() -> Vec<Migration> {
+    [
+        r#"
+        name = "create_items"
+
+        [[actions]]
+        type = "create_table"
+        name = "items"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "value"
+            type = "INTEGER"
+        "#,
+        r#"
+        name = "add_doubled"
+
+        [[actions]]
+        type = "add_column"
+        table = "items"
+        up = "wait_and_double(id, value)"
+
+            [actions.column]
+            name = "doubled"
+            type = "INTEGER"
+        "#,
+    ]
+    .iter()
+    .map(|migration| toml::from_str(migration).unwrap())
+    .collect()
+}
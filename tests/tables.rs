@@ -0,0 +1,90 @@
+use postgres::Client;
+use reshape::{migrations::Migration, testing, Reshape, TableFilter};
+
+#[test]
+fn excluded_tables_are_left_alone() {
+    let (mut reshape, mut db) = set_up();
+    db.simple_query("CREATE TABLE spatial_ref_sys (srid INTEGER)")
+        .unwrap();
+
+    reshape.set_tables(TableFilter::new().with_exclude(vec!["spatial_ref_sys".to_string()]));
+    reshape.migrate(migrations()).unwrap();
+    assert!(relation_exists(&mut db, "migration_create_users", "users"));
+    assert!(!relation_exists(
+        &mut db,
+        "migration_create_users",
+        "spatial_ref_sys"
+    ));
+
+    reshape.remove().unwrap();
+    assert!(relation_exists(&mut db, "public", "spatial_ref_sys"));
+    assert!(!relation_exists(&mut db, "public", "users"));
+
+    db.simple_query("DROP TABLE spatial_ref_sys").unwrap();
+}
+
+#[test]
+fn only_included_tables_are_managed() {
+    let (mut reshape, mut db) = set_up();
+    db.simple_query("CREATE TABLE jobs (id INTEGER)").unwrap();
+
+    reshape.set_tables(TableFilter::new().with_include(vec!["user*".to_string()]));
+    reshape.migrate(migrations()).unwrap();
+    assert!(relation_exists(&mut db, "migration_create_users", "users"));
+    assert!(!relation_exists(&mut db, "migration_create_users", "jobs"));
+
+    reshape.remove().unwrap();
+    assert!(relation_exists(&mut db, "public", "jobs"));
+
+    db.simple_query("DROP TABLE jobs").unwrap();
+}
+
+#[test]
+fn table_patterns() {
+    let filter = TableFilter::new()
+        .with_include(vec!["app_*".to_string(), "user?".to_string()])
+        .with_exclude(vec!["app_*_archive".to_string()]);
+
+    assert!(filter.manages("app_orders"));
+    assert!(filter.manages("app_"));
+    assert!(filter.manages("users"));
+    assert!(!filter.manages("user"));
+    assert!(!filter.manages("app_orders_archive"));
+    assert!(!filter.manages("spatial_ref_sys"));
+
+    assert!(TableFilter::new().manages("anything"));
+}
+
+fn set_up() -> (Reshape, Client) {
+    let (mut reshape, db) = testing::connect();
+    reshape.remove().unwrap();
+
+    (reshape, db)
+}
+
+fn migrations() -> Vec<Migration> {
+    vec![toml::from_str(
+        r#"
+        name = "create_users"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap()]
+}
+
+fn relation_exists(db: &mut Client, schema: &str, relation: &str) -> bool {
+    db.query_one(
+        "SELECT to_regclass(format('%I.%I', $1::TEXT, $2::TEXT)) IS NOT NULL",
+        &[&schema, &relation],
+    )
+    .unwrap()
+    .get(0)
+}
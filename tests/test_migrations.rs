@@ -0,0 +1,122 @@
+use postgres::{Client, NoTls};
+use reshape::{prelude::*, testing, ScratchDatabase};
+
+#[test]
+fn test_migrations_against_scratch_database() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    let create_users = Migration::new("1_create_users", None).with_action(
+        CreateTable::new("users", ["id"])
+            .with_column(Column::new("id", "INTEGER"))
+            .with_column(Column::new("name", "TEXT")),
+    );
+    let alter_name = Migration::new("2_alter_name", None).with_action(
+        AlterColumn::new("users", "name")
+            .with_up("UPPER(name)")
+            .with_down("LOWER(name)"),
+    );
+
+    reshape
+        .test(vec![create_users.clone(), alter_name.clone()])
+        .unwrap();
+    assert_eq!(
+        Some("2_alter_name".to_string()),
+        reshape.status().unwrap().current_migration
+    );
+
+    // The latest migration has already been applied
+    let err = reshape
+        .test(vec![create_users.clone(), alter_name])
+        .unwrap_err();
+    assert_eq!(Some("validation"), err.downcast_ref().map(Error::kind));
+
+    // Tables which weren't created by Reshape must not be touched
+    reshape.remove().unwrap();
+    db.simple_query("CREATE TABLE users (id INTEGER)").unwrap();
+    let err = reshape.test(vec![create_users.clone()]).unwrap_err();
+    assert_eq!(
+        Some("conflicting_state"),
+        err.downcast_ref().map(Error::kind)
+    );
+
+    // A migration which doesn't clean up after itself when aborted
+    let leaky = Migration::new("2_leaky", None).with_action(
+        Custom::new()
+            .with_start(r#"ALTER TABLE users ADD COLUMN "__reshape_leaky" INTEGER"#)
+            .with_complete(r#"ALTER TABLE users DROP COLUMN "__reshape_leaky""#),
+    );
+
+    reshape.remove().unwrap();
+    let err = reshape.test(vec![create_users, leaky]).unwrap_err();
+    assert!(
+        err.to_string().contains("column __reshape_leaky on users"),
+        "unexpected error: {}",
+        err
+    );
+
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn test_migrations_against_template_copy() {
+    let connection_string = testing::connection_string();
+    let config: postgres::Config = connection_string.parse().unwrap();
+    let mut db = Client::connect(&connection_string, NoTls).unwrap();
+
+    let create_users = Migration::new("1_create_users", None)
+        .with_action(CreateTable::new("users", ["id"]).with_column(Column::new("id", "INTEGER")));
+    let add_name = Migration::new("2_add_name", None)
+        .with_action(AddColumn::new("users", Column::new("name", "TEXT")));
+
+    // Prepare a template with the first migration already applied
+    db.simple_query("DROP DATABASE IF EXISTS reshape_test_template")
+        .unwrap();
+    db.simple_query("CREATE DATABASE reshape_test_template")
+        .unwrap();
+    let mut template_config = config.clone();
+    template_config.dbname("reshape_test_template");
+    {
+        let mut reshape = Reshape::new_with_config(&template_config).unwrap();
+        reshape.migrate(vec![create_users.clone()]).unwrap();
+        reshape.complete().unwrap();
+    }
+
+    let scratch = ScratchDatabase::create(&config, "reshape_test_template").unwrap();
+    let name = scratch.name().to_string();
+    {
+        let mut reshape = scratch.reshape().unwrap();
+        reshape
+            .test(vec![create_users.clone(), add_name.clone()])
+            .unwrap();
+    }
+    scratch.remove().unwrap();
+
+    let exists = !db
+        .query("SELECT 1 FROM pg_database WHERE datname = $1", &[&name])
+        .unwrap()
+        .is_empty();
+    assert!(!exists, "expected scratch database to be dropped");
+
+    // The template itself is left untouched
+    let mut reshape = Reshape::new_with_config(&template_config).unwrap();
+    assert_eq!(
+        Some("1_create_users".to_string()),
+        reshape.status().unwrap().current_migration
+    );
+    drop(reshape);
+
+    // Scratch databases are also dropped when they go out of scope, even with
+    // connections still open
+    let scratch = ScratchDatabase::create(&config, "reshape_test_template").unwrap();
+    let name = scratch.name().to_string();
+    let _reshape = scratch.reshape().unwrap();
+    drop(scratch);
+    assert!(db
+        .query("SELECT 1 FROM pg_database WHERE datname = $1", &[&name])
+        .unwrap()
+        .is_empty());
+
+    db.simple_query("DROP DATABASE reshape_test_template")
+        .unwrap();
+}
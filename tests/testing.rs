@@ -0,0 +1,77 @@
+use std::fs;
+
+use reshape::testing::Test;
+
+#[test]
+fn migrations_from_files() {
+    let dir = std::env::temp_dir().join("reshape_testing_migrations");
+    fs::create_dir_all(&dir).unwrap();
+
+    fs::write(
+        dir.join("1_create_users_table.toml"),
+        r#"
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+        "#,
+    )
+    .unwrap();
+    fs::write(
+        dir.join("2_add_name_column.json"),
+        r#"
+        {
+            "name": "add_name",
+            "actions": [
+                {
+                    "type": "add_column",
+                    "table": "users",
+                    "column": { "name": "name", "type": "TEXT" }
+                }
+            ]
+        }
+        "#,
+    )
+    .unwrap();
+
+    let mut test = Test::new("Migrations from files");
+    test.first_migration_file(dir.join("1_create_users_table.toml"));
+    test.second_migration_file(dir.join("2_add_name_column.json"));
+
+    test.after_first(|db| {
+        // The first migration is named after its file
+        let search_path: String = db
+            .query_one("SHOW search_path", &[])
+            .unwrap()
+            .get("search_path");
+        assert!(search_path.contains("1_create_users_table"));
+
+        db.simple_query("INSERT INTO users (id) VALUES (1)")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        old_db
+            .simple_query("INSERT INTO users (id) VALUES (2)")
+            .unwrap();
+        new_db
+            .simple_query("INSERT INTO users (id, name) VALUES (3, 'Test')")
+            .unwrap();
+    });
+
+    test.after_completion(|db| {
+        let names: Vec<Option<String>> = db
+            .query("SELECT name FROM users ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get("name"))
+            .collect();
+        assert_eq!(vec![None, None, Some("Test".to_string())], names);
+    });
+
+    test.run();
+}
@@ -0,0 +1,110 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use reshape::{migrations::Migration, testing::connect, Throttle};
+
+fn migrations() -> Vec<Migration> {
+    let first_migration: Migration = toml::from_str(
+        r#"
+        name = "create_users_table"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "name"
+            type = "TEXT"
+        "#,
+    )
+    .unwrap();
+
+    let second_migration: Migration = toml::from_str(
+        r#"
+        name = "uppercase_name"
+
+        [[actions]]
+        type = "alter_column"
+        table = "users"
+        column = "name"
+        up = "UPPER(name)"
+        down = "LOWER(name)"
+        "#,
+    )
+    .unwrap();
+
+    vec![first_migration, second_migration]
+}
+
+#[test]
+fn throttled_backfill_waits_for_probe() {
+    let (mut reshape, mut db) = connect();
+    reshape.remove().unwrap();
+
+    let migrations = migrations();
+    reshape.migrate(migrations[..1].to_vec()).unwrap();
+    reshape.complete().unwrap();
+    db.simple_query("INSERT INTO public.users (id, name) VALUES (1, 'john'), (2, 'jane')")
+        .unwrap();
+
+    // The backfill is paused until the `backfill_allowed` table is created
+    reshape.set_throttle(
+        Throttle::new()
+            .with_probe("SELECT to_regclass('public.backfill_allowed') IS NULL")
+            .with_check_interval(Duration::from_millis(50)),
+    );
+
+    let started_at = Instant::now();
+    let unpause = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(500));
+        db.simple_query("CREATE TABLE public.backfill_allowed ()")
+            .unwrap();
+        db
+    });
+
+    reshape.migrate(migrations).unwrap();
+    assert!(started_at.elapsed() >= Duration::from_millis(500));
+
+    let mut db = unpause.join().unwrap();
+    let names: Vec<String> = db
+        .query(
+            "SELECT name FROM migration_uppercase_name.users ORDER BY id",
+            &[],
+        )
+        .unwrap()
+        .iter()
+        .map(|row| row.get("name"))
+        .collect();
+    assert_eq!(vec!["JOHN", "JANE"], names);
+
+    reshape.complete().unwrap();
+    reshape.remove().unwrap();
+}
+
+#[test]
+fn throttle_probe_must_return_boolean() {
+    let (mut reshape, mut db) = connect();
+    reshape.remove().unwrap();
+
+    let migrations = migrations();
+    reshape.migrate(migrations[..1].to_vec()).unwrap();
+    reshape.complete().unwrap();
+    db.simple_query("INSERT INTO public.users (id, name) VALUES (1, 'john')")
+        .unwrap();
+
+    reshape.set_throttle(Throttle::new().with_probe("SELECT 1"));
+    let err = reshape.migrate(migrations).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<reshape::Error>(),
+        Some(reshape::Error::MigrationFailed(_))
+    ));
+
+    reshape.remove().unwrap();
+}
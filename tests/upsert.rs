@@ -0,0 +1,264 @@
+use reshape::testing::Test;
+
+// Upserts first fire the BEFORE INSERT triggers for the proposed row, even when the
+// insert ends up as an update of the conflicting row or not happening at all. Changes
+// to other tables must only be made for the rows which are actually written.
+
+#[test]
+fn upsert_moved_column() {
+    let mut test = Test::new("Upsert moved column");
+
+    test.first_migration(
+        r#"
+        name = "create_tables"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "email"
+            type = "TEXT"
+
+        [[actions]]
+        type = "create_table"
+        name = "profiles"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "user_id"
+            type = "INTEGER"
+            nullable = false
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "move_email_column"
+
+        [[actions]]
+        type = "add_column"
+        table = "profiles"
+
+            [actions.column]
+            name = "email"
+            type = "TEXT"
+            nullable = false
+
+            [actions.up]
+            table = "users"
+            value = "users.email"
+            where = "profiles.user_id = users.id"
+
+        [[actions]]
+        type = "remove_column"
+        table = "users"
+        column = "email"
+
+            [actions.down]
+            table = "profiles"
+            value = "profiles.email"
+            where = "users.id = profiles.user_id"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "
+            INSERT INTO users (id, email) VALUES (1, 'one@test.com'), (2, 'two@test.com');
+            INSERT INTO profiles (id, user_id) VALUES (1, 1), (2, 2);
+            ",
+        )
+        .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Upserts in the old schema which don't insert or update a user must not
+        // change any profiles
+        old_db
+            .simple_query(
+                "INSERT INTO users (id, email) VALUES (1, 'ignored@test.com') ON CONFLICT (id) DO NOTHING",
+            )
+            .unwrap();
+        assert_eq!("one@test.com", profile_email(new_db, 1));
+
+        old_db
+            .simple_query(
+                "
+                INSERT INTO users (id, email) VALUES (1, 'proposed@test.com')
+                ON CONFLICT (id) DO UPDATE SET email = 'one+updated@test.com'
+                ",
+            )
+            .unwrap();
+        assert_eq!("one+updated@test.com", profile_email(new_db, 1));
+
+        // The same goes for upserts of profiles in the new schema
+        new_db
+            .simple_query(
+                "
+                INSERT INTO profiles (id, user_id, email) VALUES (2, 2, 'ignored@test.com')
+                ON CONFLICT (id) DO NOTHING
+                ",
+            )
+            .unwrap();
+        assert_eq!("two@test.com", user_email(old_db, 2));
+
+        new_db
+            .simple_query(
+                "
+                INSERT INTO profiles (id, user_id, email) VALUES (2, 2, 'proposed@test.com')
+                ON CONFLICT (id) DO UPDATE SET email = 'two+updated@test.com'
+                ",
+            )
+            .unwrap();
+        assert_eq!("two+updated@test.com", user_email(old_db, 2));
+
+        // Upserts which insert new rows are propagated like any other insert
+        old_db
+            .simple_query(
+                "
+                INSERT INTO users (id, email) VALUES (3, 'three@test.com') ON CONFLICT (id) DO NOTHING;
+                INSERT INTO profiles (id, user_id) VALUES (3, 3) ON CONFLICT (id) DO NOTHING;
+                ",
+            )
+            .unwrap();
+        assert_eq!("three@test.com", profile_email(new_db, 3));
+    });
+
+    test.run();
+}
+
+#[test]
+fn upsert_extracted_table() {
+    let mut test = Test::new("Upsert extracted table");
+
+    test.first_migration(
+        r#"
+        name = "create_users"
+
+        [[actions]]
+        type = "create_table"
+        name = "users"
+        primary_key = ["id"]
+
+            [[actions.columns]]
+            name = "id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "account_id"
+            type = "INTEGER"
+            nullable = false
+
+            [[actions.columns]]
+            name = "account_role"
+            type = "TEXT"
+            nullable = false
+        "#,
+    );
+
+    test.second_migration(
+        r#"
+        name = "extract_connections"
+
+        [[actions]]
+        type = "create_table"
+        name = "user_account_connections"
+        primary_key = ["account_id", "user_id"]
+
+            [[actions.columns]]
+            name = "account_id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "user_id"
+            type = "INTEGER"
+
+            [[actions.columns]]
+            name = "role"
+            type = "TEXT"
+            nullable = false
+
+            [actions.up]
+            table = "users"
+            values = { user_id = "id", account_id = "account_id", role = "UPPER(account_role)" }
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (id, account_id, account_role) VALUES (1, 1, 'admin')")
+            .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // A conflicting upsert which does nothing must not create a connection for the
+        // proposed row
+        old_db
+            .simple_query(
+                "
+                INSERT INTO users (id, account_id, account_role) VALUES (1, 2, 'developer')
+                ON CONFLICT (id) DO NOTHING
+                ",
+            )
+            .unwrap();
+        assert_eq!(vec![(1, 1, "ADMIN".to_string())], connections(new_db));
+
+        // A conflicting upsert which updates the user updates its connection
+        old_db
+            .simple_query(
+                "
+                INSERT INTO users (id, account_id, account_role) VALUES (1, 2, 'developer')
+                ON CONFLICT (id) DO UPDATE SET account_role = EXCLUDED.account_role
+                ",
+            )
+            .unwrap();
+        assert_eq!(vec![(1, 1, "DEVELOPER".to_string())], connections(new_db));
+
+        old_db
+            .simple_query(
+                "
+                INSERT INTO users (id, account_id, account_role) VALUES (2, 1, 'admin')
+                ON CONFLICT (id) DO UPDATE SET account_role = EXCLUDED.account_role
+                ",
+            )
+            .unwrap();
+        assert_eq!(
+            vec![(1, 1, "DEVELOPER".to_string()), (1, 2, "ADMIN".to_string())],
+            connections(new_db)
+        );
+    });
+
+    test.run();
+}
+
+fn profile_email(db: &mut postgres::Client, id: i32) -> String {
+    db.query_one("SELECT email FROM profiles WHERE id = $1", &[&id])
+        .unwrap()
+        .get(0)
+}
+
+fn user_email(db: &mut postgres::Client, id: i32) -> String {
+    db.query_one("SELECT email FROM users WHERE id = $1", &[&id])
+        .unwrap()
+        .get(0)
+}
+
+fn connections(db: &mut postgres::Client) -> Vec<(i32, i32, String)> {
+    db.query(
+        "SELECT account_id, user_id, role FROM user_account_connections ORDER BY user_id",
+        &[],
+    )
+    .unwrap()
+    .iter()
+    .map(|row| (row.get(0), row.get(1), row.get(2)))
+    .collect()
+}
@@ -0,0 +1,176 @@
+use reshape::{
+    migrations::{check_migrations, validate_migrations},
+    prelude::*,
+    testing,
+};
+
+#[test]
+fn invalid_names_are_rejected() {
+    let invalid_migrations = vec![
+        vec![Migration::new("create-users", None)],
+        vec![Migration::new("a".repeat(60), None)],
+        vec![
+            Migration::new("create_users", None),
+            Migration::new("Create_Users", None),
+        ],
+        vec![Migration::new("create_users", None).with_action(
+            CreateTable::new("__reshape_users", vec!["id".to_string()])
+                .with_column(Column::new("id", "INTEGER")),
+        )],
+        vec![
+            Migration::new("add_column", None).with_action(AddColumn::new(
+                "users",
+                Column::new("__reshape_temp", "INTEGER"),
+            )),
+        ],
+    ];
+
+    for migrations in invalid_migrations {
+        assert!(validate_migrations(&migrations).is_err());
+    }
+
+    let valid_migrations = vec![Migration::new("1_create_users", None).with_action(
+        CreateTable::new("users", vec!["id".to_string()]).with_column(Column::new("id", "INTEGER")),
+    )];
+    validate_migrations(&valid_migrations).unwrap();
+}
+
+#[test]
+fn check_migrations_offline() {
+    let valid_migrations = vec![
+        Migration::new("1_create_tables", None)
+            .with_action(
+                CreateTable::new("users", ["id"])
+                    .with_column(Column::new("id", "INTEGER"))
+                    .with_column(Column::new("name", "TEXT")),
+            )
+            .with_action(
+                CreateTable::new("items", ["id"])
+                    .with_column(Column::new("id", "INTEGER"))
+                    .with_column(Column::new("user_id", "INTEGER"))
+                    .with_foreign_key(ForeignKey::new(["user_id"], "users", ["id"])),
+            ),
+        Migration::new("2_rename", None)
+            .with_action(
+                AlterColumn::new("users", "name")
+                    .with_changes(ColumnChanges::new().with_name("full_name")),
+            )
+            .with_action(RenameTable::new("users", "customers"))
+            .with_action(AddIndex::new(
+                "customers",
+                Index::new("customers_full_name_idx", ["full_name"]),
+            )),
+        Migration::new("3_clean_up", None)
+            .with_action(RemoveIndex::new("customers_full_name_idx"))
+            .with_action(RemoveColumn::new("customers", "full_name"))
+            .with_action(RemoveTable::new("items")),
+    ];
+    assert!(check_migrations(&valid_migrations).is_empty());
+
+    let invalid_migrations = vec![
+        Migration::new("1_create_users", None).with_action(
+            CreateTable::new("users", ["id"])
+                .with_column(Column::new("id", "INTEGER"))
+                .with_column(Column::new("name", "TEXT")),
+        ),
+        Migration::new("2_invalid", None)
+            .with_action(AddColumn::new("users", Column::new("name", "TEXT")))
+            .with_action(AddIndex::new(
+                "users",
+                Index::new("users_email_idx", ["email"]),
+            ))
+            .with_action(RemoveColumn::new("profiles", "bio"))
+            .with_action(RemoveEnum::new("mood")),
+    ];
+    let problems: Vec<(usize, String)> = check_migrations(&invalid_migrations)
+        .into_iter()
+        .map(|problem| {
+            assert_eq!("2_invalid", problem.migration);
+            (problem.action_index, problem.message)
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            (
+                0,
+                "column \"name\" already exists on table \"users\"".to_string()
+            ),
+            (
+                1,
+                "column \"email\" doesn't exist on table \"users\"".to_string()
+            ),
+            (2, "table \"profiles\" doesn't exist".to_string()),
+            (3, "type \"mood\" doesn't exist".to_string()),
+        ],
+        problems
+    );
+}
+
+#[test]
+fn actions_out_of_order_are_rejected() {
+    let out_of_order = vec![Migration::new("1_create_users", None)
+        .with_action(AddIndex::new(
+            "users",
+            Index::new("users_name_idx", ["name"]),
+        ))
+        .with_action(
+            CreateTable::new("users", ["id"])
+                .with_column(Column::new("id", "INTEGER"))
+                .with_column(Column::new("name", "TEXT")),
+        )];
+
+    let problems: Vec<String> = check_migrations(&out_of_order)
+        .into_iter()
+        .map(|problem| problem.message)
+        .collect();
+    assert_eq!(
+        vec!["table \"users\" doesn't exist, it's created by action 2 (Creating table \"users\") which must be moved before this one".to_string()],
+        problems
+    );
+
+    let err = validate_migrations(&out_of_order).unwrap_err();
+    assert!(format!("{:#}", err).contains("actions are in the wrong order"));
+
+    // Objects which aren't created by the migration are assumed to exist already, and
+    // an object which is removed before being created again existed before as well
+    let valid_migrations = vec![Migration::new("1_replace_users", None)
+        .with_action(AddIndex::new(
+            "accounts",
+            Index::new("accounts_name_idx", ["name"]),
+        ))
+        .with_action(RemoveTable::new("users"))
+        .with_action(
+            CreateTable::new("users", ["id"])
+                .with_column(Column::new("id", "INTEGER"))
+                .with_column(Column::new("name", "TEXT")),
+        )];
+    validate_migrations(&valid_migrations).unwrap();
+}
+
+#[test]
+fn existing_schema_collision_is_rejected() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+
+    db.simple_query("CREATE SCHEMA migration_create_users")
+        .unwrap();
+
+    let migrations = vec![Migration::new("create_users", None).with_action(
+        CreateTable::new("users", vec!["id".to_string()]).with_column(Column::new("id", "INTEGER")),
+    )];
+    let err = reshape.migrate(migrations).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::Validation(_))
+    ));
+
+    // Nothing should have been changed
+    let tables = db
+        .query("SELECT 1 FROM pg_tables WHERE tablename = 'users'", &[])
+        .unwrap();
+    assert!(tables.is_empty());
+
+    db.simple_query("DROP SCHEMA migration_create_users")
+        .unwrap();
+    reshape.remove().unwrap();
+}
@@ -0,0 +1,103 @@
+use postgres::Client;
+use reshape::prelude::*;
+use reshape::testing;
+
+#[test]
+fn view_metadata() {
+    let (mut reshape, mut db) = testing::connect();
+    reshape.remove().unwrap();
+    reshape.set_view_metadata(true);
+
+    let create_tables = Migration::new("create_tables", None)
+        .with_action(
+            CreateTable::new("users", ["id"])
+                .with_column(Column::new("id", "INTEGER"))
+                .with_column(Column::new("email", "TEXT"))
+                .with_unique(["email"]),
+        )
+        .with_action(
+            CreateTable::new("posts", ["id"])
+                .with_column(Column::new("id", "INTEGER"))
+                .with_column(Column::new("author_id", "INTEGER"))
+                .with_foreign_key(ForeignKey::new(["author_id"], "users", ["id"])),
+        )
+        .with_action(SetTableComment::new("users", Some("Users who can sign in")))
+        .with_action(SetColumnComment::new(
+            "users",
+            "email",
+            Some("Where to send mail"),
+        ));
+    let rename_id = Migration::new("rename_user_id", None).with_action(
+        AlterColumn::new("users", "id").with_changes(ColumnChanges::new().with_name("user_id")),
+    );
+
+    reshape.migrate(vec![create_tables.clone()]).unwrap();
+    reshape.complete().unwrap();
+
+    assert_eq!(
+        Some("@primaryKey id\n@unique email\nUsers who can sign in".to_string()),
+        view_comment(&mut db, "migration_create_tables.users")
+    );
+    assert_eq!(
+        Some("@primaryKey id\n@foreignKey (author_id) references users (id)".to_string()),
+        view_comment(&mut db, "migration_create_tables.posts")
+    );
+    assert_eq!(
+        Some("Where to send mail".to_string()),
+        column_comment(&mut db, "migration_create_tables.users", "email")
+    );
+
+    // Columns are referred to by their names in the new schema while migrating
+    reshape
+        .migrate(vec![create_tables.clone(), rename_id.clone()])
+        .unwrap();
+    assert_eq!(
+        Some("@primaryKey id\n@foreignKey (author_id) references users (user_id)".to_string()),
+        view_comment(&mut db, "migration_rename_user_id.posts")
+    );
+    assert_eq!(
+        Some("@primaryKey user_id\n@unique email\nUsers who can sign in".to_string()),
+        view_comment(&mut db, "migration_rename_user_id.users")
+    );
+
+    reshape.set_retained_schemas(1);
+    reshape.complete().unwrap();
+    assert_eq!(
+        Some("@primaryKey user_id\n@unique email\nUsers who can sign in".to_string()),
+        view_comment(&mut db, "migration_rename_user_id.users")
+    );
+
+    // Retained schemas are recreated without comments
+    assert_eq!(None, view_comment(&mut db, "migration_create_tables.users"));
+    reshape.set_retained_schemas(0);
+
+    // Views aren't commented unless enabled
+    reshape.remove().unwrap();
+    reshape.set_view_metadata(false);
+    reshape.migrate(vec![create_tables]).unwrap();
+    assert_eq!(None, view_comment(&mut db, "migration_create_tables.posts"));
+
+    reshape.remove().unwrap();
+}
+
+fn view_comment(db: &mut Client, view: &str) -> Option<String> {
+    db.query_one(
+        "SELECT obj_description(to_regclass($1), 'pg_class') AS comment",
+        &[&view],
+    )
+    .unwrap()
+    .get("comment")
+}
+
+fn column_comment(db: &mut Client, view: &str, column: &str) -> Option<String> {
+    db.query_one(
+        "
+        SELECT col_description(attrelid, attnum) AS comment
+        FROM pg_attribute
+        WHERE attrelid = to_regclass($1) AND attname = $2
+        ",
+        &[&view, &column],
+    )
+    .unwrap()
+    .get("comment")
+}
@@ -0,0 +1,278 @@
+use postgres::Client;
+use reshape::testing::Test;
+
+fn column_type(db: &mut Client, table: &str, column: &str) -> String {
+    db.query_one(
+        "
+        SELECT data_type::TEXT
+        FROM information_schema.columns
+        WHERE table_schema = 'public' AND table_name = $1 AND column_name = $2
+        ",
+        &[&table, &column],
+    )
+    .unwrap()
+    .get(0)
+}
+
+const CREATE_TABLES: &str = r#"
+    name = "create_tables"
+
+    [[actions]]
+    type = "create_table"
+    name = "users"
+    primary_key = ["id"]
+
+        [[actions.columns]]
+        name = "id"
+        type = "SERIAL"
+
+        [[actions.columns]]
+        name = "name"
+        type = "TEXT"
+
+    [[actions]]
+    type = "create_table"
+    name = "orders"
+    primary_key = ["id"]
+
+        [[actions.columns]]
+        name = "id"
+        type = "INTEGER"
+
+        [[actions.columns]]
+        name = "user_id"
+        type = "INTEGER"
+
+        [[actions.foreign_keys]]
+        columns = ["user_id"]
+        referenced_table = "users"
+        referenced_columns = ["id"]
+"#;
+
+#[test]
+fn widen_primary_key() {
+    let mut test = Test::new("Widen primary key");
+    test.first_migration(CREATE_TABLES);
+    test.second_migration(
+        r#"
+        name = "widen_users_id"
+
+        [[actions]]
+        type = "widen_primary_key"
+        table = "users"
+        column = "id"
+
+            [[actions.referencing_columns]]
+            table = "orders"
+            column = "user_id"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query(
+            "
+            INSERT INTO users (name) VALUES ('John'), ('Jane');
+            INSERT INTO orders (id, user_id) VALUES (1, 1), (2, 2);
+            ",
+        )
+        .unwrap();
+    });
+
+    test.intermediate(|old_db, new_db| {
+        // Both schemas get keys from the same sequence
+        old_db
+            .simple_query("INSERT INTO users (name) VALUES ('Jack')")
+            .unwrap();
+        new_db
+            .simple_query("INSERT INTO users (name) VALUES ('Jill')")
+            .unwrap();
+
+        let new_ids: Vec<i64> = new_db
+            .query("SELECT id FROM users ORDER BY name", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        let old_ids: Vec<i64> = old_db
+            .query("SELECT id::BIGINT FROM users ORDER BY name", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(4, new_ids.len());
+        assert_eq!(old_ids, new_ids);
+
+        let user_ids: Vec<i64> = new_db
+            .query("SELECT user_id FROM orders ORDER BY id", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(vec![1, 2], user_ids);
+    });
+
+    test.after_completion(|db| {
+        assert_eq!("bigint", column_type(db, "users", "id"));
+        assert_eq!("bigint", column_type(db, "orders", "user_id"));
+
+        // The primary key and foreign key have been moved over to the new columns
+        let constraints: Vec<(String, bool)> = db
+            .query(
+                "
+                SELECT contype::TEXT, convalidated
+                FROM pg_constraint
+                WHERE conrelid IN ('public.users'::regclass, 'public.orders'::regclass)
+                    AND contype IN ('p', 'f')
+                ORDER BY conrelid::regclass::TEXT, contype
+                ",
+                &[],
+            )
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        assert_eq!(
+            vec![
+                ("f".to_string(), true),
+                ("p".to_string(), true),
+                ("p".to_string(), true)
+            ],
+            constraints
+        );
+
+        let result = db.simple_query("INSERT INTO orders (id, user_id) VALUES (3, 100)");
+        assert!(result.is_err(), "expected foreign key to be enforced");
+
+        // The serial sequence has been widened and can go past the INTEGER range
+        db.simple_query(
+            "
+            SELECT setval(pg_get_serial_sequence('public.users', 'id'), 3000000000);
+            INSERT INTO users (name) VALUES ('Joe');
+            ",
+        )
+        .unwrap();
+        let id: i64 = db
+            .query_one("SELECT id FROM users WHERE name = 'Joe'", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(3000000001, id);
+    });
+
+    test.after_abort(|db| {
+        assert_eq!("integer", column_type(db, "users", "id"));
+        assert_eq!("integer", column_type(db, "orders", "user_id"));
+    });
+
+    test.crash_at_every_statement().run();
+}
+
+#[test]
+fn widen_primary_key_to_identity() {
+    let mut test = Test::new("Widen primary key to identity");
+    test.first_migration(CREATE_TABLES);
+    test.second_migration(
+        r#"
+        name = "widen_users_id"
+
+        [[actions]]
+        type = "widen_primary_key"
+        table = "users"
+        column = "id"
+        identity = "always"
+
+            [[actions.referencing_columns]]
+            table = "orders"
+            column = "user_id"
+        "#,
+    );
+
+    test.after_first(|db| {
+        db.simple_query("INSERT INTO users (name) VALUES ('John'), ('Jane')")
+            .unwrap();
+    });
+
+    test.after_completion(|db| {
+        let identity: String = db
+            .query_one(
+                "
+                SELECT attidentity::TEXT
+                FROM pg_attribute
+                WHERE attrelid = 'public.users'::regclass AND attname = 'id'
+                ",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!("a", identity);
+
+        // The identity continues where the serial sequence was
+        db.simple_query("INSERT INTO users (name) VALUES ('Jack')")
+            .unwrap();
+        let (id, max_id): (i64, i64) = db
+            .query_one(
+                "
+                SELECT
+                    (SELECT id FROM users WHERE name = 'Jack'),
+                    (SELECT MAX(id) FROM users WHERE name <> 'Jack')
+                ",
+                &[],
+            )
+            .map(|row| (row.get(0), row.get(1)))
+            .unwrap();
+        assert!(id > max_id);
+
+        // The serial sequence has been replaced by the identity sequence
+        let sequences: i64 = db
+            .query_one(
+                "
+                SELECT COUNT(*)
+                FROM pg_class
+                WHERE relkind = 'S' AND relnamespace = 'public'::regnamespace
+                ",
+                &[],
+            )
+            .unwrap()
+            .get(0);
+        assert_eq!(1, sequences);
+    });
+
+    test.run();
+}
+
+#[test]
+fn widen_primary_key_without_referencing_column() {
+    let mut test = Test::new("Widen primary key without referencing column");
+    test.first_migration(CREATE_TABLES);
+    test.second_migration(
+        r#"
+        name = "widen_users_id"
+
+        [[actions]]
+        type = "widen_primary_key"
+        table = "users"
+        column = "id"
+        "#,
+    );
+
+    test.expect_failure();
+    test.run();
+}
+
+#[test]
+fn widen_non_primary_key() {
+    let mut test = Test::new("Widen non primary key");
+    test.first_migration(CREATE_TABLES);
+    test.second_migration(
+        r#"
+        name = "widen_user_id"
+
+        [[actions]]
+        type = "widen_primary_key"
+        table = "orders"
+        column = "user_id"
+        "#,
+    );
+
+    test.expect_failure();
+    test.run();
+}